@@ -0,0 +1,116 @@
+///
+/// acceptance_filter.rs
+///
+/// ID/mask acceptance filters, matching the semantics of SocketCAN raw socket filters.
+/// A frame is accepted when its ID ANDed with the mask equals the filter ID ANDed with the mask, and by any
+/// filter of the set. Inverted filters accept every frame that the equivalent normal filter would reject.
+///
+use crate::frame_validation::{FrameError, MAX_EXTENDED_ID, MAX_STANDARD_ID};
+use crosscan::can::CanFrame;
+use godot::prelude::*;
+
+// Flag bits of a raw SocketCAN id, also used by the software filter so that both behave identically
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+#[cfg(target_os = "linux")]
+const CAN_INV_FILTER: u32 = 0x2000_0000;
+
+// All error classes that can be selected by an error mask
+pub const CAN_ERR_MASK: u32 = 0x1FFF_FFFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptanceFilter {
+    pub id: u32,
+    pub mask: u32,
+    pub is_extended: bool,
+    pub inverted: bool,
+}
+
+impl AcceptanceFilter {
+    /// Creates a filter for standard or extended frames. Mask bits beyond the width of the id are ignored.
+    pub fn new(id: i64, mask: i64, is_extended: bool, inverted: bool) -> Result<Self, FrameError> {
+        let max_id = if is_extended {
+            MAX_EXTENDED_ID
+        } else {
+            MAX_STANDARD_ID
+        };
+
+        if !(0..=max_id).contains(&id) {
+            return Err(if is_extended {
+                FrameError::ExtendedIdOutOfRange(id)
+            } else {
+                FrameError::StandardIdOutOfRange(id)
+            });
+        }
+
+        Ok(Self {
+            id: id as u32,
+            mask: (mask & max_id) as u32,
+            is_extended,
+            inverted,
+        })
+    }
+
+    /// Creates a filter from a Godot Dictionary with an "id" and "mask", and optional "extended" and "inverted" flags
+    pub fn from_dictionary(filter: &Dictionary) -> Result<Self, String> {
+        let number = |key: &str| filter.get(key).and_then(|value| value.try_to::<i64>().ok());
+        let flag = |key: &str| {
+            filter
+                .get(key)
+                .and_then(|value| value.try_to::<bool>().ok())
+                .unwrap_or(false)
+        };
+
+        let (Some(id), Some(mask)) = (number("id"), number("mask")) else {
+            return Err("Acceptance filters require an integer \"id\" and \"mask\"".to_string());
+        };
+
+        Self::new(id, mask, flag("extended"), flag("inverted")).map_err(|e| e.to_string())
+    }
+
+    pub fn to_dictionary(&self) -> Dictionary {
+        let mut filter = Dictionary::new();
+        filter.set("id", self.id);
+        filter.set("mask", self.mask);
+        filter.set("extended", self.is_extended);
+        filter.set("inverted", self.inverted);
+        filter
+    }
+
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        // The frame type must always match, so the extended flag is part of every mask
+        let mask = self.mask | CAN_EFF_FLAG;
+        let frame_id = raw_can_id(frame.id(), frame.is_extended());
+        let filter_id = raw_can_id(self.id, self.is_extended);
+
+        ((frame_id & mask) == (filter_id & mask)) != self.inverted
+    }
+
+    /// Converts the filter into the form expected by the CAN_RAW_FILTER socket option
+    #[cfg(target_os = "linux")]
+    pub fn kernel_filter(&self) -> (u32, u32) {
+        let mut can_id = raw_can_id(self.id, self.is_extended);
+        if self.inverted {
+            can_id |= CAN_INV_FILTER;
+        }
+        (can_id, self.mask | CAN_EFF_FLAG)
+    }
+}
+
+/// The set of acceptance filters applied to an interface, along with the classes of error frames to receive
+#[derive(Debug, Clone, Default)]
+pub struct FilterSet {
+    pub filters: Vec<AcceptanceFilter>,
+    /// Error class bits (CAN_ERR_* in linux/can/error.h) of the error frames to receive, where 0 receives none
+    pub error_mask: u32,
+}
+
+impl FilterSet {
+    /// Returns true if the frame passes the filters. An empty set accepts every frame.
+    pub fn accepts(&self, frame: &CanFrame) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| filter.matches(frame))
+    }
+}
+
+fn raw_can_id(id: u32, is_extended: bool) -> u32 {
+    if is_extended { id | CAN_EFF_FLAG } else { id }
+}
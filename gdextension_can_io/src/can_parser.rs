@@ -4,7 +4,8 @@
 /// Parses CanFrames into Godot Variant Arrays.
 /// Can optionally utilise a CAN DBC file to parse the raw data into named items in the Godot Arrays.
 ///
-use crate::{CanEntry, CanId};
+use crate::frame_filter::{FilterTarget, FrameFilters};
+use crate::{CanEntry, CanId, Direction};
 use can_dbc::{ByteOrder, DBC};
 use core::panic;
 use crosscan::can::CanFrame;
@@ -39,6 +40,15 @@ mod dbc_helpers {
     }
 }
 
+// A decoded signal value, before any factor or offset is applied
+enum SignalValue {
+    Signed(i64),
+    Unsigned(u64),
+    Float32(f32),
+    Float64(f64),
+}
+
+#[derive(Clone)]
 pub struct CanParser {
     dbc: Option<DBC>,
 }
@@ -68,12 +78,22 @@ impl CanParser {
         self.dbc = None
     }
 
+    /// Returns the currently loaded DBC file, if any
+    pub fn dbc(&self) -> Option<&DBC> {
+        self.dbc.as_ref()
+    }
+
     /// Parses a set of CanDataFrames into a table of Godot CAN entries. Will optionally use a DBC for deserialisation if provided.
-    pub fn parse_can_table(&self, can_entries: &HashMap<CanId, CanEntry>) -> Array<Variant> {
+    /// Each entry is flagged with whether it passes the table and plot filters.
+    pub fn parse_can_table(
+        &self,
+        can_entries: &HashMap<CanId, CanEntry>,
+        frame_filters: &FrameFilters,
+    ) -> Array<Variant> {
         let mut godot_can_table = VariantArray::new();
 
         for (_, entry) in can_entries.iter() {
-            let godot_can_entry = &self.parse_can_entry(entry).to_variant();
+            let godot_can_entry = &self.parse_can_entry(entry, frame_filters).to_variant();
             godot_can_table.push(godot_can_entry);
         }
 
@@ -81,7 +101,11 @@ impl CanParser {
     }
 
     /// Parses a given CanEntry into a Godot CAN entry. Will optionally use a DBC for deserialisation if provided.
-    fn parse_can_entry(&self, can_entry: &CanEntry) -> Array<Variant> {
+    fn parse_can_entry(
+        &self,
+        can_entry: &CanEntry,
+        frame_filters: &FrameFilters,
+    ) -> Array<Variant> {
         let mut godot_can_entry = VariantArray::new();
 
         godot_can_entry
@@ -116,10 +140,24 @@ impl CanParser {
                 Self::deserialise_unknown_data(godot_can_entry, can_entry.frame.clone());
         }
 
-        // The last element indicates to Godot whether the frame is Extended
+        // The fourth last element indicates to Godot whether the frame is Extended
         godot_can_entry
             .push(&GString::from(format!("{:?}", can_entry.frame.is_extended())).to_variant());
 
+        // The third last element indicates to Godot whether the frame was received (RX) or transmitted by us (TX)
+        let direction = match can_entry.direction {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        };
+        godot_can_entry.push(&GString::from(direction).to_variant());
+
+        // The last two elements indicate to Godot whether the frame passes the table and plot filters
+        for target in [FilterTarget::Table, FilterTarget::Plot] {
+            let accepted =
+                frame_filters.accepts(target, &can_entry.frame, can_entry.direction, self);
+            godot_can_entry.push(&GString::from(format!("{accepted:?}")).to_variant());
+        }
+
         godot_can_entry
     }
 
@@ -140,49 +178,111 @@ impl CanParser {
                 }
             };
 
-            let mut bytes = frame.data().to_vec();
-            if *signal.byte_order() == ByteOrder::BigEndian {
-                CanParser::reverse_bit_order(&mut bytes);
-            }
-
-            let formatted_value = match dbc
-                .extended_value_type_for_signal(*message_info.message_id(), signal.name())
-                .unwrap_or(&can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger)
-            {
-                can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger => {
-                    let start_bit = usize::try_from(*signal.start_bit()).unwrap();
-                    let length = usize::try_from(*signal.signal_size()).unwrap();
-                    match signal.value_type() {
-                        can_dbc::ValueType::Signed => {
-                            format!(
-                                "{:?}",
-                                CanParser::extract_bits_i64(bytes, start_bit, length)
-                            )
-                        }
-                        can_dbc::ValueType::Unsigned => {
-                            format!(
-                                "{:?}",
-                                CanParser::extract_bits_u64(bytes, start_bit, length)
-                            )
-                        }
-                    }
-                }
-                can_dbc::SignalExtendedValueType::IEEEfloat32Bit => {
-                    let start_bit = usize::try_from(*signal.start_bit()).unwrap();
-                    let raw_value = CanParser::extract_bits_u64(bytes, start_bit, 32) as u32;
-                    format!("{:?}", f32::from_bits(raw_value))
-                }
-                can_dbc::SignalExtendedValueType::IEEEdouble64bit => {
-                    let start_bit = usize::try_from(*signal.start_bit()).unwrap();
-                    let raw_value = CanParser::extract_bits_u64(bytes, start_bit, 64);
-                    format!("{:?}", f64::from_bits(raw_value))
-                }
+            let formatted_value = match Self::decode_signal(dbc, message_info, signal, &frame) {
+                Some(SignalValue::Signed(value)) => format!("{value:?}"),
+                Some(SignalValue::Unsigned(value)) => format!("{value:?}"),
+                Some(SignalValue::Float32(value)) => format!("{value:?}"),
+                Some(SignalValue::Float64(value)) => format!("{value:?}"),
+                None => String::new(),
             };
             godot_can_entry.push(&GString::from(formatted_value).to_variant());
         }
         godot_can_entry
     }
 
+    /// Returns the DBC definition of the frame's message, if a DBC is loaded and defines it
+    pub fn message_for_frame(&self, frame: &CanFrame) -> Option<&can_dbc::Message> {
+        let query_id = dbc_helpers::get_message_id(frame);
+        self.dbc
+            .as_ref()?
+            .messages()
+            .iter()
+            .find(|m| m.message_id() == &query_id)
+    }
+
+    /// Returns the raw value of a signal as shown in the CAN table, or None if the signal lies outside the frame data
+    pub fn signal_value(
+        &self,
+        message_info: &can_dbc::Message,
+        signal_name: &str,
+        frame: &CanFrame,
+    ) -> Option<f64> {
+        let dbc = self.dbc.as_ref()?;
+        let signal = message_info
+            .signals()
+            .iter()
+            .find(|s| s.name() == signal_name)?;
+
+        Some(
+            match Self::decode_signal(dbc, message_info, signal, frame)? {
+                SignalValue::Signed(value) => value as f64,
+                SignalValue::Unsigned(value) => value as f64,
+                SignalValue::Float32(value) => value as f64,
+                SignalValue::Float64(value) => value,
+            },
+        )
+    }
+
+    /// Returns the physical value of a signal, its raw value with the signal's factor and offset applied
+    pub fn physical_signal_value(
+        &self,
+        message_info: &can_dbc::Message,
+        signal_name: &str,
+        frame: &CanFrame,
+    ) -> Option<f64> {
+        let signal = message_info
+            .signals()
+            .iter()
+            .find(|s| s.name() == signal_name)?;
+        let raw_value = self.signal_value(message_info, signal_name, frame)?;
+        Some(raw_value * signal.factor() + signal.offset())
+    }
+
+    // Extracts the raw value of a signal from the frame data
+    fn decode_signal(
+        dbc: &DBC,
+        message_info: &can_dbc::Message,
+        signal: &can_dbc::Signal,
+        frame: &CanFrame,
+    ) -> Option<SignalValue> {
+        let mut bytes = frame.data().to_vec();
+        if *signal.byte_order() == ByteOrder::BigEndian {
+            CanParser::reverse_bit_order(&mut bytes);
+        }
+
+        let start_bit = usize::try_from(*signal.start_bit()).unwrap();
+        let value_type = Self::signal_value_type(dbc, message_info.message_id(), signal.name());
+        let length = match value_type {
+            can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger => {
+                usize::try_from(*signal.signal_size()).unwrap()
+            }
+            can_dbc::SignalExtendedValueType::IEEEfloat32Bit => 32,
+            can_dbc::SignalExtendedValueType::IEEEdouble64bit => 64,
+        };
+        if length == 0 || bytes.len() > 8 || start_bit + length > bytes.len() * 8 {
+            return None;
+        }
+
+        Some(match value_type {
+            can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger => {
+                match signal.value_type() {
+                    can_dbc::ValueType::Signed => {
+                        SignalValue::Signed(CanParser::extract_bits_i64(bytes, start_bit, length))
+                    }
+                    can_dbc::ValueType::Unsigned => {
+                        SignalValue::Unsigned(CanParser::extract_bits_u64(bytes, start_bit, length))
+                    }
+                }
+            }
+            can_dbc::SignalExtendedValueType::IEEEfloat32Bit => SignalValue::Float32(
+                f32::from_bits(CanParser::extract_bits_u64(bytes, start_bit, 32) as u32),
+            ),
+            can_dbc::SignalExtendedValueType::IEEEdouble64bit => SignalValue::Float64(
+                f64::from_bits(CanParser::extract_bits_u64(bytes, start_bit, 64)),
+            ),
+        })
+    }
+
     /// Deserialises and appends the raw byte data from the CAN frame to the Godot CAN entry
     fn deserialise_unknown_data(
         mut godot_can_entry: Array<Variant>,
@@ -194,6 +294,87 @@ impl CanParser {
         godot_can_entry
     }
 
+    /// Returns the extended value type of a signal, defaulting to an integer when the DBC does not specify one
+    pub fn signal_value_type(
+        dbc: &DBC,
+        message_id: &can_dbc::MessageId,
+        signal_name: &str,
+    ) -> can_dbc::SignalExtendedValueType {
+        dbc.extended_value_type_for_signal(*message_id, signal_name)
+            .cloned()
+            .unwrap_or(can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger)
+    }
+
+    /// Encodes a physical signal value into the frame data, applying the signal's factor and offset.
+    /// This is the inverse of the bit extraction performed in `deserialise_dbc_data`.
+    /// Signals that do not fit within the provided data are left untouched.
+    pub fn encode_signal(
+        signal: &can_dbc::Signal,
+        value_type: &can_dbc::SignalExtendedValueType,
+        physical_value: f64,
+        data: &mut Vec<u8>,
+    ) {
+        let start_bit = usize::try_from(*signal.start_bit()).unwrap();
+        let length = match value_type {
+            can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger => {
+                usize::try_from(*signal.signal_size()).unwrap()
+            }
+            can_dbc::SignalExtendedValueType::IEEEfloat32Bit => 32,
+            can_dbc::SignalExtendedValueType::IEEEdouble64bit => 64,
+        };
+        if length == 0 || data.len() > 8 || start_bit + length > data.len() * 8 {
+            return;
+        }
+
+        let factor = if *signal.factor() == 0.0 {
+            1.0
+        } else {
+            *signal.factor()
+        };
+        let scaled_value = (physical_value - *signal.offset()) / factor;
+
+        let raw_value = match value_type {
+            can_dbc::SignalExtendedValueType::SignedOrUnsignedInteger => {
+                match signal.value_type() {
+                    can_dbc::ValueType::Signed => {
+                        let min = if length == 64 {
+                            i64::MIN
+                        } else {
+                            -(1i64 << (length - 1))
+                        };
+                        let max = if length == 64 {
+                            i64::MAX
+                        } else {
+                            (1i64 << (length - 1)) - 1
+                        };
+                        (scaled_value.round() as i64).clamp(min, max) as u64
+                    }
+                    can_dbc::ValueType::Unsigned => {
+                        let max = if length == 64 {
+                            u64::MAX
+                        } else {
+                            (1u64 << length) - 1
+                        };
+                        (scaled_value.round().max(0.0) as u64).min(max)
+                    }
+                }
+            }
+            can_dbc::SignalExtendedValueType::IEEEfloat32Bit => {
+                (scaled_value as f32).to_bits() as u64
+            }
+            can_dbc::SignalExtendedValueType::IEEEdouble64bit => scaled_value.to_bits(),
+        };
+
+        let is_big_endian = *signal.byte_order() == ByteOrder::BigEndian;
+        if is_big_endian {
+            CanParser::reverse_bit_order(data);
+        }
+        CanParser::insert_bits_u64(data, start_bit, length, raw_value);
+        if is_big_endian {
+            CanParser::reverse_bit_order(data);
+        }
+    }
+
     fn reverse_bit_order(bytes: &mut Vec<u8>) {
         for byte in bytes.iter_mut() {
             *byte = byte.reverse_bits();
@@ -236,4 +417,27 @@ impl CanParser {
 
         value
     }
+
+    // Writes a value into a data vector given the start bit and length. Assumes little-endian bit representation.
+    fn insert_bits_u64(bytes: &mut Vec<u8>, start_bit: usize, length: usize, value: u64) {
+        assert!(bytes.len() <= 8, "Input slice must a maximum of 8 bytes");
+        assert!(
+            start_bit + length <= (bytes.len() * 8),
+            "Out of bounds bit insertion"
+        );
+
+        let mut bytes_buf = [0u8; 8];
+        bytes_buf[..bytes.len()].copy_from_slice(&bytes[..bytes.len()]);
+
+        let mask = if length == 64 {
+            u64::MAX
+        } else {
+            (1u64 << length) - 1
+        };
+        let mut word = u64::from_le_bytes(bytes_buf);
+        word = (word & !(mask << start_bit)) | ((value & mask) << start_bit);
+
+        let len = bytes.len();
+        bytes.copy_from_slice(&word.to_le_bytes()[..len]);
+    }
 }
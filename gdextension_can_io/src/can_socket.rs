@@ -0,0 +1,355 @@
+///
+/// can_socket.rs
+///
+/// Selects the CAN backend for an interface name. Names prefixed with `virtual:` open an in-process virtual bus,
+/// all other names open the platform CAN socket for the supported operating systems.
+///
+/// crosscan's frames cannot carry error frames, so on Linux the error frames selected by the error mask of the
+/// acceptance filters are read from a second raw socket on the same interface, which receives no other frames.
+///
+use crate::acceptance_filter::FilterSet;
+use crate::virtual_can::VirtualCan;
+use crosscan::CanInterface;
+use crosscan::can::CanFrame;
+
+// Select a specific CAN Socket implementation for the supported operating systems
+#[cfg(target_os = "linux")]
+use crosscan::lin_can::LinuxCan as PlatformCan;
+
+#[cfg(target_os = "windows")]
+use crosscan::win_can::WindowsCan as PlatformCan;
+
+#[derive(Debug)]
+pub enum Error {
+    Interface(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Interface(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/// A frame read from the socket
+pub enum SocketFrame {
+    Data(CanFrame),
+    /// An error frame, carried in a CanFrame with the error class bits in place of the id and the error details
+    /// as its data
+    Error(CanFrame),
+}
+
+enum Backend {
+    Platform(PlatformCan),
+    Virtual(VirtualCan),
+}
+
+pub struct CanSocket {
+    interface_name: String,
+    backend: Backend,
+    #[cfg(target_os = "linux")]
+    error_frames: Option<ErrorFrameReader>,
+}
+
+impl CanSocket {
+    pub async fn open(interface_name: &str) -> Result<Self, Error> {
+        let backend = match VirtualCan::bus_name_from_interface(interface_name) {
+            Some(bus_name) => Backend::Virtual(VirtualCan::open(bus_name)),
+            None => match PlatformCan::open(interface_name).await {
+                Ok(socket) => Backend::Platform(socket),
+                Err(err) => return Err(Error::Interface(format!("{err:?}"))),
+            },
+        };
+
+        Ok(Self {
+            interface_name: interface_name.to_string(),
+            backend,
+            #[cfg(target_os = "linux")]
+            error_frames: None,
+        })
+    }
+
+    /// Returns the bitrate of the bus if the backend is able to report it
+    pub async fn get_bitrate(&mut self) -> Result<Option<u32>, Error> {
+        match &mut self.backend {
+            Backend::Platform(socket) => socket
+                .get_bitrate()
+                .await
+                .map_err(|err| Error::Interface(format!("{err:?}"))),
+            // Virtual buses are not rate limited
+            Backend::Virtual(_) => Ok(None),
+        }
+    }
+
+    pub async fn read_frame(&mut self) -> Result<SocketFrame, Error> {
+        match &mut self.backend {
+            Backend::Platform(socket) => {
+                #[cfg(target_os = "linux")]
+                if let Some(error_frames) = &mut self.error_frames {
+                    return tokio::select! {
+                        result = socket.read_frame() => result
+                            .map(SocketFrame::Data)
+                            .map_err(|err| Error::Interface(format!("{err:?}"))),
+                        result = error_frames.read_frame() => result.map(SocketFrame::Error),
+                    };
+                }
+
+                socket
+                    .read_frame()
+                    .await
+                    .map(SocketFrame::Data)
+                    .map_err(|err| Error::Interface(format!("{err:?}")))
+            }
+            Backend::Virtual(socket) => Ok(SocketFrame::Data(socket.read_frame().await)),
+        }
+    }
+
+    pub async fn write_frame(&mut self, frame: CanFrame) -> Result<(), Error> {
+        match &mut self.backend {
+            Backend::Platform(socket) => socket
+                .write_frame(frame)
+                .await
+                .map_err(|err| Error::Interface(format!("{err:?}"))),
+            Backend::Virtual(socket) => {
+                socket.write_frame(frame);
+                Ok(())
+            }
+        }
+    }
+
+    /// Asks the backend to loop our own frames back once they have been sent, so that they can be timestamped
+    /// when they were actually on the bus. Returns true if they will be read back from read_frame.
+    pub fn enable_echo(&mut self) -> bool {
+        match &mut self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Platform(socket) => {
+                use std::os::fd::AsRawFd;
+                set_socket_option(
+                    socket.as_raw_fd(),
+                    libc::CAN_RAW_RECV_OWN_MSGS,
+                    &[1 as libc::c_int],
+                )
+                .is_ok()
+            }
+            #[cfg(not(target_os = "linux"))]
+            Backend::Platform(_) => false,
+            // Virtual frames are delivered as they are written, so there is nothing to wait for
+            Backend::Virtual(_) => false,
+        }
+    }
+
+    /// Applies acceptance filters to the socket. Returns true if the filters are applied by the kernel,
+    /// or false if the backend cannot filter and every frame must be filtered in software instead.
+    /// Backends that cannot receive error frames return an error for a non-zero error mask.
+    pub fn set_filters(&mut self, filter_set: &FilterSet) -> Result<bool, Error> {
+        match &mut self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Platform(socket) => {
+                // The error reader is opened first so that a failure leaves the previous filters in place
+                let error_frames = match filter_set.error_mask {
+                    0 => None,
+                    error_mask => Some(ErrorFrameReader::open(&self.interface_name, error_mask)?),
+                };
+                set_kernel_filters(socket, filter_set)?;
+
+                // Dropping the reader closes the socket of the previous error mask
+                self.error_frames = error_frames;
+                Ok(true)
+            }
+            #[cfg(not(target_os = "linux"))]
+            Backend::Platform(_) => software_filters(filter_set),
+            Backend::Virtual(_) => software_filters(filter_set),
+        }
+    }
+}
+
+// Backends without kernel filtering have every frame filtered in software, but no error frames to filter
+fn software_filters(filter_set: &FilterSet) -> Result<bool, Error> {
+    if filter_set.error_mask != 0 {
+        return Err(Error::Interface(
+            "error frames cannot be received from this interface".to_string(),
+        ));
+    }
+    Ok(false)
+}
+
+// Sets the CAN_RAW_FILTER and CAN_RAW_ERR_FILTER options of crosscan's raw socket
+#[cfg(target_os = "linux")]
+fn set_kernel_filters(socket: &PlatformCan, filter_set: &FilterSet) -> Result<(), Error> {
+    use std::os::fd::AsRawFd;
+
+    // An empty filter list receives nothing, so a single zero mask filter is used to accept every frame instead
+    let kernel_filters: Vec<libc::can_filter> = if filter_set.filters.is_empty() {
+        vec![libc::can_filter {
+            can_id: 0,
+            can_mask: 0,
+        }]
+    } else {
+        filter_set
+            .filters
+            .iter()
+            .map(|filter| {
+                let (can_id, can_mask) = filter.kernel_filter();
+                libc::can_filter { can_id, can_mask }
+            })
+            .collect()
+    };
+
+    let fd = socket.as_raw_fd();
+    set_socket_option(fd, libc::CAN_RAW_FILTER, kernel_filters.as_slice())?;
+    // Error frames are read from their own socket, as crosscan cannot return them
+    set_socket_option(fd, libc::CAN_RAW_ERR_FILTER, &[0 as libc::can_err_mask_t])
+}
+
+#[cfg(target_os = "linux")]
+fn set_socket_option<T>(
+    fd: std::os::fd::RawFd,
+    option: libc::c_int,
+    values: &[T],
+) -> Result<(), Error> {
+    // SAFETY: the pointer and length describe the values slice, which outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_CAN_RAW,
+            option,
+            values.as_ptr().cast(),
+            std::mem::size_of_val(values) as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Interface(
+            std::io::Error::last_os_error().to_string(),
+        ))
+    }
+}
+
+// Error frames are waited for with this timeout, so that the reading thread notices when the reader is dropped
+#[cfg(target_os = "linux")]
+const ERROR_FRAME_READ_TIMEOUT_US: libc::suseconds_t = 200_000;
+
+/// Reads error frames from a raw socket bound to the interface that receives no data frames. The socket is read
+/// by a blocking thread, which closes it once the reader has been dropped.
+#[cfg(target_os = "linux")]
+struct ErrorFrameReader {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<Result<CanFrame, Error>>,
+}
+
+#[cfg(target_os = "linux")]
+impl ErrorFrameReader {
+    fn open(interface_name: &str, error_mask: u32) -> Result<Self, Error> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        let os_error = || Error::Interface(std::io::Error::last_os_error().to_string());
+
+        let name = std::ffi::CString::new(interface_name)
+            .map_err(|_| Error::Interface(format!("invalid interface name {interface_name:?}")))?;
+        // SAFETY: the name is a valid nul terminated string
+        let interface_index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if interface_index == 0 {
+            return Err(os_error());
+        }
+
+        // SAFETY: creating a socket has no memory safety requirements
+        let fd = unsafe {
+            libc::socket(
+                libc::PF_CAN,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(os_error());
+        }
+        // SAFETY: the descriptor was just created and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // An empty filter list receives no data frames, leaving only the error classes selected by the mask
+        set_socket_option::<libc::can_filter>(fd.as_raw_fd(), libc::CAN_RAW_FILTER, &[])?;
+        set_socket_option(fd.as_raw_fd(), libc::CAN_RAW_ERR_FILTER, &[error_mask])?;
+
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: ERROR_FRAME_READ_TIMEOUT_US,
+        };
+        // SAFETY: the timeout is a timeval of the given length
+        let result = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                (&raw const timeout).cast(),
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(os_error());
+        }
+
+        // SAFETY: all zeros is a valid sockaddr_can
+        let mut address: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+        address.can_family = libc::AF_CAN as libc::sa_family_t;
+        address.can_ifindex = interface_index as libc::c_int;
+        // SAFETY: the address is a sockaddr_can of the given length
+        let result = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                (&raw const address).cast(),
+                std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(os_error());
+        }
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            while !sender.is_closed() {
+                // SAFETY: all zeros is a valid can_frame
+                let mut frame: libc::can_frame = unsafe { std::mem::zeroed() };
+                // SAFETY: the buffer is a can_frame of the given length
+                let read = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        (&raw mut frame).cast(),
+                        std::mem::size_of::<libc::can_frame>(),
+                    )
+                };
+
+                if read < 0 {
+                    let err = std::io::Error::last_os_error();
+                    match err.kind() {
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => {
+                            continue;
+                        }
+                        _ => {
+                            let _ = sender.send(Err(Error::Interface(err.to_string())));
+                            break;
+                        }
+                    }
+                }
+
+                let error_frame =
+                    crate::new_can_frame(frame.can_id & libc::CAN_ERR_MASK, true, &frame.data);
+                if let Some(error_frame) = error_frame {
+                    let _ = sender.send(Ok(error_frame));
+                }
+            }
+        });
+
+        Ok(Self { receiver })
+    }
+
+    /// Waits for the next error frame
+    async fn read_frame(&mut self) -> Result<CanFrame, Error> {
+        match self.receiver.recv().await {
+            Some(result) => result,
+            // The thread only stops after sending the error that stopped it
+            None => std::future::pending().await,
+        }
+    }
+}
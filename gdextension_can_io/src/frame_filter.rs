@@ -0,0 +1,812 @@
+///
+/// frame_filter.rs
+///
+/// Filter expressions evaluated over frame fields and DBC decoded signals, e.g.
+/// `id in 0x100..0x1FF && !extended`, `EngineSpeed > 3000` or `msg == "Imu*"`.
+///
+/// Supported fields are `id`, `extended`, `dlc`, `tx` (true for frames we transmitted), `msg` (the DBC message
+/// name) and `data[n]`. Any other name refers to a signal of the frame's DBC message, compared using its physical
+/// value, with the signal's factor and offset applied. Comparisons against signals that are not part of the frame
+/// are always false.
+///
+/// Expressions combine `||`, `&&`, `!` and parentheses with the comparisons `==`, `!=`, `<`, `<=`, `>`, `>=`
+/// and the inclusive range check `in min..max`. Comparing a string with `==` or `!=` treats the right hand
+/// side as a pattern, where `*` matches any run of characters and `?` matches a single character.
+///
+use crate::Direction;
+use crate::can_parser::CanParser;
+use crosscan::can::CanFrame;
+
+#[derive(Debug)]
+pub struct Error {
+    /// Character offset into the expression at which the error was found
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position)
+    }
+}
+
+/// The outputs that a filter can be attached to, each filtering independently
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterTarget {
+    Table,
+    Plot,
+    Recording,
+}
+
+impl FilterTarget {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "table" => Some(FilterTarget::Table),
+            "plot" => Some(FilterTarget::Plot),
+            "recording" => Some(FilterTarget::Recording),
+            _ => None,
+        }
+    }
+}
+
+/// The filters attached to each target, where no filter passes every frame
+#[derive(Default)]
+pub struct FrameFilters {
+    table: Option<FilterExpression>,
+    plot: Option<FilterExpression>,
+    recording: Option<FilterExpression>,
+}
+
+impl FrameFilters {
+    pub fn get(&self, target: FilterTarget) -> Option<&FilterExpression> {
+        match target {
+            FilterTarget::Table => self.table.as_ref(),
+            FilterTarget::Plot => self.plot.as_ref(),
+            FilterTarget::Recording => self.recording.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, target: FilterTarget, filter: Option<FilterExpression>) {
+        match target {
+            FilterTarget::Table => self.table = filter,
+            FilterTarget::Plot => self.plot = filter,
+            FilterTarget::Recording => self.recording = filter,
+        }
+    }
+
+    /// Returns true if the frame passes the filter attached to the target
+    pub fn accepts(
+        &self,
+        target: FilterTarget,
+        frame: &CanFrame,
+        direction: Direction,
+        can_parser: &CanParser,
+    ) -> bool {
+        self.get(target)
+            .is_none_or(|filter| filter.matches(frame, direction, can_parser))
+    }
+}
+
+#[derive(Clone)]
+pub struct FilterExpression {
+    source: String,
+    root: Expr,
+}
+
+impl FilterExpression {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            end: source.chars().count(),
+            depth: 0,
+        };
+
+        let root = parser.parse_or()?;
+        if let Some((position, token)) = parser.tokens.get(parser.index) {
+            return Err(Error {
+                position: *position,
+                message: format!("Unexpected '{token}'"),
+            });
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, frame: &CanFrame, direction: Direction, can_parser: &CanParser) -> bool {
+        let context = FrameContext {
+            frame,
+            direction,
+            can_parser,
+            message_info: can_parser.message_for_frame(frame),
+        };
+        context.evaluate(&self.root).is_truthy()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    And,
+    Or,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Range,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Minus,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{number}"),
+            Token::Str(string) => write!(f, "{string:?}"),
+            Token::Ident(name) => write!(f, "{name}"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::Range => write!(f, ".."),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+            Token::Minus => write!(f, "-"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone)]
+enum Field {
+    Id,
+    Extended,
+    Dlc,
+    Tx,
+    MessageName,
+    DataByte(usize),
+    Signal(String),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Field(Field),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Comparison, Box<Expr>),
+    InRange(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    /// A field that does not exist for the frame, such as a signal of another message
+    Missing,
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0.0,
+            Value::Bool(value) => *value,
+            Value::Str(string) => !string.is_empty(),
+            Value::Missing => false,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => Token::And,
+            ('|', Some('|')) => Token::Or,
+            ('=', Some('=')) => Token::Equal,
+            ('!', Some('=')) => Token::NotEqual,
+            ('<', Some('=')) => Token::LessEqual,
+            ('>', Some('=')) => Token::GreaterEqual,
+            ('.', Some('.')) => Token::Range,
+            ('!', _) => Token::Not,
+            ('<', _) => Token::Less,
+            ('>', _) => Token::Greater,
+            ('(', _) => Token::OpenParen,
+            (')', _) => Token::CloseParen,
+            ('[', _) => Token::OpenBracket,
+            (']', _) => Token::CloseBracket,
+            ('-', _) => Token::Minus,
+            ('"', _) => {
+                i += 1;
+                let mut string = String::new();
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some(c) => string.push(*c),
+                        None => {
+                            return Err(Error {
+                                position: start,
+                                message: "Unterminated string".to_string(),
+                            });
+                        }
+                    }
+                    i += 1;
+                }
+                i += 1;
+                tokens.push((start, Token::Str(string)));
+                continue;
+            }
+            (c, _) if c.is_ascii_digit() => {
+                let (number, length) = scan_number(&chars[i..]).ok_or_else(|| Error {
+                    position: start,
+                    message: "Invalid number".to_string(),
+                })?;
+                i += length;
+                tokens.push((start, Token::Number(number)));
+                continue;
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let length = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .count();
+                i += length;
+                tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+                continue;
+            }
+            (c, _) => {
+                return Err(Error {
+                    position: start,
+                    message: format!("Unexpected character {c:?}"),
+                });
+            }
+        };
+
+        i += match token {
+            Token::And
+            | Token::Or
+            | Token::Equal
+            | Token::NotEqual
+            | Token::LessEqual
+            | Token::GreaterEqual
+            | Token::Range => 2,
+            _ => 1,
+        };
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+// Scans a decimal or 0x prefixed hexadecimal number, returning its value and length in characters.
+// A '.' is only treated as a decimal point when followed by a digit, so that ranges such as 1..5 are not consumed.
+fn scan_number(chars: &[char]) -> Option<(f64, usize)> {
+    if chars.len() > 1 && chars[0] == '0' && matches!(chars[1], 'x' | 'X') {
+        let length = chars[2..]
+            .iter()
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count();
+        let digits: String = chars[2..2 + length].iter().collect();
+        let value = u64::from_str_radix(&digits, 16).ok()?;
+        return Some((value as f64, 2 + length));
+    }
+
+    let mut length = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if chars.get(length) == Some(&'.') && chars.get(length + 1).is_some_and(char::is_ascii_digit) {
+        length += 1 + chars[length + 1..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+    }
+    let digits: String = chars[..length].iter().collect();
+    Some((digits.parse().ok()?, length))
+}
+
+// The deepest nesting of parentheses, '!' and '-' accepted, so that the recursive parser cannot overflow the stack
+const MAX_DEPTH: usize = 64;
+
+// Recursive descent parser, from the lowest precedence (||) to the highest (operands)
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+    /// The current nesting depth of parentheses and unary operators
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error {
+            position: self.position(),
+            message: message.to_string(),
+        }
+    }
+
+    // Parses a nested expression, failing once the nesting exceeds MAX_DEPTH
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, Error>) -> Result<Expr, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("Expression is nested too deeply"));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn consume(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_and()?;
+        while self.consume(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_unary()?;
+        while self.consume(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        if self.consume(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.nested(Self::parse_unary)?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, Error> {
+        let lhs = self.parse_operand()?;
+
+        let comparison = match self.peek() {
+            Some(Token::Equal) => Comparison::Equal,
+            Some(Token::NotEqual) => Comparison::NotEqual,
+            Some(Token::Less) => Comparison::Less,
+            Some(Token::LessEqual) => Comparison::LessEqual,
+            Some(Token::Greater) => Comparison::Greater,
+            Some(Token::GreaterEqual) => Comparison::GreaterEqual,
+            Some(Token::Ident(keyword)) if keyword == "in" => {
+                self.index += 1;
+                let min = self.parse_operand()?;
+                if !self.consume(&Token::Range) {
+                    return Err(self.error("Expected '..' in range"));
+                }
+                let max = self.parse_operand()?;
+                return Ok(Expr::InRange(Box::new(lhs), Box::new(min), Box::new(max)));
+            }
+            _ => return Ok(lhs),
+        };
+        self.index += 1;
+
+        let rhs = self.parse_operand()?;
+        Ok(Expr::Compare(Box::new(lhs), comparison, Box::new(rhs)))
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, Error> {
+        let Some((_, token)) = self.tokens.get(self.index).cloned() else {
+            return Err(self.error("Unexpected end of expression"));
+        };
+        self.index += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expr::Literal(Value::Number(number))),
+            Token::Str(string) => Ok(Expr::Literal(Value::Str(string))),
+            Token::Minus => Ok(Expr::Negate(Box::new(self.nested(Self::parse_operand)?))),
+            Token::OpenParen => {
+                let expr = self.nested(Self::parse_or)?;
+                if !self.consume(&Token::CloseParen) {
+                    return Err(self.error("Expected ')'"));
+                }
+                Ok(expr)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "id" => Ok(Expr::Field(Field::Id)),
+                "extended" => Ok(Expr::Field(Field::Extended)),
+                "dlc" => Ok(Expr::Field(Field::Dlc)),
+                "tx" => Ok(Expr::Field(Field::Tx)),
+                "msg" => Ok(Expr::Field(Field::MessageName)),
+                "data" => {
+                    let index = match (self.consume(&Token::OpenBracket), self.peek()) {
+                        (true, Some(Token::Number(index))) if index.fract() == 0.0 => {
+                            *index as usize
+                        }
+                        _ => return Err(self.error("Expected a byte index, e.g. data[0]")),
+                    };
+                    self.index += 1;
+                    if !self.consume(&Token::CloseBracket) {
+                        return Err(self.error("Expected ']'"));
+                    }
+                    Ok(Expr::Field(Field::DataByte(index)))
+                }
+                _ => Ok(Expr::Field(Field::Signal(name))),
+            },
+            _ => {
+                self.index -= 1;
+                Err(self.error(&format!("Unexpected '{token}'")))
+            }
+        }
+    }
+}
+
+struct FrameContext<'a> {
+    frame: &'a CanFrame,
+    direction: Direction,
+    can_parser: &'a CanParser,
+    message_info: Option<&'a can_dbc::Message>,
+}
+
+impl FrameContext<'_> {
+    fn evaluate(&self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(field) => self.field_value(field),
+            Expr::Negate(expr) => match self.evaluate(expr) {
+                Value::Number(number) => Value::Number(-number),
+                _ => Value::Missing,
+            },
+            Expr::Not(expr) => Value::Bool(!self.evaluate(expr).is_truthy()),
+            Expr::And(lhs, rhs) => {
+                Value::Bool(self.evaluate(lhs).is_truthy() && self.evaluate(rhs).is_truthy())
+            }
+            Expr::Or(lhs, rhs) => {
+                Value::Bool(self.evaluate(lhs).is_truthy() || self.evaluate(rhs).is_truthy())
+            }
+            Expr::Compare(lhs, comparison, rhs) => Value::Bool(compare(
+                &self.evaluate(lhs),
+                *comparison,
+                &self.evaluate(rhs),
+            )),
+            Expr::InRange(value, min, max) => {
+                let value = self.evaluate(value);
+                Value::Bool(
+                    compare(&value, Comparison::GreaterEqual, &self.evaluate(min))
+                        && compare(&value, Comparison::LessEqual, &self.evaluate(max)),
+                )
+            }
+        }
+    }
+
+    fn field_value(&self, field: &Field) -> Value {
+        match field {
+            Field::Id => Value::Number(self.frame.id() as f64),
+            Field::Extended => Value::Bool(self.frame.is_extended()),
+            Field::Dlc => Value::Number(self.frame.dlc() as f64),
+            Field::Tx => Value::Bool(self.direction == Direction::Tx),
+            Field::MessageName => match self.message_info {
+                Some(message_info) => Value::Str(message_info.message_name().clone()),
+                None => Value::Str(String::new()),
+            },
+            Field::DataByte(index) => match self.frame.data().get(*index) {
+                Some(byte) => Value::Number(*byte as f64),
+                None => Value::Missing,
+            },
+            Field::Signal(name) => self
+                .message_info
+                .and_then(|message_info| {
+                    self.can_parser
+                        .physical_signal_value(message_info, name, self.frame)
+                })
+                .map(Value::Number)
+                .unwrap_or(Value::Missing),
+        }
+    }
+}
+
+fn compare(lhs: &Value, comparison: Comparison, rhs: &Value) -> bool {
+    let ordering = match (lhs, rhs) {
+        (Value::Missing, _) | (_, Value::Missing) => return false,
+        (Value::Str(string), Value::Str(pattern)) => {
+            return match comparison {
+                Comparison::Equal => glob_match(pattern, string),
+                Comparison::NotEqual => !glob_match(pattern, string),
+                _ => false,
+            };
+        }
+        (Value::Number(lhs), Value::Number(rhs)) => lhs.partial_cmp(rhs),
+        (Value::Bool(lhs), Value::Bool(rhs)) => Some(lhs.cmp(rhs)),
+        (Value::Bool(lhs), Value::Number(rhs)) => (*lhs as u8 as f64).partial_cmp(rhs),
+        (Value::Number(lhs), Value::Bool(rhs)) => lhs.partial_cmp(&(*rhs as u8 as f64)),
+        _ => None,
+    };
+
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match comparison {
+        Comparison::Equal => ordering.is_eq(),
+        Comparison::NotEqual => ordering.is_ne(),
+        Comparison::Less => ordering.is_lt(),
+        Comparison::LessEqual => ordering.is_le(),
+        Comparison::Greater => ordering.is_gt(),
+        Comparison::GreaterEqual => ordering.is_ge(),
+    }
+}
+
+// Matches a string against a pattern where '*' matches any run of characters and '?' matches a single character
+fn glob_match(pattern: &str, string: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+
+    let (mut p, mut s) = (0, 0);
+    // The position of the last '*' in the pattern, and the string position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == string[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_s)) => {
+                    p = star_p + 1;
+                    s = star_s + 1;
+                    backtrack = Some((star_p, star_s + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::temp_path;
+    use crate::new_can_frame;
+
+    const ENGINE_DBC: &str = r#"VERSION ""
+
+NS_ :
+
+BS_:
+
+BU_: ECU
+
+BO_ 256 Engine: 8 ECU
+    SG_ EngineSpeed : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Vector__XXX
+    SG_ CoolantTemp : 16|8@1+ (1,-40) [-40|215] "degC" Vector__XXX
+"#;
+
+    fn engine_parser() -> CanParser {
+        let path = temp_path("frame_filter_engine.dbc");
+        std::fs::write(&path, ENGINE_DBC).unwrap();
+        let mut can_parser = CanParser::new();
+        let opened = can_parser.open_dbc(path.to_string_lossy().into_owned());
+        std::fs::remove_file(&path).unwrap();
+        opened.unwrap();
+        can_parser
+    }
+
+    fn matches(source: &str, frame: &CanFrame, can_parser: &CanParser) -> bool {
+        FilterExpression::parse(source)
+            .unwrap()
+            .matches(frame, Direction::Rx, can_parser)
+    }
+
+    fn parse_error(source: &str) -> (usize, String) {
+        match FilterExpression::parse(source) {
+            Ok(_) => panic!("{source:?} should not parse"),
+            Err(error) => (error.position, error.message),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let can_parser = CanParser::new();
+        let frame = new_can_frame(0x1, false, &[0x10, 0x20]).unwrap();
+
+        assert!(matches(
+            "id == 1 || id == 2 && extended",
+            &frame,
+            &can_parser
+        ));
+        assert!(!matches(
+            "(id == 1 || id == 2) && extended",
+            &frame,
+            &can_parser
+        ));
+        assert!(matches("!extended && id == 1", &frame, &can_parser));
+        assert!(!matches("!(extended || id == 1)", &frame, &can_parser));
+        assert!(matches(
+            "data[0] < data[1] && dlc == 2",
+            &frame,
+            &can_parser
+        ));
+    }
+
+    #[test]
+    fn ranges_are_inclusive() {
+        let can_parser = CanParser::new();
+        let in_range = |id| {
+            let frame = new_can_frame(id, false, &[0]).unwrap();
+            matches("id in 0x100..0x1FF", &frame, &can_parser)
+        };
+
+        assert!(!in_range(0xFF));
+        assert!(in_range(0x100));
+        assert!(in_range(0x1FF));
+        assert!(!in_range(0x200));
+
+        let frame = new_can_frame(0x100, false, &[0]).unwrap();
+        assert!(matches("data[0] in -1..1", &frame, &can_parser));
+        assert!(matches("id in 255.5..256.5", &frame, &can_parser));
+    }
+
+    #[test]
+    fn missing_fields_never_compare() {
+        let can_parser = CanParser::new();
+        let frame = new_can_frame(0x100, false, &[0, 0]).unwrap();
+
+        assert!(!matches("data[2] == 0", &frame, &can_parser));
+        assert!(!matches("data[2] != 0", &frame, &can_parser));
+        assert!(matches("!(data[2] == 0)", &frame, &can_parser));
+        assert!(!matches("EngineSpeed >= 0", &frame, &can_parser));
+        assert!(matches("msg == \"\"", &frame, &can_parser));
+    }
+
+    #[test]
+    fn signals_compare_by_physical_value() {
+        let can_parser = engine_parser();
+        // EngineSpeed has a raw value of 8000 with a factor of 0.25, CoolantTemp 130 with an offset of -40
+        let frame = new_can_frame(0x100, false, &[0x40, 0x1F, 130, 0, 0, 0, 0, 0]).unwrap();
+
+        assert!(matches("EngineSpeed == 2000", &frame, &can_parser));
+        assert!(!matches("EngineSpeed == 8000", &frame, &can_parser));
+        assert!(matches("EngineSpeed > 1999.75", &frame, &can_parser));
+        assert!(matches("CoolantTemp in 85..95", &frame, &can_parser));
+        assert!(matches(
+            "CoolantTemp > -40 && EngineSpeed < 2000.25",
+            &frame,
+            &can_parser
+        ));
+        assert!(!matches("UnknownSignal >= 0", &frame, &can_parser));
+
+        let other_message = new_can_frame(0x101, false, &[0x40, 0x1F, 130]).unwrap();
+        assert!(!matches("EngineSpeed == 2000", &other_message, &can_parser));
+    }
+
+    #[test]
+    fn message_names_match_patterns() {
+        let can_parser = engine_parser();
+        let frame = new_can_frame(0x100, false, &[0; 8]).unwrap();
+
+        assert!(matches("msg == \"Engine\"", &frame, &can_parser));
+        assert!(matches("msg == \"Eng*\"", &frame, &can_parser));
+        assert!(matches("msg == \"?ngin?\"", &frame, &can_parser));
+        assert!(matches("msg != \"Imu*\"", &frame, &can_parser));
+        assert!(!matches("msg == \"Engine?\"", &frame, &can_parser));
+    }
+
+    #[test]
+    fn tx_matches_the_frame_direction() {
+        let can_parser = CanParser::new();
+        let frame = new_can_frame(0x100, false, &[]).unwrap();
+        let filter = FilterExpression::parse("tx").unwrap();
+
+        assert!(filter.matches(&frame, Direction::Tx, &can_parser));
+        assert!(!filter.matches(&frame, Direction::Rx, &can_parser));
+    }
+
+    #[test]
+    fn errors_report_their_position() {
+        assert_eq!(
+            parse_error("id =="),
+            (5, "Unexpected end of expression".to_string())
+        );
+        assert_eq!(
+            parse_error("id == 1 &&& 2"),
+            (10, "Unexpected character '&'".to_string())
+        );
+        assert_eq!(parse_error("(id == 1"), (8, "Expected ')'".to_string()));
+        assert_eq!(
+            parse_error("id in 1 5"),
+            (8, "Expected '..' in range".to_string())
+        );
+        assert_eq!(
+            parse_error("data[x] == 1"),
+            (5, "Expected a byte index, e.g. data[0]".to_string())
+        );
+        assert_eq!(parse_error("id == 1 2"), (8, "Unexpected '2'".to_string()));
+        assert_eq!(
+            parse_error("id == \"abc"),
+            (6, "Unterminated string".to_string())
+        );
+        assert_eq!(
+            parse_error("id == 0xFFFFFFFFFFFFFFFFF"),
+            (6, "Invalid number".to_string())
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let parenthesised = |depth| format!("{}true{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(FilterExpression::parse(&parenthesised(MAX_DEPTH)).is_ok());
+        assert!(FilterExpression::parse(&format!("{}true", "!".repeat(MAX_DEPTH))).is_ok());
+
+        for source in [
+            parenthesised(MAX_DEPTH + 1),
+            format!("{}true", "!".repeat(MAX_DEPTH + 1)),
+            format!("id == {}1", "-".repeat(MAX_DEPTH + 1)),
+            "(".repeat(100_000),
+            "!".repeat(100_000),
+        ] {
+            assert_eq!(parse_error(&source).1, "Expression is nested too deeply");
+        }
+    }
+}
@@ -0,0 +1,448 @@
+///
+/// frame_history.rs
+///
+/// A memory bounded history of timestamped frames for each CAN ID.
+/// Each ID keeps up to a fixed number of frames, and the history as a whole is capped at a global number of frames.
+/// Once the global cap is reached frames are evicted according to the configured eviction policy.
+/// The history can be queried as of any point in time, reconstructing the latest frame of each ID at that time, and
+/// navigated between the times at which the data of an ID changed. The history holds every frame since the newest
+/// evicted frame, so it is only complete from then on.
+///
+use crate::{CanId, Direction};
+use crosscan::can::CanFrame;
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_MAX_FRAMES_PER_ID: usize = 1_000;
+pub const DEFAULT_MAX_TOTAL_FRAMES: usize = 200_000;
+
+// Frequencies are measured over the frames received in this window before the frame they are measured at
+const FREQUENCY_WINDOW_US: u128 = 1_000_000;
+
+/// Which frame to evict once the global cap has been reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    /// Evict the oldest frame of any ID
+    Oldest,
+    /// Evict the oldest frame of the ID with the most frames, preserving the history of infrequent IDs
+    BusiestId,
+}
+
+impl EvictionPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "oldest" => Some(EvictionPolicy::Oldest),
+            "busiest_id" => Some(EvictionPolicy::BusiestId),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HistoryFrame {
+    /// Microseconds since the bridge was started
+    pub timestamp_us: u128,
+    pub frame: CanFrame,
+    pub direction: Direction,
+    sequence: u64,
+}
+
+/// The latest frame of an ID as of a point in time, along with the frequency of the ID at that time
+pub struct FrameAt<'a> {
+    pub history_frame: &'a HistoryFrame,
+    pub freq_hz: f32,
+}
+
+pub struct FrameHistory {
+    frames: HashMap<CanId, VecDeque<HistoryFrame>>,
+    // Insertion order of every stored frame, used to find the oldest frame for the Oldest eviction policy.
+    // Frames already evicted by the per ID cap are skipped when they reach the front.
+    insertion_order: VecDeque<(CanId, u64)>,
+    next_sequence: u64,
+    total_frames: usize,
+    // Timestamp of the newest frame evicted by either cap, after which the history holds every frame
+    evicted_until_us: Option<u128>,
+    max_frames_per_id: usize,
+    max_total_frames: usize,
+    eviction_policy: EvictionPolicy,
+}
+
+impl FrameHistory {
+    pub fn new() -> Self {
+        Self {
+            frames: HashMap::new(),
+            insertion_order: VecDeque::new(),
+            next_sequence: 0,
+            total_frames: 0,
+            evicted_until_us: None,
+            max_frames_per_id: DEFAULT_MAX_FRAMES_PER_ID,
+            max_total_frames: DEFAULT_MAX_TOTAL_FRAMES,
+            eviction_policy: EvictionPolicy::Oldest,
+        }
+    }
+
+    /// Sets the limits of the history, evicting frames straight away if the history is now over its limits
+    pub fn configure(
+        &mut self,
+        max_frames_per_id: usize,
+        max_total_frames: usize,
+        eviction_policy: EvictionPolicy,
+    ) {
+        self.max_frames_per_id = max_frames_per_id;
+        self.max_total_frames = max_total_frames;
+        self.eviction_policy = eviction_policy;
+
+        for frames in self.frames.values_mut() {
+            while frames.len() > max_frames_per_id {
+                if let Some(evicted) = frames.pop_front() {
+                    note_eviction(&mut self.evicted_until_us, &evicted);
+                }
+                self.total_frames -= 1;
+            }
+        }
+        self.frames.retain(|_, frames| !frames.is_empty());
+        self.enforce_total_limit();
+        self.compact_insertion_order();
+    }
+
+    pub fn push(&mut self, frame: CanFrame, direction: Direction, timestamp_us: u128) {
+        if self.max_frames_per_id == 0 || self.max_total_frames == 0 {
+            return;
+        }
+
+        let can_id = frame.id();
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let frames = self.frames.entry(can_id).or_default();
+        frames.push_back(HistoryFrame {
+            timestamp_us,
+            frame,
+            direction,
+            sequence,
+        });
+        self.insertion_order.push_back((can_id, sequence));
+        self.total_frames += 1;
+
+        if frames.len() > self.max_frames_per_id {
+            if let Some(evicted) = frames.pop_front() {
+                note_eviction(&mut self.evicted_until_us, &evicted);
+            }
+            self.total_frames -= 1;
+        }
+
+        self.enforce_total_limit();
+        self.compact_insertion_order();
+    }
+
+    /// Returns the frames of an ID with timestamps from start_us to end_us (inclusive), oldest first
+    pub fn frames_between(
+        &self,
+        can_id: CanId,
+        start_us: u128,
+        end_us: u128,
+    ) -> Vec<&HistoryFrame> {
+        let Some(frames) = self.frames.get(&can_id) else {
+            return Vec::new();
+        };
+
+        let start_idx = frames.partition_point(|f| f.timestamp_us < start_us);
+        let end_idx = frames.partition_point(|f| f.timestamp_us <= end_us);
+        frames.range(start_idx..end_idx.max(start_idx)).collect()
+    }
+
+    /// Returns up to the last count frames of an ID, oldest first
+    pub fn last_frames(&self, can_id: CanId, count: usize) -> Vec<&HistoryFrame> {
+        let Some(frames) = self.frames.get(&can_id) else {
+            return Vec::new();
+        };
+
+        frames.range(frames.len().saturating_sub(count)..).collect()
+    }
+
+    /// Returns the latest frame of every ID with a timestamp at or before timestamp_us, as the CAN table was at that
+    /// time. Each ID is found with a binary search, so this is fast enough to follow a time cursor.
+    pub fn frames_at(&self, timestamp_us: u128) -> Vec<FrameAt<'_>> {
+        self.frames
+            .values()
+            .filter_map(|frames| {
+                let index = frames
+                    .partition_point(|f| f.timestamp_us <= timestamp_us)
+                    .checked_sub(1)?;
+                Some(FrameAt {
+                    history_frame: &frames[index],
+                    freq_hz: frequency_at(frames, index),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the latest time before timestamp_us at which the data of an ID changed, where the first stored frame of
+    /// an ID counts as a change. The frame at timestamp_us is found with a binary search, then the frames before it are
+    /// compared one by one, so this takes time linear in the number of frames since the change, at most the ID's
+    /// max_frames_per_id frames.
+    pub fn previous_change(&self, can_id: CanId, timestamp_us: u128) -> Option<u128> {
+        let frames = self.frames.get(&can_id)?;
+        let end_idx = frames.partition_point(|f| f.timestamp_us < timestamp_us);
+        (0..end_idx)
+            .rev()
+            .find(|&index| is_change(frames, index))
+            .map(|index| frames[index].timestamp_us)
+    }
+
+    /// Returns the earliest time after timestamp_us at which the data of an ID changed, where the first stored frame
+    /// of an ID counts as a change. Like previous_change, this takes time linear in the number of frames until the
+    /// change.
+    pub fn next_change(&self, can_id: CanId, timestamp_us: u128) -> Option<u128> {
+        let frames = self.frames.get(&can_id)?;
+        let start_idx = frames.partition_point(|f| f.timestamp_us <= timestamp_us);
+        (start_idx..frames.len())
+            .find(|&index| is_change(frames, index))
+            .map(|index| frames[index].timestamp_us)
+    }
+
+    /// Returns the timestamps of the oldest and newest stored frames, or None if the history is empty
+    pub fn time_range(&self) -> Option<(u128, u128)> {
+        let oldest = self
+            .frames
+            .values()
+            .filter_map(|frames| frames.front())
+            .map(|f| f.timestamp_us)
+            .min()?;
+        let newest = self
+            .frames
+            .values()
+            .filter_map(|frames| frames.back())
+            .map(|f| f.timestamp_us)
+            .max()?;
+        Some((oldest, newest))
+    }
+
+    /// Returns the range of time over which the history holds every frame, from the newest evicted frame (or the
+    /// oldest stored frame if none have been evicted) to the newest stored frame, or None if the history is empty.
+    /// Frames from before the range may still be stored, but frames of the same IDs between them may be missing.
+    pub fn retained_range(&self) -> Option<(u128, u128)> {
+        let (oldest, newest) = self.time_range()?;
+        let start = self.evicted_until_us.map_or(oldest, |evicted_until_us| {
+            evicted_until_us.clamp(oldest, newest)
+        });
+        Some((start, newest))
+    }
+
+    pub fn ids(&self) -> Vec<CanId> {
+        let mut ids: Vec<CanId> = self.frames.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Sorts the frames of each ID by time, for frames that were not pushed in time order, such as those of a log
+    /// file whose frames are slightly out of order. Frames with equal timestamps keep the order they were pushed in,
+    /// and the Oldest eviction policy evicts frames in time order from then on.
+    pub fn sort_by_time(&mut self) {
+        let is_sorted = |frames: &VecDeque<HistoryFrame>| {
+            frames
+                .iter()
+                .zip(frames.iter().skip(1))
+                .all(|(earlier, later)| earlier.timestamp_us <= later.timestamp_us)
+        };
+        if self.frames.values().all(is_sorted) {
+            return;
+        }
+
+        let mut order = Vec::with_capacity(self.total_frames);
+        for (&can_id, frames) in &mut self.frames {
+            frames
+                .make_contiguous()
+                .sort_by_key(|f| (f.timestamp_us, f.sequence));
+            order.extend(frames.iter().map(|f| (f.timestamp_us, f.sequence, can_id)));
+        }
+        order.sort_unstable();
+
+        // Renumbers every frame in time order, which each ID's frames are now also in, so that the insertion order
+        // the Oldest eviction policy follows matches the order of each ID's frames
+        let mut next_index: HashMap<CanId, usize> = HashMap::new();
+        self.insertion_order.clear();
+        for (sequence, (_, _, can_id)) in order.into_iter().enumerate() {
+            let index = next_index.entry(can_id).or_default();
+            if let Some(frames) = self.frames.get_mut(&can_id) {
+                frames[*index].sequence = sequence as u64;
+            }
+            *index += 1;
+            self.insertion_order.push_back((can_id, sequence as u64));
+        }
+        self.next_sequence = self.insertion_order.len() as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.insertion_order.clear();
+        self.total_frames = 0;
+        self.evicted_until_us = None;
+    }
+
+    pub fn clear_id(&mut self, can_id: CanId) {
+        if let Some(frames) = self.frames.remove(&can_id) {
+            self.total_frames -= frames.len();
+            self.compact_insertion_order();
+        }
+    }
+
+    fn enforce_total_limit(&mut self) {
+        while self.total_frames > self.max_total_frames {
+            let evicted = match self.eviction_policy {
+                EvictionPolicy::Oldest => self.evict_oldest(),
+                EvictionPolicy::BusiestId => self.evict_from_busiest_id(),
+            };
+            if !evicted {
+                break;
+            }
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((can_id, sequence)) = self.insertion_order.pop_front() {
+            let Some(frames) = self.frames.get_mut(&can_id) else {
+                continue;
+            };
+
+            // Skip frames that have already been evicted by the per ID cap
+            if frames.front().is_some_and(|f| f.sequence == sequence) {
+                if let Some(evicted) = frames.pop_front() {
+                    note_eviction(&mut self.evicted_until_us, &evicted);
+                }
+                if frames.is_empty() {
+                    self.frames.remove(&can_id);
+                }
+                self.total_frames -= 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn evict_from_busiest_id(&mut self) -> bool {
+        let Some((&can_id, frames)) = self
+            .frames
+            .iter_mut()
+            .max_by_key(|(_, frames)| frames.len())
+        else {
+            return false;
+        };
+
+        if let Some(evicted) = frames.pop_front() {
+            note_eviction(&mut self.evicted_until_us, &evicted);
+        }
+        if frames.is_empty() {
+            self.frames.remove(&can_id);
+        }
+        self.total_frames -= 1;
+        true
+    }
+
+    // Drops the entries of frames that no longer exist from the insertion order, once they make up the majority of it
+    fn compact_insertion_order(&mut self) {
+        if self.insertion_order.len() <= 2 * self.total_frames.max(1024) {
+            return;
+        }
+
+        let frames = &self.frames;
+        self.insertion_order.retain(|(can_id, sequence)| {
+            frames.get(can_id).is_some_and(|frames| {
+                frames
+                    .front()
+                    .is_some_and(|oldest| oldest.sequence <= *sequence)
+            })
+        });
+    }
+}
+
+// Moves the time after which every frame is held forward to an evicted frame
+fn note_eviction(evicted_until_us: &mut Option<u128>, evicted: &HistoryFrame) {
+    *evicted_until_us = Some(evicted_until_us.map_or(evicted.timestamp_us, |until_us| {
+        until_us.max(evicted.timestamp_us)
+    }));
+}
+
+// Returns true if the frame at index is the first stored frame of its ID, or has different data to the frame before it
+fn is_change(frames: &VecDeque<HistoryFrame>, index: usize) -> bool {
+    index == 0 || frames[index].frame.data() != frames[index - 1].frame.data()
+}
+
+// Measures the frequency of an ID from its frames in the window up to the frame at index, falling back to the time
+// since the frame before it for IDs sent less often than the window
+fn frequency_at(frames: &VecDeque<HistoryFrame>, index: usize) -> f32 {
+    let timestamp_us = frames[index].timestamp_us;
+    let window_start_us = timestamp_us.saturating_sub(FREQUENCY_WINDOW_US);
+    let first_idx = frames
+        .partition_point(|f| f.timestamp_us < window_start_us)
+        .min(index.saturating_sub(1));
+
+    let span_us = timestamp_us.saturating_sub(frames[first_idx].timestamp_us);
+    if span_us == 0 {
+        return 0.0;
+    }
+    (index - first_idx) as f32 * 1e6 / span_us as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_can_frame;
+
+    fn push(history: &mut FrameHistory, can_id: CanId, data: u8, timestamp_us: u128) {
+        let frame = new_can_frame(can_id, false, &[data]).unwrap();
+        history.push(frame, Direction::Rx, timestamp_us);
+    }
+
+    fn times(frames: Vec<&HistoryFrame>) -> Vec<u128> {
+        frames.iter().map(|f| f.timestamp_us).collect()
+    }
+
+    #[test]
+    fn out_of_order_frames_are_sorted_by_time() {
+        let mut history = FrameHistory::new();
+        for (can_id, data, timestamp_us) in [
+            (0x100, 1, 30),
+            (0x100, 2, 10),
+            (0x200, 1, 20),
+            (0x100, 2, 20),
+            (0x100, 3, 40),
+        ] {
+            push(&mut history, can_id, data, timestamp_us);
+        }
+        history.sort_by_time();
+
+        assert_eq!(times(history.last_frames(0x100, 10)), [10, 20, 30, 40]);
+        assert_eq!(times(history.frames_between(0x100, 15, 35)), [20, 30]);
+        let mut at_25: Vec<_> = history
+            .frames_at(25)
+            .iter()
+            .map(|at| (at.history_frame.frame.id(), at.history_frame.timestamp_us))
+            .collect();
+        at_25.sort();
+        assert_eq!(at_25, [(0x100, 20), (0x200, 20)]);
+        assert_eq!(history.previous_change(0x100, 35), Some(30));
+        assert_eq!(history.next_change(0x100, 10), Some(30));
+    }
+
+    #[test]
+    fn sorted_frames_are_evicted_oldest_first() {
+        let mut history = FrameHistory::new();
+        for (can_id, timestamp_us) in [(0x100, 30), (0x200, 10), (0x100, 20), (0x200, 40)] {
+            push(&mut history, can_id, 0, timestamp_us);
+        }
+        history.sort_by_time();
+
+        history.configure(DEFAULT_MAX_FRAMES_PER_ID, 2, EvictionPolicy::Oldest);
+        assert_eq!(times(history.last_frames(0x100, 10)), [30]);
+        assert_eq!(times(history.last_frames(0x200, 10)), [40]);
+
+        push(&mut history, 0x200, 0, 50);
+        assert_eq!(history.total_frames(), 2);
+        assert_eq!(times(history.last_frames(0x100, 10)), Vec::<u128>::new());
+        assert_eq!(history.retained_range(), Some((40, 50)));
+    }
+}
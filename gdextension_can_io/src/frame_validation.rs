@@ -0,0 +1,197 @@
+///
+/// frame_validation.rs
+///
+/// Validates CAN frames requested from Godot before they are queued for transmission,
+/// so that invalid requests are reported back to GDScript instead of panicking.
+///
+use crate::new_can_frame;
+use crosscan::can::CanFrame;
+use godot::prelude::*;
+use std::time::Duration;
+
+pub const MAX_STANDARD_ID: i64 = 0x7FF;
+pub const MAX_EXTENDED_ID: i64 = 0x1FFF_FFFF;
+pub const MAX_CLASSIC_DLC: usize = 8;
+/// The most data bytes a CAN FD frame carries
+pub const MAX_FD_DATA_LENGTH: usize = 64;
+/// The shortest cyclic transmit period, as shorter periods would flood the bus rather than pace the frames
+pub const MIN_PERIOD_MS: f64 = 0.1;
+/// The longest cyclic transmit period or phase offset, one day
+pub const MAX_PERIOD_MS: f64 = 86_400_000.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    StandardIdOutOfRange(i64),
+    ExtendedIdOutOfRange(i64),
+    DataTooLong(usize),
+    InvalidDataByte {
+        index: usize,
+    },
+    Rejected,
+    /// The loaded DBC file does not define a message with the frame's id
+    UnknownMessage(i64),
+    InvalidSignalGenerator(String),
+    /// The period or phase offset of a cyclic transmission is not a usable time
+    InvalidTiming(String),
+}
+
+impl FrameError {
+    /// A stable numeric code for the error, allowing GDScript to react to specific errors
+    pub fn code(&self) -> i64 {
+        match self {
+            FrameError::StandardIdOutOfRange(_) => 1,
+            FrameError::ExtendedIdOutOfRange(_) => 2,
+            FrameError::DataTooLong(_) => 3,
+            FrameError::InvalidDataByte { .. } => 4,
+            FrameError::Rejected => 5,
+            FrameError::UnknownMessage(_) => 6,
+            FrameError::InvalidSignalGenerator(_) => 7,
+            FrameError::InvalidTiming(_) => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::StandardIdOutOfRange(id)
+                if *id > MAX_STANDARD_ID && *id <= MAX_EXTENDED_ID =>
+            {
+                write!(f, "CAN ID {id:#X} requires an extended frame")
+            }
+            FrameError::StandardIdOutOfRange(id) => write!(
+                f,
+                "CAN ID {id} is outside the standard range of 0x0 to {MAX_STANDARD_ID:#X}"
+            ),
+            FrameError::ExtendedIdOutOfRange(id) => write!(
+                f,
+                "CAN ID {id} is outside the extended range of 0x0 to {MAX_EXTENDED_ID:#X}"
+            ),
+            FrameError::DataTooLong(length) => write!(
+                f,
+                "Data length of {length} bytes exceeds the maximum DLC of {MAX_CLASSIC_DLC}"
+            ),
+            FrameError::InvalidDataByte { index } => {
+                write!(f, "Data byte {index} is not an integer from 0 to 255")
+            }
+            FrameError::Rejected => write!(f, "The CAN interface rejected the frame"),
+            FrameError::UnknownMessage(id) => {
+                write!(f, "The loaded DBC file has no message with CAN ID {id:#X}")
+            }
+            FrameError::InvalidSignalGenerator(msg) | FrameError::InvalidTiming(msg) => {
+                write!(f, "{msg}")
+            }
+        }
+    }
+}
+
+/// Checks the id range for the frame type, the data length (DLC) and every data byte, then builds the frame
+pub fn validated_frame(
+    can_id_value: i64,
+    is_extended: bool,
+    data: &VariantArray,
+) -> Result<CanFrame, FrameError> {
+    if is_extended {
+        if !(0..=MAX_EXTENDED_ID).contains(&can_id_value) {
+            return Err(FrameError::ExtendedIdOutOfRange(can_id_value));
+        }
+    } else if !(0..=MAX_STANDARD_ID).contains(&can_id_value) {
+        return Err(FrameError::StandardIdOutOfRange(can_id_value));
+    }
+
+    if data.len() > MAX_CLASSIC_DLC {
+        return Err(FrameError::DataTooLong(data.len()));
+    }
+
+    let mut bytes = Vec::with_capacity(data.len());
+    for (index, value) in data.iter_shared().enumerate() {
+        match value.try_to::<i64>() {
+            Ok(byte) if (0..=255).contains(&byte) => bytes.push(byte as u8),
+            _ => return Err(FrameError::InvalidDataByte { index }),
+        }
+    }
+
+    new_can_frame(can_id_value as u32, is_extended, &bytes).ok_or(FrameError::Rejected)
+}
+
+/// Checks the period and phase offset of a cyclic transmission, given in milliseconds, and converts them to
+/// durations. A period of 0 sends a single burst, any other period must be at least MIN_PERIOD_MS.
+pub fn validated_timing(
+    period_ms: f64,
+    phase_offset_ms: f64,
+) -> Result<(Duration, Duration), FrameError> {
+    if period_ms != 0.0 && !(MIN_PERIOD_MS..=MAX_PERIOD_MS).contains(&period_ms) {
+        return Err(FrameError::InvalidTiming(format!(
+            "Period of {period_ms} ms must be 0 for a single burst, or from {MIN_PERIOD_MS} to {MAX_PERIOD_MS} ms"
+        )));
+    }
+    if !(0.0..=MAX_PERIOD_MS).contains(&phase_offset_ms) {
+        return Err(FrameError::InvalidTiming(format!(
+            "Phase offset of {phase_offset_ms} ms must be from 0 to {MAX_PERIOD_MS} ms"
+        )));
+    }
+
+    // Both are finite and in range, so the conversions cannot fail
+    let duration = |ms: f64| Duration::try_from_secs_f64(ms / 1000.0).unwrap_or_default();
+    Ok((duration(period_ms), duration(phase_offset_ms)))
+}
+
+/// Builds the Dictionary returned to GDScript for a transmit request.
+/// On success it contains "ok" = true and the given id, otherwise "ok" = false with an "error_code" and "error" message.
+pub fn transmit_result(result: Result<(&str, i64), FrameError>) -> Dictionary {
+    let mut godot_result = Dictionary::new();
+    match result {
+        Ok((id_key, id)) => {
+            godot_result.set("ok", true);
+            godot_result.set(id_key, id);
+        }
+        Err(e) => {
+            godot_result.set("ok", false);
+            godot_result.set("error_code", e.code());
+            godot_result.set("error", e.to_string());
+        }
+    }
+    godot_result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timing_is_converted_to_durations() {
+        assert_eq!(
+            validated_timing(10.0, 2.5),
+            Ok((Duration::from_millis(10), Duration::from_micros(2500)))
+        );
+        assert_eq!(
+            validated_timing(0.0, 0.0),
+            Ok((Duration::ZERO, Duration::ZERO))
+        );
+        assert_eq!(
+            validated_timing(MIN_PERIOD_MS, MAX_PERIOD_MS),
+            Ok((Duration::from_micros(100), Duration::from_secs(86_400)))
+        );
+    }
+
+    #[test]
+    fn unusable_timing_is_rejected() {
+        for (period_ms, phase_offset_ms) in [
+            (0.05, 0.0),
+            (-10.0, 0.0),
+            (f64::NAN, 0.0),
+            (f64::INFINITY, 0.0),
+            (1e300, 0.0),
+            (10.0, -1.0),
+            (10.0, f64::NAN),
+            (10.0, f64::INFINITY),
+        ] {
+            let result = validated_timing(period_ms, phase_offset_ms);
+            assert!(
+                matches!(result, Err(FrameError::InvalidTiming(_))),
+                "{period_ms} ms every {phase_offset_ms} ms gave {result:?}"
+            );
+            assert_eq!(result.unwrap_err().code(), 8);
+        }
+    }
+}
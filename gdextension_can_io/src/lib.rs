@@ -1,17 +1,50 @@
+mod acceptance_filter;
 mod can_parser;
+mod can_socket;
+mod frame_filter;
+mod frame_history;
+mod frame_validation;
+mod log_import;
+mod logging;
+mod recorder;
+mod replay;
+mod restbus;
+mod signal_export;
+mod signal_generator;
+mod trace_buffer;
+mod transmit_scheduler;
+mod trigger;
+mod tx_tracker;
+mod virtual_can;
 
+use crate::acceptance_filter::{AcceptanceFilter, CAN_ERR_MASK, FilterSet};
 use crate::can_parser::CanParser;
-use crosscan::CanInterface;
+use crate::can_socket::{CanSocket, SocketFrame};
+use crate::frame_filter::{FilterExpression, FilterTarget, FrameFilters};
+use crate::frame_history::{EvictionPolicy, FrameAt, FrameHistory, HistoryFrame};
+use crate::frame_validation::{FrameError, transmit_result, validated_frame, validated_timing};
+use crate::log_import::OfflineLog;
+use crate::logging::{FrameKind, LogFormat, LogFrame, epoch_offset_us};
+use crate::recorder::Recorder;
+use crate::replay::{LogReplay, ReplayOptions};
+use crate::restbus::RestbusSimulation;
+use crate::signal_export::{Interpolation, Layout, SignalExporter};
+use crate::signal_generator::{GeneratedMessage, Waveform};
+use crate::trace_buffer::{TraceBuffer, TraceFrame};
+use crate::transmit_scheduler::{Payload, TransmitId, TransmitScheduler};
+use crate::trigger::TriggerOptions;
+use crate::tx_tracker::{EchoQueue, TxId, TxOutcome, TxRequest, TxSource, TxTracker};
 use crosscan::can::CanFrame;
 use godot::classes::{Node, ResourceLoader, Script};
 use godot::prelude::*;
+use rusqlite::types::Value as SqlValue;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::collections::hash_map::Entry;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, mpsc, oneshot};
 
 struct CanGDExtension;
 
@@ -24,15 +57,33 @@ unsafe impl ExtensionLibrary for CanGDExtension {}
 #[class(base=Node)]
 struct GodotCanBridge {
     can_parser: CanParser,
+    frame_filters: FrameFilters,
     read_handle: Option<tokio::task::JoinHandle<()>>,
     interface: String,
     bitrate: Arc<Mutex<u32>>,
     bit_counter: Arc<Mutex<usize>>,
     can_entries: Arc<Mutex<HashMap<CanId, CanEntry>>>,
-    sending_queue: Arc<Mutex<VecDeque<CanFrame>>>,
-    closure_requested: Arc<Mutex<bool>>,
+    frame_history: Arc<Mutex<FrameHistory>>,
+    trace_buffer: Arc<Mutex<TraceBuffer>>,
+    transmit_sender: Option<mpsc::UnboundedSender<TxRequest>>,
+    tx_tracker: Arc<Mutex<TxTracker>>,
+    show_transmitted_frames: Arc<Mutex<bool>>,
+    acceptance_filters: Arc<Mutex<FilterSet>>,
+    filters_notify: Arc<Notify>,
+    kernel_filtering: Arc<Mutex<bool>>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    close_sender: Option<oneshot::Sender<()>>,
     runtime: tokio::runtime::Runtime,
     start_time: Arc<Mutex<Instant>>,
+    restbus: Option<Arc<Mutex<RestbusSimulation>>>,
+    restbus_handle: Option<tokio::task::JoinHandle<()>>,
+    transmit_scheduler: Arc<Mutex<TransmitScheduler>>,
+    scheduler_notify: Arc<Notify>,
+    scheduler_handle: Option<tokio::task::JoinHandle<()>>,
+    offline_log: Option<OfflineLog>,
+    replay: Option<Arc<Mutex<LogReplay>>>,
+    replay_notify: Arc<Notify>,
+    replay_handle: Option<tokio::task::JoinHandle<()>>,
 
     base: Base<Node>,
 }
@@ -42,10 +93,37 @@ struct CanEntry {
     last_timestamp: u128,
     freq_hz: f32,
     frame: CanFrame,
+    direction: Direction,
+}
+
+/// Whether a frame was received from the bus or transmitted by us
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Rx,
+    Tx,
 }
 
 type CanId = u32;
 
+// Frames of the open bus are on channel 1, as the bridge opens a single interface
+const BUS_CHANNEL: u8 = 1;
+
+// State shared between the bridge and the CAN I/O thread
+struct CanIoState {
+    bitrate: Arc<Mutex<u32>>,
+    bit_counter: Arc<Mutex<usize>>,
+    can_entries: Arc<Mutex<HashMap<CanId, CanEntry>>>,
+    frame_history: Arc<Mutex<FrameHistory>>,
+    trace_buffer: Arc<Mutex<TraceBuffer>>,
+    start_time: Arc<Mutex<Instant>>,
+    tx_tracker: Arc<Mutex<TxTracker>>,
+    show_transmitted_frames: Arc<Mutex<bool>>,
+    acceptance_filters: Arc<Mutex<FilterSet>>,
+    filters_notify: Arc<Notify>,
+    kernel_filtering: Arc<Mutex<bool>>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+}
+
 #[godot_api]
 impl INode for GodotCanBridge {
     // 'init' is called in Godot everytime the extension is loaded into the editor
@@ -54,15 +132,33 @@ impl INode for GodotCanBridge {
 
         Self {
             can_parser: CanParser::new(),
+            frame_filters: FrameFilters::default(),
             read_handle: None,
             interface: "".to_string(),
             bitrate: Arc::new(Mutex::new(0)),
             bit_counter: Arc::new(Mutex::new(0)),
             can_entries: Arc::new(Mutex::new(HashMap::<CanId, CanEntry>::new())),
-            sending_queue: Arc::new(Mutex::new(VecDeque::<CanFrame>::new())),
-            closure_requested: Arc::new(Mutex::new(false)),
+            frame_history: Arc::new(Mutex::new(FrameHistory::new())),
+            trace_buffer: Arc::new(Mutex::new(TraceBuffer::new())),
+            transmit_sender: None,
+            tx_tracker: Arc::new(Mutex::new(TxTracker::new())),
+            show_transmitted_frames: Arc::new(Mutex::new(false)),
+            acceptance_filters: Arc::new(Mutex::new(FilterSet::default())),
+            filters_notify: Arc::new(Notify::new()),
+            kernel_filtering: Arc::new(Mutex::new(false)),
+            recorder: Arc::new(Mutex::new(None)),
+            close_sender: None,
             runtime: Runtime::new().unwrap(),
             start_time: Arc::new(Mutex::new(Instant::now())),
+            restbus: None,
+            restbus_handle: None,
+            transmit_scheduler: Arc::new(Mutex::new(TransmitScheduler::new())),
+            scheduler_notify: Arc::new(Notify::new()),
+            scheduler_handle: None,
+            offline_log: None,
+            replay: None,
+            replay_notify: Arc::new(Notify::new()),
+            replay_handle: None,
             base,
         }
     }
@@ -118,28 +214,46 @@ impl GodotCanBridge {
             error_alert_godot(format!("Error when attempting to thread: {:?}", msg));
         }
 
+        // Frames of a log file are timed from the start of the file rather than the bus, so they are cleared rather
+        // than mixed with the frames received from the bus
+        self.close_offline_log();
+
         self.interface = interface_name.clone();
 
         // Create the CAN read/write thread
         let _guard = self.runtime.enter();
-        let bitrate = Arc::clone(&self.bitrate);
-        let bit_counter = Arc::clone(&self.bit_counter);
-        let can_entries = Arc::clone(&self.can_entries);
-        let sending_queue = Arc::clone(&self.sending_queue);
-        let closure_requested = Arc::clone(&self.closure_requested);
-        let start_time = Arc::clone(&self.start_time);
+        let io_state = CanIoState {
+            bitrate: Arc::clone(&self.bitrate),
+            bit_counter: Arc::clone(&self.bit_counter),
+            can_entries: Arc::clone(&self.can_entries),
+            frame_history: Arc::clone(&self.frame_history),
+            trace_buffer: Arc::clone(&self.trace_buffer),
+            start_time: Arc::clone(&self.start_time),
+            tx_tracker: Arc::clone(&self.tx_tracker),
+            show_transmitted_frames: Arc::clone(&self.show_transmitted_frames),
+            acceptance_filters: Arc::clone(&self.acceptance_filters),
+            filters_notify: Arc::clone(&self.filters_notify),
+            kernel_filtering: Arc::clone(&self.kernel_filtering),
+            recorder: Arc::clone(&self.recorder),
+        };
+        let (transmit_sender, transmit_receiver) = mpsc::unbounded_channel();
+        let (close_sender, close_receiver) = oneshot::channel();
         self.read_handle = Some(tokio::spawn(async {
-            read_can(
-                interface_name,
-                bitrate,
-                bit_counter,
-                can_entries,
-                sending_queue,
-                closure_requested,
-                start_time,
-            )
-            .await;
+            read_can(interface_name, io_state, transmit_receiver, close_receiver).await;
         }));
+        self.transmit_sender = Some(transmit_sender.clone());
+        self.close_sender = Some(close_sender);
+
+        // Resume cyclic transmission of any entries that were scheduled before the bus was (re)opened
+        self.runtime
+            .block_on(self.transmit_scheduler.lock())
+            .restart();
+        self.scheduler_handle = Some(tokio::spawn(transmit_scheduler::run_scheduler(
+            Arc::clone(&self.transmit_scheduler),
+            Arc::clone(&self.scheduler_notify),
+            Arc::clone(&self.tx_tracker),
+            transmit_sender,
+        )));
 
         godot_print!("CAN bus opened");
         return true;
@@ -147,45 +261,1201 @@ impl GodotCanBridge {
 
     #[func]
     fn get_can_table(&mut self) -> VariantArray {
+        self.can_parser.parse_can_table(
+            &self.runtime.block_on(self.can_entries.lock()),
+            &self.frame_filters,
+        )
+    }
+
+    /// Attaches a filter expression to the "table", "plot" or "recording", replacing any existing filter.
+    /// An empty expression removes the filter. A recording in progress switches to a new "recording" filter from
+    /// the next frame it writes. See `frame_filter.rs` for the expression syntax.
+    /// Returns {"ok": true}, or {"ok": false, "error": message, "position": character} if the expression is invalid.
+    #[func]
+    fn set_frame_filter(&mut self, target: String, expression: String) -> Dictionary {
+        let mut result = Dictionary::new();
+        let Some(target) = FilterTarget::from_name(&target) else {
+            result.set("ok", false);
+            result.set("error", format!("Unknown filter target {target:?}"));
+            result.set("position", 0);
+            return result;
+        };
+
+        let filter = if expression.trim().is_empty() {
+            None
+        } else {
+            match FilterExpression::parse(&expression) {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    result.set("ok", false);
+                    result.set("error", e.message);
+                    result.set("position", e.position as i64);
+                    return result;
+                }
+            }
+        };
+
+        if target == FilterTarget::Recording
+            && let Some(recorder) = self.runtime.block_on(self.recorder.lock()).as_ref()
+        {
+            recorder.set_filter(filter.clone());
+        }
+        self.frame_filters.set(target, filter);
+        result.set("ok", true);
+        result
+    }
+
+    /// Returns the filter expression attached to the "table", "plot" or "recording", or an empty string if there is none
+    #[func]
+    fn get_frame_filter(&mut self, target: String) -> String {
+        FilterTarget::from_name(&target)
+            .and_then(|target| self.frame_filters.get(target))
+            .map(|filter| filter.source().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Sets the limits of the frame history. Each CAN ID keeps up to max_frames_per_id frames, and once the history
+    /// holds max_total_frames frames the eviction_policy ("oldest" or "busiest_id") decides which frame is dropped.
+    #[func]
+    fn configure_frame_history(
+        &mut self,
+        max_frames_per_id: u32,
+        max_total_frames: u32,
+        eviction_policy: String,
+    ) -> bool {
+        let Some(eviction_policy) = EvictionPolicy::from_name(&eviction_policy) else {
+            error_alert_godot(format!("Unknown eviction policy {eviction_policy:?}"));
+            return false;
+        };
+
+        self.runtime.block_on(self.frame_history.lock()).configure(
+            max_frames_per_id as usize,
+            max_total_frames as usize,
+            eviction_policy,
+        );
+        true
+    }
+
+    /// Returns the stored frames of a CAN ID with timestamps from start_us to end_us (inclusive), oldest first.
+    /// See `history_frame_to_godot` for the format of each frame.
+    #[func]
+    fn get_frame_history(
+        &mut self,
+        can_id_value: u32,
+        start_us: i64,
+        end_us: i64,
+    ) -> Array<Dictionary> {
+        self.runtime
+            .block_on(self.frame_history.lock())
+            .frames_between(can_id_value, start_us.max(0) as u128, end_us.max(0) as u128)
+            .into_iter()
+            .map(history_frame_to_godot)
+            .collect()
+    }
+
+    /// Returns the raw value of a DBC signal in each stored frame of a CAN ID as points of (time in seconds, value),
+    /// oldest first, so that a signal's whole history can be plotted at once, as when analysing a log file offline.
+    /// Frames rejected by the "plot" filter are left out.
+    #[func]
+    fn get_signal_history(&mut self, can_id_value: u32, signal_name: String) -> PackedVector2Array {
+        let frame_history = self.runtime.block_on(self.frame_history.lock());
+        frame_history
+            .frames_between(can_id_value, 0, u128::MAX)
+            .into_iter()
+            .filter(|history_frame| {
+                self.frame_filters.accepts(
+                    FilterTarget::Plot,
+                    &history_frame.frame,
+                    history_frame.direction,
+                    &self.can_parser,
+                )
+            })
+            .filter_map(|history_frame| {
+                let message = self.can_parser.message_for_frame(&history_frame.frame)?;
+                let value =
+                    self.can_parser
+                        .signal_value(message, &signal_name, &history_frame.frame)?;
+                Some(Vector2::new(
+                    history_frame.timestamp_us as f32 / 1e6,
+                    value as f32,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns up to the last count stored frames of a CAN ID, oldest first
+    #[func]
+    fn get_last_frames(&mut self, can_id_value: u32, count: u32) -> Array<Dictionary> {
+        self.runtime
+            .block_on(self.frame_history.lock())
+            .last_frames(can_id_value, count as usize)
+            .into_iter()
+            .map(history_frame_to_godot)
+            .collect()
+    }
+
+    /// Returns the CAN IDs with stored frames, along with the total number of stored frames. If any frames are
+    /// stored, "start_us" and "end_us" hold the timestamps of the oldest and newest, bounding the times the history
+    /// can be queried at. Once frames have been dropped by the limits of the history only some frames from before the
+    /// newest dropped frame remain, so "retained_start_us" and "retained_end_us" bound the times over which the history
+    /// holds every frame, where `get_can_table_at` and the change queries are complete.
+    #[func]
+    fn get_frame_history_info(&mut self) -> Dictionary {
+        let frame_history = self.runtime.block_on(self.frame_history.lock());
+
+        let mut info = Dictionary::new();
+        info.set(
+            "ids",
+            frame_history
+                .ids()
+                .into_iter()
+                .map(i64::from)
+                .collect::<PackedInt64Array>(),
+        );
+        info.set("total_frames", frame_history.total_frames() as i64);
+        if let Some((start_us, end_us)) = frame_history.time_range() {
+            info.set("start_us", start_us as i64);
+            info.set("end_us", end_us as i64);
+        }
+        if let Some((start_us, end_us)) = frame_history.retained_range() {
+            info.set("retained_start_us", start_us as i64);
+            info.set("retained_end_us", end_us as i64);
+        }
+        info
+    }
+
+    /// Returns the CAN table as it was at timestamp_us, in the same format as `get_can_table`, rebuilt from the latest
+    /// stored frame of each CAN ID at or before that time. Frequencies are measured from the stored frames. While a
+    /// log file is loaded for offline analysis the history holds its frames, timed from the first frame of the log.
+    /// Only the frame history is searched, not the log, so the table is only complete from "retained_start_us" of
+    /// `get_frame_history_info`, and IDs whose frames before that time have been dropped are missing.
+    #[func]
+    fn get_can_table_at(&mut self, timestamp_us: i64) -> VariantArray {
+        let frame_history = self.runtime.block_on(self.frame_history.lock());
+        let can_entries: HashMap<CanId, CanEntry> = frame_history
+            .frames_at(timestamp_us.max(0) as u128)
+            .into_iter()
+            .map(|frame_at| (frame_at.history_frame.frame.id(), can_entry_at(frame_at)))
+            .collect();
         self.can_parser
-            .parse_can_table(&self.runtime.block_on(self.can_entries.lock()))
+            .parse_can_table(&can_entries, &self.frame_filters)
+    }
+
+    /// Returns the latest time before timestamp_us at which the data of a CAN ID changed, or -1 if there is none,
+    /// for stepping a time cursor back through the changes of an ID. The first stored frame counts as a change, so
+    /// changes are only found within the frame history, see `get_frame_history_info` for the range it covers.
+    #[func]
+    fn get_previous_change(&mut self, can_id_value: u32, timestamp_us: i64) -> i64 {
+        self.runtime
+            .block_on(self.frame_history.lock())
+            .previous_change(can_id_value, timestamp_us.max(0) as u128)
+            .map_or(-1, |change_us| change_us as i64)
+    }
+
+    /// Returns the earliest time after timestamp_us at which the data of a CAN ID changed, or -1 if there is none,
+    /// for stepping a time cursor forward through the changes of an ID. The first stored frame counts as a change, so
+    /// changes are only found within the frame history, see `get_frame_history_info` for the range it covers.
+    #[func]
+    fn get_next_change(&mut self, can_id_value: u32, timestamp_us: i64) -> i64 {
+        self.runtime
+            .block_on(self.frame_history.lock())
+            .next_change(can_id_value, timestamp_us.max(0) as u128)
+            .map_or(-1, |change_us| change_us as i64)
+    }
+
+    /// Returns the range of frame indices stored in the trace as a Dictionary of "first_index" and "end_index",
+    /// where end_index is the index the next frame will be given
+    #[func]
+    fn get_trace_info(&mut self) -> Dictionary {
+        let trace_buffer = self.runtime.block_on(self.trace_buffer.lock());
+
+        let mut info = Dictionary::new();
+        info.set("first_index", trace_buffer.first_index() as i64);
+        info.set("end_index", trace_buffer.end_index() as i64);
+        info
+    }
+
+    /// Returns up to count frames of the trace in arrival order, starting from start_index.
+    /// See `trace_frame_to_godot` for the format of each frame.
+    #[func]
+    fn get_trace_page(&mut self, start_index: i64, count: u32) -> Array<Dictionary> {
+        self.runtime
+            .block_on(self.trace_buffer.lock())
+            .page(start_index.max(0) as u64, count as usize)
+            .map(|trace_frame| trace_frame_to_godot(trace_frame, &self.can_parser))
+            .collect()
+    }
+
+    /// Returns up to the last count frames of the trace in arrival order
+    #[func]
+    fn get_trace_tail(&mut self, count: u32) -> Array<Dictionary> {
+        self.runtime
+            .block_on(self.trace_buffer.lock())
+            .tail(count as usize)
+            .map(|trace_frame| trace_frame_to_godot(trace_frame, &self.can_parser))
+            .collect()
+    }
+
+    /// Sets the maximum number of frames kept in the trace, after which the oldest frames are dropped
+    #[func]
+    fn set_trace_capacity(&mut self, capacity: u32) {
+        self.runtime
+            .block_on(self.trace_buffer.lock())
+            .set_capacity(capacity as usize);
+    }
+
+    #[func]
+    fn clear_trace(&mut self) {
+        self.runtime.block_on(self.trace_buffer.lock()).clear();
     }
 
+    /// Clears every entry from the CAN table, along with the frame history
     #[func]
     fn clear_can_table(&mut self) {
         self.runtime.block_on(self.can_entries.lock()).clear();
+        self.runtime.block_on(self.frame_history.lock()).clear();
     }
 
+    /// Clears a CAN ID from the CAN table, along with its frame history
     #[func]
     fn clear_can_entry(&mut self, can_id_value: u32) {
         self.runtime
             .block_on(self.can_entries.lock())
             .remove_entry(&can_id_value);
+        self.runtime
+            .block_on(self.frame_history.lock())
+            .clear_id(can_id_value);
+    }
+
+    /// Sets the acceptance filters of the bus, replacing any existing filters. Each filter is a Dictionary with an
+    /// "id" and "mask", and optional "extended" and "inverted" flags. Frames are accepted if they match any filter,
+    /// and an empty array accepts every frame. error_mask selects the error classes of error frames to receive,
+    /// which are shown in the trace and recorded. On Linux the filters are applied by the kernel, while other
+    /// backends filter received frames in software and cannot receive error frames, so a non-zero error_mask
+    /// fails to apply when the bus is opened.
+    #[func]
+    fn set_acceptance_filters(&mut self, filters: VariantArray, error_mask: i64) -> bool {
+        let mut filter_set = FilterSet {
+            filters: Vec::with_capacity(filters.len()),
+            error_mask: (error_mask as u32) & CAN_ERR_MASK,
+        };
+
+        for (index, filter) in filters.iter_shared().enumerate() {
+            let result = filter
+                .try_to::<Dictionary>()
+                .map_err(|_| "Acceptance filters must be Dictionaries".to_string())
+                .and_then(|filter| AcceptanceFilter::from_dictionary(&filter));
+            match result {
+                Ok(filter) => filter_set.filters.push(filter),
+                Err(e) => {
+                    error_alert_godot(format!("Invalid acceptance filter {index}: {e}"));
+                    return false;
+                }
+            }
+        }
+
+        // Remove any entries that would no longer be received
+        self.runtime
+            .block_on(self.can_entries.lock())
+            .retain(|_, entry| {
+                entry.direction == Direction::Tx || filter_set.accepts(&entry.frame)
+            });
+
+        *self.runtime.block_on(self.acceptance_filters.lock()) = filter_set;
+        self.filters_notify.notify_one();
+        true
+    }
+
+    #[func]
+    fn clear_acceptance_filters(&mut self) {
+        *self.runtime.block_on(self.acceptance_filters.lock()) = FilterSet::default();
+        self.filters_notify.notify_one();
+    }
+
+    /// Returns the active filter set as a Dictionary of "filters", "error_mask" and "kernel_filtering",
+    /// where kernel_filtering is false if the bus is closed, the filters are only applied in software, or the
+    /// error mask could not be applied
+    #[func]
+    fn get_acceptance_filters(&mut self) -> Dictionary {
+        let filter_set = self
+            .runtime
+            .block_on(self.acceptance_filters.lock())
+            .clone();
+        let kernel_filtering =
+            self.is_alive() && *self.runtime.block_on(self.kernel_filtering.lock());
+
+        let mut godot_filters = Dictionary::new();
+        godot_filters.set(
+            "filters",
+            filter_set
+                .filters
+                .iter()
+                .map(AcceptanceFilter::to_dictionary)
+                .collect::<Array<Dictionary>>(),
+        );
+        godot_filters.set("error_mask", filter_set.error_mask);
+        godot_filters.set("kernel_filtering", kernel_filtering);
+        godot_filters
+    }
+
+    /// Validates and queues a single frame for transmission.
+    /// Returns {"ok": true, "tx_id": id} where the id can be passed to `get_tx_outcome`,
+    /// or {"ok": false, "error_code": code, "error": message} if the frame is invalid.
+    #[func]
+    fn send_can_frame(
+        &mut self,
+        can_id_value: i64,
+        is_extended: bool,
+        data: VariantArray,
+    ) -> Dictionary {
+        let result = validated_frame(can_id_value, is_extended, &data)
+            .map(|frame| ("tx_id", self.queue_frame(frame)));
+        transmit_result(result)
+    }
+
+    /// Returns the outcome of a queued frame as a Dictionary with a "status" of "pending", "sent", "failed",
+    /// "dropped" or "unknown". Sent frames include a "timestamp_us" and failed frames include a "reason".
+    /// On SocketCAN interfaces a frame is sent once the interface loops it back, and is timestamped with the time
+    /// the echo was received, while on other backends it is timestamped with the time it was written.
+    #[func]
+    fn get_tx_outcome(&mut self, tx_id: TxId) -> Dictionary {
+        let mut godot_outcome = Dictionary::new();
+        match self.runtime.block_on(self.tx_tracker.lock()).outcome(tx_id) {
+            Some(TxOutcome::Pending) => godot_outcome.set("status", "pending"),
+            Some(TxOutcome::Sent { timestamp_us }) => {
+                godot_outcome.set("status", "sent");
+                godot_outcome.set("timestamp_us", *timestamp_us as i64);
+            }
+            Some(TxOutcome::Failed(reason)) => {
+                godot_outcome.set("status", "failed");
+                godot_outcome.set("reason", reason.clone());
+            }
+            Some(TxOutcome::Dropped) => godot_outcome.set("status", "dropped"),
+            None => godot_outcome.set("status", "unknown"),
+        }
+        godot_outcome
+    }
+
+    /// Enables adding our own transmitted frames to the CAN table, flagged with the TX direction
+    #[func]
+    fn set_show_transmitted_frames(&mut self, enabled: bool) {
+        *self.runtime.block_on(self.show_transmitted_frames.lock()) = enabled;
+    }
+
+    /// Starts transmitting a frame every period_ms, offset by phase_offset_ms, sending burst_count frames each period.
+    /// A period of 0 sends a single burst, any other period must be at least 0.1 ms.
+    /// Returns {"ok": true, "transmit_id": id} for the scheduled entry,
+    /// or {"ok": false, "error_code": code, "error": message} if the frame, period or phase offset is invalid.
+    #[func]
+    fn start_cyclic_transmit(
+        &mut self,
+        can_id_value: i64,
+        is_extended: bool,
+        data: VariantArray,
+        period_ms: f64,
+        phase_offset_ms: f64,
+        burst_count: u32,
+    ) -> Dictionary {
+        let result = validated_frame(can_id_value, is_extended, &data).and_then(|frame| {
+            let transmit_id = self.schedule(
+                Payload::Frame(frame),
+                period_ms,
+                phase_offset_ms,
+                burst_count,
+            )?;
+            Ok(("transmit_id", transmit_id))
+        });
+        transmit_result(result)
+    }
+
+    /// Starts transmitting the DBC message with the given CAN ID like start_cyclic_transmit, encoding the message
+    /// again each period from the physical values of its signals. Signals start from the values decoded from data,
+    /// or from their DBC start values where data does not cover them. generators is a Dictionary of signal names to
+    /// the waveform driving each signal, see `Waveform::from_dictionary`.
+    /// Returns {"ok": true, "transmit_id": id} for the scheduled entry, or {"ok": false, "error_code": code,
+    /// "error": message} if the frame or timing is invalid, the loaded DBC file does not define the message, or a generator
+    /// is invalid.
+    #[func]
+    fn start_cyclic_message(
+        &mut self,
+        can_id_value: i64,
+        is_extended: bool,
+        data: VariantArray,
+        generators: Dictionary,
+        period_ms: f64,
+        phase_offset_ms: f64,
+        burst_count: u32,
+    ) -> Dictionary {
+        let result = validated_frame(can_id_value, is_extended, &data)
+            .and_then(|frame| self.generated_message(&frame, &generators))
+            .and_then(|generated_message| {
+                let transmit_id = self.schedule(
+                    Payload::Message(generated_message),
+                    period_ms,
+                    phase_offset_ms,
+                    burst_count,
+                )?;
+                Ok(("transmit_id", transmit_id))
+            });
+        transmit_result(result)
+    }
+
+    /// Overrides the physical value of a signal of an entry started with start_cyclic_message, replacing any
+    /// generator attached to it. Returns false if the entry does not send a DBC message with that signal.
+    #[func]
+    fn set_cyclic_signal(
+        &mut self,
+        transmit_id: TransmitId,
+        signal_name: String,
+        value: f64,
+    ) -> bool {
+        self.runtime
+            .block_on(self.transmit_scheduler.lock())
+            .message_mut(transmit_id)
+            .is_some_and(|generated_message| {
+                generated_message.set_signal_value(&signal_name, value)
+            })
+    }
+
+    /// Drives a signal of an entry started with start_cyclic_message with a waveform generator.
+    /// See `Waveform::from_params` for the supported waveforms and their parameters.
+    #[func]
+    fn set_cyclic_signal_generator(
+        &mut self,
+        transmit_id: TransmitId,
+        signal_name: String,
+        waveform: String,
+        params: PackedFloat64Array,
+    ) -> bool {
+        waveform_from_params(&waveform, params.as_slice()).is_some_and(|waveform| {
+            self.attach_cyclic_signal_generator(transmit_id, &signal_name, waveform)
+        })
     }
 
+    /// Drives a signal of an entry started with start_cyclic_message with a profile loaded from a `time_s,value`
+    /// CSV file
     #[func]
-    fn send_can_frame(&mut self, can_id_value: u32, is_extended: bool, data: VariantArray) {
-        // Convert from Godot Variant to typed u8 vector
-        let packed_bytes = PackedByteArray::from(&data);
-        let byte_slice_data: &[u8] = packed_bytes.as_slice();
+    fn load_cyclic_signal_profile(
+        &mut self,
+        transmit_id: TransmitId,
+        signal_name: String,
+        csv_filepath: String,
+    ) -> bool {
+        waveform_from_csv_profile(&csv_filepath).is_some_and(|waveform| {
+            self.attach_cyclic_signal_generator(transmit_id, &signal_name, waveform)
+        })
+    }
+
+    /// Stops a signal's generator, holding the signal at its last generated value
+    #[func]
+    fn clear_cyclic_signal_generator(
+        &mut self,
+        transmit_id: TransmitId,
+        signal_name: String,
+    ) -> bool {
+        self.runtime
+            .block_on(self.transmit_scheduler.lock())
+            .message_mut(transmit_id)
+            .is_some_and(|generated_message| generated_message.clear_signal_generator(&signal_name))
+    }
+
+    #[func]
+    fn stop_cyclic_transmit(&mut self, transmit_id: TransmitId) -> bool {
+        self.runtime
+            .block_on(self.tx_tracker.lock())
+            .clear_entry_counts(transmit_id);
+        self.runtime
+            .block_on(self.transmit_scheduler.lock())
+            .stop(transmit_id)
+    }
+
+    /// Returns false once an entry has been stopped, or once a single burst entry has been sent
+    #[func]
+    fn is_cyclic_transmit_active(&mut self, transmit_id: TransmitId) -> bool {
+        self.runtime
+            .block_on(self.transmit_scheduler.lock())
+            .is_active(transmit_id)
+    }
+
+    /// Returns the number of frames sent, the number of successful and failed transmissions,
+    /// and the measured TX jitter (lateness relative to the ideal send time) of an entry
+    #[func]
+    fn get_cyclic_transmit_stats(&mut self, transmit_id: TransmitId) -> Dictionary {
+        let mut godot_stats = Dictionary::new();
+        if let Some(stats) = self
+            .runtime
+            .block_on(self.transmit_scheduler.lock())
+            .stats(transmit_id)
+        {
+            godot_stats.set("frames_sent", stats.frames_sent);
+            godot_stats.set("jitter_last_us", stats.jitter_last_us);
+            godot_stats.set("jitter_mean_us", stats.jitter_mean_us());
+            godot_stats.set("jitter_max_us", stats.jitter_max_us);
+
+            let counts = self
+                .runtime
+                .block_on(self.tx_tracker.lock())
+                .entry_counts(transmit_id);
+            godot_stats.set("tx_succeeded", counts.succeeded);
+            godot_stats.set("tx_failed", counts.failed);
+        }
+        godot_stats
+    }
+
+    /// Starts recording every received and transmitted frame to a new log file at the given path, replacing any
+    /// recording already in progress. The format is chosen by the file extension, see `LogFormat::from_path`.
+    /// Only frames passing the "recording" filter are recorded. If decode_signals is set, formats that can hold
+    /// decoded signals also store the signals of the loaded DBC. crosscan only delivers classic data frames, so
+    /// remote and CAN FD frames on the bus cannot be recorded, while errors reported by the interface are recorded
+    /// as controller problem error frames.
+    #[func]
+    fn start_recording(&mut self, file_path: String, decode_signals: bool) -> bool {
+        self.begin_recording(file_path, decode_signals, None)
+    }
+
+    /// Starts a recording like start_recording that only saves the frames around each time the trigger fires:
+    /// those recorded within the pre-trigger window before it, kept in a ring buffer, and those within the
+    /// post-trigger window after it. Each trigger is marked in formats that can hold markers. A single-shot
+    /// recording finishes the file after one capture, while a re-arming recording keeps capturing until it is
+    /// stopped. See `TriggerOptions::from_dictionary` for the trigger Dictionary.
+    #[func]
+    fn start_triggered_recording(
+        &mut self,
+        file_path: String,
+        decode_signals: bool,
+        trigger: Dictionary,
+    ) -> bool {
+        match TriggerOptions::from_dictionary(&trigger, &self.can_parser) {
+            Ok(options) => self.begin_recording(file_path, decode_signals, Some(options)),
+            Err(e) => {
+                error_alert_godot(format!("Invalid recording trigger: {e}"));
+                false
+            }
+        }
+    }
+
+    /// Stops the recording in progress once every frame recorded so far has been written.
+    /// Returns false if there was no recording, or if writing the file failed.
+    #[func]
+    fn stop_recording(&mut self) -> bool {
+        let Some(recorder) = self.runtime.block_on(self.recorder.lock()).take() else {
+            return false;
+        };
+
+        let file_path = recorder.path().to_string();
+        match self.runtime.block_on(recorder.stop()) {
+            Ok(frames_written) => {
+                godot_print!("Recorded {frames_written} frames to {file_path:?}");
+                true
+            }
+            Err(e) => {
+                error_alert_godot(format!("Failed to write recording to {file_path:?}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// Marks the current time in the recording with a note, which is saved in formats that can hold one.
+    /// Returns false if there is no recording in progress.
+    #[func]
+    fn add_recording_marker(&mut self, text: String) -> bool {
+        let recorder = self.runtime.block_on(self.recorder.lock());
+        let Some(recorder) = recorder.as_ref() else {
+            return false;
+        };
+
+        let timestamp_us = self
+            .runtime
+            .block_on(self.start_time.lock())
+            .elapsed()
+            .as_micros();
+        recorder.mark(&text, timestamp_us);
+        true
+    }
+
+    /// Returns {"recording": bool, "path": String, "frames_written": int, "trigger_state": String, "triggers": int}.
+    /// Recording becomes false once a single-shot triggered recording has been written, or if the recording stopped
+    /// early because writing the file failed, which is reported by calling stop_recording. The trigger state is
+    /// "armed", "capturing" or "complete" for triggered recordings, and empty otherwise.
+    #[func]
+    fn get_recording_status(&mut self) -> Dictionary {
+        let recorder = self.runtime.block_on(self.recorder.lock());
+        let mut status = Dictionary::new();
+        status.set(
+            "recording",
+            recorder
+                .as_ref()
+                .is_some_and(|recorder| recorder.is_running()),
+        );
+        status.set(
+            "path",
+            recorder
+                .as_ref()
+                .map(|recorder| recorder.path().to_string())
+                .unwrap_or_default(),
+        );
+        status.set(
+            "frames_written",
+            recorder
+                .as_ref()
+                .map(|recorder| recorder.frames_written() as i64)
+                .unwrap_or(0),
+        );
+
+        let trigger_status = recorder
+            .as_ref()
+            .and_then(|recorder| recorder.trigger_status());
+        status.set(
+            "trigger_state",
+            trigger_status
+                .map(|(state, _)| state.name())
+                .unwrap_or_default(),
+        );
+        status.set(
+            "triggers",
+            trigger_status
+                .map(|(_, triggers)| triggers as i64)
+                .unwrap_or(0),
+        );
+        status
+    }
+
+    /// Loads a log file for offline analysis without a bus, replacing the CAN table, frame history and trace with
+    /// its frames so that they are decoded and plotted as if they had been received. Timestamps are measured from the
+    /// earliest frame of the file. The format is chosen by the file extension, see `LogFormat::from_path`. Remote,
+    /// error and CAN FD frames are only shown in the trace, as the CAN table and history cannot hold them. The file
+    /// is imported in the background, see `get_offline_log_info` for its progress. Cannot be used while the bus is
+    /// open, and opening the bus closes the log file.
+    #[func]
+    fn import_log_file(&mut self, file_path: String) -> bool {
+        if self.is_alive() {
+            error_alert_godot("Close the CAN bus before importing a log file".to_string());
+            return false;
+        }
+
+        let opened = LogFormat::from_path(&file_path)
+            .and_then(|format| Ok((format, format.open_reader(&file_path)?)));
+        let (format, reader) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                error_alert_godot(format!("Failed to import {file_path:?}: {e}"));
+                return false;
+            }
+        };
+
+        self.close_offline_log();
+        self.clear_can_table();
+        self.clear_trace();
+
+        let _guard = self.runtime.enter();
+        self.offline_log = Some(OfflineLog::import(
+            file_path,
+            format,
+            reader,
+            Arc::clone(&self.can_entries),
+            Arc::clone(&self.frame_history),
+            Arc::clone(&self.trace_buffer),
+        ));
+        true
+    }
+
+    /// Stops importing the loaded log file, keeping the frames imported so far. Does nothing if no log file is
+    /// being imported.
+    #[func]
+    fn cancel_log_import(&mut self) {
+        if let Some(offline_log) = &self.offline_log {
+            offline_log.cancel();
+        }
+    }
+
+    /// Closes the log file loaded for offline analysis, cancelling its import and clearing its frames from the CAN
+    /// table, frame history and trace. Does nothing if no log file is loaded.
+    #[func]
+    fn close_offline_log(&mut self) {
+        if let Some(offline_log) = self.offline_log.take() {
+            self.runtime.block_on(offline_log.close());
+            self.clear_can_table();
+            self.clear_trace();
+        }
+    }
+
+    /// Returns whether a log file is loaded for offline analysis
+    #[func]
+    fn is_offline(&mut self) -> bool {
+        self.offline_log.is_some()
+    }
+
+    /// Returns {"offline": bool, "path": String, "state": String, "error": String, "start_us": int,
+    /// "duration_us": int, "frame_count": int, "trace_only_frames": int, "retained_frames": int} describing the log
+    /// file loaded for offline analysis, where:
+    /// - state is "importing", "finished", "cancelled" or "failed", with the error that stopped a failed import.
+    ///   Frames imported before an import is cancelled or fails remain loaded.
+    /// - start_us is the time of its earliest frame in microseconds since the UNIX epoch, and duration_us the time
+    ///   of the latest frame imported so far from the earliest. start_us is 0 until the file has been read once
+    ///   to find it.
+    /// - frame_count counts the frames imported so far, of which trace_only_frames are the remote, error and CAN FD
+    ///   frames only shown in the trace.
+    /// - retained_frames counts the frames kept by the frame history, which is bounded by its limits, and
+    ///   "retained_start_us" and "retained_end_us" bound the times for which it holds every frame of the log. Once
+    ///   frames have been dropped this is less than the whole log, see `configure_frame_history` to keep more.
+    #[func]
+    fn get_offline_log_info(&mut self) -> Dictionary {
+        let mut info = Dictionary::new();
+        info.set("offline", self.offline_log.is_some());
+        if let Some(offline_log) = &self.offline_log {
+            let progress = self.runtime.block_on(offline_log.progress());
+            info.set("path", offline_log.path.clone());
+            info.set("state", progress.state.name());
+            info.set("error", progress.error.unwrap_or_default());
+            info.set("start_us", progress.start_us as i64);
+            info.set("duration_us", progress.duration_us as i64);
+            info.set("frame_count", progress.frame_count as i64);
+            info.set("trace_only_frames", progress.trace_only_frames as i64);
+
+            let frame_history = self.runtime.block_on(self.frame_history.lock());
+            info.set("retained_frames", frame_history.total_frames() as i64);
+            if let Some((start_us, end_us)) = frame_history.retained_range() {
+                info.set("retained_start_us", start_us as i64);
+                info.set("retained_end_us", end_us as i64);
+            }
+        }
+        info
+    }
 
-        // Create a CAN data frame with the ID and some data (up to 8 bytes for a standard CAN frame)
-        let frame = if is_extended {
-            CanFrame::new_eff(can_id_value, &byte_slice_data).unwrap()
+    /// Writes every frame of the trace to a log file, in the format chosen by the file extension. If
+    /// decode_signals is set, formats that can hold decoded signals also store the signals of the loaded DBC.
+    #[func]
+    fn export_trace(&mut self, file_path: String, decode_signals: bool) -> bool {
+        match self.write_trace(&file_path, decode_signals) {
+            Ok(()) => true,
+            Err(e) => {
+                error_alert_godot(format!("Failed to export the trace to {file_path:?}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// Writes the physical values of DBC signals, each named "Message.Signal", to a CSV file. Frames are read from
+    /// the log file at log_file_path, or from the frame history if it is empty, with times measured from the start
+    /// of the log or of the bus. If resample_period_s is 0 there is a row for each frame carrying a chosen signal,
+    /// otherwise the signals are resampled every resample_period_s seconds, with interpolation "previous",
+    /// "linear" or "nearest".
+    #[func]
+    fn export_signals_csv(
+        &mut self,
+        file_path: String,
+        signal_names: PackedStringArray,
+        log_file_path: String,
+        resample_period_s: f64,
+        interpolation: String,
+    ) -> bool {
+        let layout = if resample_period_s > 0.0 {
+            let Some(interpolation) = Interpolation::from_name(&interpolation) else {
+                error_alert_godot(format!("Unknown interpolation {interpolation:?}"));
+                return false;
+            };
+            Layout::Resampled {
+                period_us: (resample_period_s * 1_000_000.0).round() as u64,
+                interpolation,
+            }
         } else {
-            CanFrame::new(can_id_value, &byte_slice_data).unwrap()
+            Layout::Sparse
+        };
+
+        let signal_names: Vec<String> = signal_names
+            .as_slice()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        match self.write_signals_csv(&file_path, &signal_names, &log_file_path, layout) {
+            Ok(rows_written) => {
+                godot_print!("Exported {rows_written} rows of signals to {file_path:?}");
+                true
+            }
+            Err(e) => {
+                error_alert_godot(format!("Failed to export signals to {file_path:?}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// Runs a read only SQL query on a capture database recorded to a .db or .sqlite file, for example
+    /// "SELECT frames.* FROM frames JOIN signals ON signals.frame_id = frames.id WHERE signals.name = 'BatteryTemp'
+    /// AND signals.value > 60". Returns {"ok": true, "columns": names, "rows": an Array of values for each row,
+    /// "truncated": bool} with up to max_rows rows, or {"ok": false, "error": message} if the query fails.
+    /// See `logging/sqlite.rs` for the tables of the database.
+    #[func]
+    fn query_capture_database(
+        &mut self,
+        file_path: String,
+        sql: String,
+        max_rows: i64,
+    ) -> Dictionary {
+        let mut result = Dictionary::new();
+        match logging::query_database(&file_path, &sql, max_rows.max(0) as usize) {
+            Ok(query_result) => {
+                result.set("ok", true);
+                result.set(
+                    "columns",
+                    query_result
+                        .columns
+                        .iter()
+                        .map(GString::from)
+                        .collect::<PackedStringArray>(),
+                );
+                result.set(
+                    "rows",
+                    query_result
+                        .rows
+                        .iter()
+                        .map(|row| {
+                            row.iter()
+                                .map(sql_value_to_godot)
+                                .collect::<VariantArray>()
+                                .to_variant()
+                        })
+                        .collect::<VariantArray>(),
+                );
+                result.set("truncated", query_result.truncated);
+            }
+            Err(e) => {
+                result.set("ok", false);
+                result.set("error", e.to_string());
+            }
+        }
+        result
+    }
+
+    /// Returns the names of every signal defined in the loaded DBC file, as "Message.Signal"
+    #[func]
+    fn get_dbc_signal_names(&mut self) -> VariantArray {
+        let mut godot_signal_names = VariantArray::new();
+        if let Some(dbc) = self.can_parser.dbc() {
+            for message in dbc.messages() {
+                for signal in message.signals() {
+                    let name = format!("{}.{}", message.message_name(), signal.name());
+                    godot_signal_names.push(&GString::from(name).to_variant());
+                }
+            }
+        }
+        godot_signal_names
+    }
+
+    /// Returns the names of all nodes (BU_) defined in the loaded DBC file
+    #[func]
+    fn get_dbc_nodes(&mut self) -> VariantArray {
+        let mut godot_nodes = VariantArray::new();
+        if let Some(dbc) = self.can_parser.dbc() {
+            for name in dbc.nodes().iter().flat_map(|node| node.0.iter()) {
+                godot_nodes.push(&GString::from(name).to_variant());
+            }
+        }
+        godot_nodes
+    }
+
+    /// Starts simulating the cyclic messages sent by the given DBC nodes on the open bus
+    #[func]
+    fn start_restbus(&mut self, node_names: PackedStringArray) -> bool {
+        if !self.is_alive() {
+            error_alert_godot(
+                "Cannot start restbus simulation without an open CAN bus".to_string(),
+            );
+            return false;
+        }
+
+        let Some(dbc) = self.can_parser.dbc() else {
+            error_alert_godot("Restbus simulation requires a DBC file to be loaded".to_string());
+            return false;
+        };
+
+        let node_names: Vec<String> = node_names
+            .as_slice()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        let simulation = match RestbusSimulation::new(dbc, &node_names) {
+            Ok(simulation) => simulation,
+            Err(e) => {
+                match e {
+                    restbus::Error::UnknownNode(name) => {
+                        error_alert_godot(format!("Node {name:?} is not defined in the DBC file"))
+                    }
+                    restbus::Error::NoCyclicMessages => error_alert_godot(
+                        "The selected nodes do not send any cyclic messages".to_string(),
+                    ),
+                }
+                return false;
+            }
+        };
+
+        self.stop_restbus();
+
+        let Some(transmit_sender) = self.transmit_sender.clone() else {
+            return false;
+        };
+
+        let _guard = self.runtime.enter();
+        let restbus = Arc::new(Mutex::new(simulation));
+        self.restbus_handle = Some(tokio::spawn(restbus::run_restbus(
+            Arc::clone(&restbus),
+            Arc::clone(&self.tx_tracker),
+            transmit_sender,
+        )));
+        self.restbus = Some(restbus);
+
+        godot_print!("Restbus simulation started for nodes {:?}", node_names);
+        true
+    }
+
+    #[func]
+    fn stop_restbus(&mut self) {
+        if let Some(handle) = self.restbus_handle.take() {
+            handle.abort();
+            godot_print!("Restbus simulation stopped");
+        }
+        self.restbus = None;
+    }
+
+    #[func]
+    fn is_restbus_running(&mut self) -> bool {
+        if let Some(handle) = &self.restbus_handle {
+            return !handle.is_finished();
+        }
+        false
+    }
+
+    /// Returns the names of all messages transmitted by the running restbus simulation
+    #[func]
+    fn get_restbus_messages(&mut self) -> VariantArray {
+        let mut godot_messages = VariantArray::new();
+        if let Some(restbus) = &self.restbus {
+            for name in self.runtime.block_on(restbus.lock()).message_names() {
+                godot_messages.push(&GString::from(name).to_variant());
+            }
+        }
+        godot_messages
+    }
+
+    /// Overrides the physical value of a signal in the running restbus simulation
+    #[func]
+    fn set_restbus_signal(
+        &mut self,
+        message_name: String,
+        signal_name: String,
+        value: f64,
+    ) -> bool {
+        let Some(restbus) = &self.restbus else {
+            return false;
+        };
+
+        self.runtime
+            .block_on(restbus.lock())
+            .set_signal_value(&message_name, &signal_name, value)
+    }
+
+    /// Drives a signal in the running restbus simulation with a waveform generator.
+    /// See `Waveform::from_params` for the supported waveforms and their parameters.
+    #[func]
+    fn set_restbus_signal_generator(
+        &mut self,
+        message_name: String,
+        signal_name: String,
+        waveform: String,
+        params: PackedFloat64Array,
+    ) -> bool {
+        waveform_from_params(&waveform, params.as_slice()).is_some_and(|waveform| {
+            self.attach_signal_generator(&message_name, &signal_name, waveform)
+        })
+    }
+
+    /// Drives a signal in the running restbus simulation with a profile loaded from a `time_s,value` CSV file
+    #[func]
+    fn load_restbus_signal_profile(
+        &mut self,
+        message_name: String,
+        signal_name: String,
+        csv_filepath: String,
+    ) -> bool {
+        waveform_from_csv_profile(&csv_filepath).is_some_and(|waveform| {
+            self.attach_signal_generator(&message_name, &signal_name, waveform)
+        })
+    }
+
+    #[func]
+    fn clear_restbus_signal_generator(
+        &mut self,
+        message_name: String,
+        signal_name: String,
+    ) -> bool {
+        let Some(restbus) = &self.restbus else {
+            return false;
         };
 
         self.runtime
-            .block_on(self.sending_queue.lock())
-            .push_back(frame);
+            .block_on(restbus.lock())
+            .clear_signal_generator(&message_name, &signal_name)
+    }
+
+    /// Starts replaying a log file onto an interface with the original timing between frames, replacing any replay
+    /// already running. The interface is opened separately from the bus, which does not need to be open. The format
+    /// is chosen by the file extension, see `LogFormat::from_path`. Remote, error and CAN FD frames are skipped.
+    /// options is a Dictionary of:
+    /// - "speed": factor the replay is sped up by, from 0.1 to 10, or 0 to send every frame as fast as possible.
+    ///   Defaults to 1.
+    /// - "loop": whether to restart from the first frame straight after the last
+    /// - "filter": filter expression that frames must pass to be sent, see `frame_filter.rs`. Signals are decoded with
+    ///   the DBC loaded when the replay is started.
+    /// - "remap": Dictionary of the ids frames were logged with to the ids to send them with
+    /// - "paused": whether to start paused, so that the replay can be stepped from its first frame
+    #[func]
+    fn start_replay(
+        &mut self,
+        file_path: String,
+        interface_name: String,
+        options: Dictionary,
+    ) -> bool {
+        let options = match ReplayOptions::from_dictionary(&options) {
+            Ok(options) => options,
+            Err(e) => {
+                error_alert_godot(e);
+                return false;
+            }
+        };
+
+        let replay = match LogReplay::open(
+            &file_path,
+            &interface_name,
+            options,
+            self.can_parser.clone(),
+        ) {
+            Ok(replay) => replay,
+            Err(e) => {
+                error_alert_godot(format!("Failed to open {file_path:?} for replay: {e}"));
+                return false;
+            }
+        };
+
+        self.stop_replay();
+
+        let socket = match self.runtime.block_on(CanSocket::open(&interface_name)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error_alert_godot(format!("Failed to open {interface_name:?} for replay: {e}"));
+                return false;
+            }
+        };
+
+        let _guard = self.runtime.enter();
+        let replay = Arc::new(Mutex::new(replay));
+        self.replay_handle = Some(tokio::spawn(replay::run_replay(
+            Arc::clone(&replay),
+            Arc::clone(&self.replay_notify),
+            socket,
+        )));
+        self.replay = Some(replay);
+
+        godot_print!("Replaying {file_path:?} onto {interface_name:?}");
+        true
+    }
+
+    /// Stops the replay, closing its interface
+    #[func]
+    fn stop_replay(&mut self) {
+        if let Some(handle) = self.replay_handle.take() {
+            handle.abort();
+            godot_print!("Replay stopped");
+        }
+        self.replay = None;
+    }
+
+    #[func]
+    fn pause_replay(&mut self) {
+        self.control_replay(LogReplay::pause);
+    }
+
+    /// Resumes a paused replay, waiting the original time between the last frame sent and the next frame
+    #[func]
+    fn resume_replay(&mut self) {
+        self.control_replay(LogReplay::resume);
+    }
+
+    /// Sends the next count frames of the replay straight away, pausing the replay if it is playing
+    #[func]
+    fn step_replay(&mut self, count: u32) {
+        self.control_replay(|replay| replay.step(count));
+    }
+
+    /// Changes the speed of the replay, from 0.1 to 10, or 0 to send every frame as fast as possible.
+    /// Returns false if the speed is out of range or no replay has been started.
+    #[func]
+    fn set_replay_speed(&mut self, speed: f64) -> bool {
+        let speed = match replay::speed_from_factor(speed) {
+            Ok(speed) => speed,
+            Err(e) => {
+                error_alert_godot(e);
+                return false;
+            }
+        };
+        self.control_replay(|replay| replay.set_speed(speed))
+    }
+
+    /// Returns {"active": bool} along with, once a replay has been started, its "file_path", "interface", "state" of
+    /// "playing", "paused" or "finished", "speed" (0 for as fast as possible), "loop", the number of frames sent,
+    /// skipped and failed, "loops_completed", "position_us" of the last frame sent measured from the first frame of
+    /// the log, and the timing deviation (lateness of each frame relative to its original time, scaled by the speed)
+    /// of the frames sent while playing. "error" holds the last error sending a frame or reading the log, if any.
+    #[func]
+    fn get_replay_status(&mut self) -> Dictionary {
+        let mut status = Dictionary::new();
+        status.set(
+            "active",
+            self.replay_handle
+                .as_ref()
+                .is_some_and(|handle| !handle.is_finished()),
+        );
+
+        let Some(replay) = &self.replay else {
+            return status;
+        };
+        let replay = self.runtime.block_on(replay.lock());
+        let stats = replay.stats();
+        status.set("file_path", replay.file_path());
+        status.set("interface", replay.interface());
+        status.set("state", replay.state().name());
+        status.set("speed", replay.speed().unwrap_or(0.0));
+        status.set("loop", replay.is_looped());
+        status.set("frames_sent", stats.frames_sent);
+        status.set("frames_skipped", stats.frames_skipped);
+        status.set("frames_failed", stats.frames_failed);
+        status.set("loops_completed", stats.loops_completed);
+        status.set("position_us", stats.position_us);
+        status.set("deviation_last_us", stats.deviation_last_us);
+        status.set("deviation_mean_us", stats.deviation_mean_us());
+        status.set("deviation_max_us", stats.deviation_max_us);
+        if let Some(error) = replay.error() {
+            status.set("error", error);
+        }
+        status
     }
 
     #[func]
     fn close_bus(&mut self) {
+        self.stop_restbus();
+
+        if let Some(handle) = self.scheduler_handle.take() {
+            handle.abort();
+        }
+
+        // Dropping the sender stops any further frames from being queued for transmission
+        self.transmit_sender = None;
+
         if let Some(handle) = self.read_handle.take() {
-            // Flag the thread to end
-            *self.runtime.block_on(self.closure_requested.lock()) = true;
+            // Signal the thread to end. This fails if the thread has already ended on its own, which is fine.
+            if let Some(close_sender) = self.close_sender.take() {
+                let _ = close_sender.send(());
+            }
 
             // Wait for thread to complete
             self.runtime.block_on(handle).unwrap();
@@ -235,23 +1505,276 @@ impl GodotCanBridge {
     }
 }
 
+impl GodotCanBridge {
+    // Applies a control to the running replay, waking the replay task so that it takes effect straight away.
+    // Returns false if no replay has been started.
+    fn control_replay(&mut self, control: impl FnOnce(&mut LogReplay)) -> bool {
+        let Some(replay) = &self.replay else {
+            return false;
+        };
+        control(&mut self.runtime.block_on(replay.lock()));
+        self.replay_notify.notify_one();
+        true
+    }
+
+    // Starts a recording, with a trigger for triggered recordings, replacing any recording already in progress
+    fn begin_recording(
+        &mut self,
+        file_path: String,
+        decode_signals: bool,
+        trigger: Option<TriggerOptions>,
+    ) -> bool {
+        self.stop_recording();
+
+        let _guard = self.runtime.enter();
+        let start_time = *self.runtime.block_on(self.start_time.lock());
+        let filter = self.frame_filters.get(FilterTarget::Recording).cloned();
+        match Recorder::start(
+            &file_path,
+            &self.interface,
+            start_time,
+            filter,
+            self.can_parser.clone(),
+            decode_signals,
+            trigger,
+        ) {
+            Ok(recorder) => {
+                *self.runtime.block_on(self.recorder.lock()) = Some(recorder);
+                true
+            }
+            Err(e) => {
+                error_alert_godot(format!("Failed to start recording to {file_path:?}: {e}"));
+                false
+            }
+        }
+    }
+
+    // Writes every frame of the trace to a log file. The trace is copied first, so that the CAN I/O thread is only
+    // held up for the copy rather than for the whole write.
+    fn write_trace(&self, file_path: &str, decode_signals: bool) -> Result<(), logging::Error> {
+        let mut writer = LogFormat::from_path(file_path)?.create_writer(
+            file_path,
+            &self.interface,
+            decode_signals.then_some(&self.can_parser),
+        )?;
+        // Frames of a log file keep the times they were recorded at
+        let epoch_offset_us = match &self.offline_log {
+            Some(offline_log) => self.runtime.block_on(offline_log.progress()).start_us,
+            None => epoch_offset_us(*self.runtime.block_on(self.start_time.lock())),
+        };
+
+        let log_frames: Vec<LogFrame> = {
+            let trace_buffer = self.runtime.block_on(self.trace_buffer.lock());
+            trace_buffer
+                .page(trace_buffer.first_index(), usize::MAX)
+                .map(|trace_frame| LogFrame {
+                    timestamp_us: epoch_offset_us + trace_frame.timestamp_us as u64,
+                    channel: trace_frame.channel,
+                    id: trace_frame.id,
+                    is_extended: trace_frame.is_extended,
+                    kind: trace_frame.kind,
+                    data: trace_frame.data.clone(),
+                    direction: trace_frame.direction,
+                })
+                .collect()
+        };
+        for log_frame in &log_frames {
+            writer.write_frame(log_frame)?;
+        }
+        writer.finish()
+    }
+
+    // Writes signals from a log file, or from the frame history if log_file_path is empty, returning the number of
+    // rows written. The frames of the history are copied first, so that the CAN I/O thread is only held up for the
+    // copy rather than for the whole export.
+    fn write_signals_csv(
+        &self,
+        file_path: &str,
+        signal_names: &[String],
+        log_file_path: &str,
+        layout: Layout,
+    ) -> Result<u64, signal_export::Error> {
+        let mut exporter =
+            SignalExporter::create(file_path, signal_names, &self.can_parser, layout)?;
+
+        if log_file_path.is_empty() {
+            let mut frames: Vec<HistoryFrame> = {
+                let frame_history = self.runtime.block_on(self.frame_history.lock());
+                frame_history
+                    .ids()
+                    .into_iter()
+                    .flat_map(|can_id| frame_history.frames_between(can_id, 0, u128::MAX))
+                    .cloned()
+                    .collect()
+            };
+            frames.sort_by_key(|history_frame| history_frame.timestamp_us);
+            for history_frame in &frames {
+                exporter.push(&history_frame.frame, history_frame.timestamp_us as u64)?;
+            }
+        } else {
+            let mut reader = LogFormat::from_path(log_file_path)?.open_reader(log_file_path)?;
+            let mut start_us = None;
+            while let Some(log_frame) = reader.next_frame()? {
+                let start_us = *start_us.get_or_insert(log_frame.timestamp_us);
+                if let Some(frame) = log_frame.to_can_frame() {
+                    exporter.push(&frame, log_frame.timestamp_us.saturating_sub(start_us))?;
+                }
+            }
+        }
+
+        exporter.finish()
+    }
+
+    // Queues a frame for immediate transmission on the open bus. Frames are dropped if no bus is open.
+    fn queue_frame(&mut self, frame: CanFrame) -> TxId {
+        let mut tx_tracker = self.runtime.block_on(self.tx_tracker.lock());
+        let request = tx_tracker.register(TxSource::Manual, frame);
+        let tx_id = request.tx_id;
+
+        let send_result = match &self.transmit_sender {
+            Some(transmit_sender) => transmit_sender.send(request).map_err(|e| e.0),
+            None => Err(request),
+        };
+        if let Err(request) = send_result {
+            tx_tracker.record(request.tx_id, request.source, TxOutcome::Dropped);
+        }
+
+        tx_id
+    }
+
+    // Adds an entry to the transmit scheduler once its timing is validated, waking the scheduler so that it accounts
+    // for the new entry
+    fn schedule(
+        &mut self,
+        payload: Payload,
+        period_ms: f64,
+        phase_offset_ms: f64,
+        burst_count: u32,
+    ) -> Result<TransmitId, FrameError> {
+        let (period, phase_offset) = validated_timing(period_ms, phase_offset_ms)?;
+        let transmit_id = self.runtime.block_on(self.transmit_scheduler.lock()).start(
+            payload,
+            period,
+            phase_offset,
+            burst_count,
+        );
+        self.scheduler_notify.notify_one();
+        Ok(transmit_id)
+    }
+
+    // Builds the DBC message with the id of a frame, with the signal values decoded from the frame and the given
+    // generators attached
+    fn generated_message(
+        &self,
+        frame: &CanFrame,
+        generators: &Dictionary,
+    ) -> Result<GeneratedMessage, FrameError> {
+        let (Some(dbc), Some(message)) = (
+            self.can_parser.dbc(),
+            self.can_parser.message_for_frame(frame),
+        ) else {
+            return Err(FrameError::UnknownMessage(i64::from(frame.id())));
+        };
+
+        let mut signal_values = restbus::signal_start_values(dbc, message);
+        for signal in message.signals() {
+            if let Some(value) =
+                self.can_parser
+                    .physical_signal_value(message, signal.name(), frame)
+            {
+                signal_values.insert(signal.name().clone(), value);
+            }
+        }
+
+        let mut generated_message = GeneratedMessage::new(dbc, message, signal_values);
+        for (signal_name, generator) in generators.iter_shared() {
+            let signal_name = signal_name.to_string();
+            let waveform = generator
+                .try_to::<Dictionary>()
+                .map_err(|_| format!("The generator of {signal_name:?} must be a Dictionary"))
+                .and_then(|generator| {
+                    Waveform::from_dictionary(&generator)
+                        .map_err(|e| format!("Invalid generator for {signal_name:?}: {e}"))
+                })
+                .map_err(FrameError::InvalidSignalGenerator)?;
+            if !generated_message.set_signal_generator(&signal_name, waveform) {
+                return Err(FrameError::InvalidSignalGenerator(format!(
+                    "Message {} has no signal {signal_name:?}",
+                    generated_message.name()
+                )));
+            }
+        }
+        Ok(generated_message)
+    }
+
+    fn attach_cyclic_signal_generator(
+        &mut self,
+        transmit_id: TransmitId,
+        signal_name: &str,
+        waveform: Waveform,
+    ) -> bool {
+        let attached = self
+            .runtime
+            .block_on(self.transmit_scheduler.lock())
+            .message_mut(transmit_id)
+            .is_some_and(|generated_message| {
+                generated_message.set_signal_generator(signal_name, waveform)
+            });
+        if !attached {
+            error_alert_godot(format!(
+                "Signal {signal_name} is not part of a DBC message sent by transmit entry {transmit_id}"
+            ));
+        }
+        attached
+    }
+
+    fn attach_signal_generator(
+        &mut self,
+        message_name: &str,
+        signal_name: &str,
+        waveform: Waveform,
+    ) -> bool {
+        let Some(restbus) = &self.restbus else {
+            error_alert_godot("Signal generators require a running restbus simulation".to_string());
+            return false;
+        };
+
+        let attached = self.runtime.block_on(restbus.lock()).set_signal_generator(
+            message_name,
+            signal_name,
+            waveform,
+        );
+        if !attached {
+            error_alert_godot(format!(
+                "Signal {message_name}.{signal_name} is not part of the restbus simulation"
+            ));
+        }
+        attached
+    }
+}
+
 async fn read_can(
     interface_name: String,
-    bitrate: Arc<Mutex<u32>>,
-    bit_counter: Arc<Mutex<usize>>,
-    can_entries: Arc<Mutex<HashMap<CanId, CanEntry>>>,
-    sending_queue: Arc<Mutex<VecDeque<CanFrame>>>,
-    closure_requested: Arc<Mutex<bool>>,
-    start_time: Arc<Mutex<Instant>>,
+    io_state: CanIoState,
+    mut transmit_receiver: mpsc::UnboundedReceiver<TxRequest>,
+    mut close_receiver: oneshot::Receiver<()>,
 ) {
-    // Select a specific CAN Socket implementation for the supported operating systems
-    #[cfg(target_os = "linux")]
-    use crosscan::lin_can::LinuxCan as CanSocket;
-
-    #[cfg(target_os = "windows")]
-    use crosscan::win_can::WindowsCan as CanSocket;
+    let CanIoState {
+        bitrate,
+        bit_counter,
+        can_entries,
+        frame_history,
+        trace_buffer,
+        start_time,
+        tx_tracker,
+        acceptance_filters,
+        filters_notify,
+        kernel_filtering,
+        recorder,
+        ..
+    } = &io_state;
 
-    // Open async CAN socket
+    // Open async CAN socket (either a platform interface or an in-process virtual bus)
     let mut socket = match CanSocket::open(&interface_name).await {
         Ok(sock) => sock,
         Err(err) => {
@@ -259,6 +1782,7 @@ async fn read_can(
                 "Failed to open CAN device, check that {interface_name:?} is up"
             ));
             godot_error!("{err:?}");
+            drop_pending_transmissions(&mut transmit_receiver, tx_tracker).await;
             return;
         }
     };
@@ -273,110 +1797,370 @@ async fn read_can(
         }
     };
 
+    // Frames are always filtered in software as well, which covers backends without kernel filtering
+    let mut filter_set = acceptance_filters.lock().await.clone();
+    apply_acceptance_filters(&mut socket, &filter_set, kernel_filtering).await;
+
+    // Written frames are only confirmed as sent when their echo is read, if the backend loops them back
+    let echo_enabled = socket.enable_echo();
+    let mut awaiting_echo = EchoQueue::default();
+
+    // Wait on closure requests, outgoing frames and incoming frames at the same time so that each is handled
+    // as soon as it arrives. Branches are checked in order, so closing and transmitting take priority over reading.
     loop {
-        // Process outgoing CAN messages
-        {
-            let mut frames_to_send = sending_queue.lock().await;
-            for frame in frames_to_send.clone().into_iter() {
-                *bit_counter.lock().await += can_frame_bits(&frame);
-
-                if let Err(e) = socket.write_frame(frame).await {
-                    error_alert_godot(format!(
-                        "Error when transmitting frames (Some messages may have not been sent): {:?}",
-                        e.to_string()
-                    ));
-                    godot_error!("{e:?}");
-                    break;
-                }
+        let echo_deadline = awaiting_echo.next_deadline();
+
+        tokio::select! {
+            biased;
+
+            // Also triggers if the sender is dropped without sending, which can only happen once the bridge is gone
+            _ = &mut close_receiver => break,
+
+            _ = filters_notify.notified() => {
+                filter_set = acceptance_filters.lock().await.clone();
+                apply_acceptance_filters(&mut socket, &filter_set, kernel_filtering).await;
             }
-            frames_to_send.clear();
-        }
 
-        // Check if the bus should be closed
-        {
-            let mut should_close = closure_requested.lock().await;
-            if *should_close {
-                *should_close = false;
+            Some(request) = transmit_receiver.recv() => {
+                *bit_counter.lock().await += can_frame_bits(&request.frame);
 
-                // Breaks out of the loop, ending the thread
-                break;
+                match socket.write_frame(request.frame.clone()).await {
+                    Ok(()) => {
+                        let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
+                        // Frames rejected by the kernel filters are never echoed back
+                        let echoed = echo_enabled
+                            && (!*kernel_filtering.lock().await || filter_set.accepts(&request.frame));
+                        if echoed {
+                            awaiting_echo.push(request, current_timestamp_us);
+                        } else {
+                            io_state.record_sent(request, current_timestamp_us).await;
+                        }
+                    }
+                    Err(e) => {
+                        let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
+                        if let Some(recorder) = recorder.lock().await.as_ref() {
+                            recorder.record_error(current_timestamp_us);
+                        }
+                        error_alert_godot(format!(
+                            "Error when transmitting frames (Some messages may have not been sent): {:?}",
+                            e.to_string()
+                        ));
+                        godot_error!("{e:?}");
+                        tx_tracker.lock().await.record(request.tx_id, request.source, TxOutcome::Failed(e.to_string()));
+                    }
+                }
             }
-        }
 
-        // Read a CanFrame. If none found within timeout, continue the loop (rechecks for outgoing or closing requests then try again)
-        let res = match tokio::time::timeout(Duration::from_millis(100), socket.read_frame()).await
-        {
-            Ok(res) => res,
-            Err(_) => continue,
-        };
+            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(echo_deadline.unwrap_or_else(Instant::now))),
+                if echo_deadline.is_some() => {
+                for awaiting in awaiting_echo.take_expired(Instant::now()) {
+                    io_state.record_sent(awaiting.request, awaiting.written_us).await;
+                }
+            }
+
+            res = socket.read_frame() => match res {
+                // Error frames have no id, so they are only shown in the trace and recorded
+                Ok(SocketFrame::Error(frame)) => {
+                    let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
+                    if let Some(recorder) = recorder.lock().await.as_ref() {
+                        recorder.record(&frame, FrameKind::Error, Direction::Rx, current_timestamp_us);
+                    }
+                    let log_frame = LogFrame::from_can_frame(&frame, FrameKind::Error, Direction::Rx, 0);
+                    trace_buffer.lock().await.push_kind(
+                        LogFrame { channel: BUS_CHANNEL, ..log_frame },
+                        current_timestamp_us,
+                    );
+                }
+                Ok(SocketFrame::Data(frame)) => {
+                    let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
 
-        // Process the incoming CanFrame
-        match res {
-            Ok(frame) => {
-                *bit_counter.lock().await += can_frame_bits(&frame);
+                    // Our own frames are recorded as transmitted when their echo arrives
+                    if let Some((missed, echoed)) = awaiting_echo.take_echoed(&frame) {
+                        for awaiting in missed {
+                            io_state.record_sent(awaiting.request, awaiting.written_us).await;
+                        }
+                        io_state.record_sent(echoed.request, current_timestamp_us).await;
+                        continue;
+                    }
+                    if !filter_set.accepts(&frame) {
+                        continue;
+                    }
 
-                let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
+                    *bit_counter.lock().await += can_frame_bits(&frame);
+                    if let Some(recorder) = recorder.lock().await.as_ref() {
+                        recorder.record(&frame, FrameKind::Data, Direction::Rx, current_timestamp_us);
+                    }
+                    record_frame(
+                        can_entries,
+                        frame_history,
+                        trace_buffer,
+                        frame,
+                        BUS_CHANNEL,
+                        Direction::Rx,
+                        current_timestamp_us,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    let current_timestamp_us = { start_time.lock().await.elapsed().as_micros() };
+                    if let Some(recorder) = recorder.lock().await.as_ref() {
+                        recorder.record_error(current_timestamp_us);
+                    }
+                    error_alert_godot(format!("Received CAN error: {:?}", err));
+                    break;
+                }
+            },
+        }
+    }
 
-                let mut can_entries = can_entries.lock().await;
-                match can_entries.entry(frame.id()) {
-                    Entry::Occupied(mut occupied_entry) => {
-                        let can_entry = occupied_entry.get_mut();
+    // The frames were written before the bus closed, even if their echo never arrived
+    for awaiting in awaiting_echo.take_all() {
+        io_state
+            .record_sent(awaiting.request, awaiting.written_us)
+            .await;
+    }
+    drop_pending_transmissions(&mut transmit_receiver, tx_tracker).await;
+    *kernel_filtering.lock().await = false;
+}
 
-                        // push new timestamp
-                        can_entry.timestamps.push_back(current_timestamp_us);
+impl CanIoState {
+    // Marks a frame as sent at the given time, recording it and adding it to the CAN table if our own frames are shown
+    async fn record_sent(&self, request: TxRequest, timestamp_us: u128) {
+        if let Some(recorder) = self.recorder.lock().await.as_ref() {
+            recorder.record(&request.frame, FrameKind::Data, Direction::Tx, timestamp_us);
+        }
+        if *self.show_transmitted_frames.lock().await {
+            record_frame(
+                &self.can_entries,
+                &self.frame_history,
+                &self.trace_buffer,
+                request.frame,
+                BUS_CHANNEL,
+                Direction::Tx,
+                timestamp_us,
+            )
+            .await;
+        }
+        self.tx_tracker.lock().await.record(
+            request.tx_id,
+            request.source,
+            TxOutcome::Sent { timestamp_us },
+        );
+    }
+}
 
-                        // drop old (>100ms)
-                        while let Some(&front) = can_entry.timestamps.front() {
-                            if current_timestamp_us - front > 100_000 {
-                                can_entry.timestamps.pop_front();
-                            } else {
-                                break;
-                            }
-                        }
+// Applies acceptance filters to the socket, recording whether the kernel is filtering or if only the
+// software filter is in effect
+async fn apply_acceptance_filters(
+    socket: &mut CanSocket,
+    filter_set: &FilterSet,
+    kernel_filtering: &Mutex<bool>,
+) {
+    *kernel_filtering.lock().await = match socket.set_filters(filter_set) {
+        Ok(applied) => applied,
+        Err(err) => {
+            error_alert_godot(format!(
+                "Failed to apply acceptance filters to the interface, filtering in software instead: {err}"
+            ));
+            false
+        }
+    };
+}
 
-                        // default: window count if dense
-                        let mut freq_hz = if can_entry.timestamps.len() > 1 {
-                            (can_entry.timestamps.len() as f32) * 10.0
-                        } else {
-                            can_entry.freq_hz
-                        };
+// Marks every frame still waiting in the transmit queue as dropped, as the bus is closing
+async fn drop_pending_transmissions(
+    transmit_receiver: &mut mpsc::UnboundedReceiver<TxRequest>,
+    tx_tracker: &Mutex<TxTracker>,
+) {
+    transmit_receiver.close();
 
-                        // always use direct delta if >50ms gap
-                        let delta_us = current_timestamp_us - can_entry.last_timestamp;
-                        if delta_us > 50_000 {
-                            freq_hz = 1e6 / (delta_us as f32);
-                        }
+    let mut tx_tracker = tx_tracker.lock().await;
+    while let Ok(request) = transmit_receiver.try_recv() {
+        tx_tracker.record(request.tx_id, request.source, TxOutcome::Dropped);
+    }
+}
 
-                        // -------- Exponential moving average filter --------
-                        let alpha = (delta_us as f32 / 1e6).clamp(0.003, 1.0);
-                        can_entry.freq_hz = alpha * freq_hz + (1.0 - alpha) * can_entry.freq_hz;
+// Stores a received or transmitted frame in the CAN table, the frame history and the trace
+async fn record_frame(
+    can_entries: &Mutex<HashMap<CanId, CanEntry>>,
+    frame_history: &Mutex<FrameHistory>,
+    trace_buffer: &Mutex<TraceBuffer>,
+    frame: CanFrame,
+    channel: u8,
+    direction: Direction,
+    current_timestamp_us: u128,
+) {
+    trace_buffer
+        .lock()
+        .await
+        .push(frame.clone(), channel, direction, current_timestamp_us);
+    frame_history
+        .lock()
+        .await
+        .push(frame.clone(), direction, current_timestamp_us);
+    update_can_entry(
+        &mut *can_entries.lock().await,
+        frame,
+        direction,
+        current_timestamp_us,
+    );
+}
 
-                        // Update
-                        can_entry.last_timestamp = current_timestamp_us;
-                        can_entry.frame = frame;
-                    }
+// Records a received or transmitted frame against its CAN ID, updating the measured frequency of that ID
+fn update_can_entry(
+    can_entries: &mut HashMap<CanId, CanEntry>,
+    frame: CanFrame,
+    direction: Direction,
+    current_timestamp_us: u128,
+) {
+    match can_entries.entry(frame.id()) {
+        Entry::Occupied(mut occupied_entry) => {
+            let can_entry = occupied_entry.get_mut();
 
-                    Entry::Vacant(entry) => {
-                        let mut timestamps = VecDeque::new();
-                        timestamps.push_back(current_timestamp_us);
+            // push new timestamp
+            can_entry.timestamps.push_back(current_timestamp_us);
 
-                        entry.insert(CanEntry {
-                            timestamps,
-                            last_timestamp: current_timestamp_us,
-                            freq_hz: 0.0,
-                            frame,
-                        });
-                    }
+            // drop old (>100ms)
+            while let Some(&front) = can_entry.timestamps.front() {
+                if current_timestamp_us.saturating_sub(front) > 100_000 {
+                    can_entry.timestamps.pop_front();
+                } else {
+                    break;
                 }
             }
-            Err(err) => {
-                error_alert_godot(format!("Received CAN error: {:?}", err));
-                break;
+
+            // default: window count if dense
+            let mut freq_hz = if can_entry.timestamps.len() > 1 {
+                (can_entry.timestamps.len() as f32) * 10.0
+            } else {
+                can_entry.freq_hz
+            };
+
+            // always use direct delta if >50ms gap. Frames of log files may be slightly out of order.
+            let delta_us = current_timestamp_us.saturating_sub(can_entry.last_timestamp);
+            if delta_us > 50_000 {
+                freq_hz = 1e6 / (delta_us as f32);
             }
+
+            // -------- Exponential moving average filter --------
+            let alpha = (delta_us as f32 / 1e6).clamp(0.003, 1.0);
+            can_entry.freq_hz = alpha * freq_hz + (1.0 - alpha) * can_entry.freq_hz;
+
+            // Update
+            can_entry.last_timestamp = current_timestamp_us;
+            can_entry.frame = frame;
+            can_entry.direction = direction;
+        }
+
+        Entry::Vacant(entry) => {
+            let mut timestamps = VecDeque::new();
+            timestamps.push_back(current_timestamp_us);
+
+            entry.insert(CanEntry {
+                timestamps,
+                last_timestamp: current_timestamp_us,
+                freq_hz: 0.0,
+                frame,
+                direction,
+            });
         }
     }
 }
 
+// Creates a CAN table entry holding the latest frame of an ID as of a point in time
+fn can_entry_at(frame_at: FrameAt) -> CanEntry {
+    let history_frame = frame_at.history_frame;
+    CanEntry {
+        timestamps: VecDeque::from([history_frame.timestamp_us]),
+        last_timestamp: history_frame.timestamp_us,
+        freq_hz: frame_at.freq_hz,
+        frame: history_frame.frame.clone(),
+        direction: history_frame.direction,
+    }
+}
+
+// Converts a stored frame into a Godot Dictionary of "timestamp_us", "id", "extended", "data" and "direction"
+fn history_frame_to_godot(history_frame: &HistoryFrame) -> Dictionary {
+    let mut godot_frame = Dictionary::new();
+    godot_frame.set("timestamp_us", history_frame.timestamp_us as i64);
+    godot_frame.set("id", history_frame.frame.id());
+    godot_frame.set("extended", history_frame.frame.is_extended());
+    godot_frame.set("data", PackedByteArray::from(history_frame.frame.data()));
+    godot_frame.set(
+        "direction",
+        match history_frame.direction {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        },
+    );
+    godot_frame
+}
+
+// Converts a value returned by a capture database query into a Variant, with blobs as PackedByteArrays
+fn sql_value_to_godot(value: &SqlValue) -> Variant {
+    match value {
+        SqlValue::Null => Variant::nil(),
+        SqlValue::Integer(value) => value.to_variant(),
+        SqlValue::Real(value) => value.to_variant(),
+        SqlValue::Text(value) => GString::from(value).to_variant(),
+        SqlValue::Blob(value) => PackedByteArray::from(value.as_slice()).to_variant(),
+    }
+}
+
+// Converts a trace frame into a Godot Dictionary of "index", "timestamp_us", "delta_us", "id_delta_us" (-1 for the
+// first frame of an ID and for error frames), "id" (the error class bits for error frames), "extended", "kind"
+// ("DATA", "RTR", "ERROR", or "FD" followed by "BRS" and "ESI" if they are set), "msg", "data" and "direction"
+fn trace_frame_to_godot(trace_frame: &TraceFrame, can_parser: &CanParser) -> Dictionary {
+    let msg_name = trace_frame
+        .to_can_frame()
+        .and_then(|frame| {
+            can_parser
+                .message_for_frame(&frame)
+                .map(|message_info| message_info.message_name().clone())
+        })
+        .unwrap_or_default();
+    let kind = match trace_frame.kind {
+        FrameKind::Data => "DATA".to_string(),
+        FrameKind::Remote { .. } => "RTR".to_string(),
+        FrameKind::Error => "ERROR".to_string(),
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        } => {
+            let mut kind = "FD".to_string();
+            if bitrate_switch {
+                kind.push_str(" BRS");
+            }
+            if error_state_indicator {
+                kind.push_str(" ESI");
+            }
+            kind
+        }
+    };
+
+    let mut godot_frame = Dictionary::new();
+    godot_frame.set("index", trace_frame.index as i64);
+    godot_frame.set("timestamp_us", trace_frame.timestamp_us as i64);
+    godot_frame.set("delta_us", trace_frame.delta_us as i64);
+    godot_frame.set(
+        "id_delta_us",
+        trace_frame.id_delta_us.map(|d| d as i64).unwrap_or(-1),
+    );
+    godot_frame.set("id", trace_frame.id);
+    godot_frame.set("extended", trace_frame.is_extended);
+    godot_frame.set("kind", kind);
+    godot_frame.set("msg", msg_name);
+    godot_frame.set("data", PackedByteArray::from(trace_frame.data.as_slice()));
+    godot_frame.set(
+        "direction",
+        match trace_frame.direction {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        },
+    );
+    godot_frame
+}
+
 // Sends an error popup to the user in Godot and logs the error to the Godot standard output
 fn error_alert_godot(msg: String) {
     let mut script = ResourceLoader::singleton()
@@ -390,6 +2174,41 @@ fn error_alert_godot(msg: String) {
     godot_error!("{:?}", msg);
 }
 
+// Creates a waveform from its name and parameters, alerting the user if they are invalid
+fn waveform_from_params(waveform: &str, params: &[f64]) -> Option<Waveform> {
+    Waveform::from_params(waveform, params)
+        .map_err(|e| error_alert_godot(e.to_string()))
+        .ok()
+}
+
+// Loads a profile waveform from a CSV file, alerting the user if it cannot be loaded
+fn waveform_from_csv_profile(csv_filepath: &str) -> Option<Waveform> {
+    match Waveform::from_csv_profile(csv_filepath) {
+        Ok(waveform) => Some(waveform),
+        Err(signal_generator::Error::Io(error)) => {
+            error_alert_godot(format!(
+                "Error trying to open signal profile at {csv_filepath:?}"
+            ));
+            godot_error!("{error:?}");
+            None
+        }
+        Err(e) => {
+            error_alert_godot(e.to_string());
+            None
+        }
+    }
+}
+
+// Creates a CanFrame from its parts, returning None if the id or data length is invalid for the frame type
+pub(crate) fn new_can_frame(can_id: u32, is_extended: bool, data: &[u8]) -> Option<CanFrame> {
+    let frame = if is_extended {
+        CanFrame::new_eff(can_id, data)
+    } else {
+        CanFrame::new(can_id, data)
+    };
+    frame.ok()
+}
+
 // Returns the size in bytes of a given CanFrame when it is on the CAN bus
 fn can_frame_bits(frame: &CanFrame) -> usize {
     let base = if frame.is_extended() { 67 } else { 47 }; // All non-data fields
@@ -0,0 +1,240 @@
+///
+/// log_import.rs
+///
+/// Imports a log file for offline analysis, filling the CAN table, frame history and trace with its frames as if they
+/// had been received. The file is read on a blocking task so that importing a large log does not stall Godot, with
+/// the progress of the import reported as it goes. Cancelling an import keeps the frames read so far.
+///
+/// Logs are not always in time order, for example when frames of several channels were merged, so the file is read
+/// twice: first to find its earliest frame, which every timestamp is measured from, then to import the frames.
+/// The frame history is sorted by time once the import stops.
+///
+use crate::frame_history::FrameHistory;
+use crate::logging::{self, LogFormat, LogReader};
+use crate::trace_buffer::TraceBuffer;
+use crate::{CanEntry, CanId, record_frame};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportState {
+    Importing,
+    /// Every frame of the log has been imported
+    Finished,
+    Cancelled,
+    /// The log could not be read any further, keeping the frames read before the error
+    Failed,
+}
+
+impl ImportState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImportState::Importing => "importing",
+            ImportState::Finished => "finished",
+            ImportState::Cancelled => "cancelled",
+            ImportState::Failed => "failed",
+        }
+    }
+}
+
+/// The progress of an import, updated as each frame is read
+#[derive(Clone)]
+pub struct ImportProgress {
+    pub state: ImportState,
+    /// Microseconds since the UNIX epoch of the earliest frame, which the timestamps of every frame are measured from
+    pub start_us: u64,
+    /// The time of the latest frame read, measured from the earliest
+    pub duration_us: u64,
+    pub frame_count: u64,
+    /// Remote, error and CAN FD frames, which a CanFrame cannot represent, so they are only shown in the trace
+    pub trace_only_frames: u64,
+    /// The error that stopped a failed import
+    pub error: Option<String>,
+}
+
+/// A log file loaded for analysis without a bus, whose frames fill the CAN table, frame history and trace
+pub struct OfflineLog {
+    pub path: String,
+    progress: Arc<Mutex<ImportProgress>>,
+    cancelled: Arc<AtomicBool>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl OfflineLog {
+    /// Starts importing the frames of a log on a blocking task, reading the log through the given reader to find its
+    /// earliest frame before opening it again in the given format to import it. Must be called from within the
+    /// runtime.
+    pub fn import(
+        path: String,
+        format: LogFormat,
+        reader: Box<dyn LogReader>,
+        can_entries: Arc<Mutex<HashMap<CanId, CanEntry>>>,
+        frame_history: Arc<Mutex<FrameHistory>>,
+        trace_buffer: Arc<Mutex<TraceBuffer>>,
+    ) -> Self {
+        let progress = Arc::new(Mutex::new(ImportProgress {
+            state: ImportState::Importing,
+            start_us: 0,
+            duration_us: 0,
+            frame_count: 0,
+            trace_only_frames: 0,
+            error: None,
+        }));
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let path = path.clone();
+            let progress = Arc::clone(&progress);
+            let cancelled = Arc::clone(&cancelled);
+            let runtime = tokio::runtime::Handle::current();
+            tokio::task::spawn_blocking(move || {
+                let start_us = earliest_timestamp_us(reader, &cancelled).unwrap_or(0);
+                let reader = format.open_reader(&path);
+                // Blocking threads can wait on the runtime, which the shared state is locked through
+                runtime.block_on(import_frames(
+                    start_us,
+                    reader,
+                    &can_entries,
+                    &frame_history,
+                    &trace_buffer,
+                    &progress,
+                    &cancelled,
+                ))
+            })
+        };
+
+        Self {
+            path,
+            progress,
+            cancelled,
+            handle,
+        }
+    }
+
+    pub async fn progress(&self) -> ImportProgress {
+        self.progress.lock().await.clone()
+    }
+
+    /// Stops the import after the frame being read, keeping the frames read so far
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancels the import and waits for it to stop, after which no more frames are added
+    pub async fn close(self) {
+        self.cancel();
+        let _ = self.handle.await;
+    }
+}
+
+// Returns the timestamp of the earliest frame of a log, or None if it has no frames or reading it was cancelled.
+// A log that cannot be read to the end gives the earliest of the frames before the error.
+fn earliest_timestamp_us(mut reader: Box<dyn LogReader>, cancelled: &AtomicBool) -> Option<u64> {
+    let mut earliest_us: Option<u64> = None;
+    while !cancelled.load(Ordering::Relaxed) {
+        match reader.next_frame() {
+            Ok(Some(log_frame)) => {
+                earliest_us = Some(earliest_us.map_or(log_frame.timestamp_us, |earliest_us| {
+                    earliest_us.min(log_frame.timestamp_us)
+                }));
+            }
+            Ok(None) | Err(_) => return earliest_us,
+        }
+    }
+    None
+}
+
+// Reads the frames of a log one at a time, so that logs larger than memory can be imported as the table, history and
+// trace are all bounded
+async fn import_frames(
+    start_us: u64,
+    reader: Result<Box<dyn LogReader>, logging::Error>,
+    can_entries: &Mutex<HashMap<CanId, CanEntry>>,
+    frame_history: &Mutex<FrameHistory>,
+    trace_buffer: &Mutex<TraceBuffer>,
+    progress: &Mutex<ImportProgress>,
+    cancelled: &AtomicBool,
+) {
+    progress.lock().await.start_us = start_us;
+    let state = match reader {
+        Ok(reader) => {
+            read_frames(
+                reader,
+                start_us,
+                can_entries,
+                frame_history,
+                trace_buffer,
+                progress,
+                cancelled,
+            )
+            .await
+        }
+        Err(e) => {
+            progress.lock().await.error = Some(e.to_string());
+            ImportState::Failed
+        }
+    };
+
+    frame_history.lock().await.sort_by_time();
+    progress.lock().await.state = state;
+}
+
+// Imports the frames of a log with timestamps measured from start_us, returning the state the import stopped in
+async fn read_frames(
+    mut reader: Box<dyn LogReader>,
+    start_us: u64,
+    can_entries: &Mutex<HashMap<CanId, CanEntry>>,
+    frame_history: &Mutex<FrameHistory>,
+    trace_buffer: &Mutex<TraceBuffer>,
+    progress: &Mutex<ImportProgress>,
+    cancelled: &AtomicBool,
+) -> ImportState {
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            break ImportState::Cancelled;
+        }
+
+        let log_frame = match reader.next_frame() {
+            Ok(Some(log_frame)) => log_frame,
+            Ok(None) => break ImportState::Finished,
+            Err(e) => {
+                progress.lock().await.error = Some(e.to_string());
+                break ImportState::Failed;
+            }
+        };
+
+        // Only a log that changed between the two reads has frames before start_us
+        let timestamp_us = log_frame.timestamp_us.saturating_sub(start_us);
+        let trace_only = match log_frame.to_can_frame() {
+            Some(frame) => {
+                record_frame(
+                    can_entries,
+                    frame_history,
+                    trace_buffer,
+                    frame,
+                    log_frame.channel,
+                    log_frame.direction,
+                    timestamp_us.into(),
+                )
+                .await;
+                false
+            }
+            None => {
+                trace_buffer
+                    .lock()
+                    .await
+                    .push_kind(log_frame, timestamp_us.into());
+                true
+            }
+        };
+
+        let mut progress = progress.lock().await;
+        progress.duration_us = progress.duration_us.max(timestamp_us);
+        progress.frame_count += 1;
+        if trace_only {
+            progress.trace_only_frames += 1;
+        }
+    }
+}
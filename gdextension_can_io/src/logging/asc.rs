@@ -0,0 +1,575 @@
+///
+/// logging/asc.rs
+///
+/// Vector ASCII log files (.asc), as exchanged with CANalyzer and CANoe. A file starts with a header giving the
+/// date of the measurement, the number base of ids and data, and whether timestamps are absolute (seconds since
+/// the start of the measurement) or relative (seconds since the previous event). Each frame is then one line:
+///
+///   `0.001234 1  123             Rx   d 8 01 02 03 04 05 06 07 08` for classic frames, with an `x` suffix on
+///                                                                   extended ids and `r` in place of `d` for remote frames
+///   `0.002000 1  ErrorFrame`                                        for error frames
+///   `0.003000 CANFD   1 Rx        123  1 0 8  8 01 02 ...`          for CAN FD frames, followed by the BRS and ESI
+///                                                                   flags, the DLC, the data length and the data
+///
+/// Lines for other events, such as statistics and comments, are skipped when reading. Dates are read and written
+/// as UTC.
+///
+use super::{DateTime, Error, FrameKind, LogFrame, LogReader, LogWriter};
+use crate::Direction;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Flags column of CAN FD lines
+const FD_FLAG_EDL: u32 = 0x1000;
+const FD_FLAG_BRS: u32 = 0x2000;
+const FD_FLAG_ESI: u32 = 0x4000;
+
+pub struct AscWriter<W: Write + Send> {
+    output: W,
+    // Time of the first frame, which the timestamps of every frame are relative to
+    start_us: Option<u64>,
+    line: String,
+}
+
+impl<W: Write + Send> AscWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            start_us: None,
+            line: String::new(),
+        }
+    }
+
+    fn write_header(&mut self, start_us: u64) -> Result<(), Error> {
+        let date = format_date(&DateTime::from_epoch_us(start_us));
+        write!(
+            self.output,
+            "date {date}\nbase hex  timestamps absolute\ninternal events logged\n// version 9.0.0\n\
+             Begin Triggerblock {date}\n   0.000000 Start of measurement\n"
+        )?;
+        self.start_us = Some(start_us);
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> LogWriter for AscWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                // The header date only has millisecond precision, so timestamps are measured from the start of
+                // the millisecond for them to be read back exactly
+                let start_us = frame.timestamp_us - frame.timestamp_us % 1_000;
+                self.write_header(start_us)?;
+                start_us
+            }
+        };
+
+        self.line.clear();
+        format_frame(
+            &mut self.line,
+            frame,
+            frame.timestamp_us.saturating_sub(start_us),
+        );
+        self.output.write_all(self.line.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        // A file without frames still needs a header, which is dated to when the file was finished
+        if self.start_us.is_none() {
+            let now_us = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64;
+            self.write_header(now_us)?;
+        }
+
+        self.output.write_all(b"End TriggerBlock\n")?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+// Formats a date as in `Mon Oct 19 10:15:30.123 am 2026`
+fn format_date(date: &DateTime) -> String {
+    let hour = match date.hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    format!(
+        "{} {} {:02} {:02}:{:02}:{:02}.{:03} {} {}",
+        WEEKDAYS[date.weekday as usize],
+        MONTHS[(date.month - 1) as usize],
+        date.day,
+        hour,
+        date.minute,
+        date.second,
+        date.microsecond / 1_000,
+        if date.hour < 12 { "am" } else { "pm" },
+        date.year
+    )
+}
+
+// Writing into a String cannot fail, so the results of write! are ignored
+fn format_frame(line: &mut String, frame: &LogFrame, relative_us: u64) {
+    let time = format!("{}.{:06}", relative_us / 1_000_000, relative_us % 1_000_000);
+    let direction = match frame.direction {
+        Direction::Rx => "Rx",
+        Direction::Tx => "Tx",
+    };
+    let id = if frame.is_extended {
+        format!("{:X}x", frame.id)
+    } else {
+        format!("{:X}", frame.id)
+    };
+
+    match frame.kind {
+        FrameKind::Data => {
+            let _ = write!(
+                line,
+                "{time:>11} {:<2} {id:<15} {direction:<4} d {:X}",
+                frame.channel,
+                frame.data.len()
+            );
+            push_data(line, &frame.data);
+        }
+        FrameKind::Remote { dlc } => {
+            let _ = write!(
+                line,
+                "{time:>11} {:<2} {id:<15} {direction:<4} r {dlc:X}",
+                frame.channel
+            );
+        }
+        FrameKind::Error => {
+            let _ = write!(line, "{time:>11} {:<2} ErrorFrame", frame.channel);
+        }
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        } => {
+            let mut flags = FD_FLAG_EDL;
+            if bitrate_switch {
+                flags |= FD_FLAG_BRS;
+            }
+            if error_state_indicator {
+                flags |= FD_FLAG_ESI;
+            }
+
+            // The symbolic name, message duration, message length, CRC and bit timing columns are not known
+            let _ = write!(
+                line,
+                "{time:>11} CANFD {:>3} {direction:<4} {id:>8} {:>32} {} {} {:X} {:>2}",
+                frame.channel,
+                "",
+                u8::from(bitrate_switch),
+                u8::from(error_state_indicator),
+                super::fd_dlc(frame.data.len()),
+                frame.data.len()
+            );
+            push_data(line, &frame.data);
+            let _ = write!(
+                line,
+                " {:>8} {:>4} {flags:>8X} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                0, 0, 0, 0, 0, 0, 0
+            );
+        }
+    }
+    line.push('\n');
+}
+
+fn push_data(line: &mut String, data: &[u8]) {
+    for byte in data {
+        let _ = write!(line, " {byte:02X}");
+    }
+}
+
+pub struct AscReader<R: BufRead + Send> {
+    input: R,
+    line: String,
+    radix: u32,
+    relative_timestamps: bool,
+    start_us: u64,
+    last_time_us: u64,
+}
+
+impl<R: BufRead + Send> AscReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            radix: 16,
+            relative_timestamps: false,
+            start_us: 0,
+            last_time_us: 0,
+        }
+    }
+}
+
+impl<R: BufRead + Send> LogReader for AscReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            let tokens: Vec<&str> = self.line.split_whitespace().collect();
+
+            match tokens.as_slice() {
+                ["date", date @ ..] => {
+                    if let Some(date_us) = parse_date(date) {
+                        self.start_us = date_us;
+                    }
+                }
+                ["base", base, "timestamps", mode, ..] => {
+                    self.radix = if *base == "dec" { 10 } else { 16 };
+                    self.relative_timestamps = *mode == "relative";
+                }
+                [time, event @ ..] => {
+                    let Some(time_us) = parse_seconds(time) else {
+                        continue;
+                    };
+                    if self.relative_timestamps {
+                        self.last_time_us += time_us;
+                    } else {
+                        self.last_time_us = time_us;
+                    }
+
+                    if let Some(mut frame) = parse_frame(event, self.radix) {
+                        frame.timestamp_us = self.start_us + self.last_time_us;
+                        return Ok(Some(frame));
+                    }
+                }
+                [] => {}
+            }
+        }
+    }
+}
+
+// Parses a date as in `Mon Oct 19 10:15:30.123 am 2026`, where the fraction of a second and am/pm are optional
+fn parse_date(tokens: &[&str]) -> Option<u64> {
+    let (month, day, time, meridiem, year) = match tokens {
+        [_, month, day, time, meridiem, year] => (month, day, time, Some(*meridiem), year),
+        [_, month, day, time, year] => (month, day, time, None, year),
+        _ => return None,
+    };
+
+    let month = MONTHS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(month))? as u32
+        + 1;
+    let mut time_parts = time.splitn(3, ':');
+    let mut hour: u32 = time_parts.next()?.parse().ok()?;
+    let minute = time_parts.next()?.parse().ok()?;
+    let seconds_us = parse_seconds(time_parts.next()?)?;
+
+    match meridiem.map(str::to_ascii_lowercase).as_deref() {
+        Some("am") if hour == 12 => hour = 0,
+        Some("pm") if hour < 12 => hour += 12,
+        _ => {}
+    }
+
+    DateTime {
+        year: year.parse().ok()?,
+        month,
+        day: day.parse().ok()?,
+        weekday: 0,
+        hour,
+        minute,
+        second: (seconds_us / 1_000_000) as u32,
+        microsecond: (seconds_us % 1_000_000) as u32,
+    }
+    .to_epoch_us()
+}
+
+// Parses a number of seconds with an optional fraction into microseconds
+fn parse_seconds(text: &str) -> Option<u64> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut fraction_us = 0;
+    for (i, digit) in fraction.bytes().take(6).enumerate() {
+        fraction_us += u64::from(digit - b'0') * 10u64.pow(5 - i as u32);
+    }
+    Some(whole.parse::<u64>().ok()? * 1_000_000 + fraction_us)
+}
+
+// Parses the event following the timestamp of a line, returning None if it is not a frame
+fn parse_frame(event: &[&str], radix: u32) -> Option<LogFrame> {
+    match event {
+        ["CANFD", channel, direction, id, rest @ ..] => {
+            // The symbolic name column is empty when no database was used
+            let rest = match rest.first() {
+                Some(&"0" | &"1") => rest,
+                _ => rest.get(1..)?,
+            };
+            let [brs, esi, _dlc, length, rest @ ..] = rest else {
+                return None;
+            };
+            let length: usize = length.parse().ok()?;
+            let (id, is_extended) = parse_id(id, radix)?;
+
+            Some(LogFrame {
+                timestamp_us: 0,
+                channel: channel.parse().ok()?,
+                id,
+                is_extended,
+                kind: FrameKind::Fd {
+                    bitrate_switch: *brs == "1",
+                    error_state_indicator: *esi == "1",
+                },
+                data: parse_data(rest.get(..length)?, radix)?,
+                direction: parse_direction(direction)?,
+            })
+        }
+        [channel, "ErrorFrame", ..] => Some(LogFrame {
+            timestamp_us: 0,
+            channel: channel.parse().ok()?,
+            id: 0,
+            is_extended: false,
+            kind: FrameKind::Error,
+            data: Vec::new(),
+            direction: Direction::Rx,
+        }),
+        [channel, id, direction, frame_type, rest @ ..] => {
+            let (id, is_extended) = parse_id(id, radix)?;
+            let (kind, data) = match *frame_type {
+                "d" => {
+                    let dlc = usize::from_str_radix(rest.first()?, 16).ok()?.min(8);
+                    (FrameKind::Data, parse_data(rest.get(1..dlc + 1)?, radix)?)
+                }
+                "r" => {
+                    let dlc = match rest.first() {
+                        Some(dlc) => u8::from_str_radix(dlc, 16).ok()?,
+                        None => 0,
+                    };
+                    (FrameKind::Remote { dlc }, Vec::new())
+                }
+                _ => return None,
+            };
+
+            Some(LogFrame {
+                timestamp_us: 0,
+                channel: channel.parse().ok()?,
+                id,
+                is_extended,
+                kind,
+                data,
+                direction: parse_direction(direction)?,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn parse_id(text: &str, radix: u32) -> Option<(u32, bool)> {
+    match text.strip_suffix(['x', 'X']) {
+        Some(id) => Some((u32::from_str_radix(id, radix).ok()?, true)),
+        None => Some((u32::from_str_radix(text, radix).ok()?, false)),
+    }
+}
+
+fn parse_data(bytes: &[&str], radix: u32) -> Option<Vec<u8>> {
+    bytes
+        .iter()
+        .map(|byte| u8::from_str_radix(byte, radix).ok())
+        .collect()
+}
+
+// Transmit requests (TxRq) are not frames on the bus, so they are skipped
+fn parse_direction(text: &str) -> Option<Direction> {
+    match text {
+        "Rx" => Some(Direction::Rx),
+        "Tx" => Some(Direction::Tx),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frame, frames, read_all};
+
+    fn read_asc(text: &str) -> Vec<LogFrame> {
+        read_all(AscReader::new(text.as_bytes()))
+    }
+
+    // The time of the header date used by the sample files
+    fn header_date_us() -> u64 {
+        DateTime {
+            year: 2026,
+            month: 10,
+            day: 19,
+            weekday: 1,
+            hour: 10,
+            minute: 15,
+            second: 30,
+            microsecond: 123_000,
+        }
+        .to_epoch_us()
+        .unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut writer = AscWriter::new(Vec::new());
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Error frames are written without their class bits or details
+        let mut expected = frames();
+        for frame in &mut expected {
+            if frame.kind == FrameKind::Error {
+                frame.id = 0;
+                frame.data.clear();
+            }
+        }
+        assert_eq!(read_all(AscReader::new(writer.output.as_slice())), expected);
+    }
+
+    #[test]
+    fn relative_timestamps_accumulate() {
+        let frames = read_asc(
+            "date Mon Oct 19 10:15:30.123 am 2026\n\
+             base hex  timestamps relative\n\
+             Begin Triggerblock Mon Oct 19 10:15:30.123 am 2026\n   \
+                0.000000 Start of measurement\n   \
+                0.001000 1  123             Rx   d 2 01 02\n   \
+                0.000500 2  1ABCDEF0x       Tx   r 8\n\
+             End TriggerBlock\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                LogFrame {
+                    timestamp_us: header_date_us() + 1_000,
+                    ..frame(0x123, false, FrameKind::Data, &[1, 2], Direction::Rx)
+                },
+                LogFrame {
+                    timestamp_us: header_date_us() + 1_500,
+                    channel: 2,
+                    ..frame(
+                        0x1ABC_DEF0,
+                        true,
+                        FrameKind::Remote { dlc: 8 },
+                        &[],
+                        Direction::Tx
+                    )
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn decimal_ids_and_data() {
+        let frames = read_asc(
+            "date Mon Oct 19 10:15:30 pm 2026\n\
+             base dec  timestamps absolute\n   \
+                1.250000 1  291             Rx   d 3 1 16 255\n   \
+                1.500000 1  4660x           Rx   d 1 10\n",
+        );
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (
+                frames[0].id,
+                frames[0].is_extended,
+                frames[0].data.as_slice()
+            ),
+            (291, false, &[1, 16, 255][..])
+        );
+        assert_eq!(
+            (
+                frames[1].id,
+                frames[1].is_extended,
+                frames[1].data.as_slice()
+            ),
+            (4660, true, &[10][..])
+        );
+        assert_eq!(frames[1].timestamp_us - frames[0].timestamp_us, 250_000);
+    }
+
+    #[test]
+    fn fd_frames_with_and_without_symbolic_names() {
+        let frames = read_asc(
+            "base hex  timestamps absolute\n   \
+                0.002000 CANFD   1 Rx        123                                  1 0 9 12 \
+                00 01 02 03 04 05 06 07 08 09 0A 0B        0    0     3000        0        0        0        0        0\n   \
+                0.003000 CANFD   2 Tx   18DAF110x  EngineData                       0 1 8  8 \
+                FF FF FF FF FF FF FF FF        0    0     5000        0        0        0        0        0\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                LogFrame {
+                    timestamp_us: 2_000,
+                    ..frame(
+                        0x123,
+                        false,
+                        FrameKind::Fd {
+                            bitrate_switch: true,
+                            error_state_indicator: false,
+                        },
+                        &(0..12).collect::<Vec<u8>>(),
+                        Direction::Rx
+                    )
+                },
+                LogFrame {
+                    timestamp_us: 3_000,
+                    channel: 2,
+                    ..frame(
+                        0x18DA_F110,
+                        true,
+                        FrameKind::Fd {
+                            bitrate_switch: false,
+                            error_state_indicator: true,
+                        },
+                        &[0xFF; 8],
+                        Direction::Tx
+                    )
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn error_frames_and_other_events() {
+        let frames = read_asc(
+            "base hex  timestamps absolute\n   \
+                0.001000 1  Statistic: D 0 R 0 XD 0 XR 0 E 0 O 0 B 0.00%\n   \
+                0.002000 2  ErrorFrame\n   \
+                0.003000 1  ErrorFrame ECC: 10100010\n   \
+                0.004000 1  123             TxRq d 1 00\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                LogFrame {
+                    timestamp_us: 2_000,
+                    channel: 2,
+                    ..frame(0, false, FrameKind::Error, &[], Direction::Rx)
+                },
+                LogFrame {
+                    timestamp_us: 3_000,
+                    ..frame(0, false, FrameKind::Error, &[], Direction::Rx)
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,661 @@
+///
+/// logging/blf.rs
+///
+/// Vector binary log files (.blf). A file is a 144 byte header followed by a sequence of objects, each starting
+/// with a `LOBJ` header giving its size and type. Frames are stored as objects inside log container objects,
+/// which are usually zlib compressed, and an object may be split across two containers. Object timestamps are
+/// relative to the start time in the file header.
+///
+/// CAN message, CAN FD message and CAN error objects are read, all other objects are skipped. Files are written
+/// with zlib compressed containers and an application id of their own, which tells the reader that extended error
+/// objects hold error classes.
+///
+use super::{DateTime, Error, FrameKind, LogFrame, LogReader, LogWriter};
+use crate::Direction;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const FILE_SIGNATURE: &[u8; 4] = b"LOGG";
+const FILE_HEADER_SIZE: usize = 144;
+const OBJECT_SIGNATURE: &[u8; 4] = b"LOBJ";
+const OBJECT_BASE_HEADER_SIZE: usize = 16;
+const OBJECT_HEADER_V1_SIZE: usize = 32;
+const CONTAINER_HEADER_SIZE: usize = 16;
+
+// Object types
+const CAN_MESSAGE: u32 = 1;
+const CAN_ERROR: u32 = 2;
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+// Container compression methods
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_COMPRESSION: u16 = 2;
+
+// Object header flags giving the unit of the timestamp
+const TIME_TEN_MICS: u32 = 0x1;
+const TIME_ONE_NANS: u32 = 0x2;
+
+// CAN message flags
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+const DIR_TX: u8 = 0x01;
+const REMOTE_FLAG: u8 = 0x80;
+
+// CAN FD message flags
+const FD_EDL: u8 = 0x01;
+const FD_BRS: u8 = 0x02;
+const FD_ESI: u8 = 0x04;
+
+// CAN FD message 64 flags
+const FD64_REMOTE: u32 = 0x0010;
+const FD64_EDL: u32 = 0x1000;
+const FD64_BRS: u32 = 0x2000;
+const FD64_ESI: u32 = 0x4000;
+
+// Uncompressed size at which a container is compressed and written
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+
+// Application id written to the file header, outside the range of ids of Vector's own tools, so that the error class
+// bits written in place of the id of extended error objects are only read back from files written here
+const APPLICATION_ID: u8 = 0xCA;
+
+pub struct BlfReader<R: Read + Send> {
+    input: R,
+    start_us: u64,
+    // Whether the file was written here, with error class bits in place of the id of extended error objects
+    has_error_classes: bool,
+    // Uncompressed objects of the current container, which may end with the start of an object that continues in
+    // the next container
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read + Send> BlfReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Error> {
+        let mut header = [0; FILE_HEADER_SIZE];
+        input.read_exact(&mut header)?;
+        if &header[0..4] != FILE_SIGNATURE {
+            return Err(Error::Malformed("missing BLF file signature".to_string()));
+        }
+
+        // Skip the remainder of headers larger than the one read
+        let header_size = read_u32(&header, 4) as usize;
+        if header_size > FILE_HEADER_SIZE {
+            std::io::copy(
+                &mut (&mut input).take((header_size - FILE_HEADER_SIZE) as u64),
+                &mut std::io::sink(),
+            )?;
+        }
+
+        Ok(Self {
+            input,
+            start_us: read_system_time(&header, 40).unwrap_or(0),
+            has_error_classes: header[8] == APPLICATION_ID,
+            buffer: Vec::new(),
+            position: 0,
+        })
+    }
+
+    // Reads the next container into the buffer, returning false at the end of the file
+    fn read_container(&mut self) -> Result<bool, Error> {
+        loop {
+            let mut header = [0; OBJECT_BASE_HEADER_SIZE];
+            match self.input.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(err) => return Err(err.into()),
+            }
+            if &header[0..4] != OBJECT_SIGNATURE {
+                return Err(Error::Malformed("missing object signature".to_string()));
+            }
+
+            let object_size = read_u32(&header, 8) as usize;
+            let object_type = read_u32(&header, 12);
+            if object_size < OBJECT_BASE_HEADER_SIZE {
+                return Err(Error::Malformed(format!(
+                    "invalid object size {object_size}"
+                )));
+            }
+
+            let mut object = vec![0; object_size - OBJECT_BASE_HEADER_SIZE];
+            self.input.read_exact(&mut object)?;
+
+            // Objects are padded to a multiple of 4 bytes, which may be missing at the end of the file
+            let mut padding = [0; 4];
+            let _ = self.input.read_exact(&mut padding[..object_size % 4]);
+
+            // Objects outside of containers are not frames
+            if object_type != LOG_CONTAINER {
+                continue;
+            }
+            if object.len() < CONTAINER_HEADER_SIZE {
+                return Err(Error::Malformed("truncated log container".to_string()));
+            }
+
+            let compression = read_u16(&object, 0);
+            let data = &object[CONTAINER_HEADER_SIZE..];
+
+            // Keep the unread end of the previous container, which is the start of an object split across both
+            self.buffer.drain(..self.position);
+            self.position = 0;
+            match compression {
+                NO_COMPRESSION => self.buffer.extend_from_slice(data),
+                ZLIB_COMPRESSION => {
+                    ZlibDecoder::new(data).read_to_end(&mut self.buffer)?;
+                }
+                _ => {
+                    return Err(Error::Malformed(format!(
+                        "unsupported container compression method {compression}"
+                    )));
+                }
+            }
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read + Send> LogReader for BlfReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        loop {
+            // Objects are followed by up to 7 bytes of padding, depending on their type
+            let remaining = &self.buffer[self.position..];
+            let Some(offset) = remaining
+                .windows(4)
+                .take(8)
+                .position(|window| window == OBJECT_SIGNATURE)
+            else {
+                if remaining.len() >= 8 + OBJECT_SIGNATURE.len() {
+                    return Err(Error::Malformed("missing object signature".to_string()));
+                }
+                if !self.read_container()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+
+            let object = &remaining[offset..];
+            if object.len() < OBJECT_BASE_HEADER_SIZE || object.len() < read_u32(object, 8) as usize
+            {
+                // The object continues in the next container
+                if !self.read_container()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let object_size = read_u32(object, 8) as usize;
+            if object_size < OBJECT_BASE_HEADER_SIZE {
+                return Err(Error::Malformed(format!(
+                    "invalid object size {object_size}"
+                )));
+            }
+            let frame = parse_object(
+                &object[..object_size],
+                self.start_us,
+                self.has_error_classes,
+            );
+            self.position += offset + object_size;
+
+            if let Some(frame) = frame {
+                return Ok(Some(frame));
+            }
+        }
+    }
+}
+
+// Parses a frame object, returning None for other objects and objects too short for their type
+fn parse_object(object: &[u8], start_us: u64, has_error_classes: bool) -> Option<LogFrame> {
+    let header_size = read_u16(object, 4) as usize;
+    let object_type = read_u32(object, 12);
+    if header_size < OBJECT_HEADER_V1_SIZE || object.len() < header_size {
+        return None;
+    }
+
+    // Version 1 and 2 headers share the position of the flags and timestamp
+    let flags = read_u32(object, 16);
+    let timestamp = read_u64(object, 24);
+    let timestamp_us = start_us
+        + match flags {
+            TIME_TEN_MICS => timestamp * 10,
+            TIME_ONE_NANS => timestamp / 1_000,
+            _ => timestamp,
+        };
+
+    let data = &object[header_size..];
+    let mut frame = match object_type {
+        CAN_MESSAGE | CAN_MESSAGE2 if data.len() >= 16 => {
+            let message_flags = data[2];
+            let dlc = data[3];
+            let kind = if message_flags & REMOTE_FLAG != 0 {
+                FrameKind::Remote { dlc }
+            } else {
+                FrameKind::Data
+            };
+            let length = if kind == FrameKind::Data {
+                usize::from(dlc.min(8))
+            } else {
+                0
+            };
+
+            LogFrame {
+                timestamp_us,
+                channel: read_u16(data, 0) as u8,
+                id: read_u32(data, 4),
+                is_extended: false,
+                kind,
+                data: data[8..8 + length].to_vec(),
+                direction: direction(message_flags & DIR_TX != 0),
+            }
+        }
+        CAN_FD_MESSAGE if data.len() >= 84 => {
+            let message_flags = data[2];
+            let fd_flags = data[13];
+            let length = usize::from(data[14]).min(64);
+            let kind = if fd_flags & FD_EDL != 0 {
+                FrameKind::Fd {
+                    bitrate_switch: fd_flags & FD_BRS != 0,
+                    error_state_indicator: fd_flags & FD_ESI != 0,
+                }
+            } else if message_flags & REMOTE_FLAG != 0 {
+                FrameKind::Remote { dlc: data[3] }
+            } else {
+                FrameKind::Data
+            };
+
+            LogFrame {
+                timestamp_us,
+                channel: read_u16(data, 0) as u8,
+                id: read_u32(data, 4),
+                is_extended: false,
+                data: if matches!(kind, FrameKind::Remote { .. }) {
+                    Vec::new()
+                } else {
+                    data[20..20 + length].to_vec()
+                },
+                kind,
+                direction: direction(message_flags & DIR_TX != 0),
+            }
+        }
+        CAN_FD_MESSAGE_64 if data.len() >= 40 => {
+            let message_flags = read_u32(data, 12);
+            let length = usize::from(data[2]);
+            let kind = if message_flags & FD64_EDL != 0 {
+                FrameKind::Fd {
+                    bitrate_switch: message_flags & FD64_BRS != 0,
+                    error_state_indicator: message_flags & FD64_ESI != 0,
+                }
+            } else if message_flags & FD64_REMOTE != 0 {
+                FrameKind::Remote { dlc: data[1] }
+            } else {
+                FrameKind::Data
+            };
+
+            LogFrame {
+                timestamp_us,
+                channel: data[0],
+                id: read_u32(data, 4),
+                is_extended: false,
+                data: if matches!(kind, FrameKind::Remote { .. }) {
+                    Vec::new()
+                } else {
+                    data.get(40..40 + length)?.to_vec()
+                },
+                kind,
+                direction: direction(data[34] == 1),
+            }
+        }
+        // Extended error objects are written here with the error class bits in place of the id, whereas other
+        // applications write the id of the frame the error occurred in, which is not an error class
+        CAN_ERROR_EXT if data.len() >= 32 => LogFrame {
+            timestamp_us,
+            channel: read_u16(data, 0) as u8,
+            id: if has_error_classes {
+                read_u32(data, 16)
+            } else {
+                0
+            },
+            is_extended: false,
+            kind: FrameKind::Error,
+            data: data[24..32].to_vec(),
+            direction: Direction::Rx,
+        },
+        CAN_ERROR | CAN_ERROR_EXT if data.len() >= 2 => LogFrame {
+            timestamp_us,
+            channel: read_u16(data, 0) as u8,
+            id: 0,
+            is_extended: false,
+            kind: FrameKind::Error,
+            data: Vec::new(),
+            direction: Direction::Rx,
+        },
+        _ => return None,
+    };
+
+    if frame.id & CAN_MSG_EXT != 0 {
+        frame.id &= !CAN_MSG_EXT;
+        frame.is_extended = true;
+    }
+    Some(frame)
+}
+
+fn direction(is_tx: bool) -> Direction {
+    if is_tx { Direction::Tx } else { Direction::Rx }
+}
+
+pub struct BlfWriter<W: Write + Seek + Send> {
+    output: W,
+    // Uncompressed objects of the container being filled
+    container: Vec<u8>,
+    // Time of the first frame, which the timestamps of every frame are relative to
+    start_us: Option<u64>,
+    last_timestamp_us: u64,
+    object_count: u32,
+    uncompressed_size: u64,
+}
+
+impl<W: Write + Seek + Send> BlfWriter<W> {
+    pub fn new(mut output: W) -> Result<Self, Error> {
+        // The header is written once the file is finished, as it holds the size of the file and the number of
+        // objects
+        output.write_all(&[0; FILE_HEADER_SIZE])?;
+
+        Ok(Self {
+            output,
+            container: Vec::with_capacity(MAX_CONTAINER_SIZE),
+            start_us: None,
+            last_timestamp_us: 0,
+            object_count: 0,
+            uncompressed_size: FILE_HEADER_SIZE as u64,
+        })
+    }
+
+    // Compresses the container being filled and writes it as a log container object
+    fn write_container(&mut self) -> Result<(), Error> {
+        if self.container.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.container)?;
+        let compressed = encoder.finish()?;
+
+        let object_size = OBJECT_BASE_HEADER_SIZE + CONTAINER_HEADER_SIZE + compressed.len();
+        let mut header = Vec::with_capacity(OBJECT_BASE_HEADER_SIZE + CONTAINER_HEADER_SIZE);
+        header.extend_from_slice(OBJECT_SIGNATURE);
+        header.extend_from_slice(&(OBJECT_BASE_HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(object_size as u32).to_le_bytes());
+        header.extend_from_slice(&LOG_CONTAINER.to_le_bytes());
+        header.extend_from_slice(&ZLIB_COMPRESSION.to_le_bytes());
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&(self.container.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+
+        self.output.write_all(&header)?;
+        self.output.write_all(&compressed)?;
+        self.output.write_all(&[0; 4][..object_size % 4])?;
+
+        self.uncompressed_size +=
+            (OBJECT_BASE_HEADER_SIZE + CONTAINER_HEADER_SIZE + self.container.len()) as u64;
+        self.container.clear();
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        let file_size = self.output.stream_position()?;
+        let start_us = self.start_us.unwrap_or(0);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_SIGNATURE);
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+        // Application id, application version and BLF format version
+        header.extend_from_slice(&[APPLICATION_ID, 0, 0, 0, 2, 6, 8, 1]);
+        header.extend_from_slice(&file_size.to_le_bytes());
+        header.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&self.object_count.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        push_system_time(&mut header, start_us);
+        push_system_time(&mut header, self.last_timestamp_us.max(start_us));
+        header.resize(FILE_HEADER_SIZE, 0);
+
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.write_all(&header)?;
+        self.output.seek(SeekFrom::Start(file_size))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek + Send> LogWriter for BlfWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        // The header start time only has millisecond precision, so timestamps are measured from the start of the
+        // millisecond for them to be read back exactly
+        let start_us = *self
+            .start_us
+            .get_or_insert(frame.timestamp_us - frame.timestamp_us % 1_000);
+        self.last_timestamp_us = frame.timestamp_us;
+
+        let timestamp_ns = frame.timestamp_us.saturating_sub(start_us) * 1_000;
+        let id = if frame.is_extended {
+            frame.id | CAN_MSG_EXT
+        } else {
+            frame.id
+        };
+        let direction_flag = match frame.direction {
+            Direction::Rx => 0,
+            Direction::Tx => DIR_TX,
+        };
+
+        let mut body = Vec::with_capacity(84);
+        let object_type = match frame.kind {
+            FrameKind::Data | FrameKind::Remote { .. } => {
+                let (flags, dlc) = match frame.kind {
+                    FrameKind::Remote { dlc } => (direction_flag | REMOTE_FLAG, dlc),
+                    _ => (direction_flag, frame.data.len().min(8) as u8),
+                };
+                body.extend_from_slice(&u16::from(frame.channel).to_le_bytes());
+                body.extend_from_slice(&[flags, dlc]);
+                body.extend_from_slice(&id.to_le_bytes());
+                push_padded(&mut body, &frame.data, 8);
+                CAN_MESSAGE
+            }
+            FrameKind::Fd {
+                bitrate_switch,
+                error_state_indicator,
+            } => {
+                let mut fd_flags = FD_EDL;
+                if bitrate_switch {
+                    fd_flags |= FD_BRS;
+                }
+                if error_state_indicator {
+                    fd_flags |= FD_ESI;
+                }
+                let length = frame.data.len().min(64);
+
+                body.extend_from_slice(&u16::from(frame.channel).to_le_bytes());
+                body.extend_from_slice(&[direction_flag, super::fd_dlc(length)]);
+                body.extend_from_slice(&id.to_le_bytes());
+                // Frame length in nanoseconds and bit count, which are not known
+                body.extend_from_slice(&[0; 5]);
+                body.extend_from_slice(&[fd_flags, length as u8]);
+                body.extend_from_slice(&[0; 5]);
+                push_padded(&mut body, &frame.data, 64);
+                CAN_FD_MESSAGE
+            }
+            FrameKind::Error => {
+                body.extend_from_slice(&u16::from(frame.channel).to_le_bytes());
+                // Length, flags, error code, position, DLC, padding and frame length, which are not known
+                body.extend_from_slice(&[0; 14]);
+                body.extend_from_slice(&id.to_le_bytes());
+                // Extended flags and padding
+                body.extend_from_slice(&[0; 4]);
+                push_padded(&mut body, &frame.data, 8);
+                CAN_ERROR_EXT
+            }
+        };
+
+        let object_size = OBJECT_HEADER_V1_SIZE + body.len();
+        self.container.extend_from_slice(OBJECT_SIGNATURE);
+        self.container
+            .extend_from_slice(&(OBJECT_HEADER_V1_SIZE as u16).to_le_bytes());
+        self.container.extend_from_slice(&1u16.to_le_bytes());
+        self.container
+            .extend_from_slice(&(object_size as u32).to_le_bytes());
+        self.container.extend_from_slice(&object_type.to_le_bytes());
+        self.container
+            .extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+        // Client index and object version
+        self.container.extend_from_slice(&[0; 4]);
+        self.container
+            .extend_from_slice(&timestamp_ns.to_le_bytes());
+        self.container.extend_from_slice(&body);
+        self.object_count += 1;
+
+        if self.container.len() >= MAX_CONTAINER_SIZE {
+            self.write_container()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.write_container()?;
+        self.write_header()?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+fn push_padded(buffer: &mut Vec<u8>, data: &[u8], length: usize) {
+    let data = &data[..data.len().min(length)];
+    buffer.extend_from_slice(data);
+    buffer.resize(buffer.len() + length - data.len(), 0);
+}
+
+// Reads a SYSTEMTIME, returning None if it is not a valid date
+fn read_system_time(buffer: &[u8], offset: usize) -> Option<u64> {
+    let field = |index: usize| u32::from(read_u16(buffer, offset + index * 2));
+    DateTime {
+        year: i64::from(field(0)),
+        month: field(1),
+        weekday: field(2),
+        day: field(3),
+        hour: field(4),
+        minute: field(5),
+        second: field(6),
+        microsecond: field(7) * 1_000,
+    }
+    .to_epoch_us()
+}
+
+fn push_system_time(buffer: &mut Vec<u8>, epoch_us: u64) {
+    let date = DateTime::from_epoch_us(epoch_us);
+    for field in [
+        date.year as u32,
+        date.month,
+        date.weekday,
+        date.day,
+        date.hour,
+        date.minute,
+        date.second,
+        date.microsecond / 1_000,
+    ] {
+        buffer.extend_from_slice(&(field as u16).to_le_bytes());
+    }
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frames, read_all};
+    use std::io::Cursor;
+
+    fn read_blf(bytes: Vec<u8>) -> Vec<LogFrame> {
+        read_all(BlfReader::new(Cursor::new(bytes)).unwrap())
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut writer = BlfWriter::new(Cursor::new(Vec::new())).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(read_blf(writer.output.into_inner()), frames());
+    }
+
+    #[test]
+    fn error_classes_are_only_read_from_files_written_here() {
+        let mut writer = BlfWriter::new(Cursor::new(Vec::new())).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Files of other applications carry the id of the errored frame rather than error classes
+        let mut bytes = writer.output.into_inner();
+        bytes[8] = 1;
+        let expected: Vec<LogFrame> = frames()
+            .into_iter()
+            .map(|mut frame| {
+                if frame.kind == FrameKind::Error {
+                    frame.id = 0;
+                }
+                frame
+            })
+            .collect();
+        assert!(expected.iter().any(|frame| frame.kind == FrameKind::Error));
+        assert_eq!(read_blf(bytes), expected);
+    }
+
+    #[test]
+    fn frames_round_trip_across_containers() {
+        let mut writer = BlfWriter::new(Cursor::new(Vec::new())).unwrap();
+        for _ in 0..MAX_CONTAINER_SIZE / 64 {
+            for frame in frames() {
+                writer.write_frame(&frame).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let read = read_blf(writer.output.into_inner());
+        assert_eq!(read.len(), MAX_CONTAINER_SIZE / 64 * frames().len());
+        assert!(read.chunks(frames().len()).all(|chunk| chunk == frames()));
+    }
+
+    #[test]
+    fn object_split_across_containers() {
+        let mut writer = BlfWriter::new(Cursor::new(Vec::new())).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+
+        // Vector tools fill containers completely, splitting objects across them, so the second object is split
+        // part way through its header
+        let objects = std::mem::take(&mut writer.container);
+        let first_object_size = read_u32(&objects, 8) as usize;
+        let split = first_object_size + 20;
+        writer.container = objects[..split].to_vec();
+        writer.write_container().unwrap();
+        writer.container = objects[split..].to_vec();
+        writer.finish().unwrap();
+
+        assert_eq!(read_blf(writer.output.into_inner()), frames());
+    }
+}
@@ -0,0 +1,236 @@
+///
+/// logging/candump.rs
+///
+/// Linux can-utils `candump -l` log files, with one frame per line in the form `(timestamp) interface id#data`.
+/// Standard ids are written as 3 hex digits and extended ids as 8, remote frames as `id#R` followed by the DLC,
+/// and CAN FD frames as `id##` followed by a flags digit and the data. Error frames are written with the
+/// CAN_ERR_FLAG set in an 8 digit id. Each line ends with `R` or `T` for received or transmitted frames,
+/// matching `candump -l -x`. Lines without the direction are read as received frames, and each interface named in
+/// a log is read as its own channel, numbered in the order the interfaces first appear.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter};
+use crate::Direction;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+pub struct CandumpWriter<W: Write + Send> {
+    output: W,
+    interface: String,
+    line: String,
+}
+
+impl<W: Write + Send> CandumpWriter<W> {
+    pub fn new(output: W, interface: &str) -> Self {
+        // Interface names cannot contain whitespace in the log format
+        let interface = if interface.trim().is_empty() {
+            "can0".to_string()
+        } else {
+            interface.split_whitespace().collect::<Vec<_>>().join("_")
+        };
+
+        Self {
+            output,
+            interface,
+            line: String::new(),
+        }
+    }
+}
+
+impl<W: Write + Send> LogWriter for CandumpWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        self.line.clear();
+        format_frame(&mut self.line, &self.interface, frame);
+        self.output.write_all(self.line.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+// Writing into a String cannot fail, so the results of write! are ignored
+fn format_frame(line: &mut String, interface: &str, frame: &LogFrame) {
+    let _ = write!(
+        line,
+        "({}.{:06}) {interface} ",
+        frame.timestamp_us / 1_000_000,
+        frame.timestamp_us % 1_000_000
+    );
+
+    match frame.kind {
+        FrameKind::Error => {
+            let _ = write!(line, "{:08X}#", frame.id | CAN_ERR_FLAG);
+        }
+        _ if frame.is_extended => {
+            let _ = write!(line, "{:08X}#", frame.id);
+        }
+        _ => {
+            let _ = write!(line, "{:03X}#", frame.id);
+        }
+    }
+
+    match frame.kind {
+        FrameKind::Remote { dlc } => {
+            line.push('R');
+            if dlc > 0 {
+                let _ = write!(line, "{dlc}");
+            }
+        }
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        } => {
+            let mut flags = 0;
+            if bitrate_switch {
+                flags |= CANFD_BRS;
+            }
+            if error_state_indicator {
+                flags |= CANFD_ESI;
+            }
+            let _ = write!(line, "#{flags:X}");
+            push_data(line, &frame.data);
+        }
+        FrameKind::Data | FrameKind::Error => push_data(line, &frame.data),
+    }
+
+    line.push_str(match frame.direction {
+        Direction::Rx => " R\n",
+        Direction::Tx => " T\n",
+    });
+}
+
+fn push_data(line: &mut String, data: &[u8]) {
+    for byte in data {
+        let _ = write!(line, "{byte:02X}");
+    }
+}
+
+pub struct CandumpReader<R: BufRead + Send> {
+    input: R,
+    line: String,
+    /// The interfaces seen so far, whose positions give their channel numbers
+    interfaces: Vec<String>,
+}
+
+impl<R: BufRead + Send> CandumpReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+impl<R: BufRead + Send> LogReader for CandumpReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            let tokens: Vec<&str> = self.line.split_whitespace().collect();
+
+            match tokens.as_slice() {
+                [] => {}
+                [time, interface, frame, rest @ ..] => {
+                    let timestamp_us = time
+                        .strip_prefix('(')
+                        .and_then(|time| time.strip_suffix(')'))
+                        .and_then(parse_seconds)
+                        .ok_or_else(|| Error::Malformed(format!("bad timestamp {time:?}")))?;
+                    let direction = match rest.first() {
+                        Some(&"T") => Direction::Tx,
+                        _ => Direction::Rx,
+                    };
+                    let mut frame = parse_frame(frame)
+                        .ok_or_else(|| Error::Malformed(format!("bad frame {frame:?}")))?;
+                    frame.timestamp_us = timestamp_us;
+                    frame.channel = channel(&mut self.interfaces, interface);
+                    frame.direction = direction;
+                    return Ok(Some(frame));
+                }
+                _ => return Err(Error::Malformed(format!("bad line {:?}", self.line.trim()))),
+            }
+        }
+    }
+}
+
+// Returns the channel number of an interface, numbering interfaces in the order they are first seen
+fn channel(interfaces: &mut Vec<String>, interface: &str) -> u8 {
+    let index = match interfaces.iter().position(|name| name == interface) {
+        Some(index) => index,
+        None => {
+            interfaces.push(interface.to_string());
+            interfaces.len() - 1
+        }
+    };
+    (index + 1).min(usize::from(u8::MAX)) as u8
+}
+
+// Parses a number of seconds with an optional fraction into microseconds
+fn parse_seconds(text: &str) -> Option<u64> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut fraction_us = 0;
+    for (i, digit) in fraction.bytes().take(6).enumerate() {
+        fraction_us += u64::from(digit - b'0') * 10u64.pow(5 - i as u32);
+    }
+    Some(whole.parse::<u64>().ok()? * 1_000_000 + fraction_us)
+}
+
+// Parses a frame as in `123#DEADBEEF`, returning the frame with its timestamp, channel and direction left unset
+fn parse_frame(text: &str) -> Option<LogFrame> {
+    let (id, rest) = text.split_once('#')?;
+    // Ids of more than 3 digits are extended, as candump pads them to 8
+    let is_extended = id.len() > 3;
+    let id = u32::from_str_radix(id, 16).ok()?;
+
+    let (kind, data) = if let Some(rest) = rest.strip_prefix('#') {
+        let flags = u8::from_str_radix(rest.get(..1)?, 16).ok()?;
+        let kind = FrameKind::Fd {
+            bitrate_switch: flags & CANFD_BRS != 0,
+            error_state_indicator: flags & CANFD_ESI != 0,
+        };
+        (kind, parse_data(&rest[1..])?)
+    } else if let Some(dlc) = rest.strip_prefix(['R', 'r']) {
+        let dlc = if dlc.is_empty() { 0 } else { dlc.parse().ok()? };
+        (FrameKind::Remote { dlc }, Vec::new())
+    } else if id & CAN_ERR_FLAG != 0 {
+        (FrameKind::Error, parse_data(rest)?)
+    } else {
+        (FrameKind::Data, parse_data(rest)?)
+    };
+
+    Some(LogFrame {
+        timestamp_us: 0,
+        channel: 1,
+        id: id & CAN_EFF_MASK,
+        is_extended: is_extended && kind != FrameKind::Error,
+        kind,
+        data,
+        direction: Direction::Rx,
+    })
+}
+
+// Parses hex data, which candump may separate into bytes with dots
+fn parse_data(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|&b| b != b'.').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
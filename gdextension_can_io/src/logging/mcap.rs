@@ -0,0 +1,730 @@
+///
+/// logging/mcap.rs
+///
+/// MCAP files (.mcap), for viewing CAN traffic alongside other robot data in Foxglove and the mcap tools.
+/// Messages are JSON encoded and described by JSON schemas, which Foxglove can show and plot without any plugins.
+///
+/// Every frame is written to the "/<interface>/frames" topic with the CanFrame schema, giving its time, channel,
+/// id, flags, DLC and data. When a DBC is given, each frame of a message the DBC defines is also decoded onto a
+/// topic named after the message, "/<interface>/<Message>", whose schema has a number property for each signal
+/// holding its physical value, described by its unit. Signals that cannot be decoded from a frame are left out.
+///
+/// Records are collected into uncompressed chunks, each followed by an index of its messages. The summary written
+/// when the file is finished repeats the schemas and channels, and indexes every chunk, so readers can seek
+/// through the file without reading it all.
+///
+/// Reading a file reads the messages of every JSON encoded channel with the CanFrame schema, in the order they
+/// appear in the file, and skips every other channel. Chunks must be uncompressed.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter, fd_dlc};
+use crate::Direction;
+use crate::can_parser::CanParser;
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{ErrorKind, Read, Write};
+
+const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+// Chunks are written once their records reach this many bytes
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_CHUNK: u8 = 0x06;
+const OP_MESSAGE_INDEX: u8 = 0x07;
+const OP_CHUNK_INDEX: u8 = 0x08;
+const OP_STATISTICS: u8 = 0x0B;
+const OP_SUMMARY_OFFSET: u8 = 0x0E;
+const OP_DATA_END: u8 = 0x0F;
+
+// Appends a record, made of its opcode, the length of its content and the content
+fn push_record(buffer: &mut Vec<u8>, opcode: u8, content: &[u8]) {
+    buffer.push(opcode);
+    buffer.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(content);
+}
+
+// Appends a string or byte array, prefixed with its length
+fn push_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+// Appends a map or array whose entries have already been encoded, prefixed with their length in bytes
+fn push_entries(buffer: &mut Vec<u8>, entries: &[u8]) {
+    push_bytes(buffer, entries);
+}
+
+// The JSON schema of a time, which Foxglove recognises by its title
+fn time_schema() -> Value {
+    json!({
+        "type": "object",
+        "title": "time",
+        "properties": {
+            "sec": { "type": "integer", "minimum": 0 },
+            "nsec": { "type": "integer", "minimum": 0, "maximum": 999_999_999 },
+        },
+    })
+}
+
+fn time_value(timestamp_us: u64) -> Value {
+    json!({
+        "sec": timestamp_us / 1_000_000,
+        "nsec": timestamp_us % 1_000_000 * 1_000,
+    })
+}
+
+fn frame_schema() -> Value {
+    json!({
+        "title": "CanFrame",
+        "description": "A frame seen on the CAN bus",
+        "type": "object",
+        "properties": {
+            "timestamp": time_schema(),
+            "channel": {
+                "type": "integer",
+                "description": "The channel the frame was seen on, starting from 1",
+            },
+            "id": { "type": "integer" },
+            "is_extended": { "type": "boolean" },
+            "is_remote": { "type": "boolean" },
+            "is_error": { "type": "boolean" },
+            "is_fd": { "type": "boolean" },
+            "bitrate_switch": { "type": "boolean" },
+            "error_state_indicator": { "type": "boolean" },
+            "direction": { "type": "string", "enum": ["Rx", "Tx"] },
+            "dlc": { "type": "integer" },
+            "data": {
+                "type": "array",
+                "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+            },
+        },
+    })
+}
+
+fn frame_message(frame: &LogFrame) -> Value {
+    let (dlc, bitrate_switch, error_state_indicator) = match frame.kind {
+        FrameKind::Data | FrameKind::Error => (frame.data.len() as u8, false, false),
+        FrameKind::Remote { dlc } => (dlc, false, false),
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        } => (
+            fd_dlc(frame.data.len()),
+            bitrate_switch,
+            error_state_indicator,
+        ),
+    };
+
+    json!({
+        "timestamp": time_value(frame.timestamp_us),
+        "channel": frame.channel,
+        "id": frame.id,
+        "is_extended": frame.is_extended,
+        "is_remote": matches!(frame.kind, FrameKind::Remote { .. }),
+        "is_error": frame.kind == FrameKind::Error,
+        "is_fd": matches!(frame.kind, FrameKind::Fd { .. }),
+        "bitrate_switch": bitrate_switch,
+        "error_state_indicator": error_state_indicator,
+        "direction": match frame.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        },
+        "dlc": dlc,
+        "data": frame.data,
+    })
+}
+
+fn message_schema(message: &can_dbc::Message) -> Value {
+    let mut properties = Map::new();
+    properties.insert("timestamp".to_string(), time_schema());
+    for signal in message.signals() {
+        let mut property = json!({ "type": "number" });
+        if !signal.unit().is_empty() {
+            property["description"] = json!(signal.unit());
+        }
+        properties.insert(signal.name().clone(), property);
+    }
+
+    json!({
+        "title": message.message_name(),
+        "type": "object",
+        "properties": properties,
+    })
+}
+
+/// The records of the chunk being collected, along with an index of its messages
+#[derive(Default)]
+struct Chunk {
+    records: Vec<u8>,
+    start_ns: u64,
+    end_ns: u64,
+    /// The log time and offset within the records of each message, for each channel
+    message_indexes: BTreeMap<u16, Vec<(u64, u64)>>,
+}
+
+pub struct McapWriter<W: Write + Send> {
+    output: W,
+    position: u64,
+    topic_prefix: String,
+    can_parser: Option<CanParser>,
+    /// The content of every schema and channel record, repeated in the summary
+    schemas: Vec<Vec<u8>>,
+    channels: Vec<Vec<u8>>,
+    /// The number of messages written to each channel, indexed by its id less one
+    channel_message_counts: Vec<u64>,
+    frames_channel: Option<u16>,
+    /// The channel of each DBC message seen so far, keyed by its id and whether the id is extended
+    message_channels: HashMap<(u32, bool), u16>,
+    chunk: Chunk,
+    chunk_indexes: Vec<Vec<u8>>,
+    message_count: u64,
+    start_ns: Option<u64>,
+    end_ns: u64,
+}
+
+impl<W: Write + Send> McapWriter<W> {
+    /// Creates the writer, which also decodes the signals of the messages the CanParser's DBC defines if given
+    pub fn new(
+        mut output: W,
+        interface: &str,
+        can_parser: Option<CanParser>,
+    ) -> Result<Self, Error> {
+        let mut header = Vec::new();
+        // Files without a well known profile leave it empty
+        push_bytes(&mut header, b"");
+        push_bytes(
+            &mut header,
+            format!("CyderVis {}", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        let mut start = MAGIC.to_vec();
+        push_record(&mut start, OP_HEADER, &header);
+        output.write_all(&start)?;
+
+        Ok(Self {
+            output,
+            position: start.len() as u64,
+            topic_prefix: if interface.is_empty() {
+                "/can".to_string()
+            } else {
+                format!("/{interface}")
+            },
+            can_parser,
+            schemas: Vec::new(),
+            channels: Vec::new(),
+            channel_message_counts: Vec::new(),
+            frames_channel: None,
+            message_channels: HashMap::new(),
+            chunk: Chunk::default(),
+            chunk_indexes: Vec::new(),
+            message_count: 0,
+            start_ns: None,
+            end_ns: 0,
+        })
+    }
+
+    // Adds a channel with its own JSON schema, returning the channel's id
+    fn add_channel(&mut self, topic: &str, schema_name: &str, schema: &Value) -> u16 {
+        // Schema ids start from 1, as 0 marks a channel without a schema
+        let schema_id = self.schemas.len() as u16 + 1;
+        let mut content = schema_id.to_le_bytes().to_vec();
+        push_bytes(&mut content, schema_name.as_bytes());
+        push_bytes(&mut content, b"jsonschema");
+        push_bytes(&mut content, schema.to_string().as_bytes());
+        push_record(&mut self.chunk.records, OP_SCHEMA, &content);
+        self.schemas.push(content);
+
+        let channel_id = self.channels.len() as u16 + 1;
+        let mut content = channel_id.to_le_bytes().to_vec();
+        content.extend_from_slice(&schema_id.to_le_bytes());
+        push_bytes(&mut content, topic.as_bytes());
+        push_bytes(&mut content, b"json");
+        push_entries(&mut content, &[]);
+        push_record(&mut self.chunk.records, OP_CHANNEL, &content);
+        self.channels.push(content);
+        self.channel_message_counts.push(0);
+        channel_id
+    }
+
+    fn push_message(&mut self, channel_id: u16, timestamp_us: u64, message: &Value) {
+        let log_time_ns = timestamp_us * 1_000;
+        let message_count = &mut self.channel_message_counts[usize::from(channel_id) - 1];
+        let mut content = channel_id.to_le_bytes().to_vec();
+        // Sequence numbers count the messages of each channel, wrapping around
+        content.extend_from_slice(&(*message_count as u32).to_le_bytes());
+        // The frames are published at the time they were seen
+        content.extend_from_slice(&log_time_ns.to_le_bytes());
+        content.extend_from_slice(&log_time_ns.to_le_bytes());
+        content.extend_from_slice(message.to_string().as_bytes());
+        *message_count += 1;
+
+        let chunk = &mut self.chunk;
+        if chunk.message_indexes.is_empty() {
+            chunk.start_ns = log_time_ns;
+            chunk.end_ns = log_time_ns;
+        }
+        chunk.start_ns = chunk.start_ns.min(log_time_ns);
+        chunk.end_ns = chunk.end_ns.max(log_time_ns);
+        chunk
+            .message_indexes
+            .entry(channel_id)
+            .or_default()
+            .push((log_time_ns, chunk.records.len() as u64));
+        push_record(&mut chunk.records, OP_MESSAGE, &content);
+
+        self.message_count += 1;
+        self.start_ns = Some(
+            self.start_ns
+                .map_or(log_time_ns, |start| start.min(log_time_ns)),
+        );
+        self.end_ns = self.end_ns.max(log_time_ns);
+    }
+
+    fn push_decoded_signals(&mut self, frame: &LogFrame) {
+        let Some(can_parser) = &self.can_parser else {
+            return;
+        };
+        let Some(can_frame) = frame.to_can_frame() else {
+            return;
+        };
+        let Some(message) = can_parser.message_for_frame(&can_frame) else {
+            return;
+        };
+
+        let mut values = Map::new();
+        values.insert("timestamp".to_string(), time_value(frame.timestamp_us));
+        for signal in message.signals() {
+            if let Some(value) =
+                can_parser.physical_signal_value(message, signal.name(), &can_frame)
+            {
+                values.insert(signal.name().clone(), json!(value));
+            }
+        }
+
+        let channel_id = match self.message_channels.get(&(frame.id, frame.is_extended)) {
+            Some(&channel_id) => channel_id,
+            None => {
+                let topic = format!("{}/{}", self.topic_prefix, message.message_name());
+                let schema = message_schema(message);
+                let message_name = message.message_name().clone();
+                let channel_id = self.add_channel(&topic, &message_name, &schema);
+                self.message_channels
+                    .insert((frame.id, frame.is_extended), channel_id);
+                channel_id
+            }
+        };
+        self.push_message(channel_id, frame.timestamp_us, &Value::Object(values));
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.output.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    // Writes the chunk being collected followed by the index of its messages, and records where they were written
+    fn write_chunk(&mut self) -> Result<(), Error> {
+        let chunk = std::mem::take(&mut self.chunk);
+        if chunk.records.is_empty() {
+            return Ok(());
+        }
+
+        let records_size = chunk.records.len() as u64;
+        let mut content = Vec::with_capacity(chunk.records.len() + 40);
+        content.extend_from_slice(&chunk.start_ns.to_le_bytes());
+        content.extend_from_slice(&chunk.end_ns.to_le_bytes());
+        content.extend_from_slice(&records_size.to_le_bytes());
+        content.extend_from_slice(&crc32fast::hash(&chunk.records).to_le_bytes());
+        // The records are stored uncompressed
+        push_bytes(&mut content, b"");
+        content.extend_from_slice(&records_size.to_le_bytes());
+        content.extend_from_slice(&chunk.records);
+
+        let chunk_start = self.position;
+        let mut record = Vec::with_capacity(content.len() + 9);
+        push_record(&mut record, OP_CHUNK, &content);
+        self.write(&record)?;
+        let chunk_length = self.position - chunk_start;
+
+        let message_index_start = self.position;
+        let mut message_index_offsets = Vec::new();
+        for (channel_id, messages) in &chunk.message_indexes {
+            message_index_offsets.extend_from_slice(&channel_id.to_le_bytes());
+            message_index_offsets.extend_from_slice(&self.position.to_le_bytes());
+
+            let mut entries = Vec::with_capacity(messages.len() * 16);
+            for (log_time_ns, offset) in messages {
+                entries.extend_from_slice(&log_time_ns.to_le_bytes());
+                entries.extend_from_slice(&offset.to_le_bytes());
+            }
+            let mut content = channel_id.to_le_bytes().to_vec();
+            push_entries(&mut content, &entries);
+            let mut record = Vec::new();
+            push_record(&mut record, OP_MESSAGE_INDEX, &content);
+            self.write(&record)?;
+        }
+
+        let mut content = Vec::new();
+        content.extend_from_slice(&chunk.start_ns.to_le_bytes());
+        content.extend_from_slice(&chunk.end_ns.to_le_bytes());
+        content.extend_from_slice(&chunk_start.to_le_bytes());
+        content.extend_from_slice(&chunk_length.to_le_bytes());
+        push_entries(&mut content, &message_index_offsets);
+        content.extend_from_slice(&(self.position - message_index_start).to_le_bytes());
+        push_bytes(&mut content, b"");
+        content.extend_from_slice(&records_size.to_le_bytes());
+        content.extend_from_slice(&records_size.to_le_bytes());
+        self.chunk_indexes.push(content);
+        Ok(())
+    }
+
+    fn statistics(&self) -> Vec<u8> {
+        let mut counts = Vec::new();
+        for (index, count) in self.channel_message_counts.iter().enumerate() {
+            counts.extend_from_slice(&(index as u16 + 1).to_le_bytes());
+            counts.extend_from_slice(&count.to_le_bytes());
+        }
+
+        let mut content = Vec::new();
+        content.extend_from_slice(&self.message_count.to_le_bytes());
+        content.extend_from_slice(&(self.schemas.len() as u16).to_le_bytes());
+        content.extend_from_slice(&(self.channels.len() as u32).to_le_bytes());
+        // Attachment and metadata counts
+        content.extend_from_slice(&0u32.to_le_bytes());
+        content.extend_from_slice(&0u32.to_le_bytes());
+        content.extend_from_slice(&(self.chunk_indexes.len() as u32).to_le_bytes());
+        content.extend_from_slice(&self.start_ns.unwrap_or_default().to_le_bytes());
+        content.extend_from_slice(&self.end_ns.to_le_bytes());
+        push_entries(&mut content, &counts);
+        content
+    }
+}
+
+impl<W: Write + Send> LogWriter for McapWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let channel_id = match self.frames_channel {
+            Some(channel_id) => channel_id,
+            None => {
+                let topic = format!("{}/frames", self.topic_prefix);
+                let channel_id = self.add_channel(&topic, "CanFrame", &frame_schema());
+                *self.frames_channel.insert(channel_id)
+            }
+        };
+        self.push_message(channel_id, frame.timestamp_us, &frame_message(frame));
+        self.push_decoded_signals(frame);
+
+        if self.chunk.records.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.write_chunk()?;
+
+        // The CRC of the data section is optional, and left as zero
+        let mut data_end = Vec::new();
+        push_record(&mut data_end, OP_DATA_END, &0u32.to_le_bytes());
+        self.write(&data_end)?;
+
+        // The summary holds groups of records of the same kind, with the start and length of each group given by
+        // the summary offsets that follow it
+        let summary_start = self.position;
+        let mut summary = Vec::new();
+        let mut summary_offsets = Vec::new();
+        let statistics = vec![self.statistics()];
+        let groups = [
+            (OP_SCHEMA, &self.schemas),
+            (OP_CHANNEL, &self.channels),
+            (OP_STATISTICS, &statistics),
+            (OP_CHUNK_INDEX, &self.chunk_indexes),
+        ];
+        for (opcode, records) in groups {
+            if records.is_empty() {
+                continue;
+            }
+            let group_start = summary_start + summary.len() as u64;
+            for content in records {
+                push_record(&mut summary, opcode, content);
+            }
+            let mut content = vec![opcode];
+            content.extend_from_slice(&group_start.to_le_bytes());
+            content.extend_from_slice(
+                &(summary_start + summary.len() as u64 - group_start).to_le_bytes(),
+            );
+            push_record(&mut summary_offsets, OP_SUMMARY_OFFSET, &content);
+        }
+        let summary_offset_start = summary_start + summary.len() as u64;
+        summary.extend_from_slice(&summary_offsets);
+
+        // The footer's CRC covers the summary up to and including the footer's own offsets
+        summary.push(OP_FOOTER);
+        summary.extend_from_slice(&20u64.to_le_bytes());
+        summary.extend_from_slice(&summary_start.to_le_bytes());
+        summary.extend_from_slice(&summary_offset_start.to_le_bytes());
+        let summary_crc = crc32fast::hash(&summary);
+        summary.extend_from_slice(&summary_crc.to_le_bytes());
+        summary.extend_from_slice(MAGIC);
+        self.write(&summary)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+pub struct McapReader<R: Read + Send> {
+    input: R,
+    /// The records of the chunk being read, and the position of the next record within them
+    chunk: Vec<u8>,
+    chunk_position: usize,
+    /// The name of each schema seen so far, keyed by its id
+    schema_names: HashMap<u16, String>,
+    /// The channels holding frames
+    frame_channels: HashSet<u16>,
+    data_ended: bool,
+}
+
+impl<R: Read + Send> McapReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Malformed("not an MCAP file".to_string()));
+        }
+
+        Ok(Self {
+            input,
+            chunk: Vec::new(),
+            chunk_position: 0,
+            schema_names: HashMap::new(),
+            frame_channels: HashSet::new(),
+            data_ended: false,
+        })
+    }
+
+    // Returns the opcode and content of the next record, from the chunk being read if any records of it are left.
+    // Files that end part way through a record, as when a recording was cut short, end at the last whole record.
+    fn next_record(&mut self) -> Result<Option<(u8, Vec<u8>)>, Error> {
+        if self.chunk_position < self.chunk.len() {
+            let mut fields = Fields(&self.chunk[self.chunk_position..]);
+            let opcode = fields.bytes(1)?[0];
+            let length = fields.u64()? as usize;
+            let content = fields.bytes(length)?.to_vec();
+            self.chunk_position += 9 + length;
+            return Ok(Some((opcode, content)));
+        }
+
+        let mut header = [0; 9];
+        let mut content = Vec::new();
+        let result = self.input.read_exact(&mut header).and_then(|()| {
+            let length = u64::from_le_bytes(header[1..].try_into().expect("length is 8 bytes"));
+            content.reserve(length as usize);
+            if (&mut self.input).take(length).read_to_end(&mut content)? as u64 == length {
+                Ok(())
+            } else {
+                Err(ErrorKind::UnexpectedEof.into())
+            }
+        });
+        match result {
+            Ok(()) => Ok(Some((header[0], content))),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<R: Read + Send> LogReader for McapReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        while !self.data_ended {
+            let Some((opcode, content)) = self.next_record()? else {
+                break;
+            };
+            let mut fields = Fields(&content);
+
+            match opcode {
+                OP_SCHEMA => {
+                    let id = fields.u16()?;
+                    let name = fields.string()?;
+                    self.schema_names.insert(id, name.to_string());
+                }
+                OP_CHANNEL => {
+                    let id = fields.u16()?;
+                    let schema_id = fields.u16()?;
+                    let _topic = fields.string()?;
+                    let message_encoding = fields.string()?;
+                    let schema_name = self.schema_names.get(&schema_id).map(String::as_str);
+                    if schema_name == Some("CanFrame") && message_encoding == "json" {
+                        self.frame_channels.insert(id);
+                    }
+                }
+                OP_MESSAGE => {
+                    let channel_id = fields.u16()?;
+                    if !self.frame_channels.contains(&channel_id) {
+                        continue;
+                    }
+                    let _sequence = fields.u32()?;
+                    let log_time_ns = fields.u64()?;
+                    let _publish_time_ns = fields.u64()?;
+                    return message_to_frame(log_time_ns / 1_000, fields.0).map(Some);
+                }
+                OP_CHUNK => {
+                    let _start_ns = fields.u64()?;
+                    let _end_ns = fields.u64()?;
+                    let _records_size = fields.u64()?;
+                    let _records_crc = fields.u32()?;
+                    let compression = fields.string()?;
+                    if !compression.is_empty() {
+                        return Err(Error::Malformed(format!(
+                            "chunks compressed with {compression} cannot be read"
+                        )));
+                    }
+                    let records_length = fields.u64()? as usize;
+                    self.chunk = fields.bytes(records_length)?.to_vec();
+                    self.chunk_position = 0;
+                }
+                // The summary after the data section repeats records already read
+                OP_DATA_END | OP_FOOTER => self.data_ended = true,
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Reads the fields of a record in order
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < length {
+            return Err(Error::Malformed(
+                "record shorter than its fields".to_string(),
+            ));
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(
+            self.bytes(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(
+            self.bytes(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(
+            self.bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn string(&mut self) -> Result<&'a str, Error> {
+        let length = self.u32()? as usize;
+        std::str::from_utf8(self.bytes(length)?)
+            .map_err(|_| Error::Malformed("string is not UTF-8".to_string()))
+    }
+}
+
+// Reads a message of the CanFrame schema
+fn message_to_frame(timestamp_us: u64, message: &[u8]) -> Result<LogFrame, Error> {
+    let message: Value = serde_json::from_slice(message)
+        .map_err(|err| Error::Malformed(format!("bad CanFrame message, {err}")))?;
+    let missing = |name: &str| Error::Malformed(format!("CanFrame message without {name:?}"));
+    let flag = |name: &str| message[name].as_bool().unwrap_or(false);
+
+    let data = message["data"]
+        .as_array()
+        .ok_or_else(|| missing("data"))?
+        .iter()
+        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| Error::Malformed("CanFrame data is not bytes".to_string()))?;
+    let kind = if flag("is_error") {
+        FrameKind::Error
+    } else if flag("is_remote") {
+        FrameKind::Remote {
+            dlc: message["dlc"].as_u64().unwrap_or(0) as u8,
+        }
+    } else if flag("is_fd") {
+        FrameKind::Fd {
+            bitrate_switch: flag("bitrate_switch"),
+            error_state_indicator: flag("error_state_indicator"),
+        }
+    } else {
+        FrameKind::Data
+    };
+
+    Ok(LogFrame {
+        timestamp_us,
+        channel: message["channel"].as_u64().unwrap_or(1) as u8,
+        id: message["id"].as_u64().ok_or_else(|| missing("id"))? as u32,
+        is_extended: flag("is_extended"),
+        kind,
+        data,
+        direction: match message["direction"].as_str() {
+            Some("Tx") => Direction::Tx,
+            _ => Direction::Rx,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frames, read_all};
+
+    fn write_all(frames: &[LogFrame]) -> Vec<u8> {
+        let mut writer = McapWriter::new(Vec::new(), "can0", None).unwrap();
+        for frame in frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.output
+    }
+
+    // Repeats the frames of every kind, each repetition 10 ms after the last
+    fn repeated_frames(repetitions: u64) -> Vec<LogFrame> {
+        (0..repetitions)
+            .flat_map(|index| {
+                frames().into_iter().map(move |mut frame| {
+                    frame.timestamp_us += index * 10_000;
+                    frame
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let bytes = write_all(&frames());
+        assert_eq!(
+            read_all(McapReader::new(bytes.as_slice()).unwrap()),
+            frames()
+        );
+    }
+
+    #[test]
+    fn frames_round_trip_across_chunks() {
+        let frames = repeated_frames(5_000);
+        let bytes = write_all(&frames);
+        assert_eq!(read_all(McapReader::new(bytes.as_slice()).unwrap()), frames);
+    }
+
+    #[test]
+    fn truncated_files_end_at_the_last_whole_record() {
+        let frames = repeated_frames(5_000);
+        let bytes = write_all(&frames);
+
+        // Cutting the file part way through the data section loses the chunk that was being written
+        let read = read_all(McapReader::new(&bytes[..bytes.len() / 2]).unwrap());
+        assert!(!read.is_empty() && read.len() < frames.len());
+        assert_eq!(read, frames[..read.len()]);
+    }
+}
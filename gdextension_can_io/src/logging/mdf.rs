@@ -0,0 +1,1131 @@
+///
+/// logging/mdf.rs
+///
+/// ASAM MDF 4.1 measurement files (.mf4), as used by asammdf, CANape and other measurement tools. Frames are
+/// stored following the ASAM MDF bus logging convention, in a channel group of CAN_DataFrame records for data and
+/// CAN FD frames, and CAN_RemoteFrame and CAN_ErrorFrame groups for remote and error frames. Each record holds the
+/// time in seconds since the start of the measurement, and a structure of channels giving the bus channel, id,
+/// flags and data of the frame.
+///
+/// When a DBC is given, each frame of a message the DBC defines is also decoded into a channel group named after
+/// the message. The group has a channel for each signal holding its raw value, along with the signal's unit and a
+/// linear conversion to its physical value. Signals that cannot be decoded from a frame are marked invalid.
+///
+/// Every channel group has its own data group, whose records are written as they arrive in zlib compressed data
+/// blocks. The blocks describing the groups and channels are written when the file is finished.
+///
+/// Reading a file reads the frames of every bus event channel group following the bus logging convention, finding
+/// the members of each frame by name, and merges the groups into time order. Other channel groups are skipped.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter, fd_dlc, fd_length};
+use crate::Direction;
+use crate::can_parser::CanParser;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ID_BLOCK_SIZE: usize = 64;
+const HEADER_BLOCK_OFFSET: u64 = ID_BLOCK_SIZE as u64;
+const HEADER_BLOCK_SIZE: usize = 104;
+const BLOCK_HEADER_SIZE: usize = 24;
+// Records are compressed in blocks of up to this many bytes
+const DATA_BLOCK_SIZE: usize = 256 * 1024;
+
+const CN_TYPE_FIXED_LENGTH: u8 = 0;
+const CN_TYPE_MASTER: u8 = 2;
+const CN_SYNC_NONE: u8 = 0;
+const CN_SYNC_TIME: u8 = 1;
+const CN_FLAG_INVALIDATION_BIT: u32 = 0x02;
+const CN_FLAG_BUS_EVENT: u32 = 0x400;
+
+const DATA_TYPE_UNSIGNED: u8 = 0;
+const DATA_TYPE_REAL: u8 = 4;
+const DATA_TYPE_BYTE_ARRAY: u8 = 10;
+
+const CG_FLAG_VLSD: u16 = 0x01;
+const CG_FLAG_BUS_EVENT: u16 = 0x02;
+const CG_FLAG_PLAIN_BUS_EVENT: u16 = 0x04;
+const CG_PATH_SEPARATOR: u16 = b'.' as u16;
+
+const CC_TYPE_LINEAR: u8 = 1;
+const SI_TYPE_BUS: u8 = 2;
+const SI_BUS_TYPE_CAN: u8 = 2;
+const DZ_ZIP_TYPE_DEFLATE: u8 = 0;
+const DZ_ZIP_TYPE_TRANSPOSITION_DEFLATE: u8 = 1;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+// Offsets of the fields of the bus event records, which start with the time
+const RECORD_BUS_CHANNEL: usize = 8;
+const RECORD_ID: usize = 9;
+const RECORD_FLAGS: usize = 13;
+const RECORD_DLC: usize = 14;
+const RECORD_DATA_LENGTH: usize = 15;
+const RECORD_DATA: usize = 16;
+
+// Bits of the flags byte of the bus event records
+const FLAG_IDE: u8 = 0;
+const FLAG_DIR: u8 = 1;
+const FLAG_EDL: u8 = 2;
+const FLAG_BRS: u8 = 3;
+const FLAG_ESI: u8 = 4;
+
+struct Channel {
+    name: String,
+    channel_type: u8,
+    sync_type: u8,
+    data_type: u8,
+    byte_offset: u32,
+    bit_offset: u8,
+    bit_count: u32,
+    flags: u32,
+    invalidation_bit: u32,
+    unit: Option<String>,
+    /// Offset and factor of a linear conversion from the stored value to the physical value
+    conversion: Option<(f64, f64)>,
+    /// The channels making up a structure channel
+    members: Vec<Channel>,
+}
+
+impl Channel {
+    fn new(name: &str, data_type: u8, byte_offset: usize, bit_offset: u8, bit_count: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            channel_type: CN_TYPE_FIXED_LENGTH,
+            sync_type: CN_SYNC_NONE,
+            data_type,
+            byte_offset: byte_offset as u32,
+            bit_offset,
+            bit_count,
+            flags: 0,
+            invalidation_bit: 0,
+            unit: None,
+            conversion: None,
+            members: Vec::new(),
+        }
+    }
+
+    // The time of each record in seconds, which is the first channel of every group
+    fn time() -> Self {
+        Self {
+            channel_type: CN_TYPE_MASTER,
+            sync_type: CN_SYNC_TIME,
+            unit: Some("s".to_string()),
+            ..Self::new("t", DATA_TYPE_REAL, 0, 0, 64)
+        }
+    }
+}
+
+struct Group {
+    /// The acquisition name of the channel group
+    name: String,
+    flags: u16,
+    channels: Vec<Channel>,
+    data_bytes: usize,
+    invalidation_bytes: usize,
+    /// Records waiting to be written in the next data block
+    records: Vec<u8>,
+    record_count: u64,
+    /// The file position and uncompressed length of each data block written
+    blocks: Vec<(u64, u64)>,
+}
+
+impl Group {
+    fn new(name: &str, flags: u16, channels: Vec<Channel>, data_bytes: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            flags,
+            channels,
+            data_bytes,
+            invalidation_bytes: 0,
+            records: Vec::new(),
+            record_count: 0,
+            blocks: Vec::new(),
+        }
+    }
+
+    // A group of bus event records, with room for the given number of data bytes
+    fn bus_event(name: &str, data_capacity: usize, is_fd: bool) -> Self {
+        let member = |field: &str, data_type, byte_offset, bit_offset, bit_count| {
+            Channel::new(
+                &format!("{name}.{field}"),
+                data_type,
+                byte_offset,
+                bit_offset,
+                bit_count,
+            )
+        };
+
+        let mut members = vec![
+            member("BusChannel", DATA_TYPE_UNSIGNED, RECORD_BUS_CHANNEL, 0, 8),
+            member("ID", DATA_TYPE_UNSIGNED, RECORD_ID, 0, 29),
+            member("IDE", DATA_TYPE_UNSIGNED, RECORD_FLAGS, FLAG_IDE, 1),
+            member("Dir", DATA_TYPE_UNSIGNED, RECORD_FLAGS, FLAG_DIR, 1),
+        ];
+        if is_fd {
+            members.push(member("EDL", DATA_TYPE_UNSIGNED, RECORD_FLAGS, FLAG_EDL, 1));
+            members.push(member("BRS", DATA_TYPE_UNSIGNED, RECORD_FLAGS, FLAG_BRS, 1));
+            members.push(member("ESI", DATA_TYPE_UNSIGNED, RECORD_FLAGS, FLAG_ESI, 1));
+        }
+        members.push(member("DLC", DATA_TYPE_UNSIGNED, RECORD_DLC, 0, 4));
+        if data_capacity > 0 {
+            members.push(member(
+                "DataLength",
+                DATA_TYPE_UNSIGNED,
+                RECORD_DATA_LENGTH,
+                0,
+                8,
+            ));
+            members.push(member(
+                "DataBytes",
+                DATA_TYPE_BYTE_ARRAY,
+                RECORD_DATA,
+                0,
+                data_capacity as u32 * 8,
+            ));
+        }
+
+        let data_bytes = RECORD_DATA + data_capacity;
+        let frame = Channel {
+            flags: CN_FLAG_BUS_EVENT,
+            members,
+            ..Channel::new(
+                name,
+                DATA_TYPE_BYTE_ARRAY,
+                RECORD_BUS_CHANNEL,
+                0,
+                (data_bytes - RECORD_BUS_CHANNEL) as u32 * 8,
+            )
+        };
+
+        Self::new(
+            name,
+            CG_FLAG_BUS_EVENT | CG_FLAG_PLAIN_BUS_EVENT,
+            vec![Channel::time(), frame],
+            data_bytes,
+        )
+    }
+
+    // A group of the decoded signals of a DBC message, holding the raw value of each signal as a double
+    fn message(message: &can_dbc::Message) -> Self {
+        let mut channels = vec![Channel::time()];
+        for (index, signal) in message.signals().iter().enumerate() {
+            let has_conversion = *signal.factor() != 1.0 || *signal.offset() != 0.0;
+            channels.push(Channel {
+                flags: CN_FLAG_INVALIDATION_BIT,
+                invalidation_bit: index as u32,
+                unit: Some(signal.unit().clone()).filter(|unit| !unit.is_empty()),
+                conversion: has_conversion.then_some((*signal.offset(), *signal.factor())),
+                ..Channel::new(signal.name(), DATA_TYPE_REAL, 8 + 8 * index, 0, 64)
+            });
+        }
+
+        let signal_count = message.signals().len();
+        Self {
+            invalidation_bytes: signal_count.div_ceil(8),
+            ..Self::new(message.message_name(), 0, channels, 8 + 8 * signal_count)
+        }
+    }
+
+    fn record_size(&self) -> usize {
+        self.data_bytes + self.invalidation_bytes
+    }
+}
+
+// Writes blocks, keeping track of the position in the file
+struct BlockWriter<W: Write> {
+    output: W,
+    position: u64,
+}
+
+impl<W: Write> BlockWriter<W> {
+    // Writes a block, returning its position in the file. Blocks are padded so each starts on an 8 byte boundary.
+    fn write_block(&mut self, id: &[u8; 2], links: &[u64], data: &[u8]) -> Result<u64, Error> {
+        let length = BLOCK_HEADER_SIZE + 8 * links.len() + data.len();
+        let mut block = Vec::with_capacity(length.next_multiple_of(8));
+        block.extend_from_slice(b"##");
+        block.extend_from_slice(id);
+        block.extend_from_slice(&[0; 4]);
+        block.extend_from_slice(&(length as u64).to_le_bytes());
+        block.extend_from_slice(&(links.len() as u64).to_le_bytes());
+        for link in links {
+            block.extend_from_slice(&link.to_le_bytes());
+        }
+        block.extend_from_slice(data);
+        block.resize(length.next_multiple_of(8), 0);
+        self.output.write_all(&block)?;
+
+        let position = self.position;
+        self.position += block.len() as u64;
+        Ok(position)
+    }
+
+    // Writes a zero terminated text (TX) or XML metadata (MD) block
+    fn write_text(&mut self, id: &[u8; 2], text: &str) -> Result<u64, Error> {
+        let mut data = text.as_bytes().to_vec();
+        data.resize((data.len() + 1).next_multiple_of(8), 0);
+        self.write_block(id, &[], &data)
+    }
+
+    // Compresses the group's waiting records into a data block
+    fn write_records(&mut self, group: &mut Group) -> Result<(), Error> {
+        if group.records.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&group.records)?;
+        let compressed = encoder.finish()?;
+
+        let mut data = Vec::with_capacity(24 + compressed.len());
+        data.extend_from_slice(b"DT");
+        data.push(DZ_ZIP_TYPE_DEFLATE);
+        data.push(0);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(group.records.len() as u64).to_le_bytes());
+        data.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        let position = self.write_block(b"DZ", &[], &data)?;
+
+        group.blocks.push((position, group.records.len() as u64));
+        group.records.clear();
+        Ok(())
+    }
+
+    // Writes a data group and everything it links to, returning the position of the data group
+    fn write_group(&mut self, group: &Group, next: u64, source: u64) -> Result<u64, Error> {
+        let data_list = if group.blocks.is_empty() {
+            0
+        } else {
+            let mut links = vec![0];
+            let mut data = Vec::new();
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(&(group.blocks.len() as u32).to_le_bytes());
+            let mut offset = 0u64;
+            for (position, length) in &group.blocks {
+                links.push(*position);
+                data.extend_from_slice(&offset.to_le_bytes());
+                offset += length;
+            }
+            self.write_block(b"DL", &links, &data)?
+        };
+
+        let first_channel = self.write_channels(&group.channels)?;
+        let name = self.write_text(b"TX", &group.name)?;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&group.record_count.to_le_bytes());
+        data.extend_from_slice(&group.flags.to_le_bytes());
+        data.extend_from_slice(&CG_PATH_SEPARATOR.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(group.data_bytes as u32).to_le_bytes());
+        data.extend_from_slice(&(group.invalidation_bytes as u32).to_le_bytes());
+        let channel_group =
+            self.write_block(b"CG", &[0, first_channel, name, source, 0, 0], &data)?;
+
+        // Each data group holds one channel group, so records need no record id
+        self.write_block(b"DG", &[next, channel_group, data_list, 0], &[0; 8])
+    }
+
+    // Writes a list of channels, returning the position of the first
+    fn write_channels(&mut self, channels: &[Channel]) -> Result<u64, Error> {
+        let mut next = 0;
+        // Each channel links to the next, so the list is written from the end
+        for channel in channels.iter().rev() {
+            let composition = self.write_channels(&channel.members)?;
+            let name = self.write_text(b"TX", &channel.name)?;
+            let unit = match &channel.unit {
+                Some(unit) => self.write_text(b"TX", unit)?,
+                None => 0,
+            };
+            let conversion = match channel.conversion {
+                Some((offset, factor)) => {
+                    let mut data = vec![CC_TYPE_LINEAR, 0];
+                    data.extend_from_slice(&0u16.to_le_bytes());
+                    data.extend_from_slice(&0u16.to_le_bytes());
+                    data.extend_from_slice(&2u16.to_le_bytes());
+                    data.extend_from_slice(&[0; 16]);
+                    data.extend_from_slice(&offset.to_le_bytes());
+                    data.extend_from_slice(&factor.to_le_bytes());
+                    self.write_block(b"CC", &[0, 0, 0, 0], &data)?
+                }
+                None => 0,
+            };
+
+            let mut data = vec![
+                channel.channel_type,
+                channel.sync_type,
+                channel.data_type,
+                channel.bit_offset,
+            ];
+            data.extend_from_slice(&channel.byte_offset.to_le_bytes());
+            data.extend_from_slice(&channel.bit_count.to_le_bytes());
+            data.extend_from_slice(&channel.flags.to_le_bytes());
+            data.extend_from_slice(&channel.invalidation_bit.to_le_bytes());
+            // Precision, reserved and attachment count, followed by the unused value ranges and limits
+            data.extend_from_slice(&[0; 4 + 48]);
+            next = self.write_block(
+                b"CN",
+                &[next, composition, name, 0, conversion, 0, unit, 0],
+                &data,
+            )?;
+        }
+        Ok(next)
+    }
+}
+
+pub struct MdfWriter<W: Write + Seek + Send> {
+    output: BlockWriter<W>,
+    source_name: String,
+    can_parser: Option<CanParser>,
+    start_us: Option<u64>,
+    groups: Vec<Group>,
+    remote_frames: Option<usize>,
+    error_frames: Option<usize>,
+    /// The group of each DBC message seen so far, keyed by its id and whether the id is extended
+    message_groups: HashMap<(u32, bool), usize>,
+}
+
+// The group of data and CAN FD frames, which is always the first group
+const DATA_FRAMES: usize = 0;
+
+impl<W: Write + Seek + Send> MdfWriter<W> {
+    /// Creates the writer, which also decodes the signals of the messages the CanParser's DBC defines if given
+    pub fn new(
+        mut output: W,
+        interface: &str,
+        can_parser: Option<CanParser>,
+    ) -> Result<Self, Error> {
+        let mut id = Vec::with_capacity(ID_BLOCK_SIZE);
+        id.extend_from_slice(b"MDF     4.10    CyderVis");
+        id.extend_from_slice(&[0; 4]);
+        id.extend_from_slice(&410u16.to_le_bytes());
+        id.resize(ID_BLOCK_SIZE, 0);
+        output.write_all(&id)?;
+        // The header block is written once everything it links to is known
+        output.write_all(&[0; HEADER_BLOCK_SIZE])?;
+
+        Ok(Self {
+            output: BlockWriter {
+                output,
+                position: (ID_BLOCK_SIZE + HEADER_BLOCK_SIZE) as u64,
+            },
+            source_name: if interface.is_empty() {
+                "CAN".to_string()
+            } else {
+                interface.to_string()
+            },
+            can_parser,
+            start_us: None,
+            groups: vec![Group::bus_event("CAN_DataFrame", 64, true)],
+            remote_frames: None,
+            error_frames: None,
+            message_groups: HashMap::new(),
+        })
+    }
+
+    fn push_record(&mut self, group: usize, record: &[u8]) -> Result<(), Error> {
+        let group = &mut self.groups[group];
+        group.records.extend_from_slice(record);
+        group.record_count += 1;
+        if group.records.len() >= DATA_BLOCK_SIZE {
+            self.output.write_records(group)?;
+        }
+        Ok(())
+    }
+
+    fn push_decoded_signals(&mut self, frame: &LogFrame, time_s: f64) -> Result<(), Error> {
+        let Some(can_parser) = &self.can_parser else {
+            return Ok(());
+        };
+        let Some(can_frame) = frame.to_can_frame() else {
+            return Ok(());
+        };
+        let Some(message) = can_parser.message_for_frame(&can_frame) else {
+            return Ok(());
+        };
+
+        let group = match self.message_groups.entry((frame.id, frame.is_extended)) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                self.groups.push(Group::message(message));
+                *entry.insert(self.groups.len() - 1)
+            }
+        };
+
+        let mut record = vec![0; self.groups[group].record_size()];
+        record[..8].copy_from_slice(&time_s.to_le_bytes());
+        let invalidation_start = self.groups[group].data_bytes;
+        for (index, signal) in message.signals().iter().enumerate() {
+            match can_parser.signal_value(message, signal.name(), &can_frame) {
+                Some(value) => {
+                    record[8 + 8 * index..16 + 8 * index].copy_from_slice(&value.to_le_bytes())
+                }
+                None => record[invalidation_start + index / 8] |= 1 << (index % 8),
+            }
+        }
+        self.push_record(group, &record)
+    }
+}
+
+impl<W: Write + Seek + Send> LogWriter for MdfWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let start_us = *self.start_us.get_or_insert(frame.timestamp_us);
+        let time_s = (frame.timestamp_us as i64 - start_us as i64) as f64 / 1_000_000.0;
+
+        let (group, dlc, data_capacity, fd_flags) = match frame.kind {
+            FrameKind::Data => (DATA_FRAMES, frame.data.len().min(8) as u8, 64, 0),
+            FrameKind::Fd {
+                bitrate_switch,
+                error_state_indicator,
+            } => {
+                let fd_flags = 1 << FLAG_EDL
+                    | u8::from(bitrate_switch) << FLAG_BRS
+                    | u8::from(error_state_indicator) << FLAG_ESI;
+                (DATA_FRAMES, fd_dlc(frame.data.len()), 64, fd_flags)
+            }
+            FrameKind::Remote { dlc } => {
+                let groups = &mut self.groups;
+                let group = *self.remote_frames.get_or_insert_with(|| {
+                    groups.push(Group::bus_event("CAN_RemoteFrame", 0, false));
+                    groups.len() - 1
+                });
+                (group, dlc, 0, 0)
+            }
+            FrameKind::Error => {
+                let groups = &mut self.groups;
+                let group = *self.error_frames.get_or_insert_with(|| {
+                    groups.push(Group::bus_event("CAN_ErrorFrame", 8, false));
+                    groups.len() - 1
+                });
+                (group, frame.data.len().min(8) as u8, 8, 0)
+            }
+        };
+
+        let data = &frame.data[..frame.data.len().min(data_capacity)];
+        let mut record = vec![0; RECORD_DATA + data_capacity];
+        record[..8].copy_from_slice(&time_s.to_le_bytes());
+        record[RECORD_BUS_CHANNEL] = frame.channel;
+        record[RECORD_ID..RECORD_ID + 4].copy_from_slice(&frame.id.to_le_bytes());
+        record[RECORD_FLAGS] = u8::from(frame.is_extended) << FLAG_IDE
+            | u8::from(frame.direction == Direction::Tx) << FLAG_DIR
+            | fd_flags;
+        record[RECORD_DLC] = dlc;
+        record[RECORD_DATA_LENGTH] = data.len() as u8;
+        record[RECORD_DATA..RECORD_DATA + data.len()].copy_from_slice(data);
+        self.push_record(group, &record)?;
+
+        self.push_decoded_signals(frame, time_s)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        for group in &mut self.groups {
+            self.output.write_records(group)?;
+        }
+
+        let mut data = vec![SI_TYPE_BUS, SI_BUS_TYPE_CAN];
+        data.resize(8, 0);
+        let source_name = self.output.write_text(b"TX", &self.source_name)?;
+        let source = self
+            .output
+            .write_block(b"SI", &[source_name, 0, 0], &data)?;
+
+        // Each data group links to the next, so the list is written from the end
+        let mut first_data_group = 0;
+        for group in self.groups.iter().rev() {
+            first_data_group = self.output.write_group(group, first_data_group, source)?;
+        }
+
+        let start_ns = self.start_us.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64
+        }) * 1_000;
+
+        let history_comment = self.output.write_text(
+            b"MD",
+            &format!(
+                "<FHcomment xmlns=\"http://www.asam.net/mdf/v4\"><TX>Recorded by CyderVis</TX>\
+                 <tool_id>CyderVis</tool_id><tool_vendor>Cyborg Dynamics Engineering</tool_vendor>\
+                 <tool_version>{}</tool_version></FHcomment>",
+                env!("CARGO_PKG_VERSION")
+            ),
+        )?;
+        let mut data = start_ns.to_le_bytes().to_vec();
+        data.resize(16, 0);
+        let file_history = self
+            .output
+            .write_block(b"FH", &[0, history_comment], &data)?;
+
+        // The start time is in UTC, with no time zone offsets given
+        let mut data = start_ns.to_le_bytes().to_vec();
+        data.resize(32, 0);
+        let mut header = BlockWriter {
+            output: Vec::with_capacity(HEADER_BLOCK_SIZE),
+            position: HEADER_BLOCK_OFFSET,
+        };
+        header.write_block(b"HD", &[first_data_group, file_history, 0, 0, 0, 0], &data)?;
+
+        let output = &mut self.output.output;
+        output.seek(SeekFrom::Start(HEADER_BLOCK_OFFSET))?;
+        output.write_all(&header.output)?;
+        output.seek(SeekFrom::End(0))?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// Where a value is stored within a record
+#[derive(Debug, Clone, Copy)]
+struct BitField {
+    byte_offset: usize,
+    bit_offset: u8,
+    bit_count: u32,
+}
+
+impl BitField {
+    // Reads an unsigned little endian value of up to 64 bits
+    fn read(&self, record: &[u8]) -> Option<u64> {
+        let length = (usize::from(self.bit_offset) + self.bit_count as usize).div_ceil(8);
+        if length > 8 {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes[..length].copy_from_slice(record.get(self.byte_offset..self.byte_offset + length)?);
+        let value = u64::from_le_bytes(bytes) >> self.bit_offset;
+        Some(match self.bit_count {
+            64 => value,
+            bit_count => value & ((1 << bit_count) - 1),
+        })
+    }
+
+    fn bytes<'a>(&self, record: &'a [u8]) -> Option<&'a [u8]> {
+        record.get(self.byte_offset..self.byte_offset + self.bit_count as usize / 8)
+    }
+}
+
+// A channel as read from a file
+struct ChannelInfo {
+    name: String,
+    channel_type: u8,
+    data_type: u8,
+    field: BitField,
+    /// The position of the channel's first member, or 0 if it has none
+    composition: u64,
+}
+
+// Which kind of frame the records of a bus event channel group hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BusEvent {
+    Data,
+    Remote,
+    Error,
+}
+
+// Where the time and each member of a frame are stored within the records of a bus event channel group
+struct BusEventLayout {
+    event: BusEvent,
+    time: BitField,
+    bus_channel: Option<BitField>,
+    id: BitField,
+    ide: Option<BitField>,
+    dir: Option<BitField>,
+    edl: Option<BitField>,
+    brs: Option<BitField>,
+    esi: Option<BitField>,
+    dlc: Option<BitField>,
+    data_length: Option<BitField>,
+    data_bytes: Option<BitField>,
+}
+
+impl BusEventLayout {
+    // Reads the layout from the channels of a channel group, returning None if the group does not hold CAN frames
+    fn read<R: Read + Seek>(input: &mut R, first_channel: u64) -> Result<Option<Self>, Error> {
+        let channels = read_channels(input, first_channel)?;
+        let Some(time) = channels.iter().find(|c| c.channel_type == CN_TYPE_MASTER) else {
+            return Ok(None);
+        };
+        if time.data_type != DATA_TYPE_REAL || !matches!(time.field.bit_count, 32 | 64) {
+            return Err(Error::Malformed(format!(
+                "unsupported time channel {:?}",
+                time.name
+            )));
+        }
+        let Some(frame) = channels.iter().find(|c| c.composition != 0) else {
+            return Ok(None);
+        };
+        let event = match frame.name.as_str() {
+            "CAN_DataFrame" => BusEvent::Data,
+            "CAN_RemoteFrame" => BusEvent::Remote,
+            "CAN_ErrorFrame" => BusEvent::Error,
+            _ => return Ok(None),
+        };
+
+        // Members are named after the frame channel, as in "CAN_DataFrame.ID"
+        let members = read_channels(input, frame.composition)?;
+        let member = |name: &str| {
+            members
+                .iter()
+                .find(|member| member.name.rsplit('.').next() == Some(name))
+                .map(|member| member.field)
+        };
+        let Some(id) = member("ID") else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            event,
+            time: time.field,
+            bus_channel: member("BusChannel"),
+            id,
+            ide: member("IDE"),
+            dir: member("Dir"),
+            edl: member("EDL"),
+            brs: member("BRS"),
+            esi: member("ESI"),
+            dlc: member("DLC"),
+            data_length: member("DataLength"),
+            data_bytes: member("DataBytes"),
+        }))
+    }
+
+    // Reads the frame of a record, returning None if the record is too short to hold its members
+    fn frame(&self, record: &[u8], start_us: u64) -> Option<LogFrame> {
+        let time_bytes = self.time.bytes(record)?;
+        let time_s = match time_bytes.len() {
+            8 => f64::from_le_bytes(time_bytes.try_into().ok()?),
+            _ => f64::from(f32::from_le_bytes(time_bytes.try_into().ok()?)),
+        };
+        let read = |field: Option<BitField>| match field {
+            Some(field) => field.read(record),
+            None => Some(0),
+        };
+        let dlc = read(self.dlc)? as u8;
+        let is_fd = read(self.edl)? != 0;
+
+        let mut data = match self.data_bytes {
+            Some(field) => field.bytes(record)?.to_vec(),
+            None => Vec::new(),
+        };
+        let length = match self.data_length {
+            Some(field) => field.read(record)? as usize,
+            None if is_fd => fd_length(dlc),
+            None => usize::from(dlc.min(8)),
+        };
+        data.truncate(length);
+
+        let kind = match self.event {
+            BusEvent::Data if is_fd => FrameKind::Fd {
+                bitrate_switch: read(self.brs)? != 0,
+                error_state_indicator: read(self.esi)? != 0,
+            },
+            BusEvent::Data => FrameKind::Data,
+            BusEvent::Remote => FrameKind::Remote { dlc },
+            BusEvent::Error => FrameKind::Error,
+        };
+
+        Some(LogFrame {
+            timestamp_us: start_us.saturating_add_signed((time_s * 1_000_000.0).round() as i64),
+            channel: read(self.bus_channel)? as u8,
+            id: self.id.read(record)? as u32 & CAN_EFF_MASK,
+            is_extended: read(self.ide)? != 0,
+            kind,
+            data,
+            direction: if read(self.dir)? != 0 {
+                Direction::Tx
+            } else {
+                Direction::Rx
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RecordSize {
+    Fixed(usize),
+    /// Variable length records, each prefixed with its length
+    Variable,
+}
+
+// Reads the records of a data group, which may hold the records of several channel groups told apart by record id
+struct DataGroupReader {
+    record_id_size: usize,
+    /// The size of the records of each channel group, and the index of its layout if it holds frames
+    channel_groups: HashMap<u64, (RecordSize, Option<usize>)>,
+    layouts: Vec<BusEventLayout>,
+    /// The positions of the data blocks not read yet
+    blocks: VecDeque<u64>,
+    buffer: Vec<u8>,
+    buffer_position: usize,
+    /// The next frame of the group, read ahead so that frames of every group can be returned in time order
+    next: Option<LogFrame>,
+}
+
+impl DataGroupReader {
+    // Reads data blocks until at least the given number of bytes are waiting, returning false at the end of the data
+    fn fill<R: Read + Seek>(&mut self, input: &mut R, length: usize) -> Result<bool, Error> {
+        while self.buffer.len() - self.buffer_position < length {
+            let Some(block) = self.blocks.pop_front() else {
+                return Ok(false);
+            };
+            self.buffer.drain(..self.buffer_position);
+            self.buffer_position = 0;
+            self.buffer.extend(read_data_block(input, block)?);
+        }
+        Ok(true)
+    }
+
+    fn read_next<R: Read + Seek>(&mut self, input: &mut R, start_us: u64) -> Result<(), Error> {
+        self.next = None;
+        loop {
+            let id_size = self.record_id_size;
+            if !self.fill(input, id_size)? {
+                return Ok(());
+            }
+            let mut record_id = [0; 8];
+            record_id[..id_size].copy_from_slice(
+                &self.buffer[self.buffer_position..self.buffer_position + id_size],
+            );
+            let record_id = u64::from_le_bytes(record_id);
+            let Some(&(record_size, layout)) = self.channel_groups.get(&record_id) else {
+                return Err(Error::Malformed(format!("unknown record id {record_id}")));
+            };
+
+            let (start, length) = match record_size {
+                RecordSize::Fixed(length) => (id_size, length),
+                RecordSize::Variable => {
+                    if !self.fill(input, id_size + 4)? {
+                        return Ok(());
+                    }
+                    let position = self.buffer_position + id_size;
+                    let length = &self.buffer[position..position + 4];
+                    let length = u32::from_le_bytes(length.try_into().expect("4 bytes"));
+                    (id_size + 4, length as usize)
+                }
+            };
+            if !self.fill(input, start + length)? {
+                return Ok(());
+            }
+            let record_start = self.buffer_position + start;
+            let record = &self.buffer[record_start..record_start + length];
+            self.buffer_position = record_start + length;
+
+            if let Some(layout) = layout {
+                let frame = self.layouts[layout]
+                    .frame(record, start_us)
+                    .ok_or_else(|| Error::Malformed("record shorter than its channels".into()))?;
+                self.next = Some(frame);
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub struct MdfReader<R: Read + Seek + Send> {
+    input: R,
+    start_us: u64,
+    data_groups: Vec<DataGroupReader>,
+}
+
+impl<R: Read + Seek + Send> MdfReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Error> {
+        let mut id = [0; ID_BLOCK_SIZE];
+        input.read_exact(&mut id)?;
+        if &id[..8] != b"MDF     " {
+            return Err(Error::Malformed("not an MDF file".to_string()));
+        }
+        let version = u16::from_le_bytes([id[28], id[29]]);
+        if version < 400 {
+            return Err(Error::Malformed(format!(
+                "MDF version {version} files cannot be read"
+            )));
+        }
+
+        let (block_id, links, data) = read_block(&mut input, HEADER_BLOCK_OFFSET)?;
+        if &block_id != b"HD" || links.is_empty() || data.len() < 8 {
+            return Err(Error::Malformed("bad header block".to_string()));
+        }
+        let start_us = u64::from_le_bytes(data[..8].try_into().expect("8 bytes")) / 1_000;
+
+        let mut data_groups = Vec::new();
+        let mut data_group = links[0];
+        while data_group != 0 {
+            let (_, links, data) = read_block(&mut input, data_group)?;
+            let [next, first_channel_group, data_block, ..] = links[..] else {
+                return Err(Error::Malformed("bad data group block".to_string()));
+            };
+            let mut reader = DataGroupReader {
+                record_id_size: usize::from(*data.first().unwrap_or(&0)),
+                channel_groups: HashMap::new(),
+                layouts: Vec::new(),
+                blocks: VecDeque::new(),
+                buffer: Vec::new(),
+                buffer_position: 0,
+                next: None,
+            };
+            if !matches!(reader.record_id_size, 0 | 1 | 2 | 4 | 8) {
+                return Err(Error::Malformed("bad record id size".to_string()));
+            }
+
+            let mut channel_group = first_channel_group;
+            while channel_group != 0 {
+                let (_, links, data) = read_block(&mut input, channel_group)?;
+                if links.len() < 2 || data.len() < 32 {
+                    return Err(Error::Malformed("bad channel group block".to_string()));
+                }
+                let field = |offset: usize| {
+                    u64::from_le_bytes(data[offset..offset + 8].try_into().expect("8 bytes"))
+                };
+                let record_id = field(0);
+                let flags = u16::from_le_bytes([data[16], data[17]]);
+                let data_bytes = u32::from_le_bytes(data[24..28].try_into().expect("4 bytes"));
+                let invalidation_bytes =
+                    u32::from_le_bytes(data[28..32].try_into().expect("4 bytes"));
+
+                let record_size = if flags & CG_FLAG_VLSD != 0 {
+                    RecordSize::Variable
+                } else {
+                    RecordSize::Fixed(data_bytes as usize + invalidation_bytes as usize)
+                };
+                let layout = if flags & CG_FLAG_BUS_EVENT != 0 {
+                    BusEventLayout::read(&mut input, links[1])?
+                } else {
+                    None
+                };
+                let layout = layout.map(|layout| {
+                    reader.layouts.push(layout);
+                    reader.layouts.len() - 1
+                });
+                reader
+                    .channel_groups
+                    .insert(record_id, (record_size, layout));
+                channel_group = links[0];
+            }
+
+            // The data of groups without frames is never read
+            if !reader.layouts.is_empty() && data_block != 0 {
+                reader.blocks = data_blocks(&mut input, data_block)?.into();
+                reader.read_next(&mut input, start_us)?;
+                data_groups.push(reader);
+            }
+            data_group = next;
+        }
+
+        Ok(Self {
+            input,
+            start_us,
+            data_groups,
+        })
+    }
+}
+
+impl<R: Read + Seek + Send> LogReader for MdfReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        let earliest = self
+            .data_groups
+            .iter_mut()
+            .filter(|data_group| data_group.next.is_some())
+            .min_by_key(|data_group| data_group.next.as_ref().map(|frame| frame.timestamp_us));
+        let Some(data_group) = earliest else {
+            return Ok(None);
+        };
+
+        let frame = data_group.next.take();
+        data_group.read_next(&mut self.input, self.start_us)?;
+        Ok(frame)
+    }
+}
+
+// The id, links and data of a block
+type Block = ([u8; 2], Vec<u64>, Vec<u8>);
+
+// Reads the id and links of the block at the position, leaving the input at the start of its data and returning
+// the length of the data
+fn read_block_header<R: Read + Seek>(
+    input: &mut R,
+    position: u64,
+) -> Result<([u8; 2], Vec<u64>, u64), Error> {
+    input.seek(SeekFrom::Start(position))?;
+    let mut header = [0; BLOCK_HEADER_SIZE];
+    input.read_exact(&mut header)?;
+    if &header[..2] != b"##" {
+        return Err(Error::Malformed(format!("no block at offset {position}")));
+    }
+
+    let length = u64::from_le_bytes(header[8..16].try_into().expect("8 bytes"));
+    let link_count = u64::from_le_bytes(header[16..24].try_into().expect("8 bytes"));
+    let data_length = link_count
+        .checked_mul(8)
+        .and_then(|links_length| length.checked_sub(BLOCK_HEADER_SIZE as u64 + links_length))
+        .ok_or_else(|| Error::Malformed(format!("bad block length at offset {position}")))?;
+
+    let mut links = vec![0; link_count as usize * 8];
+    input.read_exact(&mut links)?;
+    let links = links
+        .chunks_exact(8)
+        .map(|link| u64::from_le_bytes(link.try_into().expect("8 bytes")))
+        .collect();
+    Ok(([header[2], header[3]], links, data_length))
+}
+
+fn read_block<R: Read + Seek>(input: &mut R, position: u64) -> Result<Block, Error> {
+    let (id, links, data_length) = read_block_header(input, position)?;
+    let mut data = Vec::new();
+    if input.take(data_length).read_to_end(&mut data)? as u64 != data_length {
+        return Err(Error::Malformed(format!(
+            "block at offset {position} cut short"
+        )));
+    }
+    Ok((id, links, data))
+}
+
+// Reads the zero terminated text of a TX or MD block, which is empty if there is no block
+fn read_text<R: Read + Seek>(input: &mut R, position: u64) -> Result<String, Error> {
+    if position == 0 {
+        return Ok(String::new());
+    }
+    let (_, _, data) = read_block(input, position)?;
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+// Reads a list of channels, starting from the first
+fn read_channels<R: Read + Seek>(input: &mut R, first: u64) -> Result<Vec<ChannelInfo>, Error> {
+    let mut channels = Vec::new();
+    let mut channel = first;
+    while channel != 0 {
+        let (_, links, data) = read_block(input, channel)?;
+        if links.len() < 3 || data.len() < 16 {
+            return Err(Error::Malformed("bad channel block".to_string()));
+        }
+        channels.push(ChannelInfo {
+            name: read_text(input, links[2])?,
+            channel_type: data[0],
+            data_type: data[2],
+            field: BitField {
+                byte_offset: u32::from_le_bytes(data[4..8].try_into().expect("4 bytes")) as usize,
+                bit_offset: data[3],
+                bit_count: u32::from_le_bytes(data[8..12].try_into().expect("4 bytes")),
+            },
+            composition: links[1],
+        });
+        channel = links[0];
+    }
+    Ok(channels)
+}
+
+// Returns the positions of the data blocks of a data group, in order, following any lists of blocks
+fn data_blocks<R: Read + Seek>(input: &mut R, position: u64) -> Result<Vec<u64>, Error> {
+    let (id, links, _) = read_block_header(input, position)?;
+    match &id {
+        b"DT" | b"DZ" => Ok(vec![position]),
+        // Header lists link to a list of compressed blocks
+        b"HL" => data_blocks(input, *links.first().unwrap_or(&0)),
+        b"DL" => {
+            let mut blocks = Vec::new();
+            let mut list = Some(links);
+            while let Some(links) = list.take() {
+                let Some((&next, data_blocks)) = links.split_first() else {
+                    break;
+                };
+                blocks.extend(data_blocks.iter().copied().filter(|&block| block != 0));
+                if next != 0 {
+                    list = Some(read_block_header(input, next)?.1);
+                }
+            }
+            Ok(blocks)
+        }
+        _ => Err(Error::Malformed(format!(
+            "unexpected {} block holding data",
+            String::from_utf8_lossy(&id)
+        ))),
+    }
+}
+
+// Reads the records held by a DT or DZ block
+fn read_data_block<R: Read + Seek>(input: &mut R, position: u64) -> Result<Vec<u8>, Error> {
+    let (id, _, data) = read_block(input, position)?;
+    if &id == b"DT" {
+        return Ok(data);
+    }
+    if &id != b"DZ" || data.len() < 24 {
+        return Err(Error::Malformed(format!(
+            "no data block at offset {position}"
+        )));
+    }
+
+    let zip_type = data[2];
+    let zip_parameter = u32::from_le_bytes(data[4..8].try_into().expect("4 bytes")) as usize;
+    let original_length = u64::from_le_bytes(data[8..16].try_into().expect("8 bytes"));
+    let compressed_length = u64::from_le_bytes(data[16..24].try_into().expect("8 bytes"));
+    let compressed = data
+        .get(24..24 + compressed_length as usize)
+        .ok_or_else(|| Error::Malformed(format!("data block at offset {position} cut short")))?;
+
+    let mut records = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(original_length)
+        .read_to_end(&mut records)?;
+    match zip_type {
+        DZ_ZIP_TYPE_DEFLATE => Ok(records),
+        // The records were transposed into columns of zip_parameter bytes before compression, leaving any bytes
+        // that do not fill a whole row at the end
+        DZ_ZIP_TYPE_TRANSPOSITION_DEFLATE if zip_parameter > 0 => {
+            let rows = records.len() / zip_parameter;
+            let mut transposed = records.clone();
+            for column in 0..zip_parameter {
+                for row in 0..rows {
+                    transposed[row * zip_parameter + column] = records[column * rows + row];
+                }
+            }
+            Ok(transposed)
+        }
+        _ => Err(Error::Malformed(format!(
+            "unsupported compression {zip_type}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frames, read_all};
+    use std::io::Cursor;
+
+    fn write_all(frames: &[LogFrame]) -> Vec<u8> {
+        let mut writer = MdfWriter::new(Cursor::new(Vec::new()), "can0", None).unwrap();
+        for frame in frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.output.output.into_inner()
+    }
+
+    fn read_mdf(bytes: Vec<u8>) -> Vec<LogFrame> {
+        read_all(MdfReader::new(Cursor::new(bytes)).unwrap())
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        assert_eq!(read_mdf(write_all(&frames())), frames());
+    }
+
+    #[test]
+    fn frames_round_trip_across_data_blocks() {
+        // Enough frames to fill several compressed data blocks, read back in time order across the groups
+        let frames: Vec<LogFrame> = (0..20_000u64)
+            .flat_map(|index| {
+                frames().into_iter().map(move |mut frame| {
+                    frame.timestamp_us += index * 10_000;
+                    frame
+                })
+            })
+            .collect();
+        assert_eq!(read_mdf(write_all(&frames)), frames);
+    }
+}
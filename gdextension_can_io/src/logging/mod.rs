@@ -0,0 +1,528 @@
+///
+/// logging/mod.rs
+///
+/// Log file formats for recording and exchanging CAN traffic.
+/// Every format reads and writes LogFrames, a format independent frame that can also describe the remote, error
+/// and CAN FD frames that log files may contain.
+///
+mod asc;
+mod blf;
+mod candump;
+mod mcap;
+mod mdf;
+mod parquet;
+mod pcap;
+mod sqlite;
+mod trc;
+
+use crate::Direction;
+use crate::can_parser::CanParser;
+use crosscan::can::CanFrame;
+pub use sqlite::query_database;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    UnsupportedFormat(String),
+    Malformed(String),
+    Database(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::UnsupportedFormat(path) => write!(f, "Unsupported log file format for {path:?}"),
+            Error::Malformed(msg) => write!(f, "Malformed log file, {msg}"),
+            Error::Database(msg) => write!(f, "Database error, {msg}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Data,
+    /// A remote transmission request, which carries a DLC but no data
+    Remote {
+        dlc: u8,
+    },
+    /// An error frame, where the id holds the error class bits and the data holds the error details
+    Error,
+    Fd {
+        bitrate_switch: bool,
+        error_state_indicator: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFrame {
+    /// Microseconds since the UNIX epoch
+    pub timestamp_us: u64,
+    /// The channel the frame was seen on, starting from 1
+    pub channel: u8,
+    pub id: u32,
+    pub is_extended: bool,
+    pub kind: FrameKind,
+    pub data: Vec<u8>,
+    pub direction: Direction,
+}
+
+impl LogFrame {
+    /// Creates a frame of the given kind from a CanFrame. Error frames are carried in a CanFrame with the error
+    /// class bits in place of the id.
+    pub fn from_can_frame(
+        frame: &CanFrame,
+        kind: FrameKind,
+        direction: Direction,
+        timestamp_us: u64,
+    ) -> Self {
+        Self {
+            timestamp_us,
+            channel: 1,
+            id: frame.id(),
+            is_extended: frame.is_extended() && kind != FrameKind::Error,
+            kind,
+            data: frame.data().to_vec(),
+            direction,
+        }
+    }
+
+    /// Converts the frame into a CanFrame, returning None for remote, error and CAN FD frames which a CanFrame
+    /// cannot represent
+    pub fn to_can_frame(&self) -> Option<CanFrame> {
+        match self.kind {
+            FrameKind::Data => crate::new_can_frame(self.id, self.is_extended, &self.data),
+            _ => None,
+        }
+    }
+}
+
+pub trait LogWriter: Send {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error>;
+
+    /// Marks a point in the log with a note, in formats that can hold one. Other formats ignore markers.
+    fn write_marker(&mut self, _timestamp_us: u64, _text: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when nothing has been recorded for a while, so that formats writing in batches can make everything
+    /// written so far visible to readers of the file
+    fn idle(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Flushes everything written so far, after which no more frames may be written
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+pub trait LogReader: Send {
+    /// Returns the next frame of the log, or None once the end of the log has been reached
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error>;
+}
+
+/// The log file formats that traffic can be recorded to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Linux can-utils `candump -l` log files
+    Candump,
+    /// Vector ASCII log files, as used by CANalyzer and CANoe
+    Asc,
+    /// Vector binary log files
+    Blf,
+    /// PEAK PCAN-View trace files
+    Trc,
+    /// Wireshark pcap capture files
+    Pcap,
+    /// Wireshark pcapng capture files
+    Pcapng,
+    /// ASAM MDF 4 measurement files
+    Mdf,
+    /// Apache Parquet files
+    Parquet,
+    /// MCAP files, as used by Foxglove
+    Mcap,
+    /// SQLite capture databases
+    Sqlite,
+}
+
+impl LogFormat {
+    /// Selects the format from the extension of a file path
+    pub fn from_path(path: &str) -> Result<Self, Error> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("log") => Ok(LogFormat::Candump),
+            Some("asc") => Ok(LogFormat::Asc),
+            Some("blf") => Ok(LogFormat::Blf),
+            Some("trc") => Ok(LogFormat::Trc),
+            Some("pcap") => Ok(LogFormat::Pcap),
+            Some("pcapng") => Ok(LogFormat::Pcapng),
+            Some("mf4") => Ok(LogFormat::Mdf),
+            Some("parquet") => Ok(LogFormat::Parquet),
+            Some("mcap") => Ok(LogFormat::Mcap),
+            Some("db" | "sqlite") => Ok(LogFormat::Sqlite),
+            _ => Err(Error::UnsupportedFormat(path.to_string())),
+        }
+    }
+
+    /// Creates a new log file at the path, replacing any existing file, except for capture databases which are
+    /// opened and given a new session. The interface name is recorded in formats that name the interface each
+    /// frame was seen on. If a CanParser is given, formats that can hold decoded signals also store the signals of
+    /// each frame its DBC defines.
+    pub fn create_writer(
+        self,
+        path: &str,
+        interface: &str,
+        signals: Option<&CanParser>,
+    ) -> Result<Box<dyn LogWriter>, Error> {
+        let file = || File::create(path).map(BufWriter::new);
+        match self {
+            LogFormat::Candump => Ok(Box::new(candump::CandumpWriter::new(file()?, interface))),
+            LogFormat::Asc => Ok(Box::new(asc::AscWriter::new(file()?))),
+            LogFormat::Blf => Ok(Box::new(blf::BlfWriter::new(file()?)?)),
+            LogFormat::Trc => Ok(Box::new(trc::TrcWriter::new(file()?))),
+            LogFormat::Pcap => Ok(Box::new(pcap::PcapWriter::new(file()?)?)),
+            LogFormat::Pcapng => Ok(Box::new(pcap::PcapngWriter::new(file()?, interface)?)),
+            LogFormat::Mdf => Ok(Box::new(mdf::MdfWriter::new(
+                file()?,
+                interface,
+                signals.cloned(),
+            )?)),
+            LogFormat::Parquet => {
+                let signals = match signals {
+                    Some(can_parser) => Some((
+                        BufWriter::new(File::create(signals_path(path))?),
+                        can_parser.clone(),
+                    )),
+                    None => None,
+                };
+                Ok(Box::new(parquet::ParquetWriter::new(file()?, signals)?))
+            }
+            LogFormat::Mcap => Ok(Box::new(mcap::McapWriter::new(
+                file()?,
+                interface,
+                signals.cloned(),
+            )?)),
+            LogFormat::Sqlite => Ok(Box::new(sqlite::SqliteWriter::open(
+                path,
+                interface,
+                signals.cloned(),
+            )?)),
+        }
+    }
+
+    /// Opens a log file to read its frames one at a time, in the order they appear in the file. Formats that keep
+    /// frames of different kinds apart, such as MDF, are read in time order instead.
+    pub fn open_reader(self, path: &str) -> Result<Box<dyn LogReader>, Error> {
+        let file = || File::open(path).map(BufReader::new);
+        match self {
+            LogFormat::Candump => Ok(Box::new(candump::CandumpReader::new(file()?))),
+            LogFormat::Asc => Ok(Box::new(asc::AscReader::new(file()?))),
+            LogFormat::Blf => Ok(Box::new(blf::BlfReader::new(file()?)?)),
+            LogFormat::Trc => Ok(Box::new(trc::TrcReader::new(file()?))),
+            LogFormat::Pcap => Ok(Box::new(pcap::PcapReader::new(file()?)?)),
+            LogFormat::Pcapng => Ok(Box::new(pcap::PcapngReader::new(file()?))),
+            LogFormat::Mdf => Ok(Box::new(mdf::MdfReader::new(file()?)?)),
+            LogFormat::Parquet => Ok(Box::new(parquet::ParquetReader::new(File::open(path)?)?)),
+            LogFormat::Mcap => Ok(Box::new(mcap::McapReader::new(file()?)?)),
+            LogFormat::Sqlite => Ok(Box::new(sqlite::SqliteReader::open(path)?)),
+        }
+    }
+}
+
+// Returns the path of the file holding the decoded signals of a log file, for formats that store them separately
+// from the frames. The signals of "capture.parquet" are written to "capture_signals.parquet".
+fn signals_path(path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}_signals.{}", extension.to_string_lossy()),
+        None => format!("{stem}_signals"),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Returns the DLC of a CAN FD frame with the given data length, rounding up to the next valid length
+fn fd_dlc(length: usize) -> u8 {
+    match length {
+        0..=8 => length as u8,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+// Returns the data length of a CAN FD frame with the given DLC
+fn fd_length(dlc: u8) -> usize {
+    match dlc {
+        0..=8 => usize::from(dlc),
+        9 => 12,
+        10 => 16,
+        11 => 20,
+        12 => 24,
+        13 => 32,
+        14 => 48,
+        _ => 64,
+    }
+}
+
+/// Returns the time since the UNIX epoch in microseconds at which start_time occurred, used to convert
+/// timestamps measured from start_time into absolute times
+pub fn epoch_offset_us(start_time: Instant) -> u64 {
+    let now_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    now_us.saturating_sub(start_time.elapsed().as_micros() as u64)
+}
+
+/// A UTC calendar date and time, used by formats that store wall clock times
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+    /// 0 (Sunday) to 6 (Saturday)
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub microsecond: u32,
+}
+
+impl DateTime {
+    pub fn from_epoch_us(epoch_us: u64) -> Self {
+        let days = (epoch_us / 86_400_000_000) as i64;
+        let time_of_day_us = epoch_us % 86_400_000_000;
+
+        // Converts days since the epoch into a date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 4).rem_euclid(7)) as u32,
+            hour: (time_of_day_us / 3_600_000_000) as u32,
+            minute: (time_of_day_us / 60_000_000 % 60) as u32,
+            second: (time_of_day_us / 1_000_000 % 60) as u32,
+            microsecond: (time_of_day_us % 1_000_000) as u32,
+        }
+    }
+
+    /// Returns the microseconds since the UNIX epoch, or None if the date is before the epoch or invalid.
+    /// The weekday is ignored.
+    pub fn to_epoch_us(self) -> Option<u64> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+        {
+            return None;
+        }
+
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month_index = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * month_index + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        let seconds = days * 86_400
+            + i64::from(self.hour) * 3_600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        u64::try_from(seconds)
+            .ok()
+            .map(|seconds| seconds * 1_000_000 + u64::from(self.microsecond))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_frames {
+    use super::*;
+
+    pub(crate) fn frame(
+        id: u32,
+        is_extended: bool,
+        kind: FrameKind,
+        data: &[u8],
+        direction: Direction,
+    ) -> LogFrame {
+        LogFrame {
+            timestamp_us: 0,
+            channel: 1,
+            id,
+            is_extended,
+            kind,
+            data: data.to_vec(),
+            direction,
+        }
+    }
+
+    /// One frame of every kind, 1.5 ms apart, alternating between channels 1 and 2
+    pub(crate) fn frames() -> Vec<LogFrame> {
+        let mut frames = vec![
+            frame(0x123, false, FrameKind::Data, &[1, 2, 3], Direction::Rx),
+            frame(
+                0x1ABC_DEF0,
+                true,
+                FrameKind::Data,
+                &[0xFF; 8],
+                Direction::Tx,
+            ),
+            frame(
+                0x7FF,
+                false,
+                FrameKind::Remote { dlc: 4 },
+                &[],
+                Direction::Rx,
+            ),
+            frame(
+                0x18DA_F110,
+                true,
+                FrameKind::Remote { dlc: 8 },
+                &[],
+                Direction::Tx,
+            ),
+            // Controller problem (CAN_ERR_CRTL) with the receive error passive details
+            frame(
+                0x04,
+                false,
+                FrameKind::Error,
+                &[0, 0x10, 0, 0, 0, 0, 0, 0],
+                Direction::Rx,
+            ),
+            frame(
+                0x0CF0_0400,
+                true,
+                FrameKind::Fd {
+                    bitrate_switch: true,
+                    error_state_indicator: true,
+                },
+                &(0..64).collect::<Vec<u8>>(),
+                Direction::Rx,
+            ),
+            frame(
+                0x456,
+                false,
+                FrameKind::Fd {
+                    bitrate_switch: false,
+                    error_state_indicator: false,
+                },
+                &[0xA5; 12],
+                Direction::Tx,
+            ),
+        ];
+        for (index, frame) in frames.iter_mut().enumerate() {
+            frame.timestamp_us = 1_700_000_000_123_456 + index as u64 * 1_500;
+            frame.channel = 1 + (index % 2) as u8;
+        }
+        frames
+    }
+
+    /// A path in the temporary directory for formats that are read from a file, unique to the test process
+    pub(crate) fn temp_path(file_name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("cydervis_{}_{file_name}", std::process::id()))
+    }
+
+    /// Reads frames until the end of the log, panicking on any error
+    pub(crate) fn read_all(mut reader: impl LogReader) -> Vec<LogFrame> {
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i64, month: u32, day: u32, weekday: u32) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            weekday,
+            hour: 12,
+            minute: 34,
+            second: 56,
+            microsecond: 789_012,
+        }
+    }
+
+    #[test]
+    fn date_time_round_trips_around_leap_days() {
+        // 2000 is a leap year as it is divisible by 400, while 2100 is not as it is only divisible by 100
+        let dates = [
+            (date(1972, 2, 29, 2), 68_169_600),
+            (date(2000, 2, 29, 2), 951_782_400),
+            (date(2000, 3, 1, 3), 951_868_800),
+            (date(2024, 2, 29, 4), 1_709_164_800),
+            (date(2100, 2, 28, 0), 4_107_456_000),
+            (date(2100, 3, 1, 1), 4_107_542_400),
+        ];
+
+        for (date, midnight_s) in dates {
+            let epoch_us = (midnight_s + 45_296) * 1_000_000 + 789_012;
+            assert_eq!(date.to_epoch_us(), Some(epoch_us), "{date:?}");
+            assert_eq!(DateTime::from_epoch_us(epoch_us), date);
+        }
+    }
+
+    #[test]
+    fn date_time_starts_at_the_epoch() {
+        let epoch = DateTime::from_epoch_us(0);
+        assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+        assert_eq!(epoch.weekday, 4);
+        assert_eq!(epoch.to_epoch_us(), Some(0));
+    }
+
+    #[test]
+    fn date_time_rejects_invalid_dates() {
+        assert_eq!(date(1969, 12, 31, 3).to_epoch_us(), None);
+        assert_eq!(date(2024, 13, 1, 0).to_epoch_us(), None);
+        assert_eq!(date(2024, 2, 0, 0).to_epoch_us(), None);
+    }
+}
@@ -0,0 +1,558 @@
+///
+/// logging/parquet.rs
+///
+/// Apache Parquet files (.parquet), for analysing large captures with pandas, polars, DuckDB or Spark. Frames are
+/// written to a table with a row for each frame, giving its time, channel, id, flags, DLC and data.
+///
+/// When a DBC is given, a second file named after the first with a "_signals" suffix holds a wide table of decoded
+/// signals. It has a row for each frame of a message the DBC defines, and a column for every signal of the DBC
+/// named "Message.Signal", holding the signal's physical value. Signals not carried by a frame, or that cannot be
+/// decoded from it, are null. The unit of each signal is stored in the file's key-value metadata as
+/// "unit:Message.Signal".
+///
+/// Rows are buffered and written in row groups as they arrive, so recordings of any length can be written without
+/// holding them in memory. Reading a file reads the frames table, looking its columns up by name, so tables written
+/// by other tools can also be read as long as they have at least the timestamp, id and data columns.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter, fd_dlc};
+use crate::Direction;
+use crate::can_parser::CanParser;
+use parquet::basic::{Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{
+    BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int32Type, Int64Type,
+};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::SerializedFileReader;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::format::MicroSeconds;
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
+use parquet::schema::types::{Type, TypePtr};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+// Rows are written in row groups of this many rows
+const ROW_GROUP_ROWS: usize = 100_000;
+
+impl From<ParquetError> for Error {
+    fn from(err: ParquetError) -> Self {
+        match err {
+            ParquetError::External(err) => match err.downcast::<std::io::Error>() {
+                Ok(err) => Error::Io(*err),
+                Err(err) => Error::Io(std::io::Error::other(err)),
+            },
+            err => Error::Io(std::io::Error::other(err)),
+        }
+    }
+}
+
+fn column(name: &str, physical_type: PhysicalType, logical_type: Option<LogicalType>) -> TypePtr {
+    column_with_repetition(name, physical_type, logical_type, Repetition::REQUIRED)
+}
+
+fn column_with_repetition(
+    name: &str,
+    physical_type: PhysicalType,
+    logical_type: Option<LogicalType>,
+    repetition: Repetition,
+) -> TypePtr {
+    Arc::new(
+        Type::primitive_type_builder(name, physical_type)
+            .with_repetition(repetition)
+            .with_logical_type(logical_type)
+            .build()
+            .expect("column types are valid"),
+    )
+}
+
+fn timestamp_column() -> TypePtr {
+    column(
+        "timestamp",
+        PhysicalType::INT64,
+        Some(LogicalType::Timestamp {
+            is_adjusted_to_u_t_c: true,
+            unit: TimeUnit::MICROS(MicroSeconds {}),
+        }),
+    )
+}
+
+fn unsigned_column(name: &str, bit_width: i8) -> TypePtr {
+    column(
+        name,
+        PhysicalType::INT32,
+        Some(LogicalType::Integer {
+            bit_width,
+            is_signed: false,
+        }),
+    )
+}
+
+fn create_file<W: Write + Send>(
+    output: W,
+    name: &str,
+    columns: Vec<TypePtr>,
+    metadata: Vec<KeyValue>,
+) -> Result<SerializedFileWriter<W>, Error> {
+    let schema = Type::group_type_builder(name)
+        .with_fields(columns)
+        .build()?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_created_by(format!("CyderVis {}", env!("CARGO_PKG_VERSION")))
+        .set_key_value_metadata(Some(metadata).filter(|metadata| !metadata.is_empty()))
+        .build();
+    Ok(SerializedFileWriter::new(
+        output,
+        Arc::new(schema),
+        Arc::new(properties),
+    )?)
+}
+
+// Writes the values of the next column of a row group, with definition levels for optional columns
+fn write_column<T: DataType, W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+    definition_levels: Option<&[i16]>,
+) -> Result<(), Error> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("more columns written than in the schema".into()))?;
+    column
+        .typed::<T>()
+        .write_batch(values, definition_levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+/// The rows of the frames table waiting to be written in the next row group, a column at a time
+#[derive(Default)]
+struct FrameRows {
+    timestamps: Vec<i64>,
+    channels: Vec<i32>,
+    ids: Vec<i32>,
+    is_extended: Vec<bool>,
+    is_remote: Vec<bool>,
+    is_error: Vec<bool>,
+    is_fd: Vec<bool>,
+    bitrate_switch: Vec<bool>,
+    error_state_indicator: Vec<bool>,
+    directions: Vec<ByteArray>,
+    dlcs: Vec<i32>,
+    data: Vec<ByteArray>,
+}
+
+impl FrameRows {
+    fn schema() -> Vec<TypePtr> {
+        vec![
+            timestamp_column(),
+            unsigned_column("channel", 8),
+            unsigned_column("id", 32),
+            column("is_extended", PhysicalType::BOOLEAN, None),
+            column("is_remote", PhysicalType::BOOLEAN, None),
+            column("is_error", PhysicalType::BOOLEAN, None),
+            column("is_fd", PhysicalType::BOOLEAN, None),
+            column("bitrate_switch", PhysicalType::BOOLEAN, None),
+            column("error_state_indicator", PhysicalType::BOOLEAN, None),
+            column(
+                "direction",
+                PhysicalType::BYTE_ARRAY,
+                Some(LogicalType::String),
+            ),
+            unsigned_column("dlc", 8),
+            column("data", PhysicalType::BYTE_ARRAY, None),
+        ]
+    }
+
+    fn push(&mut self, frame: &LogFrame) {
+        let (dlc, bitrate_switch, error_state_indicator) = match frame.kind {
+            FrameKind::Data | FrameKind::Error => (frame.data.len() as u8, false, false),
+            FrameKind::Remote { dlc } => (dlc, false, false),
+            FrameKind::Fd {
+                bitrate_switch,
+                error_state_indicator,
+            } => (
+                fd_dlc(frame.data.len()),
+                bitrate_switch,
+                error_state_indicator,
+            ),
+        };
+
+        self.timestamps.push(frame.timestamp_us as i64);
+        self.channels.push(i32::from(frame.channel));
+        // Unsigned 32 bit columns are stored in the bits of signed integers
+        self.ids.push(frame.id as i32);
+        self.is_extended.push(frame.is_extended);
+        self.is_remote
+            .push(matches!(frame.kind, FrameKind::Remote { .. }));
+        self.is_error.push(frame.kind == FrameKind::Error);
+        self.is_fd.push(matches!(frame.kind, FrameKind::Fd { .. }));
+        self.bitrate_switch.push(bitrate_switch);
+        self.error_state_indicator.push(error_state_indicator);
+        self.directions.push(ByteArray::from(match frame.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        }));
+        self.dlcs.push(i32::from(dlc));
+        self.data.push(ByteArray::from(frame.data.clone()));
+    }
+
+    fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    fn write<W: Write + Send>(&mut self, file: &mut SerializedFileWriter<W>) -> Result<(), Error> {
+        if self.len() == 0 {
+            return Ok(());
+        }
+
+        let mut row_group = file.next_row_group()?;
+        write_column::<Int64Type, _>(&mut row_group, &self.timestamps, None)?;
+        write_column::<Int32Type, _>(&mut row_group, &self.channels, None)?;
+        write_column::<Int32Type, _>(&mut row_group, &self.ids, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.is_extended, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.is_remote, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.is_error, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.is_fd, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.bitrate_switch, None)?;
+        write_column::<BoolType, _>(&mut row_group, &self.error_state_indicator, None)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &self.directions, None)?;
+        write_column::<Int32Type, _>(&mut row_group, &self.dlcs, None)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &self.data, None)?;
+        row_group.close()?;
+
+        *self = Self::default();
+        Ok(())
+    }
+}
+
+/// The wide table of decoded signals, with a column for every signal of the DBC
+struct SignalTable<W: Write + Send> {
+    file: SerializedFileWriter<W>,
+    can_parser: CanParser,
+    /// The first column of each message's signals, which follow one another in the order the DBC lists them
+    message_columns: HashMap<String, usize>,
+    timestamps: Vec<i64>,
+    /// The values of each column in the rows waiting to be written, as the row and value. Columns only hold the
+    /// rows they have a value for, as most signals are null in most rows.
+    columns: Vec<Vec<(usize, f64)>>,
+}
+
+impl<W: Write + Send> SignalTable<W> {
+    fn new(output: W, can_parser: CanParser) -> Result<Self, Error> {
+        let mut schema = vec![timestamp_column()];
+        let mut metadata = Vec::new();
+        let mut message_columns = HashMap::new();
+        if let Some(dbc) = can_parser.dbc() {
+            for message in dbc.messages() {
+                message_columns.insert(message.message_name().clone(), schema.len() - 1);
+                for signal in message.signals() {
+                    let name = format!("{}.{}", message.message_name(), signal.name());
+                    if !signal.unit().is_empty() {
+                        metadata.push(KeyValue::new(format!("unit:{name}"), signal.unit().clone()));
+                    }
+                    schema.push(column_with_repetition(
+                        &name,
+                        PhysicalType::DOUBLE,
+                        None,
+                        Repetition::OPTIONAL,
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            file: create_file(output, "signals", schema.clone(), metadata)?,
+            can_parser,
+            message_columns,
+            timestamps: Vec::new(),
+            columns: vec![Vec::new(); schema.len() - 1],
+        })
+    }
+
+    fn push(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let Some(can_frame) = frame.to_can_frame() else {
+            return Ok(());
+        };
+        let Some(message) = self.can_parser.message_for_frame(&can_frame) else {
+            return Ok(());
+        };
+        let Some(&first_column) = self.message_columns.get(message.message_name()) else {
+            return Ok(());
+        };
+
+        let row = self.timestamps.len();
+        self.timestamps.push(frame.timestamp_us as i64);
+        for (index, signal) in message.signals().iter().enumerate() {
+            if let Some(value) =
+                self.can_parser
+                    .physical_signal_value(message, signal.name(), &can_frame)
+            {
+                self.columns[first_column + index].push((row, value));
+            }
+        }
+
+        if self.timestamps.len() >= ROW_GROUP_ROWS {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        let rows = self.timestamps.len();
+        if rows == 0 {
+            return Ok(());
+        }
+
+        let mut row_group = self.file.next_row_group()?;
+        write_column::<Int64Type, _>(&mut row_group, &self.timestamps, None)?;
+        for column in &mut self.columns {
+            // A definition level of 1 marks the rows holding a value, and 0 the null rows
+            let mut definition_levels = vec![0; rows];
+            let mut values = Vec::with_capacity(column.len());
+            for &(row, value) in column.iter() {
+                definition_levels[row] = 1;
+                values.push(value);
+            }
+            write_column::<DoubleType, _>(&mut row_group, &values, Some(&definition_levels))?;
+            column.clear();
+        }
+        row_group.close()?;
+
+        self.timestamps.clear();
+        Ok(())
+    }
+}
+
+pub struct ParquetWriter<W: Write + Send> {
+    file: SerializedFileWriter<W>,
+    rows: FrameRows,
+    signals: Option<SignalTable<W>>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    /// Creates the writer. If a second output and CanParser are given, the signals of the messages the
+    /// CanParser's DBC defines are decoded into a table written to the second output.
+    pub fn new(output: W, signals: Option<(W, CanParser)>) -> Result<Self, Error> {
+        Ok(Self {
+            file: create_file(output, "frames", FrameRows::schema(), Vec::new())?,
+            rows: FrameRows::default(),
+            signals: signals
+                .map(|(output, can_parser)| SignalTable::new(output, can_parser))
+                .transpose()?,
+        })
+    }
+}
+
+impl<W: Write + Send> LogWriter for ParquetWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        self.rows.push(frame);
+        if self.rows.len() >= ROW_GROUP_ROWS {
+            self.rows.write(&mut self.file)?;
+        }
+
+        if let Some(signals) = &mut self.signals {
+            signals.push(frame)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.rows.write(&mut self.file)?;
+        self.file.finish()?;
+
+        if let Some(signals) = &mut self.signals {
+            signals.write()?;
+            signals.file.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ParquetReader {
+    rows: RowIter<'static>,
+}
+
+impl ParquetReader {
+    pub fn new(file: File) -> Result<Self, Error> {
+        Ok(Self {
+            rows: SerializedFileReader::new(file)?.into_iter(),
+        })
+    }
+}
+
+impl LogReader for ParquetReader {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        match self.rows.next().transpose()? {
+            Some(row) => row_to_frame(row).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// Reads a row of the frames table. Columns other than the timestamp, id and data may be left out, in which case the
+// frame is a received data frame on channel 1 whose DLC is given by its data.
+fn row_to_frame(row: Row) -> Result<LogFrame, Error> {
+    let mut timestamp_us = None;
+    let mut id = None;
+    let mut data = None;
+    let mut channel = 1;
+    let mut is_extended = false;
+    let (mut is_remote, mut is_error, mut is_fd) = (false, false, false);
+    let (mut bitrate_switch, mut error_state_indicator) = (false, false);
+    let mut direction = Direction::Rx;
+    let mut dlc = None;
+
+    for (name, field) in row.into_columns() {
+        let malformed = || Error::Malformed(format!("unexpected value {field} in column {name:?}"));
+        match (name.as_str(), &field) {
+            ("timestamp", Field::TimestampMicros(value) | Field::Long(value)) => {
+                timestamp_us = Some(u64::try_from(*value).map_err(|_| malformed())?)
+            }
+            ("channel", _) => channel = unsigned(&field).ok_or_else(malformed)? as u8,
+            ("id", _) => id = Some(unsigned(&field).ok_or_else(malformed)? as u32),
+            ("is_extended", Field::Bool(value)) => is_extended = *value,
+            ("is_remote", Field::Bool(value)) => is_remote = *value,
+            ("is_error", Field::Bool(value)) => is_error = *value,
+            ("is_fd", Field::Bool(value)) => is_fd = *value,
+            ("bitrate_switch", Field::Bool(value)) => bitrate_switch = *value,
+            ("error_state_indicator", Field::Bool(value)) => error_state_indicator = *value,
+            ("direction", Field::Str(value)) => {
+                direction = match value.as_str() {
+                    "Tx" => Direction::Tx,
+                    _ => Direction::Rx,
+                }
+            }
+            ("dlc", _) => dlc = Some(unsigned(&field).ok_or_else(malformed)? as u8),
+            ("data", Field::Bytes(value)) => data = Some(value.data().to_vec()),
+            (
+                "timestamp"
+                | "is_extended"
+                | "is_remote"
+                | "is_error"
+                | "is_fd"
+                | "bitrate_switch"
+                | "error_state_indicator"
+                | "direction"
+                | "data",
+                _,
+            ) => return Err(malformed()),
+            _ => {}
+        }
+    }
+
+    let missing = |name: &str| Error::Malformed(format!("the frames table has no {name:?} column"));
+    let data = data.ok_or_else(|| missing("data"))?;
+    let kind = if is_error {
+        FrameKind::Error
+    } else if is_remote {
+        FrameKind::Remote {
+            dlc: dlc.unwrap_or(0),
+        }
+    } else if is_fd {
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        }
+    } else {
+        FrameKind::Data
+    };
+
+    Ok(LogFrame {
+        timestamp_us: timestamp_us.ok_or_else(|| missing("timestamp"))?,
+        channel,
+        id: id.ok_or_else(|| missing("id"))?,
+        is_extended,
+        kind,
+        data: if matches!(kind, FrameKind::Remote { .. }) {
+            Vec::new()
+        } else {
+            data
+        },
+        direction,
+    })
+}
+
+// Returns the value of an integer column that cannot be negative
+fn unsigned(field: &Field) -> Option<u64> {
+    match *field {
+        Field::UByte(value) => Some(u64::from(value)),
+        Field::UShort(value) => Some(u64::from(value)),
+        Field::UInt(value) => Some(u64::from(value)),
+        Field::ULong(value) => Some(value),
+        Field::Byte(value) => u64::try_from(value).ok(),
+        Field::Short(value) => u64::try_from(value).ok(),
+        Field::Int(value) => u64::try_from(value).ok(),
+        Field::Long(value) => u64::try_from(value).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frames, read_all, temp_path};
+
+    // Parquet files are read from a File, so the bytes are written to a temporary file first
+    fn read_parquet(name: &str, bytes: &[u8]) -> Vec<LogFrame> {
+        let path = temp_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        let frames = read_all(ParquetReader::new(File::open(&path).unwrap()).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        frames
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut writer = ParquetWriter::new(Vec::new(), None).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(
+            read_parquet("round_trip.parquet", writer.file.inner()),
+            frames()
+        );
+    }
+
+    #[test]
+    fn tables_with_only_the_required_columns() {
+        let schema = vec![
+            timestamp_column(),
+            unsigned_column("id", 32),
+            column("data", PhysicalType::BYTE_ARRAY, None),
+        ];
+        let mut file = create_file(Vec::new(), "frames", schema, Vec::new()).unwrap();
+        let mut row_group = file.next_row_group().unwrap();
+        write_column::<Int64Type, _>(&mut row_group, &[1_000, 2_000], None).unwrap();
+        write_column::<Int32Type, _>(&mut row_group, &[0x123, 0x456], None).unwrap();
+        write_column::<ByteArrayType, _>(
+            &mut row_group,
+            &[ByteArray::from(vec![1, 2]), ByteArray::from(vec![])],
+            None,
+        )
+        .unwrap();
+        row_group.close().unwrap();
+        file.finish().unwrap();
+
+        // Frames default to data frames received on channel 1
+        let frames = read_parquet("required_columns.parquet", file.inner());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (
+                frames[0].timestamp_us,
+                frames[0].id,
+                frames[0].data.as_slice()
+            ),
+            (1_000, 0x123, &[1, 2][..])
+        );
+        assert_eq!((frames[1].timestamp_us, frames[1].id), (2_000, 0x456));
+        assert!(frames.iter().all(|frame| frame.kind == FrameKind::Data
+            && frame.channel == 1
+            && frame.direction == Direction::Rx));
+    }
+}
@@ -0,0 +1,723 @@
+///
+/// logging/pcap.rs
+///
+/// Wireshark capture files, in both the original pcap format (.pcap) and pcapng (.pcapng). Frames are stored with
+/// the SocketCAN link layer (LINKTYPE_CAN_SOCKETCAN), which holds each frame as a Linux can_frame or canfd_frame
+/// with the id in network byte order:
+///
+///   can_id [4], length [1], FD flags [1], reserved [2], data [8, or 64 for CAN FD frames]
+///
+/// where the top bits of can_id flag extended (0x80000000), remote (0x40000000) and error (0x20000000) frames.
+///
+/// pcap files hold a single interface and no direction, so their frames are read as received on channel 1.
+/// pcapng files are written with an interface description block for each channel and an enhanced packet block
+/// for each frame, whose flags give the direction. Markers are written as comments on the next frame, and those
+/// left over when the file is finished as comments on an empty name resolution block at its end. Timestamps
+/// are written in nanoseconds, and files are read in either byte order and with any timestamp resolution.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter, fd_dlc, fd_length};
+use crate::Direction;
+use std::io::{ErrorKind, Read, Write};
+
+const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+const SNAPLEN: u32 = 262_144;
+
+// pcap magic numbers for microsecond and nanosecond timestamps
+const PCAP_MAGIC_US: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+// pcapng block types
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const PACKET_BLOCK: u32 = 2;
+const NAME_RESOLUTION_BLOCK: u32 = 4;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+// Blocks larger than this are rejected rather than read into memory
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+// pcapng option codes
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+const EPB_FLAGS: u16 = 2;
+const EPB_FLAGS_INBOUND: u32 = 1;
+const EPB_FLAGS_OUTBOUND: u32 = 2;
+
+// SocketCAN can_id flags and masks
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+const CAN_SFF_MASK: u32 = 0x0000_07FF;
+
+// SocketCAN CAN FD flags
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+const CANFD_FDF: u8 = 0x04;
+
+const SOCKETCAN_HEADER_SIZE: usize = 8;
+const CAN_MTU: usize = SOCKETCAN_HEADER_SIZE + 8;
+const CANFD_MTU: usize = SOCKETCAN_HEADER_SIZE + 64;
+
+pub struct PcapWriter<W: Write + Send> {
+    output: W,
+}
+
+impl<W: Write + Send> PcapWriter<W> {
+    pub fn new(mut output: W) -> Result<Self, Error> {
+        let mut header = Vec::with_capacity(PCAP_HEADER_SIZE);
+        header.extend_from_slice(&PCAP_MAGIC_NS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // Time zone offset and timestamp accuracy, both always 0
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&u32::from(LINKTYPE_CAN_SOCKETCAN).to_le_bytes());
+        output.write_all(&header)?;
+
+        Ok(Self { output })
+    }
+}
+
+impl<W: Write + Send> LogWriter for PcapWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let packet = encode_socketcan(frame);
+
+        let mut record = Vec::with_capacity(PCAP_RECORD_HEADER_SIZE + packet.len());
+        record.extend_from_slice(&((frame.timestamp_us / 1_000_000) as u32).to_le_bytes());
+        record.extend_from_slice(&((frame.timestamp_us % 1_000_000 * 1_000) as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet);
+        self.output.write_all(&record)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+pub struct PcapReader<R: Read + Send> {
+    input: R,
+    big_endian: bool,
+    nanosecond: bool,
+}
+
+impl<R: Read + Send> PcapReader<R> {
+    pub fn new(mut input: R) -> Result<Self, Error> {
+        let mut header = [0; PCAP_HEADER_SIZE];
+        input.read_exact(&mut header)?;
+
+        let magic = read_u32(&header, 0, false);
+        let (big_endian, nanosecond) = match magic {
+            PCAP_MAGIC_US => (false, false),
+            PCAP_MAGIC_NS => (false, true),
+            _ if magic.swap_bytes() == PCAP_MAGIC_US => (true, false),
+            _ if magic.swap_bytes() == PCAP_MAGIC_NS => (true, true),
+            _ => return Err(Error::Malformed("missing pcap file header".to_string())),
+        };
+
+        // The upper bits of the link type hold FCS information, which SocketCAN captures never have
+        let link_type = read_u32(&header, 20, big_endian) & 0xFFFF;
+        if link_type != u32::from(LINKTYPE_CAN_SOCKETCAN) {
+            return Err(Error::Malformed(format!(
+                "link type {link_type} is not SocketCAN"
+            )));
+        }
+
+        Ok(Self {
+            input,
+            big_endian,
+            nanosecond,
+        })
+    }
+}
+
+impl<R: Read + Send> LogReader for PcapReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        let mut header = [0; PCAP_RECORD_HEADER_SIZE];
+        if !read_exact_or_eof(&mut self.input, &mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = u64::from(read_u32(&header, 0, self.big_endian));
+        let fraction = u64::from(read_u32(&header, 4, self.big_endian));
+        let captured_length = read_u32(&header, 8, self.big_endian) as usize;
+        if captured_length > MAX_BLOCK_SIZE {
+            return Err(Error::Malformed(format!(
+                "packet of {captured_length} bytes"
+            )));
+        }
+
+        let mut packet = vec![0; captured_length];
+        self.input.read_exact(&mut packet)?;
+
+        let timestamp_us = if self.nanosecond {
+            seconds * 1_000_000 + fraction / 1_000
+        } else {
+            seconds * 1_000_000 + fraction
+        };
+        decode_socketcan(&packet, timestamp_us, 1, Direction::Rx).map(Some)
+    }
+}
+
+pub struct PcapngWriter<W: Write + Send> {
+    output: W,
+    interface: String,
+    /// The number of interfaces described so far, where interface n carries channel n + 1
+    interfaces: u32,
+    /// Markers waiting to be written as comments on the next frame
+    comments: Vec<String>,
+}
+
+impl<W: Write + Send> PcapngWriter<W> {
+    pub fn new(mut output: W, interface: &str) -> Result<Self, Error> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // The length of the section is not known in advance
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut body, SHB_USERAPPL, b"CyderVis");
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        write_block(&mut output, SECTION_HEADER_BLOCK, &body)?;
+
+        Ok(Self {
+            output,
+            interface: interface.to_string(),
+            interfaces: 0,
+            comments: Vec::new(),
+        })
+    }
+
+    // Returns the id of the interface for a channel, which is always the channel less one so that readers can tell
+    // the channel from the interface id. The first time a channel is seen, its interface and those of any lower
+    // channels not seen yet are described.
+    fn interface_id(&mut self, channel: u8) -> Result<u32, Error> {
+        let interface_id = u32::from(channel.max(1)) - 1;
+        while self.interfaces <= interface_id {
+            self.describe_interface(self.interfaces + 1)?;
+            self.interfaces += 1;
+        }
+        Ok(interface_id)
+    }
+
+    fn describe_interface(&mut self, channel: u32) -> Result<(), Error> {
+        let name = if channel == 1 {
+            self.interface.clone()
+        } else {
+            format!("{}:{channel}", self.interface)
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&SNAPLEN.to_le_bytes());
+        push_option(&mut body, IF_NAME, name.as_bytes());
+        // Timestamps are in units of 10^-9 seconds
+        push_option(&mut body, IF_TSRESOL, &[9]);
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        write_block(&mut self.output, INTERFACE_DESCRIPTION_BLOCK, &body)
+    }
+}
+
+impl<W: Write + Send> LogWriter for PcapngWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let interface_id = self.interface_id(frame.channel)?;
+        let packet = encode_socketcan(frame);
+        let timestamp_ns = frame.timestamp_us * 1_000;
+
+        let mut body = Vec::with_capacity(20 + CANFD_MTU + 16);
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((timestamp_ns >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp_ns as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        push_padded(&mut body, &packet);
+
+        let flags = match frame.direction {
+            Direction::Rx => EPB_FLAGS_INBOUND,
+            Direction::Tx => EPB_FLAGS_OUTBOUND,
+        };
+        push_option(&mut body, EPB_FLAGS, &flags.to_le_bytes());
+        for comment in self.comments.drain(..) {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+        }
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+
+        write_block(&mut self.output, ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    fn write_marker(&mut self, _timestamp_us: u64, text: &str) -> Result<(), Error> {
+        self.comments.push(text.to_string());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self.comments.is_empty() {
+            // A name resolution block with only the end of records record carries the comments on its own
+            let mut body = vec![0; 4];
+            for comment in self.comments.drain(..) {
+                push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            }
+            push_option(&mut body, OPT_ENDOFOPT, &[]);
+            write_block(&mut self.output, NAME_RESOLUTION_BLOCK, &body)?;
+        }
+
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+struct Interface {
+    link_type: u16,
+    ticks_per_second: u64,
+    offset_us: i64,
+}
+
+pub struct PcapngReader<R: Read + Send> {
+    input: R,
+    big_endian: bool,
+    /// The interfaces of the current section, indexed by interface id
+    interfaces: Vec<Interface>,
+}
+
+impl<R: Read + Send> PcapngReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            big_endian: false,
+            interfaces: Vec::new(),
+        }
+    }
+
+    // Reads the rest of a block whose type has been read, returning its body without the trailing length
+    fn read_block(&mut self, block_type: u32) -> Result<Vec<u8>, Error> {
+        let mut length = [0; 4];
+        self.input.read_exact(&mut length)?;
+
+        // The byte order of a section is given by the magic number that starts its header
+        if block_type == SECTION_HEADER_BLOCK {
+            let mut magic = [0; 4];
+            self.input.read_exact(&mut magic)?;
+            self.big_endian = match u32::from_le_bytes(magic) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                _ => return Err(Error::Malformed("invalid pcapng byte order".to_string())),
+            };
+            self.interfaces.clear();
+
+            let body_length = self.block_body_length(&length)?;
+            let mut body = magic.to_vec();
+            body.resize(body_length + 4, 0);
+            self.input.read_exact(&mut body[4..])?;
+            body.truncate(body_length);
+            return Ok(body);
+        }
+
+        let body_length = self.block_body_length(&length)?;
+        let mut body = vec![0; body_length + 4];
+        self.input.read_exact(&mut body)?;
+        body.truncate(body_length);
+        Ok(body)
+    }
+
+    fn block_body_length(&self, length: &[u8; 4]) -> Result<usize, Error> {
+        let length = read_u32(length, 0, self.big_endian) as usize;
+        if length < 12 || !length.is_multiple_of(4) || length > MAX_BLOCK_SIZE {
+            return Err(Error::Malformed(format!("pcapng block of {length} bytes")));
+        }
+        Ok(length - 12)
+    }
+
+    fn read_interface(&self, body: &[u8]) -> Result<Interface, Error> {
+        if body.len() < 8 {
+            return Err(Error::Malformed(
+                "truncated interface description".to_string(),
+            ));
+        }
+
+        // Timestamps are in microseconds unless the interface gives a resolution
+        let mut interface = Interface {
+            link_type: read_u16(body, 0, self.big_endian),
+            ticks_per_second: 1_000_000,
+            offset_us: 0,
+        };
+        for (code, value) in options(&body[8..], self.big_endian) {
+            match (code, value) {
+                (IF_TSRESOL, [resolution, ..]) => {
+                    // The top bit selects a power of 2 rather than a power of 10
+                    let ticks_per_second = if resolution & 0x80 != 0 {
+                        1u64.checked_shl(u32::from(resolution & 0x7F))
+                    } else {
+                        10u64.checked_pow(u32::from(*resolution))
+                    };
+                    interface.ticks_per_second =
+                        ticks_per_second.filter(|&ticks| ticks > 0).ok_or_else(|| {
+                            Error::Malformed(format!("timestamp resolution {resolution:#x}"))
+                        })?;
+                }
+                (IF_TSOFFSET, value) if value.len() >= 8 => {
+                    let offset_s = read_u64(value, 0, self.big_endian) as i64;
+                    interface.offset_us = offset_s.saturating_mul(1_000_000);
+                }
+                _ => {}
+            }
+        }
+        Ok(interface)
+    }
+
+    // Decodes an enhanced packet block, or an obsolete packet block which has a 16 bit interface id
+    fn read_packet(&self, block_type: u32, body: &[u8]) -> Result<Option<LogFrame>, Error> {
+        if body.len() < 20 {
+            return Err(Error::Malformed("truncated packet block".to_string()));
+        }
+
+        let interface_id = if block_type == PACKET_BLOCK {
+            usize::from(read_u16(body, 0, self.big_endian))
+        } else {
+            read_u32(body, 0, self.big_endian) as usize
+        };
+        let Some(interface) = self.interfaces.get(interface_id) else {
+            return Err(Error::Malformed(format!(
+                "packet on undescribed interface {interface_id}"
+            )));
+        };
+        if interface.link_type != LINKTYPE_CAN_SOCKETCAN {
+            return Ok(None);
+        }
+
+        let ticks = u64::from(read_u32(body, 4, self.big_endian)) << 32
+            | u64::from(read_u32(body, 8, self.big_endian));
+        let captured_length = read_u32(body, 12, self.big_endian) as usize;
+        let Some(packet) = body.get(20..20 + captured_length) else {
+            return Err(Error::Malformed("truncated packet block".to_string()));
+        };
+
+        let mut direction = Direction::Rx;
+        let options_start = 20 + captured_length.next_multiple_of(4);
+        for (code, value) in options(
+            body.get(options_start..).unwrap_or_default(),
+            self.big_endian,
+        ) {
+            if code == EPB_FLAGS
+                && value.len() >= 4
+                && read_u32(value, 0, self.big_endian) & 0x3 == EPB_FLAGS_OUTBOUND
+            {
+                direction = Direction::Tx;
+            }
+        }
+
+        let timestamp_us = (u128::from(ticks) * 1_000_000 / u128::from(interface.ticks_per_second))
+            as i64
+            + interface.offset_us;
+        let channel = u8::try_from(interface_id + 1).unwrap_or(u8::MAX);
+        decode_socketcan(packet, timestamp_us.max(0) as u64, channel, direction).map(Some)
+    }
+}
+
+impl<R: Read + Send> LogReader for PcapngReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        loop {
+            let mut block_type = [0; 4];
+            if !read_exact_or_eof(&mut self.input, &mut block_type)? {
+                return Ok(None);
+            }
+            let block_type = read_u32(&block_type, 0, self.big_endian);
+            let body = self.read_block(block_type)?;
+
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    let interface = self.read_interface(&body)?;
+                    self.interfaces.push(interface);
+                }
+                ENHANCED_PACKET_BLOCK | PACKET_BLOCK => {
+                    if let Some(frame) = self.read_packet(block_type, &body)? {
+                        return Ok(Some(frame));
+                    }
+                }
+                // Simple packet blocks have no timestamp, and other blocks do not hold frames
+                _ => {}
+            }
+        }
+    }
+}
+
+// Encodes a frame as a SocketCAN can_frame, or a canfd_frame for CAN FD frames
+fn encode_socketcan(frame: &LogFrame) -> Vec<u8> {
+    let mut can_id = if frame.is_extended {
+        (frame.id & CAN_EFF_MASK) | CAN_EFF_FLAG
+    } else {
+        frame.id & CAN_SFF_MASK
+    };
+
+    let (length, fd_flags, data, mtu): (u8, u8, &[u8], usize) = match frame.kind {
+        FrameKind::Data => {
+            let data = &frame.data[..frame.data.len().min(8)];
+            (data.len() as u8, 0, data, CAN_MTU)
+        }
+        FrameKind::Remote { dlc } => {
+            can_id |= CAN_RTR_FLAG;
+            (dlc.min(8), 0, &[], CAN_MTU)
+        }
+        FrameKind::Error => {
+            // Error frames carry the error class bits in place of an id, and always have 8 bytes of details
+            can_id = (frame.id & CAN_EFF_MASK) | CAN_ERR_FLAG;
+            let data = &frame.data[..frame.data.len().min(8)];
+            (8, 0, data, CAN_MTU)
+        }
+        FrameKind::Fd {
+            bitrate_switch,
+            error_state_indicator,
+        } => {
+            let data = &frame.data[..frame.data.len().min(64)];
+            let mut fd_flags = CANFD_FDF;
+            if bitrate_switch {
+                fd_flags |= CANFD_BRS;
+            }
+            if error_state_indicator {
+                fd_flags |= CANFD_ESI;
+            }
+            let length = fd_length(fd_dlc(data.len())) as u8;
+            (length, fd_flags, data, CANFD_MTU)
+        }
+    };
+
+    let mut packet = Vec::with_capacity(mtu);
+    packet.extend_from_slice(&can_id.to_be_bytes());
+    packet.push(length);
+    packet.push(fd_flags);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(data);
+    packet.resize(mtu, 0);
+    packet
+}
+
+// Decodes a SocketCAN can_frame or canfd_frame. CAN FD frames are recognised by their flags, or by their size in
+// captures from before the flag was introduced.
+fn decode_socketcan(
+    packet: &[u8],
+    timestamp_us: u64,
+    channel: u8,
+    direction: Direction,
+) -> Result<LogFrame, Error> {
+    if packet.len() < SOCKETCAN_HEADER_SIZE {
+        return Err(Error::Malformed(format!(
+            "SocketCAN frame of {} bytes",
+            packet.len()
+        )));
+    }
+
+    let can_id = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+    let length = usize::from(packet[4]);
+    let fd_flags = packet[5];
+    let is_error = can_id & CAN_ERR_FLAG != 0;
+    // Error frames carry the error class bits in place of an id
+    let is_extended = can_id & CAN_EFF_FLAG != 0 && !is_error;
+    let id = if is_extended || is_error {
+        can_id & CAN_EFF_MASK
+    } else {
+        can_id & CAN_SFF_MASK
+    };
+
+    let kind = if is_error {
+        FrameKind::Error
+    } else if fd_flags & CANFD_FDF != 0 || packet.len() == CANFD_MTU {
+        FrameKind::Fd {
+            bitrate_switch: fd_flags & CANFD_BRS != 0,
+            error_state_indicator: fd_flags & CANFD_ESI != 0,
+        }
+    } else if can_id & CAN_RTR_FLAG != 0 {
+        FrameKind::Remote {
+            dlc: length.min(8) as u8,
+        }
+    } else {
+        FrameKind::Data
+    };
+
+    let data = match kind {
+        FrameKind::Remote { .. } => Vec::new(),
+        _ => packet
+            .get(SOCKETCAN_HEADER_SIZE..SOCKETCAN_HEADER_SIZE + length)
+            .ok_or_else(|| Error::Malformed(format!("SocketCAN frame with length {length}")))?
+            .to_vec(),
+    };
+
+    Ok(LogFrame {
+        timestamp_us,
+        channel,
+        id,
+        is_extended,
+        kind,
+        data,
+        direction,
+    })
+}
+
+// Writes a pcapng block, whose length is given both before and after the body
+fn write_block(output: &mut impl Write, block_type: u32, body: &[u8]) -> Result<(), Error> {
+    let length = (body.len() + 12) as u32;
+    let mut block = Vec::with_capacity(length as usize);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.extend_from_slice(&length.to_le_bytes());
+    output.write_all(&block)?;
+    Ok(())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    push_padded(body, value);
+}
+
+// Appends data padded with zeros to a multiple of 4 bytes
+fn push_padded(body: &mut Vec<u8>, data: &[u8]) {
+    body.extend_from_slice(data);
+    body.resize(body.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+// Returns the code and value of each option in a list of pcapng options
+fn options(mut buffer: &[u8], big_endian: bool) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buffer.len() < 4 {
+            return None;
+        }
+        let code = read_u16(buffer, 0, big_endian);
+        let length = usize::from(read_u16(buffer, 2, big_endian));
+        if code == OPT_ENDOFOPT || buffer.len() < 4 + length {
+            return None;
+        }
+        let value = &buffer[4..4 + length];
+        buffer = buffer
+            .get(4 + length.next_multiple_of(4)..)
+            .unwrap_or_default();
+        Some((code, value))
+    })
+}
+
+// Fills the buffer, returning false if the input ended before the first byte
+fn read_exact_or_eof(input: &mut impl Read, buffer: &mut [u8]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(Error::Malformed("unexpected end of file".to_string())),
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn read_u16(buffer: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let bytes = [buffer[offset], buffer[offset + 1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let bytes = buffer[offset..offset + 4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+fn read_u64(buffer: &[u8], offset: usize, big_endian: bool) -> u64 {
+    let bytes = buffer[offset..offset + 8].try_into().unwrap();
+    if big_endian {
+        u64::from_be_bytes(bytes)
+    } else {
+        u64::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frames, read_all};
+
+    #[test]
+    fn pcap_frames_round_trip() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // pcap files have a single interface and no direction, so every frame is read as received on channel 1
+        let mut expected = frames();
+        for frame in &mut expected {
+            frame.channel = 1;
+            frame.direction = Direction::Rx;
+        }
+        let reader = PcapReader::new(writer.output.as_slice()).unwrap();
+        assert_eq!(read_all(reader), expected);
+    }
+
+    #[test]
+    fn pcapng_frames_round_trip() {
+        let mut writer = PcapngWriter::new(Vec::new(), "can0").unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = PcapngReader::new(writer.output.as_slice());
+        assert_eq!(read_all(reader), frames());
+    }
+
+    #[test]
+    fn pcapng_channels_keep_their_numbers_in_any_order() {
+        let mut frames = frames();
+        for (frame, channel) in frames.iter_mut().zip([2, 1, 4, 2, 1, 4, 2]) {
+            frame.channel = channel;
+        }
+
+        let mut writer = PcapngWriter::new(Vec::new(), "can0").unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(writer.interfaces, 4);
+
+        let reader = PcapngReader::new(writer.output.as_slice());
+        assert_eq!(read_all(reader), frames);
+    }
+
+    #[test]
+    fn pcapng_pending_markers_are_written_on_finish() {
+        let mut writer = PcapngWriter::new(Vec::new(), "can0").unwrap();
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.write_marker(0, "Recording stopped").unwrap();
+        writer.finish().unwrap();
+
+        // The comment is in a trailing name resolution block, which readers skip over
+        let output = &writer.output;
+        let last_block_length = read_u32(output, output.len() - 4, false) as usize;
+        let last_block = &output[output.len() - last_block_length..];
+        assert_eq!(read_u32(last_block, 0, false), NAME_RESOLUTION_BLOCK);
+        let options: Vec<_> = options(&last_block[12..last_block_length - 4], false).collect();
+        assert_eq!(options, vec![(OPT_COMMENT, &b"Recording stopped"[..])]);
+
+        let reader = PcapngReader::new(output.as_slice());
+        assert_eq!(read_all(reader), frames());
+    }
+}
@@ -0,0 +1,531 @@
+///
+/// logging/sqlite.rs
+///
+/// SQLite capture databases (.db, .sqlite), which keep frames and their decoded signals in indexed tables so that
+/// long recordings can be searched with SQL after the fact, for example for every frame where BatteryTemp > 60.
+/// Recording to an existing database adds a new session to it, leaving earlier sessions in place.
+///
+/// The database has these tables:
+///  - sessions, a row for each recording, giving the interface and when the recording started and ended
+///  - frames, a row for each frame, giving its session, time, channel, id, flags, DLC and data, and the DBC
+///    message it was decoded as if any
+///  - messages, a row for each DBC message frames have been decoded as, giving its id and name
+///  - signals, a row for each signal decoded from a frame, giving the frame, the signal's name, physical value and
+///    unit
+///  - markers, a row for each recording marker, giving its session, time and text
+///
+/// Times are in microseconds since the UNIX epoch. Rows are committed in batches, and whenever the recording goes
+/// quiet, and the database is kept in WAL mode, so it can be queried while a recording is still being written.
+/// Signals decoded as NaN or infinity have no row, as the value column only holds numbers. Reading a database reads the frames of
+/// every session in the order they were recorded.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter, fd_dlc};
+use crate::Direction;
+use crate::can_parser::CanParser;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, params};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Rows are committed once this many have been written, or this long after the last commit
+const COMMIT_ROWS: u64 = 10_000;
+const COMMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        interface TEXT NOT NULL,
+        application TEXT NOT NULL,
+        started_us INTEGER NOT NULL,
+        ended_us INTEGER,
+        frame_count INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        can_id INTEGER NOT NULL,
+        is_extended INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        UNIQUE (can_id, is_extended, name)
+    );
+    CREATE TABLE IF NOT EXISTS frames (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp_us INTEGER NOT NULL,
+        channel INTEGER NOT NULL,
+        can_id INTEGER NOT NULL,
+        is_extended INTEGER NOT NULL,
+        is_remote INTEGER NOT NULL,
+        is_error INTEGER NOT NULL,
+        is_fd INTEGER NOT NULL,
+        bitrate_switch INTEGER NOT NULL,
+        error_state_indicator INTEGER NOT NULL,
+        direction TEXT NOT NULL,
+        dlc INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        message_id INTEGER REFERENCES messages (id)
+    );
+    CREATE INDEX IF NOT EXISTS frames_by_time ON frames (session_id, timestamp_us);
+    CREATE INDEX IF NOT EXISTS frames_by_can_id ON frames (can_id, timestamp_us);
+    CREATE INDEX IF NOT EXISTS frames_by_message ON frames (message_id, timestamp_us);
+    CREATE TABLE IF NOT EXISTS signals (
+        id INTEGER PRIMARY KEY,
+        frame_id INTEGER NOT NULL REFERENCES frames (id),
+        name TEXT NOT NULL,
+        value REAL NOT NULL,
+        unit TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS signals_by_value ON signals (name, value);
+    CREATE INDEX IF NOT EXISTS signals_by_frame ON signals (frame_id);
+    CREATE TABLE IF NOT EXISTS markers (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp_us INTEGER NOT NULL,
+        text TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS markers_by_time ON markers (session_id, timestamp_us);
+";
+
+const INSERT_FRAME: &str = "INSERT INTO frames (session_id, timestamp_us, channel, can_id, is_extended, \
+    is_remote, is_error, is_fd, bitrate_switch, error_state_indicator, direction, dlc, data, message_id) \
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)";
+const INSERT_SIGNAL: &str =
+    "INSERT INTO signals (frame_id, name, value, unit) VALUES (?1, ?2, ?3, ?4)";
+
+// Frames are read in batches of this many, following on from the row of the last frame read
+const READ_FRAMES: i64 = 1_000;
+const SELECT_FRAMES: &str = "SELECT id, timestamp_us, channel, can_id, is_extended, is_remote, is_error, \
+    is_fd, bitrate_switch, error_state_indicator, direction, dlc, data FROM frames WHERE id > ?1 ORDER BY id \
+    LIMIT ?2";
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Database(err.to_string())
+    }
+}
+
+fn now_us() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+pub struct SqliteWriter {
+    connection: Connection,
+    session_id: i64,
+    can_parser: Option<CanParser>,
+    /// The row of each DBC message seen so far, keyed by its id and whether the id is extended
+    message_ids: HashMap<(u32, bool), i64>,
+    frame_count: u64,
+    /// Frames and markers written since the last commit
+    uncommitted_rows: u64,
+    last_commit: Instant,
+}
+
+impl SqliteWriter {
+    /// Opens the database at the path, creating it if needed, and starts a new session. Signals of the messages
+    /// the CanParser's DBC defines are also decoded if given.
+    pub fn open(path: &str, interface: &str, can_parser: Option<CanParser>) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        connection.execute_batch("PRAGMA synchronous = NORMAL;")?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute(
+            "INSERT INTO sessions (interface, application, started_us) VALUES (?1, ?2, ?3)",
+            params![
+                interface,
+                format!("CyderVis {}", env!("CARGO_PKG_VERSION")),
+                now_us()
+            ],
+        )?;
+        let session_id = connection.last_insert_rowid();
+        connection.execute_batch("BEGIN;")?;
+
+        Ok(Self {
+            connection,
+            session_id,
+            can_parser,
+            message_ids: HashMap::new(),
+            frame_count: 0,
+            uncommitted_rows: 0,
+            last_commit: Instant::now(),
+        })
+    }
+
+    // Returns the row of the DBC message a frame is decoded as, adding the message if it is new to the database
+    fn message_id(&mut self, frame: &LogFrame, message_name: &str) -> Result<i64, Error> {
+        if let Some(&message_id) = self.message_ids.get(&(frame.id, frame.is_extended)) {
+            return Ok(message_id);
+        }
+
+        let can_id = i64::from(frame.id);
+        self.connection.execute(
+            "INSERT OR IGNORE INTO messages (can_id, is_extended, name) VALUES (?1, ?2, ?3)",
+            params![can_id, frame.is_extended, message_name],
+        )?;
+        let message_id = self.connection.query_row(
+            "SELECT id FROM messages WHERE can_id = ?1 AND is_extended = ?2 AND name = ?3",
+            params![can_id, frame.is_extended, message_name],
+            |row| row.get(0),
+        )?;
+        self.message_ids
+            .insert((frame.id, frame.is_extended), message_id);
+        Ok(message_id)
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        self.connection.execute(
+            "UPDATE sessions SET frame_count = ?1 WHERE id = ?2",
+            params![self.frame_count as i64, self.session_id],
+        )?;
+        self.connection.execute_batch("COMMIT; BEGIN;")?;
+        self.uncommitted_rows = 0;
+        self.last_commit = Instant::now();
+        Ok(())
+    }
+
+    // Commits once enough rows have been written, or enough time has passed since the last commit
+    fn row_written(&mut self) -> Result<(), Error> {
+        self.uncommitted_rows += 1;
+        if self.uncommitted_rows >= COMMIT_ROWS || self.last_commit.elapsed() >= COMMIT_INTERVAL {
+            self.commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl LogWriter for SqliteWriter {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let (dlc, bitrate_switch, error_state_indicator) = match frame.kind {
+            FrameKind::Data | FrameKind::Error => (frame.data.len() as u8, false, false),
+            FrameKind::Remote { dlc } => (dlc, false, false),
+            FrameKind::Fd {
+                bitrate_switch,
+                error_state_indicator,
+            } => (
+                fd_dlc(frame.data.len()),
+                bitrate_switch,
+                error_state_indicator,
+            ),
+        };
+
+        // The signals of the frame, along with the row and name of its message, if the frame can be decoded
+        let can_frame = frame.to_can_frame();
+        let decoded = match (&self.can_parser, &can_frame) {
+            (Some(can_parser), Some(can_frame)) => {
+                can_parser.message_for_frame(can_frame).map(|message| {
+                    let signals: Vec<(String, f64, String)> = message
+                        .signals()
+                        .iter()
+                        .filter_map(|signal| {
+                            let value = can_parser.physical_signal_value(
+                                message,
+                                signal.name(),
+                                can_frame,
+                            )?;
+                            value
+                                .is_finite()
+                                .then(|| (signal.name().clone(), value, signal.unit().clone()))
+                        })
+                        .collect();
+                    (message.message_name().clone(), signals)
+                })
+            }
+            _ => None,
+        };
+        let message_id = match &decoded {
+            Some((message_name, _)) => Some(self.message_id(frame, message_name)?),
+            None => None,
+        };
+
+        self.connection
+            .prepare_cached(INSERT_FRAME)?
+            .execute(params![
+                self.session_id,
+                frame.timestamp_us as i64,
+                frame.channel,
+                i64::from(frame.id),
+                frame.is_extended,
+                matches!(frame.kind, FrameKind::Remote { .. }),
+                frame.kind == FrameKind::Error,
+                matches!(frame.kind, FrameKind::Fd { .. }),
+                bitrate_switch,
+                error_state_indicator,
+                match frame.direction {
+                    Direction::Rx => "Rx",
+                    Direction::Tx => "Tx",
+                },
+                dlc,
+                frame.data,
+                message_id,
+            ])?;
+        let frame_id = self.connection.last_insert_rowid();
+
+        if let Some((_, signals)) = decoded {
+            let mut insert_signal = self.connection.prepare_cached(INSERT_SIGNAL)?;
+            for (name, value, unit) in signals {
+                insert_signal.execute(params![frame_id, name, value, unit])?;
+            }
+        }
+
+        self.frame_count += 1;
+        self.row_written()
+    }
+
+    fn write_marker(&mut self, timestamp_us: u64, text: &str) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO markers (session_id, timestamp_us, text) VALUES (?1, ?2, ?3)",
+            params![self.session_id, timestamp_us as i64, text],
+        )?;
+        self.row_written()
+    }
+
+    fn idle(&mut self) -> Result<(), Error> {
+        if self.uncommitted_rows > 0 {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.connection.execute(
+            "UPDATE sessions SET ended_us = ?1, frame_count = ?2 WHERE id = ?3",
+            params![now_us(), self.frame_count as i64, self.session_id],
+        )?;
+        self.connection.execute_batch("COMMIT;")?;
+        Ok(())
+    }
+}
+
+pub struct SqliteReader {
+    connection: Connection,
+    /// Frames read in the last batch that have not been returned yet
+    frames: VecDeque<LogFrame>,
+    last_frame_id: i64,
+}
+
+impl SqliteReader {
+    /// Opens the database read only, so that reading cannot change it
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Ok(Self {
+            connection: Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?,
+            frames: VecDeque::new(),
+            last_frame_id: 0,
+        })
+    }
+
+    fn read_frames(&mut self) -> Result<(), Error> {
+        let mut statement = self.connection.prepare_cached(SELECT_FRAMES)?;
+        let mut rows = statement.query(params![self.last_frame_id, READ_FRAMES])?;
+        while let Some(row) = rows.next()? {
+            self.last_frame_id = row.get(0)?;
+            let timestamp_us: i64 = row.get(1)?;
+            let can_id: i64 = row.get(3)?;
+            let direction: String = row.get(10)?;
+
+            let kind = if row.get(6)? {
+                FrameKind::Error
+            } else if row.get(5)? {
+                FrameKind::Remote { dlc: row.get(11)? }
+            } else if row.get(7)? {
+                FrameKind::Fd {
+                    bitrate_switch: row.get(8)?,
+                    error_state_indicator: row.get(9)?,
+                }
+            } else {
+                FrameKind::Data
+            };
+
+            self.frames.push_back(LogFrame {
+                timestamp_us: timestamp_us.max(0) as u64,
+                channel: row.get(2)?,
+                id: can_id as u32,
+                is_extended: row.get(4)?,
+                kind,
+                data: row.get(12)?,
+                direction: match direction.as_str() {
+                    "Tx" => Direction::Tx,
+                    _ => Direction::Rx,
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+impl LogReader for SqliteReader {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        if self.frames.is_empty() {
+            self.read_frames()?;
+        }
+        Ok(self.frames.pop_front())
+    }
+}
+
+/// The result of a query, with the value of each column in each row
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// Whether rows after the first max_rows were left out
+    pub truncated: bool,
+}
+
+/// Runs a SQL query on a capture database, returning up to max_rows rows. The database is opened read only, so
+/// the query cannot change it.
+pub fn query_database(path: &str, sql: &str, max_rows: usize) -> Result<QueryResult, Error> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare(sql)?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut result_rows = Vec::new();
+    let mut truncated = false;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        if result_rows.len() == max_rows {
+            truncated = true;
+            break;
+        }
+        let values = (0..columns.len())
+            .map(|index| row.get::<_, Value>(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        result_rows.push(values);
+    }
+
+    Ok(QueryResult {
+        columns,
+        rows: result_rows,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frame, frames, read_all, temp_path};
+
+    // Creates an empty database path, removing the database and its WAL files when dropped
+    struct TempDatabase(String);
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            let database = Self(temp_path(name).to_string_lossy().to_string());
+            database.remove();
+            database
+        }
+
+        fn remove(&self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.0));
+            }
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    fn write_session(path: &str, frames: &[LogFrame]) {
+        let mut writer = SqliteWriter::open(path, "can0", None).unwrap();
+        for frame in frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn frames_round_trip_across_sessions() {
+        let database = TempDatabase::new("round_trip.db");
+        write_session(&database.0, &frames());
+        write_session(&database.0, &frames());
+
+        let read = read_all(SqliteReader::open(&database.0).unwrap());
+        assert_eq!(read, [frames(), frames()].concat());
+    }
+
+    #[test]
+    fn rows_are_visible_once_idle() {
+        let database = TempDatabase::new("idle.db");
+        let mut writer = SqliteWriter::open(&database.0, "can0", None).unwrap();
+        for frame in &frames()[..3] {
+            writer.write_frame(frame).unwrap();
+        }
+        writer
+            .write_marker(1_700_000_000_000_000, "Marker")
+            .unwrap();
+
+        let counts = || {
+            query_database(
+                &database.0,
+                "SELECT (SELECT COUNT(*) FROM frames), (SELECT COUNT(*) FROM markers)",
+                1,
+            )
+            .unwrap()
+            .rows
+        };
+        assert_eq!(counts(), vec![vec![Value::Integer(0), Value::Integer(0)]]);
+        writer.idle().unwrap();
+        assert_eq!(counts(), vec![vec![Value::Integer(3), Value::Integer(1)]]);
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn signals_that_are_not_numbers_are_skipped() {
+        let mut can_parser = CanParser::new();
+        can_parser
+            .open_dbc(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/imu.dbc").to_string())
+            .unwrap();
+        // ScalingFactors holds two float signals, accel_scale and gyro_scale
+        let data = [f32::NAN.to_le_bytes(), 1.5f32.to_le_bytes()].concat();
+        let scaling_factors = frame(0x10002, true, FrameKind::Data, &data, Direction::Rx);
+
+        let database = TempDatabase::new("signals.db");
+        let mut writer = SqliteWriter::open(&database.0, "can0", Some(can_parser)).unwrap();
+        writer.write_frame(&scaling_factors).unwrap();
+        writer.finish().unwrap();
+
+        let result = query_database(&database.0, "SELECT name, value FROM signals", 10).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Text("gyro_scale".to_string()),
+                Value::Real(1.5)
+            ]]
+        );
+    }
+
+    #[test]
+    fn sessions_record_their_frame_counts() {
+        let database = TempDatabase::new("sessions.db");
+        write_session(&database.0, &frames());
+        write_session(&database.0, &frames()[..2]);
+
+        let result = query_database(
+            &database.0,
+            "SELECT interface, frame_count, ended_us IS NOT NULL FROM sessions ORDER BY id",
+            10,
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Text("can0".to_string()),
+                    Value::Integer(frames().len() as i64),
+                    Value::Integer(1)
+                ],
+                vec![
+                    Value::Text("can0".to_string()),
+                    Value::Integer(2),
+                    Value::Integer(1)
+                ],
+            ]
+        );
+        assert!(!result.truncated);
+    }
+}
@@ -0,0 +1,689 @@
+///
+/// logging/trc.rs
+///
+/// PEAK PCAN-View trace files (.trc). The header is a set of comment lines starting with `;`, of which
+/// `;$FILEVERSION` gives the version of the file, `;$STARTTIME` the start of the trace in days since 1899-12-30
+/// and, from version 2.1, `;$COLUMNS` the order of the columns. Each frame is then one line, whose columns depend
+/// on the version:
+///
+///   1.0 `1)  1841  0001  8  00 00 00 00 00 00 00 00`                        number, offset [ms], id, length, data
+///   1.1 `1)  1841.0  Rx  0001  8  00 00 ...`                                 adds the type (Rx, Tx, Error or Warng)
+///   1.2 `1)  1841.0 1  Rx  0001  8  00 00 ...`                               adds the bus
+///   1.3 `1)  1841.0 1  Rx  0001 -  8  00 00 ...`                             adds a reserved column
+///   2.0 `1  1059.900 DT  0300 Rx 7  00 00 ...`                               number, offset, type, id, direction,
+///                                                                          length, data
+///   2.1 `1  1059.900 DT 1  0300 Rx -  7  00 00 ...`                          columns as given by `;$COLUMNS`
+///
+/// Version 1 remote frames have `RTR` in place of the data. Version 2 types are DT for data frames, RR for remote
+/// frames, ER for error frames, and FD, FB (bit rate switch), FE (error state indicator) or BI (both) for CAN FD
+/// frames. Standard ids are written with 4 digits and extended ids with 8. Files are written as version 2.1, and
+/// start times are read and written as UTC.
+///
+use super::{Error, FrameKind, LogFrame, LogReader, LogWriter};
+use crate::Direction;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Days from 1899-12-30, the epoch of start times, to 1970-01-01
+const UNIX_EPOCH_DAYS: u128 = 25_569;
+const US_PER_DAY: u128 = 86_400_000_000;
+// Start times are written with 10 decimal places
+const START_TIME_SCALE: u128 = 10_000_000_000;
+
+// The columns of version 2.0 files, and the default columns of version 2.1 files
+const VERSION_2_0_COLUMNS: &str = "N,O,T,I,d,l,D";
+const VERSION_2_1_COLUMNS: &str = "N,O,T,B,I,d,R,L,D";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Version {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+    V2,
+}
+
+pub struct TrcReader<R: BufRead + Send> {
+    input: R,
+    line: String,
+    version: Version,
+    columns: Vec<char>,
+    start_us: u64,
+}
+
+impl<R: BufRead + Send> TrcReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            version: Version::V1_0,
+            columns: VERSION_2_0_COLUMNS
+                .split(',')
+                .flat_map(str::chars)
+                .collect(),
+            start_us: 0,
+        }
+    }
+
+    fn parse_header(&mut self, line: &str) {
+        let Some((key, value)) = line.trim_start_matches(';').trim().split_once('=') else {
+            return;
+        };
+
+        match key {
+            "$FILEVERSION" => {
+                self.version = match value.trim() {
+                    "1.1" => Version::V1_1,
+                    "1.2" => Version::V1_2,
+                    "1.3" => Version::V1_3,
+                    version if version.starts_with('2') => Version::V2,
+                    _ => Version::V1_0,
+                };
+                if value.trim() == "2.1" {
+                    self.columns = VERSION_2_1_COLUMNS
+                        .split(',')
+                        .flat_map(str::chars)
+                        .collect();
+                }
+            }
+            "$STARTTIME" => {
+                if let Some(start_us) = parse_start_time(value.trim()) {
+                    self.start_us = start_us;
+                }
+            }
+            "$COLUMNS" => {
+                self.columns = value
+                    .split(',')
+                    .filter_map(|column| column.trim().chars().next())
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<R: BufRead + Send> LogReader for TrcReader<R> {
+    fn next_frame(&mut self) -> Result<Option<LogFrame>, Error> {
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let frame = if line.trim_start().starts_with(';') {
+                self.parse_header(&line);
+                None
+            } else {
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match self.version {
+                    Version::V2 => parse_columns(&tokens, &self.columns),
+                    version => parse_version_1(&tokens, version),
+                }
+            };
+            self.line = line;
+
+            if let Some(mut frame) = frame {
+                frame.timestamp_us += self.start_us;
+                return Ok(Some(frame));
+            }
+        }
+    }
+}
+
+// Parses a version 1 line, returning None if it is not a frame. The timestamp is the offset from the start time.
+fn parse_version_1(tokens: &[&str], version: Version) -> Option<LogFrame> {
+    let (offset, bus, frame_type, id, rest) = match (version, tokens) {
+        (Version::V1_0, [_, offset, id, rest @ ..]) => (offset, "1", "Rx", id, rest),
+        (Version::V1_1, [_, offset, frame_type, id, rest @ ..]) => {
+            (offset, "1", *frame_type, id, rest)
+        }
+        (Version::V1_2, [_, offset, bus, frame_type, id, rest @ ..]) => {
+            (offset, *bus, *frame_type, id, rest)
+        }
+        (Version::V1_3, [_, offset, bus, frame_type, id, _, rest @ ..]) => {
+            (offset, *bus, *frame_type, id, rest)
+        }
+        _ => return None,
+    };
+    let [length, data @ ..] = rest else {
+        return None;
+    };
+    let timestamp_us = parse_milliseconds(offset)?;
+    let channel = bus.parse().ok()?;
+
+    let direction = match frame_type {
+        "Rx" => Direction::Rx,
+        "Tx" => Direction::Tx,
+        "Error" => {
+            return Some(LogFrame {
+                timestamp_us,
+                channel,
+                id: 0,
+                is_extended: false,
+                kind: FrameKind::Error,
+                data: Vec::new(),
+                direction: Direction::Rx,
+            });
+        }
+        // Warnings are changes of the bus state rather than frames
+        _ => return None,
+    };
+
+    let length: u8 = length.parse().ok()?;
+    let (kind, data) = if data.first() == Some(&"RTR") {
+        (FrameKind::Remote { dlc: length }, Vec::new())
+    } else {
+        (
+            FrameKind::Data,
+            parse_data(data.get(..usize::from(length.min(8)))?)?,
+        )
+    };
+
+    Some(LogFrame {
+        timestamp_us,
+        channel,
+        id: u32::from_str_radix(id, 16).ok()?,
+        // Extended ids are written with 8 digits
+        is_extended: id.len() > 4,
+        kind,
+        data,
+        direction,
+    })
+}
+
+// Parses a version 2 line with the given columns, returning None if it is not a frame.
+// The timestamp is the offset from the start time.
+fn parse_columns(tokens: &[&str], columns: &[char]) -> Option<LogFrame> {
+    let mut frame = LogFrame {
+        timestamp_us: 0,
+        channel: 1,
+        id: 0,
+        is_extended: false,
+        kind: FrameKind::Data,
+        data: Vec::new(),
+        direction: Direction::Rx,
+    };
+    let mut dlc = None;
+    let mut length = None;
+
+    for (index, column) in columns.iter().enumerate() {
+        // The data column is empty for frames without data
+        let token = match tokens.get(index) {
+            Some(token) => *token,
+            None if *column == 'D' => "",
+            None => return None,
+        };
+        match column {
+            'O' => frame.timestamp_us = parse_milliseconds(token)?,
+            'T' => {
+                frame.kind = match token {
+                    "DT" => FrameKind::Data,
+                    "RR" => FrameKind::Remote { dlc: 0 },
+                    "ER" => FrameKind::Error,
+                    "FD" | "FB" | "FE" | "BI" => FrameKind::Fd {
+                        bitrate_switch: matches!(token, "FB" | "BI"),
+                        error_state_indicator: matches!(token, "FE" | "BI"),
+                    },
+                    // Status changes, error counter changes and events are not frames
+                    _ => return None,
+                }
+            }
+            'B' => frame.channel = token.parse().ok()?,
+            // Error frames have no id
+            'I' if token != "-" => {
+                frame.id = u32::from_str_radix(token, 16).ok()?;
+                frame.is_extended = token.len() > 4;
+            }
+            'd' => {
+                frame.direction = match token {
+                    "Tx" => Direction::Tx,
+                    _ => Direction::Rx,
+                }
+            }
+            'L' => dlc = Some(token.parse::<u8>().ok()?),
+            'l' => length = Some(token.parse::<usize>().ok()?),
+            'D' => {
+                let length = match (length, dlc, frame.kind) {
+                    (Some(length), _, _) => length,
+                    (None, Some(dlc), FrameKind::Fd { .. }) => super::fd_length(dlc),
+                    (None, Some(dlc), _) => usize::from(dlc.min(8)),
+                    (None, None, _) => tokens.len() - index,
+                };
+                if let FrameKind::Remote { dlc: remote_dlc } = &mut frame.kind {
+                    *remote_dlc = dlc.unwrap_or(length as u8);
+                } else {
+                    frame.data = parse_data(tokens.get(index..index + length)?)?;
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    Some(frame)
+}
+
+fn parse_data(bytes: &[&str]) -> Option<Vec<u8>> {
+    bytes
+        .iter()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect()
+}
+
+// Parses a number of milliseconds with an optional fraction into microseconds
+fn parse_milliseconds(text: &str) -> Option<u64> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let whole: u64 = whole.parse().ok()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut fraction_us = 0;
+    for (i, digit) in fraction.bytes().take(3).enumerate() {
+        fraction_us += u64::from(digit - b'0') * 10u64.pow(2 - i as u32);
+    }
+    Some(whole * 1_000 + fraction_us)
+}
+
+// Parses a start time in days since 1899-12-30 into microseconds since the UNIX epoch
+fn parse_start_time(text: &str) -> Option<u64> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let whole: u128 = whole.parse().ok()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut scaled_days = whole * START_TIME_SCALE;
+    for (i, digit) in fraction.bytes().take(10).enumerate() {
+        scaled_days += u128::from(digit - b'0') * 10u128.pow(9 - i as u32);
+    }
+    let epoch_us =
+        (scaled_days * US_PER_DAY / START_TIME_SCALE).checked_sub(UNIX_EPOCH_DAYS * US_PER_DAY)?;
+    u64::try_from(epoch_us).ok()
+}
+
+pub struct TrcWriter<W: Write + Send> {
+    output: W,
+    // The start time as written to the header, which the timestamps of every frame are relative to
+    start_us: Option<u64>,
+    message_number: u64,
+    line: String,
+}
+
+impl<W: Write + Send> TrcWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            start_us: None,
+            message_number: 0,
+            line: String::new(),
+        }
+    }
+
+    // Writes the header with a start time at or before start_us, returning the start time as written
+    fn write_header(&mut self, start_us: u64) -> Result<u64, Error> {
+        let scaled_days =
+            (u128::from(start_us) + UNIX_EPOCH_DAYS * US_PER_DAY) * START_TIME_SCALE / US_PER_DAY;
+        let start_time = format!(
+            "{}.{:010}",
+            scaled_days / START_TIME_SCALE,
+            scaled_days % START_TIME_SCALE
+        );
+
+        write!(
+            self.output,
+            ";$FILEVERSION=2.1\n\
+             ;$STARTTIME={start_time}\n\
+             ;$COLUMNS={VERSION_2_1_COLUMNS}\n\
+             ;\n\
+             ;   Message   Time    Type    ID     Rx/Tx\n\
+             ;   Number    Offset  |  Bus  [hex]  |  Reserved\n\
+             ;   |         [ms]    |  |    |      |  |  Data Length Code\n\
+             ;   |         |       |  |    |      |  |  |    Data [hex] ...\n\
+             ;   |         |       |  |    |      |  |  |    |\n\
+             ;---+-- ------+------ +- +- --+----- +- +- +--- +- -- -- -- -- -- -- --\n"
+        )?;
+
+        let start_us = parse_start_time(&start_time).unwrap_or(start_us);
+        self.start_us = Some(start_us);
+        Ok(start_us)
+    }
+}
+
+impl<W: Write + Send> LogWriter for TrcWriter<W> {
+    fn write_frame(&mut self, frame: &LogFrame) -> Result<(), Error> {
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => self.write_header(frame.timestamp_us)?,
+        };
+        self.message_number += 1;
+
+        let offset_us = frame.timestamp_us.saturating_sub(start_us);
+        let (frame_type, dlc) = match frame.kind {
+            FrameKind::Data => ("DT", frame.data.len().min(8) as u8),
+            FrameKind::Remote { dlc } => ("RR", dlc),
+            FrameKind::Error => ("ER", frame.data.len().min(8) as u8),
+            FrameKind::Fd {
+                bitrate_switch,
+                error_state_indicator,
+            } => (
+                match (bitrate_switch, error_state_indicator) {
+                    (false, false) => "FD",
+                    (true, false) => "FB",
+                    (false, true) => "FE",
+                    (true, true) => "BI",
+                },
+                super::fd_dlc(frame.data.len()),
+            ),
+        };
+        let id = match frame.kind {
+            FrameKind::Error => "-".to_string(),
+            _ if frame.is_extended => format!("{:08X}", frame.id),
+            _ => format!("{:04X}", frame.id),
+        };
+        let direction = match frame.direction {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        };
+
+        // Writing into a String cannot fail, so the results of write! are ignored
+        self.line.clear();
+        let _ = write!(
+            self.line,
+            "{:>7} {:>9}.{:03} {frame_type} {:<2} {id:>8} {direction} -  {dlc:<4}",
+            self.message_number,
+            offset_us / 1_000,
+            offset_us % 1_000,
+            frame.channel
+        );
+        if !matches!(frame.kind, FrameKind::Remote { .. }) {
+            for byte in &frame.data {
+                let _ = write!(self.line, " {byte:02X}");
+            }
+        }
+        self.line.push('\n');
+
+        self.output.write_all(self.line.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        // A file without frames still needs a header, which starts from when the file was finished
+        if self.start_us.is_none() {
+            let now_us = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64;
+            self.write_header(now_us)?;
+        }
+
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::test_frames::{frame, frames, read_all};
+
+    fn read_trc(text: &str) -> Vec<LogFrame> {
+        read_all(TrcReader::new(text.as_bytes()))
+    }
+
+    fn at(timestamp_us: u64, channel: u8, frame: LogFrame) -> LogFrame {
+        LogFrame {
+            timestamp_us,
+            channel,
+            ..frame
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut writer = TrcWriter::new(Vec::new());
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Error frames are written without their class bits
+        let mut expected = frames();
+        for frame in &mut expected {
+            if frame.kind == FrameKind::Error {
+                frame.id = 0;
+            }
+        }
+        assert_eq!(read_all(TrcReader::new(writer.output.as_slice())), expected);
+    }
+
+    #[test]
+    fn version_1_0_lines() {
+        let frames = read_trc(
+            ";$FILEVERSION=1.0\n\
+             ;   Message Number\n\
+             \x20    1)      1841  0001  8  00 11 22 33 44 55 66 77\n\
+             \x20    2)      1842  18FF0001  2  AA BB\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                at(
+                    1_841_000,
+                    1,
+                    frame(
+                        0x1,
+                        false,
+                        FrameKind::Data,
+                        &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77],
+                        Direction::Rx
+                    )
+                ),
+                at(
+                    1_842_000,
+                    1,
+                    frame(
+                        0x18FF_0001,
+                        true,
+                        FrameKind::Data,
+                        &[0xAA, 0xBB],
+                        Direction::Rx
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_1_1_lines() {
+        let frames = read_trc(
+            ";$FILEVERSION=1.1\n\
+             ;$STARTTIME=25569.5\n\
+             \x20    1)      1841.5  Tx  0123  2  01 02\n\
+             \x20    2)      1842.0  Rx  0456  4  RTR\n\
+             \x20    3)      1843.0  Warng  FFFFFFFF  4  00 00 00 08  BUSHEAVY\n\
+             \x20    4)      1844.0  Error  00000000  4  00 00 08 00\n",
+        );
+
+        // The start time is noon on 1970-01-01
+        let start_us = 43_200_000_000;
+        assert_eq!(
+            frames,
+            vec![
+                at(
+                    start_us + 1_841_500,
+                    1,
+                    frame(0x123, false, FrameKind::Data, &[1, 2], Direction::Tx)
+                ),
+                at(
+                    start_us + 1_842_000,
+                    1,
+                    frame(
+                        0x456,
+                        false,
+                        FrameKind::Remote { dlc: 4 },
+                        &[],
+                        Direction::Rx
+                    )
+                ),
+                at(
+                    start_us + 1_844_000,
+                    1,
+                    frame(0, false, FrameKind::Error, &[], Direction::Rx)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_1_2_lines() {
+        let frames = read_trc(
+            ";$FILEVERSION=1.2\n\
+             \x20    1)      1841.0 2  Rx        0001  3  01 02 03\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![at(
+                1_841_000,
+                2,
+                frame(0x1, false, FrameKind::Data, &[1, 2, 3], Direction::Rx)
+            )]
+        );
+    }
+
+    #[test]
+    fn version_1_3_lines() {
+        let frames = read_trc(
+            ";$FILEVERSION=1.3\n\
+             \x20    1)      1841.0 1  Tx        0001 -  3  01 02 03\n\
+             \x20    2)      1842.1 2  Rx    18DAF110 -  8  RTR\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                at(
+                    1_841_000,
+                    1,
+                    frame(0x1, false, FrameKind::Data, &[1, 2, 3], Direction::Tx)
+                ),
+                at(
+                    1_842_100,
+                    2,
+                    frame(
+                        0x18DA_F110,
+                        true,
+                        FrameKind::Remote { dlc: 8 },
+                        &[],
+                        Direction::Rx
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_2_0_lines() {
+        let frames = read_trc(
+            ";$FILEVERSION=2.0\n\
+             \x20     1      1059.900 DT     0300 Rx 7  00 11 22 33 44 55 66\n\
+             \x20     2      1060.000 FB 18DAF110 Tx 12 00 01 02 03 04 05 06 07 08 09 0A 0B\n\
+             \x20     3      1061.000 RR     0123 Rx 4\n\
+             \x20     4      1062.000 ST          Rx    00 00 00 08\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                at(
+                    1_059_900,
+                    1,
+                    frame(
+                        0x300,
+                        false,
+                        FrameKind::Data,
+                        &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+                        Direction::Rx
+                    )
+                ),
+                at(
+                    1_060_000,
+                    1,
+                    frame(
+                        0x18DA_F110,
+                        true,
+                        FrameKind::Fd {
+                            bitrate_switch: true,
+                            error_state_indicator: false,
+                        },
+                        &(0..12).collect::<Vec<u8>>(),
+                        Direction::Tx
+                    )
+                ),
+                at(
+                    1_061_000,
+                    1,
+                    frame(
+                        0x123,
+                        false,
+                        FrameKind::Remote { dlc: 4 },
+                        &[],
+                        Direction::Rx
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_2_1_custom_columns() {
+        // No reserved column, and the bus after the id
+        let frames = read_trc(
+            ";$FILEVERSION=2.1\n\
+             ;$COLUMNS=N,O,T,I,B,d,L,D\n\
+             \x20     1      1059.900 DT     0300 2  Rx 3  01 02 03\n\
+             \x20     2      1060.000 BI     0456 1  Tx 9  00 01 02 03 04 05 06 07 08 09 0A 0B\n\
+             \x20     3      1061.000 ER        - 2  Rx 5  04 00 08 00 00\n",
+        );
+
+        assert_eq!(
+            frames,
+            vec![
+                at(
+                    1_059_900,
+                    2,
+                    frame(0x300, false, FrameKind::Data, &[1, 2, 3], Direction::Rx)
+                ),
+                at(
+                    1_060_000,
+                    1,
+                    frame(
+                        0x456,
+                        false,
+                        FrameKind::Fd {
+                            bitrate_switch: true,
+                            error_state_indicator: true,
+                        },
+                        &(0..12).collect::<Vec<u8>>(),
+                        Direction::Tx
+                    )
+                ),
+                at(
+                    1_061_000,
+                    2,
+                    frame(
+                        0,
+                        false,
+                        FrameKind::Error,
+                        &[0x04, 0x00, 0x08, 0x00, 0x00],
+                        Direction::Rx
+                    )
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,384 @@
+///
+/// recorder.rs
+///
+/// Records received and transmitted frames to a log file, in the format selected by the file extension.
+/// Frames are written by a blocking task so that file I/O never delays the CAN I/O thread. The recording filter
+/// is also evaluated there, as it may need to decode signals. Error frames have no id, so they are recorded
+/// whatever the filter, and the errors reported by the interface itself are recorded as error frames.
+///
+/// Triggered recordings keep recent frames in a ring buffer until the trigger fires, then save the frames of the
+/// pre-trigger window followed by those of the post-trigger window. They either finish after a single capture, or
+/// re-arm to save a capture each time the trigger fires.
+///
+use crate::Direction;
+use crate::can_parser::CanParser;
+use crate::frame_filter::FilterExpression;
+use crate::logging::{Error, FrameKind, LogFormat, LogFrame, LogWriter, epoch_offset_us};
+use crate::trigger::{Trigger, TriggerOptions, TriggerState};
+use crosscan::can::CanFrame;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Bounds the memory used by the pre-trigger ring buffer on busy buses, whatever the window
+const MAX_PRE_TRIGGER_EVENTS: usize = 1_000_000;
+
+// How long the recording has to be quiet before the writer is told that it is idle
+const IDLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Errors reported by the interface are recorded as controller problem error frames with unspecified details
+const CAN_ERR_CRTL: u32 = 0x0000_0004;
+
+enum RecorderEvent {
+    Frame {
+        frame: CanFrame,
+        kind: FrameKind,
+        direction: Direction,
+        timestamp_us: u128,
+    },
+    Marker {
+        text: String,
+        timestamp_us: u128,
+    },
+}
+
+// What the recorder is sent, either an event to record or a replacement for the recording filter
+enum RecorderMessage {
+    Event(RecorderEvent),
+    SetFilter(Option<FilterExpression>),
+}
+
+impl RecorderEvent {
+    fn timestamp_us(&self) -> u128 {
+        match self {
+            RecorderEvent::Frame { timestamp_us, .. }
+            | RecorderEvent::Marker { timestamp_us, .. } => *timestamp_us,
+        }
+    }
+}
+
+/// State of a triggered recording, shared between the writer and the recorder
+struct TriggerStatus {
+    state: AtomicU8,
+    triggers: AtomicU64,
+}
+
+pub struct Recorder {
+    path: String,
+    sender: mpsc::UnboundedSender<RecorderMessage>,
+    handle: JoinHandle<Result<(), Error>>,
+    frames_written: Arc<AtomicU64>,
+    trigger_status: Option<Arc<TriggerStatus>>,
+}
+
+impl Recorder {
+    /// Creates the log file and starts recording. Timestamps are given in microseconds since start_time, and are
+    /// written to the log as absolute times. The DBC in use when recording starts applies to the whole
+    /// recording, while the filter can be replaced with set_filter. If decode_signals is set, formats that can
+    /// hold decoded signals also store them. With a trigger, only the frames around each time the trigger fires
+    /// are written.
+    pub fn start(
+        path: &str,
+        interface: &str,
+        start_time: Instant,
+        filter: Option<FilterExpression>,
+        can_parser: CanParser,
+        decode_signals: bool,
+        trigger: Option<TriggerOptions>,
+    ) -> Result<Self, Error> {
+        let writer = LogFormat::from_path(path)?.create_writer(
+            path,
+            interface,
+            decode_signals.then_some(&can_parser),
+        )?;
+
+        let epoch_offset_us = epoch_offset_us(start_time);
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let frames_written = Arc::new(AtomicU64::new(0));
+        let task_frames_written = Arc::clone(&frames_written);
+        let trigger_status = trigger.is_some().then(|| {
+            Arc::new(TriggerStatus {
+                state: AtomicU8::new(TriggerState::Armed.index()),
+                triggers: AtomicU64::new(0),
+            })
+        });
+        let capture = trigger
+            .zip(trigger_status.clone())
+            .map(|(options, status)| TriggeredCapture {
+                trigger: Trigger::new(options.condition.clone()),
+                options,
+                status,
+                pre_trigger_events: VecDeque::new(),
+                capture_end_us: 0,
+            });
+        let handle = tokio::task::spawn_blocking(move || {
+            write_frames(
+                writer,
+                receiver,
+                filter,
+                can_parser,
+                capture,
+                epoch_offset_us,
+                &task_frames_written,
+            )
+        });
+
+        Ok(Self {
+            path: path.to_string(),
+            sender,
+            handle,
+            frames_written,
+            trigger_status,
+        })
+    }
+
+    /// Records a frame of the given kind. Error frames carry the error class bits in place of the id.
+    pub fn record(
+        &self,
+        frame: &CanFrame,
+        kind: FrameKind,
+        direction: Direction,
+        timestamp_us: u128,
+    ) {
+        // Sending only fails once the writer has stopped on an error, which is reported when recording is stopped
+        let _ = self
+            .sender
+            .send(RecorderMessage::Event(RecorderEvent::Frame {
+                frame: frame.clone(),
+                kind,
+                direction,
+                timestamp_us,
+            }));
+    }
+
+    /// Marks a point in the recording with a note, in formats that can hold one
+    pub fn mark(&self, text: &str, timestamp_us: u128) {
+        let _ = self
+            .sender
+            .send(RecorderMessage::Event(RecorderEvent::Marker {
+                text: text.to_string(),
+                timestamp_us,
+            }));
+    }
+
+    /// Replaces the recording filter. The new filter applies to every frame written after the frames already
+    /// recorded, including those of a triggered recording's pre-trigger window.
+    pub fn set_filter(&self, filter: Option<FilterExpression>) {
+        let _ = self.sender.send(RecorderMessage::SetFilter(filter));
+    }
+
+    /// Records an error reported by the interface, a failed transmission or read, as a controller problem error
+    /// frame, which can also fire the trigger of a triggered recording
+    pub fn record_error(&self, timestamp_us: u128) {
+        if let Some(frame) = crate::new_can_frame(CAN_ERR_CRTL, true, &[0; 8]) {
+            self.record(&frame, FrameKind::Error, Direction::Rx, timestamp_us);
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the state of a triggered recording and the number of times its trigger has fired, or None if the
+    /// recording is not triggered
+    pub fn trigger_status(&self) -> Option<(TriggerState, u64)> {
+        self.trigger_status.as_ref().map(|status| {
+            (
+                TriggerState::from_index(status.state.load(Ordering::Relaxed)),
+                status.triggers.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Returns false once the writer has stopped, which happens early if writing to the file failed, or once a
+    /// single-shot triggered recording has been written
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    /// Stops recording once every frame recorded so far has been written, returning the number of frames written
+    pub async fn stop(self) -> Result<u64, Error> {
+        drop(self.sender);
+        match self.handle.await {
+            Ok(result) => result.map(|_| self.frames_written.load(Ordering::Relaxed)),
+            Err(err) => Err(Error::Io(std::io::Error::other(err))),
+        }
+    }
+}
+
+// Decides which events of a triggered recording are written, buffering events until the trigger fires
+struct TriggeredCapture {
+    options: TriggerOptions,
+    trigger: Trigger,
+    status: Arc<TriggerStatus>,
+    pre_trigger_events: VecDeque<RecorderEvent>,
+    capture_end_us: u128,
+}
+
+impl TriggeredCapture {
+    fn state(&self) -> TriggerState {
+        TriggerState::from_index(self.status.state.load(Ordering::Relaxed))
+    }
+
+    fn set_state(&self, state: TriggerState) {
+        self.status.state.store(state.index(), Ordering::Relaxed);
+    }
+
+    // Returns the events to write, in order. The post-trigger window only ends with the first event after it,
+    // so a capture on a quiet bus stays in progress until the next frame.
+    fn process(&mut self, event: RecorderEvent, can_parser: &CanParser) -> Vec<RecorderEvent> {
+        let timestamp_us = event.timestamp_us();
+        let fired = match &event {
+            RecorderEvent::Frame {
+                frame,
+                kind: FrameKind::Error,
+                ..
+            } => self.trigger.fires_on_error_frame(frame.id()),
+            RecorderEvent::Frame { frame, .. } => self.trigger.fires_on_frame(frame, can_parser),
+            RecorderEvent::Marker { .. } => false,
+        };
+
+        if self.state() == TriggerState::Capturing && timestamp_us > self.capture_end_us {
+            if self.options.rearm {
+                self.set_state(TriggerState::Armed);
+            } else {
+                self.set_state(TriggerState::Complete);
+            }
+        }
+
+        if self.state() == TriggerState::Armed {
+            while self.pre_trigger_events.front().is_some_and(|oldest| {
+                oldest.timestamp_us() + self.options.pre_trigger_us < timestamp_us
+            }) {
+                self.pre_trigger_events.pop_front();
+            }
+        }
+
+        match self.state() {
+            TriggerState::Capturing => vec![event],
+            TriggerState::Armed if fired => {
+                let triggers = self.status.triggers.fetch_add(1, Ordering::Relaxed) + 1;
+                self.set_state(TriggerState::Capturing);
+                self.capture_end_us = timestamp_us + self.options.post_trigger_us;
+
+                let mut events: Vec<_> = self.pre_trigger_events.drain(..).collect();
+                events.push(RecorderEvent::Marker {
+                    text: format!("Trigger {triggers}: {}", self.trigger.condition()),
+                    timestamp_us,
+                });
+                events.push(event);
+                events
+            }
+            TriggerState::Armed => {
+                if self.pre_trigger_events.len() == MAX_PRE_TRIGGER_EVENTS {
+                    self.pre_trigger_events.pop_front();
+                }
+                self.pre_trigger_events.push_back(event);
+                Vec::new()
+            }
+            TriggerState::Complete => Vec::new(),
+        }
+    }
+}
+
+fn write_frames(
+    mut writer: Box<dyn LogWriter>,
+    mut receiver: mpsc::UnboundedReceiver<RecorderMessage>,
+    mut filter: Option<FilterExpression>,
+    can_parser: CanParser,
+    mut capture: Option<TriggeredCapture>,
+    epoch_offset_us: u64,
+    frames_written: &AtomicU64,
+) -> Result<(), Error> {
+    let runtime = tokio::runtime::Handle::current();
+    loop {
+        let message = match runtime.block_on(tokio::time::timeout(IDLE_INTERVAL, receiver.recv())) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(_) => {
+                writer.idle()?;
+                continue;
+            }
+        };
+        let event = match message {
+            RecorderMessage::Event(event) => event,
+            RecorderMessage::SetFilter(new_filter) => {
+                filter = new_filter;
+                continue;
+            }
+        };
+        let Some(capture) = capture.as_mut() else {
+            write_event(
+                writer.as_mut(),
+                event,
+                filter.as_ref(),
+                &can_parser,
+                epoch_offset_us,
+                frames_written,
+            )?;
+            continue;
+        };
+
+        for event in capture.process(event, &can_parser) {
+            write_event(
+                writer.as_mut(),
+                event,
+                filter.as_ref(),
+                &can_parser,
+                epoch_offset_us,
+                frames_written,
+            )?;
+        }
+        // A single-shot capture is finished straight away, so the file is complete without stopping the recording
+        if capture.state() == TriggerState::Complete {
+            break;
+        }
+    }
+
+    writer.finish()
+}
+
+fn write_event(
+    writer: &mut dyn LogWriter,
+    event: RecorderEvent,
+    filter: Option<&FilterExpression>,
+    can_parser: &CanParser,
+    epoch_offset_us: u64,
+    frames_written: &AtomicU64,
+) -> Result<(), Error> {
+    match event {
+        RecorderEvent::Frame {
+            frame,
+            kind,
+            direction,
+            timestamp_us,
+        } => {
+            if kind != FrameKind::Error
+                && filter.is_some_and(|filter| !filter.matches(&frame, direction, can_parser))
+            {
+                return Ok(());
+            }
+
+            let timestamp_us = epoch_offset_us + timestamp_us as u64;
+            writer.write_frame(&LogFrame::from_can_frame(
+                &frame,
+                kind,
+                direction,
+                timestamp_us,
+            ))?;
+            frames_written.fetch_add(1, Ordering::Relaxed);
+        }
+        RecorderEvent::Marker { text, timestamp_us } => {
+            writer.write_marker(epoch_offset_us + timestamp_us as u64, &text)?;
+        }
+    }
+    Ok(())
+}
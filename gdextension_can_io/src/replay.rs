@@ -0,0 +1,474 @@
+///
+/// replay.rs
+///
+/// Replays the frames of a log file onto a CAN interface with their original inter-frame timing, scaled by a speed
+/// factor, or as fast as possible. Frames can be filtered with a filter expression and have their ids remapped, and
+/// the replay can be paused, stepped a frame at a time and looped. The lateness of every frame relative to its
+/// scaled original time is tracked as the timing deviation.
+///
+use crate::can_parser::CanParser;
+use crate::can_socket::CanSocket;
+use crate::frame_filter::FilterExpression;
+use crate::frame_validation::MAX_EXTENDED_ID;
+use crate::logging::{self, LogFormat, LogReader};
+use crate::new_can_frame;
+use crosscan::can::CanFrame;
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 10.0;
+
+// Sleeps wake up late, so the last part of the wait for each frame is spent yielding. Sleeps are ended early by how
+// late recent ones woke up, keeping this window short enough not to busy a core.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(200);
+
+// Bounds how early sleeps are ended, so that a stall of the runtime does not turn every later wait into a spin
+const MAX_OVERSLEEP: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayState {
+    Playing,
+    Paused,
+    /// Every frame has been sent, or the log could not be read any further
+    Finished,
+}
+
+impl ReplayState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplayState::Playing => "playing",
+            ReplayState::Paused => "paused",
+            ReplayState::Finished => "finished",
+        }
+    }
+}
+
+pub struct ReplayOptions {
+    /// Factor that the original inter-frame times are divided by, or None to send every frame as fast as possible
+    pub speed: Option<f64>,
+    pub looped: bool,
+    /// Frames of the log that do not pass the filter are skipped
+    pub filter: Option<FilterExpression>,
+    /// Ids that frames are sent with in place of the ids they were logged with. Frames keep their format, so frames
+    /// remapped to an id that does not fit the format are skipped.
+    pub id_map: HashMap<u32, u32>,
+    pub start_paused: bool,
+}
+
+impl ReplayOptions {
+    /// Creates options from a Godot Dictionary with an optional "speed" (0 for as fast as possible), "loop", "filter"
+    /// expression, "remap" Dictionary of logged id to sent id, and "paused" flag to start paused
+    pub fn from_dictionary(options: &Dictionary) -> Result<Self, String> {
+        let flag = |key: &str| {
+            options
+                .get(key)
+                .and_then(|value| value.try_to::<bool>().ok())
+                .unwrap_or(false)
+        };
+
+        let speed = match options.get("speed") {
+            Some(value) => {
+                // Variants are converted strictly, so whole numbers are read as integers
+                let speed = value
+                    .try_to::<f64>()
+                    .or_else(|_| value.try_to::<i64>().map(|speed| speed as f64))
+                    .map_err(|_| "The replay \"speed\" must be a number".to_string())?;
+                speed_from_factor(speed)?
+            }
+            None => Some(1.0),
+        };
+
+        let filter = match options.get("filter") {
+            Some(value) => {
+                let source = value
+                    .try_to::<GString>()
+                    .map_err(|_| "The replay \"filter\" must be a String".to_string())?
+                    .to_string();
+                if source.trim().is_empty() {
+                    None
+                } else {
+                    Some(
+                        FilterExpression::parse(&source)
+                            .map_err(|e| format!("Invalid replay filter: {e}"))?,
+                    )
+                }
+            }
+            None => None,
+        };
+
+        let mut id_map = HashMap::new();
+        if let Some(value) = options.get("remap") {
+            let remap = value
+                .try_to::<Dictionary>()
+                .map_err(|_| "The replay \"remap\" must be a Dictionary".to_string())?;
+            for (from, to) in remap.iter_shared() {
+                let (Ok(from), Ok(to)) = (from.try_to::<i64>(), to.try_to::<i64>()) else {
+                    return Err("Replay remapping requires integer ids".to_string());
+                };
+                if let Some(id) = [from, to]
+                    .into_iter()
+                    .find(|id| !(0..=MAX_EXTENDED_ID).contains(id))
+                {
+                    return Err(format!("Cannot remap id {id}, which is not a valid CAN id"));
+                }
+                id_map.insert(from as u32, to as u32);
+            }
+        }
+
+        Ok(Self {
+            speed,
+            looped: flag("loop"),
+            filter,
+            id_map,
+            start_paused: flag("paused"),
+        })
+    }
+}
+
+/// Converts a speed factor into a replay speed, where 0 replays as fast as possible
+pub fn speed_from_factor(speed: f64) -> Result<Option<f64>, String> {
+    if speed == 0.0 {
+        return Ok(None);
+    }
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(format!(
+            "Replay speed must be between {MIN_SPEED}x and {MAX_SPEED}x, or 0 for as fast as possible"
+        ));
+    }
+    Ok(Some(speed))
+}
+
+/// Progress and timing deviation of a replay
+#[derive(Default, Clone)]
+pub struct ReplayStats {
+    pub frames_sent: u64,
+    /// Frames rejected by the filter, and frames that cannot be sent such as remote, error and CAN FD frames
+    pub frames_skipped: u64,
+    pub frames_failed: u64,
+    pub loops_completed: u64,
+    /// Time of the last frame sent, measured from the first frame of the log
+    pub position_us: u64,
+    pub deviation_last_us: u64,
+    pub deviation_max_us: u64,
+    deviation_total_us: u64,
+    deviation_samples: u64,
+}
+
+impl ReplayStats {
+    pub fn deviation_mean_us(&self) -> f64 {
+        if self.deviation_samples == 0 {
+            return 0.0;
+        }
+        self.deviation_total_us as f64 / self.deviation_samples as f64
+    }
+
+    fn record_deviation(&mut self, deviation: Duration) {
+        let deviation_us = deviation.as_micros() as u64;
+        self.deviation_last_us = deviation_us;
+        self.deviation_max_us = self.deviation_max_us.max(deviation_us);
+        self.deviation_total_us += deviation_us;
+        self.deviation_samples += 1;
+    }
+}
+
+// A frame read ahead of being sent, so that the time it is due is known
+struct PendingFrame {
+    timestamp_us: u64,
+    frame: CanFrame,
+}
+
+enum Action {
+    Send(CanFrame),
+    /// Sleep until the time given, or until notified if there is nothing to do until the replay is controlled
+    Sleep(Option<Instant>),
+    /// The next frame is due within the spin threshold at the time given
+    Spin(Instant),
+}
+
+pub struct LogReplay {
+    file_path: String,
+    format: LogFormat,
+    interface: String,
+    reader: Box<dyn LogReader>,
+    options: ReplayOptions,
+    // Used to evaluate signal comparisons of the filter, with the DBC loaded when the replay was started
+    can_parser: CanParser,
+    state: ReplayState,
+    pending_steps: u32,
+    next_frame: Option<PendingFrame>,
+    // The ideal send time of the last frame sent in time, along with its log timestamp, that the next frame is
+    // timed from. Timing from the ideal rather than the actual send time keeps lateness from accumulating.
+    anchor: Option<(Instant, u64)>,
+    last_timestamp_us: Option<u64>,
+    first_timestamp_us: Option<u64>,
+    frames_sent_this_loop: u64,
+    stats: ReplayStats,
+    error: Option<String>,
+}
+
+impl LogReplay {
+    /// Opens a log file for replay. The format is chosen by the file extension, see `LogFormat::from_path`.
+    pub fn open(
+        file_path: &str,
+        interface: &str,
+        options: ReplayOptions,
+        can_parser: CanParser,
+    ) -> Result<Self, logging::Error> {
+        let format = LogFormat::from_path(file_path)?;
+        let reader = format.open_reader(file_path)?;
+        let state = if options.start_paused {
+            ReplayState::Paused
+        } else {
+            ReplayState::Playing
+        };
+
+        Ok(Self {
+            file_path: file_path.to_string(),
+            format,
+            interface: interface.to_string(),
+            reader,
+            options,
+            can_parser,
+            state,
+            pending_steps: 0,
+            next_frame: None,
+            anchor: None,
+            last_timestamp_us: None,
+            first_timestamp_us: None,
+            frames_sent_this_loop: 0,
+            stats: ReplayStats::default(),
+            error: None,
+        })
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn state(&self) -> ReplayState {
+        self.state
+    }
+
+    pub fn speed(&self) -> Option<f64> {
+        self.options.speed
+    }
+
+    pub fn is_looped(&self) -> bool {
+        self.options.looped
+    }
+
+    pub fn stats(&self) -> &ReplayStats {
+        &self.stats
+    }
+
+    /// Returns the last error, from either sending a frame or reading the log, where the replay finishes early if the
+    /// log could not be read to its end
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn pause(&mut self) {
+        if self.state == ReplayState::Playing {
+            self.state = ReplayState::Paused;
+        }
+    }
+
+    /// Resumes a paused replay, waiting the original time between the last frame sent and the next frame
+    pub fn resume(&mut self) {
+        if self.state == ReplayState::Paused {
+            self.state = ReplayState::Playing;
+            self.pending_steps = 0;
+            self.anchor = self
+                .last_timestamp_us
+                .map(|timestamp_us| (Instant::now(), timestamp_us));
+        }
+    }
+
+    /// Sends the next count frames of a paused replay immediately, pausing the replay if it is playing
+    pub fn step(&mut self, count: u32) {
+        self.pause();
+        if self.state == ReplayState::Paused {
+            self.pending_steps = self.pending_steps.saturating_add(count);
+        }
+    }
+
+    /// Changes the speed of the replay, which applies from the last frame sent
+    pub fn set_speed(&mut self, speed: Option<f64>) {
+        self.options.speed = speed;
+    }
+
+    // Decides what the replay task should do next, taking the next frame if it should be sent
+    fn next_action(&mut self, now: Instant) -> Action {
+        if self.state == ReplayState::Finished {
+            return Action::Sleep(None);
+        }
+
+        if self.next_frame.is_none() {
+            match self.read_next_frame() {
+                Ok(Some(pending_frame)) => self.next_frame = Some(pending_frame),
+                Ok(None) => {
+                    self.state = ReplayState::Finished;
+                    return Action::Sleep(None);
+                }
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    self.state = ReplayState::Finished;
+                    return Action::Sleep(None);
+                }
+            }
+        }
+        let Some(pending_frame) = &self.next_frame else {
+            return Action::Sleep(None);
+        };
+
+        if self.state == ReplayState::Paused {
+            if self.pending_steps == 0 {
+                return Action::Sleep(None);
+            }
+            self.pending_steps -= 1;
+            return Action::Send(self.take_next_frame(now));
+        }
+
+        if let (Some(speed), Some((anchor_time, anchor_timestamp_us))) =
+            (self.options.speed, self.anchor)
+        {
+            // Frames logged out of order are sent straight after the frame before them
+            let elapsed_us = pending_frame
+                .timestamp_us
+                .saturating_sub(anchor_timestamp_us);
+            let due = anchor_time + Duration::from_secs_f64(elapsed_us as f64 / 1e6 / speed);
+            if due > now + SPIN_THRESHOLD {
+                return Action::Sleep(Some(due - SPIN_THRESHOLD));
+            }
+            if due > now {
+                return Action::Spin(due);
+            }
+
+            self.stats.record_deviation(now - due);
+            let frame = self.take_next_frame(due);
+            return Action::Send(frame);
+        }
+
+        Action::Send(self.take_next_frame(now))
+    }
+
+    // Takes the next frame to be sent, timing the frame after it from the given time
+    fn take_next_frame(&mut self, send_time: Instant) -> CanFrame {
+        let pending_frame = self
+            .next_frame
+            .take()
+            .expect("a frame is read ahead before it is taken");
+
+        let first_timestamp_us = *self
+            .first_timestamp_us
+            .get_or_insert(pending_frame.timestamp_us);
+        self.stats.position_us = pending_frame
+            .timestamp_us
+            .saturating_sub(first_timestamp_us);
+        self.last_timestamp_us = Some(pending_frame.timestamp_us);
+        self.anchor = Some((send_time, pending_frame.timestamp_us));
+        self.frames_sent_this_loop += 1;
+        pending_frame.frame
+    }
+
+    // Reads up to the next frame that passes the filter and can be sent, restarting the log if looped
+    fn read_next_frame(&mut self) -> Result<Option<PendingFrame>, logging::Error> {
+        loop {
+            let Some(log_frame) = self.reader.next_frame()? else {
+                // A log without any frames to send would otherwise be reread endlessly
+                if !self.options.looped || self.frames_sent_this_loop == 0 {
+                    return Ok(None);
+                }
+                self.reader = self.format.open_reader(&self.file_path)?;
+                self.stats.loops_completed += 1;
+                self.frames_sent_this_loop = 0;
+                self.first_timestamp_us = None;
+                self.last_timestamp_us = None;
+                self.anchor = None;
+                continue;
+            };
+
+            let Some(frame) = log_frame.to_can_frame() else {
+                self.stats.frames_skipped += 1;
+                continue;
+            };
+            if let Some(filter) = &self.options.filter
+                && !filter.matches(&frame, log_frame.direction, &self.can_parser)
+            {
+                self.stats.frames_skipped += 1;
+                continue;
+            }
+
+            let frame = match self.options.id_map.get(&frame.id()) {
+                Some(&id) => match new_can_frame(id, frame.is_extended(), frame.data()) {
+                    Some(frame) => frame,
+                    None => {
+                        self.stats.frames_skipped += 1;
+                        continue;
+                    }
+                },
+                None => frame,
+            };
+
+            return Ok(Some(PendingFrame {
+                timestamp_us: log_frame.timestamp_us,
+                frame,
+            }));
+        }
+    }
+
+    fn record_sent(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.stats.frames_sent += 1,
+            Err(e) => {
+                self.stats.frames_failed += 1;
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Sends the frames of the replay at their due times until the task is aborted.
+/// The replay must be notified whenever it is controlled so that the task can wake up early.
+pub async fn run_replay(
+    replay: Arc<Mutex<LogReplay>>,
+    replay_notify: Arc<Notify>,
+    mut socket: CanSocket,
+) {
+    // How late recent sleeps woke up, smoothed over several sleeps
+    let mut oversleep = Duration::ZERO;
+    loop {
+        let action = replay.lock().await.next_action(Instant::now());
+        match action {
+            Action::Send(frame) => {
+                let result = socket.write_frame(frame).await.map_err(|e| e.to_string());
+                replay.lock().await.record_sent(result);
+            }
+            Action::Sleep(Some(wake_time)) => {
+                let sleep_until = wake_time.checked_sub(oversleep).unwrap_or(wake_time);
+                tokio::select! {
+                    _ = tokio::time::sleep_until(tokio::time::Instant::from_std(sleep_until)) => {
+                        let late = Instant::now().saturating_duration_since(sleep_until);
+                        oversleep = ((oversleep * 7 + late) / 8).min(MAX_OVERSLEEP);
+                    }
+                    _ = replay_notify.notified() => {}
+                }
+            }
+            Action::Sleep(None) => replay_notify.notified().await,
+            // The replay is not locked while spinning, as nothing it is controlled with needs handling this quickly
+            Action::Spin(due) => {
+                while Instant::now() < due {
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+    }
+}
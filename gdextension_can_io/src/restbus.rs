@@ -0,0 +1,282 @@
+///
+/// restbus.rs
+///
+/// Simulates the rest of a CAN network from a DBC file. Every cyclic message sent by the emulated nodes is
+/// transmitted at its `GenMsgCycleTime`, starting from the `GenSigStartValue` of each signal.
+/// Signal values can be overridden at runtime while the simulation is running, either with fixed values or
+/// with waveform generators that are evaluated each time their message is transmitted.
+///
+use crate::signal_generator::{GeneratedMessage, Waveform};
+use crate::tx_tracker::{self, TxRequest, TxSource, TxTracker};
+use can_dbc::{AttributeValue, AttributeValuedForObjectType, DBC, Message, MessageId, Transmitter};
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, mpsc};
+
+const CYCLE_TIME_ATTRIBUTE: &str = "GenMsgCycleTime";
+const START_VALUE_ATTRIBUTE: &str = "GenSigStartValue";
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownNode(String),
+    NoCyclicMessages,
+}
+
+pub struct RestbusSimulation {
+    messages: Vec<SimulatedMessage>,
+}
+
+struct SimulatedMessage {
+    generated_message: GeneratedMessage,
+    cycle_time: Duration,
+    next_send: Instant,
+}
+
+impl RestbusSimulation {
+    /// Creates a simulation of every cyclic message transmitted by the given DBC nodes
+    pub fn new(dbc: &DBC, node_names: &[String]) -> Result<Self, Error> {
+        let dbc_nodes: Vec<&String> = dbc.nodes().iter().flat_map(|node| node.0.iter()).collect();
+        if let Some(unknown) = node_names.iter().find(|name| !dbc_nodes.contains(name)) {
+            return Err(Error::UnknownNode(unknown.clone()));
+        }
+
+        let now = Instant::now();
+        let messages: Vec<SimulatedMessage> = dbc
+            .messages()
+            .iter()
+            .filter(|message| Self::is_sent_by(dbc, message, node_names))
+            .filter_map(|message| {
+                let cycle_time_ms = Self::message_cycle_time_ms(dbc, message.message_id())?;
+                Some(SimulatedMessage::new(
+                    dbc,
+                    message,
+                    Duration::from_millis(cycle_time_ms),
+                    now,
+                ))
+            })
+            .collect();
+
+        if messages.is_empty() {
+            return Err(Error::NoCyclicMessages);
+        }
+
+        Ok(Self { messages })
+    }
+
+    /// Overrides the physical value of a signal, replacing any generator attached to it.
+    /// Returns false if the message or signal is not being simulated.
+    pub fn set_signal_value(&mut self, message_name: &str, signal_name: &str, value: f64) -> bool {
+        let Some(message) = self.find_message_with_signal(message_name, signal_name) else {
+            return false;
+        };
+
+        message
+            .generated_message
+            .set_signal_value(signal_name, value)
+    }
+
+    /// Drives a signal with a waveform generator. Returns false if the message or signal is not being simulated.
+    pub fn set_signal_generator(
+        &mut self,
+        message_name: &str,
+        signal_name: &str,
+        waveform: Waveform,
+    ) -> bool {
+        let Some(message) = self.find_message_with_signal(message_name, signal_name) else {
+            return false;
+        };
+
+        message
+            .generated_message
+            .set_signal_generator(signal_name, waveform)
+    }
+
+    /// Stops a signal's generator, holding the signal at its last generated value
+    pub fn clear_signal_generator(&mut self, message_name: &str, signal_name: &str) -> bool {
+        let Some(message) = self.find_message_with_signal(message_name, signal_name) else {
+            return false;
+        };
+
+        message
+            .generated_message
+            .clear_signal_generator(signal_name)
+    }
+
+    /// Returns the names of all simulated messages
+    pub fn message_names(&self) -> Vec<String> {
+        self.messages
+            .iter()
+            .map(|m| m.generated_message.name().to_string())
+            .collect()
+    }
+
+    /// Returns the frames of every message whose cycle time has elapsed, scheduling their next transmission
+    fn take_due_frames(&mut self, now: Instant) -> Vec<(TxSource, CanFrame)> {
+        let mut frames = Vec::new();
+        for message in self.messages.iter_mut() {
+            if message.next_send > now {
+                continue;
+            }
+
+            if let Some(frame) = message.generated_message.next_frame() {
+                frames.push((TxSource::Restbus, frame));
+            }
+
+            // Keep the original phase unless we have fallen a whole cycle behind
+            message.next_send += message.cycle_time;
+            if message.next_send <= now {
+                message.next_send = now + message.cycle_time;
+            }
+        }
+        frames
+    }
+
+    fn find_message_with_signal(
+        &mut self,
+        message_name: &str,
+        signal_name: &str,
+    ) -> Option<&mut SimulatedMessage> {
+        self.messages.iter_mut().find(|m| {
+            m.generated_message.name() == message_name
+                && m.generated_message.has_signal(signal_name)
+        })
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.messages.iter().map(|m| m.next_send).min()
+    }
+
+    // Returns true if the message is transmitted by any of the given nodes, either directly or through BO_TX_BU_
+    fn is_sent_by(dbc: &DBC, message: &Message, node_names: &[String]) -> bool {
+        let sent_by = |transmitter: &Transmitter| match transmitter {
+            Transmitter::NodeName(name) => node_names.contains(name),
+            Transmitter::VectorXXX => false,
+        };
+
+        sent_by(message.transmitter())
+            || dbc
+                .message_transmitters()
+                .iter()
+                .filter(|mt| mt.message_id() == message.message_id())
+                .any(|mt| mt.transmitter().iter().any(sent_by))
+    }
+
+    // Returns the cycle time of a message, or None if the message is not cyclic
+    fn message_cycle_time_ms(dbc: &DBC, message_id: &MessageId) -> Option<u64> {
+        let message_value = dbc.attribute_values().iter().find_map(|attribute| {
+            if attribute.attribute_name() != CYCLE_TIME_ATTRIBUTE {
+                return None;
+            }
+            match attribute.attribute_value() {
+                AttributeValuedForObjectType::MessageDefinitionAttributeValue(id, Some(value))
+                    if id == message_id =>
+                {
+                    attribute_as_f64(value)
+                }
+                _ => None,
+            }
+        });
+
+        let cycle_time_ms =
+            message_value.or_else(|| attribute_default(dbc, CYCLE_TIME_ATTRIBUTE))?;
+        if cycle_time_ms >= 1.0 {
+            Some(cycle_time_ms as u64)
+        } else {
+            None
+        }
+    }
+}
+
+impl SimulatedMessage {
+    fn new(dbc: &DBC, message: &Message, cycle_time: Duration, now: Instant) -> Self {
+        Self {
+            generated_message: GeneratedMessage::new(
+                dbc,
+                message,
+                signal_start_values(dbc, message),
+            ),
+            cycle_time,
+            next_send: now,
+        }
+    }
+}
+
+/// Transmits the simulated messages at their cycle times until the task is aborted or the bus is closed
+pub async fn run_restbus(
+    restbus: Arc<Mutex<RestbusSimulation>>,
+    tx_tracker: Arc<Mutex<TxTracker>>,
+    transmit_sender: mpsc::UnboundedSender<TxRequest>,
+) {
+    loop {
+        let next_deadline = {
+            let mut restbus = restbus.lock().await;
+            let frames = restbus.take_due_frames(Instant::now());
+
+            // The bus has been closed, so there is nothing left to transmit to
+            if !tx_tracker::queue_frames(&tx_tracker, &transmit_sender, frames).await {
+                return;
+            }
+            restbus.next_deadline()
+        };
+
+        match next_deadline {
+            Some(deadline) => {
+                tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await
+            }
+            None => return,
+        }
+    }
+}
+
+/// Returns the physical start value of every signal of a message, as the simulation starts from
+pub fn signal_start_values(dbc: &DBC, message: &Message) -> HashMap<String, f64> {
+    // Start values are specified as raw values, so convert them to physical values
+    message
+        .signals()
+        .iter()
+        .map(|signal| {
+            let raw_start_value = signal_start_value(dbc, message.message_id(), signal.name());
+            let physical_value = raw_start_value * *signal.factor() + *signal.offset();
+            (signal.name().clone(), physical_value)
+        })
+        .collect()
+}
+
+// Returns the raw start value of a signal, falling back to the attribute default and then zero
+fn signal_start_value(dbc: &DBC, message_id: &MessageId, signal_name: &str) -> f64 {
+    dbc.attribute_values()
+        .iter()
+        .find_map(|attribute| {
+            if attribute.attribute_name() != START_VALUE_ATTRIBUTE {
+                return None;
+            }
+            match attribute.attribute_value() {
+                AttributeValuedForObjectType::SignalAttributeValue(id, name, value)
+                    if id == message_id && name == signal_name =>
+                {
+                    attribute_as_f64(value)
+                }
+                _ => None,
+            }
+        })
+        .or_else(|| attribute_default(dbc, START_VALUE_ATTRIBUTE))
+        .unwrap_or(0.0)
+}
+
+fn attribute_default(dbc: &DBC, attribute_name: &str) -> Option<f64> {
+    dbc.attribute_defaults()
+        .iter()
+        .find(|default| default.attribute_name() == attribute_name)
+        .and_then(|default| attribute_as_f64(default.attribute_value()))
+}
+
+fn attribute_as_f64(value: &AttributeValue) -> Option<f64> {
+    match value {
+        AttributeValue::AttributeValueU64(v) => Some(*v as f64),
+        AttributeValue::AttributeValueI64(v) => Some(*v as f64),
+        AttributeValue::AttributeValueF64(v) => Some(*v),
+        AttributeValue::AttributeValueCharString(s) => s.trim().parse().ok(),
+    }
+}
@@ -0,0 +1,312 @@
+///
+/// signal_export.rs
+///
+/// Exports the values of chosen DBC signals as time series in a CSV file, for analysis in other tools.
+/// Signals are written as physical values, with their units in the header. Rows are either written for each frame
+/// carrying a chosen signal, leaving the other signals empty, or resampled onto a common time base.
+///
+use crate::can_parser::CanParser;
+use crate::logging;
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Log(logging::Error),
+    NoDbc,
+    UnknownSignal(String),
+    NoSignals,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<logging::Error> for Error {
+    fn from(err: logging::Error) -> Self {
+        Error::Log(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Log(err) => write!(f, "{err}"),
+            Error::NoDbc => write!(f, "Signals can only be exported once a DBC file is loaded"),
+            Error::UnknownSignal(name) => {
+                write!(f, "{name:?} is not a signal of the loaded DBC file")
+            }
+            Error::NoSignals => write!(f, "No signals were chosen"),
+        }
+    }
+}
+
+/// How a signal's value is found at a time between its samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// The value of the latest sample at or before the time
+    Previous,
+    /// The value on the line between the samples either side of the time
+    Linear,
+    /// The value of the sample closest to the time
+    Nearest,
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "previous" => Some(Interpolation::Previous),
+            "linear" => Some(Interpolation::Linear),
+            "nearest" => Some(Interpolation::Nearest),
+            _ => None,
+        }
+    }
+}
+
+/// How the rows of the CSV file are laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// A row for each frame carrying a chosen signal
+    Sparse,
+    /// A row every period_us microseconds, from the first sample of any signal to the last
+    Resampled {
+        period_us: u64,
+        interpolation: Interpolation,
+    },
+}
+
+// A chosen signal, which is written to its own column
+struct Column {
+    name: String,
+    unit: String,
+    /// Every sample of the signal, which are only kept when resampling
+    samples: Vec<(u64, f64)>,
+}
+
+pub struct SignalExporter<'a> {
+    can_parser: &'a CanParser,
+    output: BufWriter<File>,
+    layout: Layout,
+    columns: Vec<Column>,
+    /// The chosen signals of each message, as the signal's name and column
+    message_columns: HashMap<String, Vec<(String, usize)>>,
+    rows_written: u64,
+}
+
+impl<'a> SignalExporter<'a> {
+    /// Creates the CSV file and writes its header. Signals are named "Message.Signal", and are looked up in the
+    /// DBC of the CanParser.
+    pub fn create(
+        path: &str,
+        signal_names: &[String],
+        can_parser: &'a CanParser,
+        layout: Layout,
+    ) -> Result<Self, Error> {
+        let dbc = can_parser.dbc().ok_or(Error::NoDbc)?;
+        if signal_names.is_empty() {
+            return Err(Error::NoSignals);
+        }
+
+        let mut columns = Vec::new();
+        let mut message_columns: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+        for name in signal_names {
+            let (message_name, signal_name) = name
+                .split_once('.')
+                .ok_or_else(|| Error::UnknownSignal(name.clone()))?;
+            let signal = dbc
+                .messages()
+                .iter()
+                .find(|message| message.message_name() == message_name)
+                .and_then(|message| {
+                    message
+                        .signals()
+                        .iter()
+                        .find(|signal| signal.name() == signal_name)
+                })
+                .ok_or_else(|| Error::UnknownSignal(name.clone()))?;
+
+            message_columns
+                .entry(message_name.to_string())
+                .or_default()
+                .push((signal_name.to_string(), columns.len()));
+            columns.push(Column {
+                name: name.clone(),
+                unit: signal.unit().clone(),
+                samples: Vec::new(),
+            });
+        }
+
+        let mut output = BufWriter::new(File::create(path)?);
+        let mut header = String::from("time [s]");
+        for column in &columns {
+            header.push(',');
+            if column.unit.is_empty() {
+                header.push_str(&csv_field(&column.name));
+            } else {
+                header.push_str(&csv_field(&format!("{} [{}]", column.name, column.unit)));
+            }
+        }
+        writeln!(output, "{header}")?;
+
+        Ok(Self {
+            can_parser,
+            output,
+            layout,
+            columns,
+            message_columns,
+            rows_written: 0,
+        })
+    }
+
+    /// Adds the chosen signals of a frame, with its timestamp in microseconds since the start of the export.
+    /// Sparse rows are written in the order frames are pushed, while resampling sorts the samples by time first.
+    pub fn push(&mut self, frame: &CanFrame, timestamp_us: u64) -> Result<(), Error> {
+        let Some(message_info) = self.can_parser.message_for_frame(frame) else {
+            return Ok(());
+        };
+        let Some(signals) = self.message_columns.get(message_info.message_name()) else {
+            return Ok(());
+        };
+
+        let mut values = vec![None; self.columns.len()];
+        for (signal_name, column_index) in signals {
+            let column = &mut self.columns[*column_index];
+            let Some(value) =
+                self.can_parser
+                    .physical_signal_value(message_info, signal_name, frame)
+            else {
+                continue;
+            };
+
+            match self.layout {
+                Layout::Sparse => values[*column_index] = Some(value),
+                Layout::Resampled { .. } => column.samples.push((timestamp_us, value)),
+            }
+        }
+
+        if self.layout == Layout::Sparse && values.iter().any(Option::is_some) {
+            self.write_row(timestamp_us, &values)?;
+        }
+        Ok(())
+    }
+
+    /// Writes any resampled rows and flushes the file, returning the number of rows written
+    pub fn finish(mut self) -> Result<u64, Error> {
+        if let Layout::Resampled {
+            period_us,
+            interpolation,
+        } = self.layout
+        {
+            self.write_resampled(period_us.max(1), interpolation)?;
+        }
+        self.output.flush()?;
+        Ok(self.rows_written)
+    }
+
+    fn write_resampled(
+        &mut self,
+        period_us: u64,
+        interpolation: Interpolation,
+    ) -> Result<(), Error> {
+        // Frames of a log file may be slightly out of order, and interpolation needs the samples in time order
+        for column in &mut self.columns {
+            column.samples.sort_by_key(|(time_us, _)| *time_us);
+        }
+
+        let samples = self.columns.iter().map(|column| &column.samples);
+        let Some(start_us) = samples.clone().filter_map(|s| s.first()).map(|s| s.0).min() else {
+            return Ok(());
+        };
+        let end_us = samples
+            .filter_map(|s| s.last())
+            .map(|s| s.0)
+            .max()
+            .unwrap_or(start_us);
+
+        // The index of the first sample after the current time, for each column
+        let mut next_samples = vec![0; self.columns.len()];
+        let mut values = vec![None; self.columns.len()];
+        let mut time_us = start_us;
+        while time_us <= end_us {
+            for (index, column) in self.columns.iter().enumerate() {
+                let samples = &column.samples;
+                let next = &mut next_samples[index];
+                while *next < samples.len() && samples[*next].0 <= time_us {
+                    *next += 1;
+                }
+                values[index] = interpolate(samples, *next, time_us, interpolation);
+            }
+            self.write_row(time_us, &values)?;
+            time_us += period_us;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, timestamp_us: u64, values: &[Option<f64>]) -> Result<(), Error> {
+        let mut row = format!(
+            "{}.{:06}",
+            timestamp_us / 1_000_000,
+            timestamp_us % 1_000_000
+        );
+        for value in values {
+            row.push(',');
+            if let Some(value) = value {
+                row.push_str(&value.to_string());
+            }
+        }
+        writeln!(self.output, "{row}")?;
+        self.rows_written += 1;
+        Ok(())
+    }
+}
+
+// Returns a signal's value at a time, given the index of its first sample after that time
+fn interpolate(
+    samples: &[(u64, f64)],
+    next: usize,
+    time_us: u64,
+    interpolation: Interpolation,
+) -> Option<f64> {
+    let previous = next.checked_sub(1).map(|index| samples[index]);
+    let next = samples.get(next).copied();
+    match interpolation {
+        Interpolation::Previous => previous.map(|(_, value)| value),
+        Interpolation::Linear => match (previous, next) {
+            (Some((previous_us, previous_value)), Some((next_us, next_value))) => {
+                let fraction = (time_us - previous_us) as f64 / (next_us - previous_us) as f64;
+                Some(previous_value + (next_value - previous_value) * fraction)
+            }
+            // Values are only given outside of the samples at the time of the last sample
+            (Some((previous_us, previous_value)), None) if previous_us == time_us => {
+                Some(previous_value)
+            }
+            _ => None,
+        },
+        Interpolation::Nearest => match (previous, next) {
+            (Some((previous_us, previous_value)), Some((next_us, next_value))) => {
+                if time_us - previous_us <= next_us - time_us {
+                    Some(previous_value)
+                } else {
+                    Some(next_value)
+                }
+            }
+            (Some((_, value)), None) | (None, Some((_, value))) => Some(value),
+            (None, None) => None,
+        },
+    }
+}
+
+// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,495 @@
+///
+/// signal_generator.rs
+///
+/// Waveform generators that drive DBC signal values over time.
+/// Generators are evaluated each time the message they are attached to is transmitted, after which the message is
+/// encoded from the physical values of its signals.
+///
+use crate::can_parser::CanParser;
+use crate::new_can_frame;
+use can_dbc::{DBC, Message, MessageId, MultiplexIndicator, SignalExtendedValueType};
+use crosscan::can::CanFrame;
+use godot::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    UnknownWaveform(String),
+    InvalidParameters(String),
+    InvalidProfile(usize),
+    EmptyProfile,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "Error reading signal profile: {e}"),
+            Error::UnknownWaveform(name) => write!(f, "Unknown waveform {name:?}"),
+            Error::InvalidParameters(msg) => write!(f, "Invalid waveform parameters: {msg}"),
+            Error::InvalidProfile(line) => write!(
+                f,
+                "Signal profile is not in chronological order (line {line})"
+            ),
+            Error::EmptyProfile => write!(f, "Signal profile contains no time,value rows"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Waveform {
+    Constant {
+        value: f64,
+    },
+    /// Rises linearly from start to end over the period, then jumps back to start
+    Ramp {
+        start: f64,
+        end: f64,
+        period_s: f64,
+    },
+    Sine {
+        offset: f64,
+        amplitude: f64,
+        period_s: f64,
+    },
+    Square {
+        low: f64,
+        high: f64,
+        period_s: f64,
+        duty_cycle: f64,
+    },
+    /// Moves by a random amount of up to max_step each evaluation, staying within min and max
+    RandomWalk {
+        start: f64,
+        max_step: f64,
+        min: f64,
+        max: f64,
+    },
+    /// Holds each (duration_s, value) step in turn, repeating from the first step after the last
+    StepSequence {
+        steps: Vec<(f64, f64)>,
+    },
+    /// Linearly interpolates between (time_s, value) points, repeating from the start after the last point
+    Profile {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+impl Waveform {
+    /// Creates a waveform from its name and a flat list of parameters:
+    ///
+    /// - `constant`: value
+    /// - `ramp`: start, end, period_s
+    /// - `sine`: offset, amplitude, period_s
+    /// - `square`: low, high, period_s, duty_cycle
+    /// - `random_walk`: start, max_step, min, max
+    /// - `step`: duration_s, value, [duration_s, value, ...]
+    pub fn from_params(name: &str, params: &[f64]) -> Result<Self, Error> {
+        // NaN would slip through the range checks below, and infinities cannot be evaluated over time
+        if let Some(index) = params.iter().position(|param| !param.is_finite()) {
+            return Err(Error::InvalidParameters(format!(
+                "parameter {index} is not a finite number"
+            )));
+        }
+
+        let expect_params = |count: usize| {
+            if params.len() == count {
+                Ok(())
+            } else {
+                Err(Error::InvalidParameters(format!(
+                    "{name:?} expects {count} parameters, {} provided",
+                    params.len()
+                )))
+            }
+        };
+        let expect_positive = |param: &str, value: f64| {
+            if value > 0.0 {
+                Ok(value)
+            } else {
+                Err(Error::InvalidParameters(format!(
+                    "{param} must be greater than zero"
+                )))
+            }
+        };
+
+        match name {
+            "constant" => {
+                expect_params(1)?;
+                Ok(Waveform::Constant { value: params[0] })
+            }
+            "ramp" => {
+                expect_params(3)?;
+                Ok(Waveform::Ramp {
+                    start: params[0],
+                    end: params[1],
+                    period_s: expect_positive("period_s", params[2])?,
+                })
+            }
+            "sine" => {
+                expect_params(3)?;
+                Ok(Waveform::Sine {
+                    offset: params[0],
+                    amplitude: params[1],
+                    period_s: expect_positive("period_s", params[2])?,
+                })
+            }
+            "square" => {
+                expect_params(4)?;
+                Ok(Waveform::Square {
+                    low: params[0],
+                    high: params[1],
+                    period_s: expect_positive("period_s", params[2])?,
+                    duty_cycle: params[3].clamp(0.0, 1.0),
+                })
+            }
+            "random_walk" => {
+                expect_params(4)?;
+                if params[2] > params[3] {
+                    return Err(Error::InvalidParameters(
+                        "min must not be greater than max".to_string(),
+                    ));
+                }
+                Ok(Waveform::RandomWalk {
+                    start: params[0].clamp(params[2], params[3]),
+                    max_step: params[1].abs(),
+                    min: params[2],
+                    max: params[3],
+                })
+            }
+            "step" => {
+                if params.is_empty() || !params.len().is_multiple_of(2) {
+                    return Err(Error::InvalidParameters(
+                        "\"step\" expects pairs of duration_s and value".to_string(),
+                    ));
+                }
+                let steps = params
+                    .chunks(2)
+                    .map(|step| -> Result<(f64, f64), Error> {
+                        Ok((expect_positive("duration_s", step[0])?, step[1]))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Ok(Waveform::StepSequence { steps })
+            }
+            _ => Err(Error::UnknownWaveform(name.to_string())),
+        }
+    }
+
+    /// Loads a profile waveform from a CSV file of `time_s,value` rows. Rows that are not finite numbers (e.g. a header)
+    /// are skipped.
+    pub fn from_csv_profile(file_path: &str) -> Result<Self, Error> {
+        let mut file = File::open(file_path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for (line_idx, line) in contents.lines().enumerate() {
+            let mut columns = line.split(',').map(str::trim);
+            let (Some(time), Some(value)) = (columns.next(), columns.next()) else {
+                continue;
+            };
+            let (Ok(time_s), Ok(value)) = (time.parse::<f64>(), value.parse::<f64>()) else {
+                continue;
+            };
+            // Rows such as "nan,inf" parse as numbers, but are skipped like any other non-numeric row
+            if !time_s.is_finite() || !value.is_finite() {
+                continue;
+            }
+
+            // Points must be in chronological order
+            if points
+                .last()
+                .is_some_and(|(last_time_s, _)| time_s < *last_time_s)
+            {
+                return Err(Error::InvalidProfile(line_idx + 1));
+            }
+            points.push((time_s, value));
+        }
+
+        if points.is_empty() {
+            return Err(Error::EmptyProfile);
+        }
+
+        Ok(Waveform::Profile { points })
+    }
+
+    /// Creates a waveform from a Godot Dictionary of either a "waveform" name and its "params" (see `from_params`),
+    /// or the "profile" path of a CSV file (see `from_csv_profile`)
+    pub fn from_dictionary(generator: &Dictionary) -> Result<Self, Error> {
+        if let Some(profile) = generator.get("profile") {
+            let csv_filepath = profile.try_to::<GString>().map_err(|_| {
+                Error::InvalidParameters("\"profile\" must be a file path".to_string())
+            })?;
+            return Self::from_csv_profile(&csv_filepath.to_string());
+        }
+
+        let name = generator
+            .get("waveform")
+            .and_then(|name| name.try_to::<GString>().ok())
+            .ok_or_else(|| {
+                Error::InvalidParameters(
+                    "expected a \"waveform\" name or a \"profile\"".to_string(),
+                )
+            })?;
+        let params = match generator.get("params") {
+            Some(params) => params
+                .try_to::<PackedFloat64Array>()
+                .map_err(|_| {
+                    Error::InvalidParameters("\"params\" must be an array of numbers".to_string())
+                })?
+                .to_vec(),
+            None => Vec::new(),
+        };
+        Self::from_params(&name.to_string(), &params)
+    }
+}
+
+/// A waveform attached to a signal, along with the state required to evaluate it over time
+pub struct SignalGenerator {
+    waveform: Waveform,
+    start_time: Instant,
+    random_walk_value: f64,
+    rng_state: u64,
+}
+
+impl SignalGenerator {
+    pub fn new(waveform: Waveform) -> Self {
+        let random_walk_value = match waveform {
+            Waveform::RandomWalk { start, .. } => start,
+            _ => 0.0,
+        };
+
+        Self {
+            waveform,
+            start_time: Instant::now(),
+            random_walk_value,
+            // Seed from the clock so that separate random walks diverge
+            rng_state: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+                | 1,
+        }
+    }
+
+    /// Returns the physical value of the waveform at the current time
+    pub fn next_value(&mut self) -> f64 {
+        let elapsed_s = self.start_time.elapsed().as_secs_f64();
+
+        match &self.waveform {
+            Waveform::Constant { value } => *value,
+            Waveform::Ramp {
+                start,
+                end,
+                period_s,
+            } => start + (end - start) * (elapsed_s % period_s) / period_s,
+            Waveform::Sine {
+                offset,
+                amplitude,
+                period_s,
+            } => offset + amplitude * (std::f64::consts::TAU * elapsed_s / period_s).sin(),
+            Waveform::Square {
+                low,
+                high,
+                period_s,
+                duty_cycle,
+            } => {
+                if (elapsed_s % period_s) / period_s < *duty_cycle {
+                    *high
+                } else {
+                    *low
+                }
+            }
+            Waveform::RandomWalk {
+                max_step, min, max, ..
+            } => {
+                let step = (next_random(&mut self.rng_state) * 2.0 - 1.0) * max_step;
+                self.random_walk_value = (self.random_walk_value + step).clamp(*min, *max);
+                self.random_walk_value
+            }
+            Waveform::StepSequence { steps } => {
+                let total_s: f64 = steps.iter().map(|(duration_s, _)| duration_s).sum();
+                let mut remaining_s = elapsed_s % total_s;
+                for (duration_s, value) in steps {
+                    if remaining_s < *duration_s {
+                        return *value;
+                    }
+                    remaining_s -= duration_s;
+                }
+                steps.last().map(|(_, value)| *value).unwrap_or(0.0)
+            }
+            Waveform::Profile { points } => {
+                let total_s = points.last().map(|(time_s, _)| *time_s).unwrap_or(0.0);
+                let t = if total_s > 0.0 {
+                    elapsed_s % total_s
+                } else {
+                    0.0
+                };
+                interpolate_profile(points, t)
+            }
+        }
+    }
+}
+
+// Returns a pseudo-random number in [0, 1) using xorshift64
+fn next_random(rng_state: &mut u64) -> f64 {
+    *rng_state ^= *rng_state << 13;
+    *rng_state ^= *rng_state >> 7;
+    *rng_state ^= *rng_state << 17;
+    (*rng_state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Linearly interpolates the profile value at time t, holding the first and last values outside of the profile
+fn interpolate_profile(points: &[(f64, f64)], t: f64) -> f64 {
+    let next_idx = points.partition_point(|(time_s, _)| *time_s <= t);
+    if next_idx == 0 {
+        return points[0].1;
+    }
+    if next_idx == points.len() {
+        return points[points.len() - 1].1;
+    }
+
+    let (t0, v0) = points[next_idx - 1];
+    let (t1, v1) = points[next_idx];
+    if t1 - t0 <= 0.0 {
+        return v1;
+    }
+    v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+}
+
+/// A DBC message encoded from the physical values of its signals, some of which may be driven by generators
+pub struct GeneratedMessage {
+    message: Message,
+    value_types: Vec<SignalExtendedValueType>,
+    can_id: u32,
+    is_extended: bool,
+    // Physical values of each signal, keyed by signal name
+    signal_values: HashMap<String, f64>,
+    generators: HashMap<String, SignalGenerator>,
+    data: Vec<u8>,
+}
+
+impl GeneratedMessage {
+    /// Creates a message from the physical values of its signals, where signals without a value start from a raw
+    /// value of zero
+    pub fn new(dbc: &DBC, message: &Message, mut signal_values: HashMap<String, f64>) -> Self {
+        let value_types = message
+            .signals()
+            .iter()
+            .map(|signal| CanParser::signal_value_type(dbc, message.message_id(), signal.name()))
+            .collect();
+
+        let (can_id, is_extended) = match message.message_id() {
+            MessageId::Standard(id) => (*id as u32, false),
+            MessageId::Extended(id) => (*id, true),
+        };
+
+        for signal in message.signals() {
+            signal_values
+                .entry(signal.name().clone())
+                .or_insert(*signal.offset());
+        }
+
+        let data_length = (*message.message_size() as usize).min(8);
+        let mut generated_message = Self {
+            message: message.clone(),
+            value_types,
+            can_id,
+            is_extended,
+            signal_values,
+            generators: HashMap::new(),
+            data: vec![0; data_length],
+        };
+        generated_message.encode();
+        generated_message
+    }
+
+    pub fn name(&self) -> &str {
+        self.message.message_name()
+    }
+
+    pub fn has_signal(&self, signal_name: &str) -> bool {
+        self.signal_values.contains_key(signal_name)
+    }
+
+    /// Overrides the physical value of a signal, replacing any generator attached to it.
+    /// Returns false if the message has no such signal.
+    pub fn set_signal_value(&mut self, signal_name: &str, value: f64) -> bool {
+        if !self.has_signal(signal_name) {
+            return false;
+        }
+
+        self.generators.remove(signal_name);
+        self.signal_values.insert(signal_name.to_string(), value);
+        self.encode();
+        true
+    }
+
+    /// Drives a signal with a waveform generator. Returns false if the message has no such signal.
+    pub fn set_signal_generator(&mut self, signal_name: &str, waveform: Waveform) -> bool {
+        if !self.has_signal(signal_name) {
+            return false;
+        }
+
+        self.generators
+            .insert(signal_name.to_string(), SignalGenerator::new(waveform));
+        true
+    }
+
+    /// Stops a signal's generator, holding the signal at its last generated value
+    pub fn clear_signal_generator(&mut self, signal_name: &str) -> bool {
+        self.generators.remove(signal_name).is_some()
+    }
+
+    /// Evaluates the generators of the message and returns the frame encoded from the resulting signal values
+    pub fn next_frame(&mut self) -> Option<CanFrame> {
+        if !self.generators.is_empty() {
+            for (signal_name, generator) in self.generators.iter_mut() {
+                self.signal_values
+                    .insert(signal_name.clone(), generator.next_value());
+            }
+            self.encode();
+        }
+
+        new_can_frame(self.can_id, self.is_extended, &self.data)
+    }
+
+    // Re-encodes the frame data from the current signal values
+    fn encode(&mut self) {
+        // Multiplexed signals are only encoded when they match the current multiplexor value
+        let multiplexor_value = self
+            .message
+            .signals()
+            .iter()
+            .find(|s| matches!(s.multiplexer_indicator(), MultiplexIndicator::Multiplexor))
+            .and_then(|s| {
+                let physical_value = self.signal_values.get(s.name())?;
+                // A zero factor is treated as one, as in `CanParser::encode_signal`
+                let factor = if *s.factor() == 0.0 { 1.0 } else { *s.factor() };
+                Some(((physical_value - *s.offset()) / factor).round() as u64)
+            });
+
+        let mut data = vec![0; self.data.len()];
+        for (signal, value_type) in self.message.signals().iter().zip(&self.value_types) {
+            if let MultiplexIndicator::MultiplexedSignal(switch_value) =
+                signal.multiplexer_indicator()
+            {
+                if Some(*switch_value) != multiplexor_value {
+                    continue;
+                }
+            }
+
+            let physical_value = self.signal_values[signal.name()];
+            CanParser::encode_signal(signal, value_type, physical_value, &mut data);
+        }
+        self.data = data;
+    }
+}
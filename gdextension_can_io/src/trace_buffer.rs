@@ -0,0 +1,149 @@
+///
+/// trace_buffer.rs
+///
+/// A chronological trace of every frame in arrival order, used by the trace view.
+/// Each frame is given an index that stays the same for as long as the frame is stored, so that a view can page
+/// through the trace by index while the oldest frames are dropped once the trace reaches its capacity.
+/// The trace holds frames of every kind, including the error frames that the rest of the bridge ignores.
+///
+use crate::logging::{FrameKind, LogFrame};
+use crate::{CanId, Direction};
+use crosscan::can::CanFrame;
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_TRACE_CAPACITY: usize = 2_000_000;
+
+pub struct TraceFrame {
+    pub index: u64,
+    /// Microseconds since the bridge was started
+    pub timestamp_us: u128,
+    /// Microseconds since the previous frame of any ID
+    pub delta_us: u128,
+    /// Microseconds since the previous frame of the same ID, if there was one. Always None for error frames.
+    pub id_delta_us: Option<u128>,
+    /// The channel the frame was on, which is always 1 for the open bus but may differ for imported log files
+    pub channel: u8,
+    /// The error class bits for error frames
+    pub id: u32,
+    pub is_extended: bool,
+    pub kind: FrameKind,
+    pub data: Vec<u8>,
+    pub direction: Direction,
+}
+
+impl TraceFrame {
+    /// Returns the frame as a CanFrame, or None for remote, error and CAN FD frames which a CanFrame cannot represent
+    pub fn to_can_frame(&self) -> Option<CanFrame> {
+        match self.kind {
+            FrameKind::Data => crate::new_can_frame(self.id, self.is_extended, &self.data),
+            _ => None,
+        }
+    }
+}
+
+pub struct TraceBuffer {
+    frames: VecDeque<TraceFrame>,
+    capacity: usize,
+    next_index: u64,
+    last_timestamp_us: Option<u128>,
+    last_id_timestamps_us: HashMap<CanId, u128>,
+}
+
+impl TraceBuffer {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            capacity: DEFAULT_TRACE_CAPACITY,
+            next_index: 0,
+            last_timestamp_us: None,
+            last_id_timestamps_us: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, frame: CanFrame, channel: u8, direction: Direction, timestamp_us: u128) {
+        let frame = LogFrame::from_can_frame(&frame, FrameKind::Data, direction, 0);
+        self.push_kind(LogFrame { channel, ..frame }, timestamp_us);
+    }
+
+    /// Adds a frame of any kind, where error frames have the error class bits in place of the id.
+    /// The frame is traced at the given time since the bridge was started rather than at its own timestamp.
+    pub fn push_kind(&mut self, frame: LogFrame, timestamp_us: u128) {
+        let LogFrame {
+            channel,
+            id,
+            is_extended,
+            kind,
+            data,
+            direction,
+            ..
+        } = frame;
+        let delta_us = self
+            .last_timestamp_us
+            .map(|last| timestamp_us.saturating_sub(last))
+            .unwrap_or(0);
+        let id_delta_us = match kind {
+            FrameKind::Error => None,
+            _ => self
+                .last_id_timestamps_us
+                .insert(id, timestamp_us)
+                .map(|last| timestamp_us.saturating_sub(last)),
+        };
+        self.last_timestamp_us = Some(timestamp_us);
+
+        self.frames.push_back(TraceFrame {
+            index: self.next_index,
+            timestamp_us,
+            delta_us,
+            id_delta_us,
+            channel,
+            id,
+            is_extended,
+            kind,
+            data,
+            direction,
+        });
+        self.next_index += 1;
+
+        while self.frames.len() > self.capacity {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Sets the maximum number of frames stored, dropping the oldest frames if the trace is now over capacity
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.frames.len() > capacity {
+            self.frames.pop_front();
+        }
+    }
+
+    /// The index of the oldest stored frame
+    pub fn first_index(&self) -> u64 {
+        self.next_index - self.frames.len() as u64
+    }
+
+    /// The index that the next frame will be given
+    pub fn end_index(&self) -> u64 {
+        self.next_index
+    }
+
+    /// Returns up to count frames starting from the given index. Indices of frames that have already been dropped
+    /// start the page from the oldest stored frame instead.
+    pub fn page(&self, start_index: u64, count: usize) -> impl Iterator<Item = &TraceFrame> {
+        let len = self.frames.len();
+        let start = (start_index.saturating_sub(self.first_index()) as usize).min(len);
+        self.frames
+            .range(start..start.saturating_add(count).min(len))
+    }
+
+    /// Returns up to the last count frames, used to follow the tail of the trace
+    pub fn tail(&self, count: usize) -> impl Iterator<Item = &TraceFrame> {
+        self.frames.range(self.frames.len().saturating_sub(count)..)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.last_timestamp_us = None;
+        self.last_id_timestamps_us.clear();
+    }
+}
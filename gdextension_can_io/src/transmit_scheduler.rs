@@ -0,0 +1,233 @@
+///
+/// transmit_scheduler.rs
+///
+/// Timer driven cyclic transmission of CAN frames, independent of the Godot frame rate.
+/// Each entry is sent every period, offset by its phase from a common scheduler epoch, with a burst of
+/// back-to-back frames per period. The lateness of every send relative to its ideal time is tracked as TX jitter.
+/// An entry either sends a fixed frame, or a DBC message that is encoded again each period from its signals, which
+/// can be driven by waveform generators.
+///
+use crate::signal_generator::GeneratedMessage;
+use crate::tx_tracker::{self, TxRequest, TxSource, TxTracker};
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify, mpsc};
+
+pub type TransmitId = i64;
+
+pub struct TransmitScheduler {
+    entries: HashMap<TransmitId, ScheduledEntry>,
+    next_transmit_id: TransmitId,
+    epoch: Instant,
+}
+
+/// What an entry sends each period
+pub enum Payload {
+    Frame(CanFrame),
+    /// A DBC message whose generators are evaluated and which is encoded again each period
+    Message(GeneratedMessage),
+}
+
+impl Payload {
+    fn next_frame(&mut self) -> Option<CanFrame> {
+        match self {
+            Payload::Frame(frame) => Some(frame.clone()),
+            Payload::Message(generated_message) => generated_message.next_frame(),
+        }
+    }
+}
+
+struct ScheduledEntry {
+    payload: Payload,
+    period: Duration,
+    phase_offset: Duration,
+    burst_count: u32,
+    next_send: Instant,
+    stats: TransmitStats,
+}
+
+/// Timing statistics of a scheduled entry
+#[derive(Default, Clone, Copy)]
+pub struct TransmitStats {
+    pub frames_sent: u64,
+    pub jitter_last_us: u64,
+    pub jitter_max_us: u64,
+    jitter_total_us: u64,
+    jitter_samples: u64,
+}
+
+impl TransmitStats {
+    pub fn jitter_mean_us(&self) -> f64 {
+        if self.jitter_samples == 0 {
+            return 0.0;
+        }
+        self.jitter_total_us as f64 / self.jitter_samples as f64
+    }
+
+    fn record(&mut self, jitter: Duration, frames_sent: u32) {
+        let jitter_us = jitter.as_micros() as u64;
+        self.frames_sent += frames_sent as u64;
+        self.jitter_last_us = jitter_us;
+        self.jitter_max_us = self.jitter_max_us.max(jitter_us);
+        self.jitter_total_us += jitter_us;
+        self.jitter_samples += 1;
+    }
+}
+
+impl TransmitScheduler {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            next_transmit_id: 0,
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Schedules a payload for transmission. A period of zero sends a single burst and then removes the entry.
+    pub fn start(
+        &mut self,
+        payload: Payload,
+        period: Duration,
+        phase_offset: Duration,
+        burst_count: u32,
+    ) -> TransmitId {
+        let transmit_id = self.next_transmit_id;
+        self.next_transmit_id += 1;
+
+        let mut entry = ScheduledEntry {
+            payload,
+            period,
+            phase_offset,
+            burst_count: burst_count.max(1),
+            next_send: Instant::now(),
+            stats: TransmitStats::default(),
+        };
+        entry.next_send = self.first_send_time(&entry, Instant::now());
+        self.entries.insert(transmit_id, entry);
+
+        transmit_id
+    }
+
+    /// Stops and removes an entry. Returns false if the entry does not exist.
+    pub fn stop(&mut self, transmit_id: TransmitId) -> bool {
+        self.entries.remove(&transmit_id).is_some()
+    }
+
+    pub fn is_active(&self, transmit_id: TransmitId) -> bool {
+        self.entries.contains_key(&transmit_id)
+    }
+
+    pub fn stats(&self, transmit_id: TransmitId) -> Option<TransmitStats> {
+        self.entries.get(&transmit_id).map(|entry| entry.stats)
+    }
+
+    /// Returns the DBC message sent by an entry, or None if the entry does not exist or sends a fixed frame
+    pub fn message_mut(&mut self, transmit_id: TransmitId) -> Option<&mut GeneratedMessage> {
+        match &mut self.entries.get_mut(&transmit_id)?.payload {
+            Payload::Message(generated_message) => Some(generated_message),
+            Payload::Frame(_) => None,
+        }
+    }
+
+    /// Realigns every entry to a new epoch, used when transmission resumes after the bus was closed
+    pub fn restart(&mut self) {
+        let now = Instant::now();
+        self.epoch = now;
+        for entry in self.entries.values_mut() {
+            entry.next_send = now + entry.phase_offset;
+        }
+    }
+
+    /// Returns the frames of every entry that is due, measuring how late each entry is relative to its ideal time
+    fn take_due_frames(&mut self, now: Instant) -> Vec<(TxSource, CanFrame)> {
+        let mut frames = Vec::new();
+        let mut finished = Vec::new();
+
+        for (transmit_id, entry) in self.entries.iter_mut() {
+            if entry.next_send > now {
+                continue;
+            }
+
+            // Every frame of a burst carries the same signal values
+            if let Some(frame) = entry.payload.next_frame() {
+                for _ in 0..entry.burst_count {
+                    frames.push((TxSource::Scheduled(*transmit_id), frame.clone()));
+                }
+            }
+            entry.stats.record(now - entry.next_send, entry.burst_count);
+
+            if entry.period.is_zero() {
+                finished.push(*transmit_id);
+                continue;
+            }
+
+            // Stay aligned to the epoch, skipping any periods that were missed entirely
+            entry.next_send += entry.period;
+            if entry.next_send <= now {
+                let missed_periods =
+                    (now - entry.next_send).as_nanos() / entry.period.as_nanos() + 1;
+                entry.next_send += periods(entry.period, missed_periods);
+            }
+        }
+
+        for transmit_id in finished {
+            self.entries.remove(&transmit_id);
+        }
+
+        frames
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.entries.values().map(|entry| entry.next_send).min()
+    }
+
+    // Returns the first time at or after now that lies on the entry's period grid
+    fn first_send_time(&self, entry: &ScheduledEntry, now: Instant) -> Instant {
+        let first = self.epoch + entry.phase_offset;
+        if entry.period.is_zero() || first >= now {
+            return first.max(now);
+        }
+
+        let periods_elapsed = (now - first).as_nanos().div_ceil(entry.period.as_nanos());
+        first + periods(entry.period, periods_elapsed)
+    }
+}
+
+// Returns the duration of a number of periods, computed in nanoseconds so large counts don't truncate
+fn periods(period: Duration, count: u128) -> Duration {
+    Duration::from_nanos(u64::try_from(period.as_nanos() * count).unwrap_or(u64::MAX))
+}
+
+/// Sends the scheduled frames at their due times until the task is aborted or the bus is closed.
+/// The scheduler must be notified whenever an entry is started so that the task can wake up early.
+pub async fn run_scheduler(
+    scheduler: Arc<Mutex<TransmitScheduler>>,
+    scheduler_notify: Arc<Notify>,
+    tx_tracker: Arc<Mutex<TxTracker>>,
+    transmit_sender: mpsc::UnboundedSender<TxRequest>,
+) {
+    loop {
+        let next_deadline = {
+            let mut scheduler = scheduler.lock().await;
+            let frames = scheduler.take_due_frames(Instant::now());
+
+            // The bus has been closed, so there is nothing left to transmit to
+            if !tx_tracker::queue_frames(&tx_tracker, &transmit_sender, frames).await {
+                return;
+            }
+            scheduler.next_deadline()
+        };
+
+        match next_deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)) => {}
+                    _ = scheduler_notify.notified() => {}
+                }
+            }
+            None => scheduler_notify.notified().await,
+        }
+    }
+}
@@ -0,0 +1,430 @@
+///
+/// trigger.rs
+///
+/// Trigger conditions for triggered recordings, which save a window of frames before and after the trigger fires.
+/// A trigger fires on a frame with a specific id, on frame data matching a pattern under a mask, on a DBC signal
+/// crossing a threshold, or on an error frame, including the error frames recorded for errors reported by the
+/// interface.
+///
+use crate::acceptance_filter::CAN_ERR_MASK;
+use crate::can_parser::CanParser;
+use crate::frame_validation::{MAX_EXTENDED_ID, MAX_FD_DATA_LENGTH, MAX_STANDARD_ID};
+use crosscan::can::CanFrame;
+use godot::prelude::*;
+
+const DEFAULT_WINDOW_MS: f64 = 1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Either,
+}
+
+impl Edge {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Edge::Rising => "rising",
+            Edge::Falling => "falling",
+            Edge::Either => "either",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Edge::Rising, Edge::Falling, Edge::Either]
+            .into_iter()
+            .find(|edge| edge.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerCondition {
+    Id {
+        id: u32,
+        is_extended: bool,
+    },
+    /// Data bytes are compared wherever a bit is set in the mask. Any frame can match if no id is given.
+    /// Patterns may be as long as the data of a CAN FD frame.
+    DataPattern {
+        id: Option<(u32, bool)>,
+        pattern: Vec<u8>,
+        mask: Vec<u8>,
+    },
+    /// Compares the physical value of the signal, with its factor and offset applied
+    SignalThreshold {
+        message_name: String,
+        signal_name: String,
+        threshold: f64,
+        edge: Edge,
+    },
+    /// Fires on error frames with any of the error class bits of the mask set
+    ErrorFrame {
+        error_mask: u32,
+    },
+}
+
+impl std::fmt::Display for TriggerCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match self {
+            TriggerCondition::Id { id, .. } => write!(f, "id {id:#X}"),
+            TriggerCondition::DataPattern { id, pattern, mask } => {
+                write!(f, "data {} mask {}", hex(pattern), hex(mask))?;
+                match id {
+                    Some((id, _)) => write!(f, " on id {id:#X}"),
+                    None => Ok(()),
+                }
+            }
+            TriggerCondition::SignalThreshold {
+                message_name,
+                signal_name,
+                threshold,
+                edge,
+            } => write!(
+                f,
+                "{message_name}.{signal_name} crossing {threshold} ({})",
+                edge.name()
+            ),
+            TriggerCondition::ErrorFrame { error_mask } => {
+                if *error_mask == CAN_ERR_MASK {
+                    write!(f, "error frame")
+                } else {
+                    write!(f, "error frame of classes 0x{error_mask:X}")
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerState {
+    /// Buffering frames, waiting for the trigger to fire
+    Armed,
+    /// Writing frames until the post-trigger window ends
+    Capturing,
+    /// A single-shot capture has been written
+    Complete,
+}
+
+impl TriggerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerState::Armed => "armed",
+            TriggerState::Capturing => "capturing",
+            TriggerState::Complete => "complete",
+        }
+    }
+
+    pub fn from_index(index: u8) -> Self {
+        match index {
+            0 => TriggerState::Armed,
+            1 => TriggerState::Capturing,
+            _ => TriggerState::Complete,
+        }
+    }
+
+    pub fn index(&self) -> u8 {
+        match self {
+            TriggerState::Armed => 0,
+            TriggerState::Capturing => 1,
+            TriggerState::Complete => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TriggerOptions {
+    pub condition: TriggerCondition,
+    /// Frames recorded up to this long before the trigger fires are saved
+    pub pre_trigger_us: u128,
+    /// Frames recorded up to this long after the trigger fires are saved
+    pub post_trigger_us: u128,
+    /// Re-arm once the post-trigger window ends, rather than finishing the recording after a single capture
+    pub rearm: bool,
+}
+
+impl TriggerOptions {
+    /// Creates options from a Godot Dictionary with a "condition" of "id", "data", "signal" or "error", the
+    /// "pre_trigger_ms" and "post_trigger_ms" windows (1000 by default), and a "rearm" flag.
+    ///
+    /// - "id" conditions take an integer "id" and an "extended" flag.
+    /// - "data" conditions take an array of "data" bytes, an optional array of "mask" bytes (all bits by
+    ///   default), and an optional "id" and "extended" flag.
+    /// - "signal" conditions take a "message" and "signal" name from the loaded DBC, a physical "threshold" and
+    ///   an "edge" of "rising", "falling" or "either" (the default).
+    /// - "error" conditions take an optional "error_mask" of error class bits (all classes by default).
+    pub fn from_dictionary(options: &Dictionary, can_parser: &CanParser) -> Result<Self, String> {
+        let flag = |key: &str| {
+            options
+                .get(key)
+                .and_then(|value| value.try_to::<bool>().ok())
+                .unwrap_or(false)
+        };
+        // Variants are converted strictly, so whole numbers are read as integers
+        let number = |key: &str| -> Result<Option<f64>, String> {
+            options
+                .get(key)
+                .map(|value| {
+                    value
+                        .try_to::<f64>()
+                        .or_else(|_| value.try_to::<i64>().map(|number| number as f64))
+                        .map_err(|_| format!("The trigger {key:?} must be a number"))
+                })
+                .transpose()
+        };
+        let text = |key: &str| {
+            options
+                .get(key)
+                .and_then(|value| value.try_to::<GString>().ok())
+                .map(|text| text.to_string())
+        };
+        let window_us = |key: &str| -> Result<u128, String> {
+            let window_ms = number(key)?.unwrap_or(DEFAULT_WINDOW_MS);
+            if !window_ms.is_finite() || window_ms < 0.0 {
+                return Err(format!(
+                    "The trigger {key:?} must be a non-negative number of milliseconds"
+                ));
+            }
+            Ok((window_ms * 1000.0) as u128)
+        };
+
+        let condition = match text("condition").as_deref() {
+            Some("id") => {
+                let Some(id) = options
+                    .get("id")
+                    .and_then(|value| value.try_to::<i64>().ok())
+                else {
+                    return Err("Id triggers require an integer \"id\"".to_string());
+                };
+                let is_extended = flag("extended");
+                TriggerCondition::Id {
+                    id: validated_id(id, is_extended)?,
+                    is_extended,
+                }
+            }
+            Some("data") => {
+                let pattern = bytes(options, "data")?.ok_or_else(|| {
+                    "Data triggers require an array of \"data\" bytes".to_string()
+                })?;
+                let mask = bytes(options, "mask")?.unwrap_or_else(|| vec![0xFF; pattern.len()]);
+                if pattern.is_empty() || pattern.len() > MAX_FD_DATA_LENGTH {
+                    return Err(format!(
+                        "Data triggers require 1 to {MAX_FD_DATA_LENGTH} \"data\" bytes"
+                    ));
+                }
+                if mask.len() != pattern.len() {
+                    return Err(
+                        "The trigger \"mask\" must have as many bytes as \"data\"".to_string()
+                    );
+                }
+                let id = match options.get("id") {
+                    Some(value) => {
+                        let id = value
+                            .try_to::<i64>()
+                            .map_err(|_| "The trigger \"id\" must be an integer".to_string())?;
+                        let is_extended = flag("extended");
+                        Some((validated_id(id, is_extended)?, is_extended))
+                    }
+                    None => None,
+                };
+                TriggerCondition::DataPattern { id, pattern, mask }
+            }
+            Some("signal") => {
+                let (Some(message_name), Some(signal_name), Some(threshold)) =
+                    (text("message"), text("signal"), number("threshold")?)
+                else {
+                    return Err(
+                        "Signal triggers require a \"message\", \"signal\" and \"threshold\""
+                            .to_string(),
+                    );
+                };
+                let edge = match text("edge") {
+                    Some(name) => Edge::from_name(&name).ok_or_else(|| {
+                        format!("Unknown trigger edge {name:?}, expected rising, falling or either")
+                    })?,
+                    None => Edge::Either,
+                };
+
+                let Some(dbc) = can_parser.dbc() else {
+                    return Err("Signal triggers require a loaded DBC file".to_string());
+                };
+                let has_signal = dbc
+                    .messages()
+                    .iter()
+                    .find(|message| message.message_name() == &message_name)
+                    .is_some_and(|message| {
+                        message
+                            .signals()
+                            .iter()
+                            .any(|signal| signal.name() == &signal_name)
+                    });
+                if !has_signal {
+                    return Err(format!(
+                        "The DBC file has no signal {signal_name:?} in message {message_name:?}"
+                    ));
+                }
+
+                TriggerCondition::SignalThreshold {
+                    message_name,
+                    signal_name,
+                    threshold,
+                    edge,
+                }
+            }
+            Some("error") => {
+                let error_mask = match options.get("error_mask") {
+                    Some(value) => match value.try_to::<i64>() {
+                        Ok(mask) if mask > 0 && mask <= i64::from(CAN_ERR_MASK) => mask as u32,
+                        _ => {
+                            return Err(format!(
+                                "The trigger \"error_mask\" must be an integer from 0x1 to 0x{CAN_ERR_MASK:X}"
+                            ));
+                        }
+                    },
+                    None => CAN_ERR_MASK,
+                };
+                TriggerCondition::ErrorFrame { error_mask }
+            }
+            Some(name) => {
+                return Err(format!(
+                    "Unknown trigger condition {name:?}, expected id, data, signal or error"
+                ));
+            }
+            None => return Err("Triggers require a \"condition\"".to_string()),
+        };
+
+        Ok(Self {
+            condition,
+            pre_trigger_us: window_us("pre_trigger_ms")?,
+            post_trigger_us: window_us("post_trigger_ms")?,
+            rearm: flag("rearm"),
+        })
+    }
+}
+
+/// Evaluates a trigger condition against the recorded frames, keeping the last value of a threshold signal so that
+/// crossings can be detected
+pub struct Trigger {
+    condition: TriggerCondition,
+    previous_signal_value: Option<f64>,
+}
+
+impl Trigger {
+    pub fn new(condition: TriggerCondition) -> Self {
+        Self {
+            condition,
+            previous_signal_value: None,
+        }
+    }
+
+    pub fn condition(&self) -> &TriggerCondition {
+        &self.condition
+    }
+
+    /// Checks whether the frame fires the trigger. Every frame must be checked, including those recorded while a
+    /// capture is in progress, as signal crossings are found by comparing consecutive values.
+    pub fn fires_on_frame(&mut self, frame: &CanFrame, can_parser: &CanParser) -> bool {
+        match &self.condition {
+            TriggerCondition::Id { id, is_extended } => {
+                frame.id() == *id && frame.is_extended() == *is_extended
+            }
+            TriggerCondition::DataPattern { id, pattern, mask } => {
+                let id_matches = id.is_none_or(|(id, is_extended)| {
+                    frame.id() == id && frame.is_extended() == is_extended
+                });
+                // Masked bytes beyond the end of the frame data never match
+                id_matches
+                    && pattern
+                        .iter()
+                        .zip(mask)
+                        .enumerate()
+                        .all(|(index, (byte, mask))| {
+                            *mask == 0
+                                || frame
+                                    .data()
+                                    .get(index)
+                                    .is_some_and(|data| data & mask == byte & mask)
+                        })
+            }
+            TriggerCondition::SignalThreshold {
+                message_name,
+                signal_name,
+                threshold,
+                edge,
+            } => {
+                let Some(value) = can_parser
+                    .message_for_frame(frame)
+                    .filter(|message| message.message_name() == message_name)
+                    .and_then(|message| {
+                        can_parser.physical_signal_value(message, signal_name, frame)
+                    })
+                else {
+                    return false;
+                };
+
+                // The first value only sets the starting side of the threshold
+                let previous = self.previous_signal_value.replace(value);
+                previous.is_some_and(|previous| {
+                    let rising = previous < *threshold && value >= *threshold;
+                    let falling = previous > *threshold && value <= *threshold;
+                    match edge {
+                        Edge::Rising => rising,
+                        Edge::Falling => falling,
+                        Edge::Either => rising || falling,
+                    }
+                })
+            }
+            TriggerCondition::ErrorFrame { .. } => false,
+        }
+    }
+
+    /// Checks whether an error frame with the given error class bits fires the trigger
+    pub fn fires_on_error_frame(&self, error_class: u32) -> bool {
+        match self.condition {
+            TriggerCondition::ErrorFrame { error_mask } => error_class & error_mask != 0,
+            _ => false,
+        }
+    }
+}
+
+// Checks the id range for the frame type, as frames of any other id could never fire the trigger
+fn validated_id(id: i64, is_extended: bool) -> Result<u32, String> {
+    let max_id = if is_extended {
+        MAX_EXTENDED_ID
+    } else {
+        MAX_STANDARD_ID
+    };
+    if !(0..=max_id).contains(&id) {
+        return Err(format!(
+            "The trigger id {id:#X} is outside the range of 0x0 to {max_id:#X}"
+        ));
+    }
+    Ok(id as u32)
+}
+
+// Reads an array of bytes given as a PackedByteArray or an Array of integers
+fn bytes(options: &Dictionary, key: &str) -> Result<Option<Vec<u8>>, String> {
+    let Some(value) = options.get(key) else {
+        return Ok(None);
+    };
+    if let Ok(bytes) = value.try_to::<PackedByteArray>() {
+        return Ok(Some(bytes.to_vec()));
+    }
+
+    let invalid = || format!("The trigger {key:?} must be an array of integers from 0 to 255");
+    let array = value.try_to::<VariantArray>().map_err(|_| invalid())?;
+    array
+        .iter_shared()
+        .map(|byte| match byte.try_to::<i64>() {
+            Ok(byte) if (0..=255).contains(&byte) => Ok(byte as u8),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
@@ -0,0 +1,210 @@
+///
+/// tx_tracker.rs
+///
+/// Tracks the outcome of every frame queued for transmission.
+/// Each frame is given an id when queued, and is later marked as sent, failed or dropped by the CAN I/O thread.
+/// On interfaces that loop sent frames back, a written frame is only marked as sent once its echo is read.
+///
+use crate::transmit_scheduler::TransmitId;
+use crosscan::can::CanFrame;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, mpsc};
+
+pub type TxId = i64;
+
+// Outcomes of older frames are forgotten once this many frames have been queued since
+const MAX_TRACKED_OUTCOMES: usize = 10_000;
+
+// A written frame whose echo has not arrived after this long is marked as sent at the time it was written
+const ECHO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where a queued frame originated from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxSource {
+    Manual,
+    Scheduled(TransmitId),
+    Restbus,
+}
+
+/// A frame queued for transmission on the CAN I/O thread
+pub struct TxRequest {
+    pub tx_id: TxId,
+    pub source: TxSource,
+    pub frame: CanFrame,
+}
+
+#[derive(Debug, Clone)]
+pub enum TxOutcome {
+    Pending,
+    /// Sent, timestamped in microseconds since the bridge was started. On interfaces that loop sent frames back
+    /// (SocketCAN) this is when the echo was received, otherwise it is the software time the frame was written to
+    /// the interface, which on the virtual bus is also when it was delivered to the other nodes.
+    Sent {
+        timestamp_us: u128,
+    },
+    Failed(String),
+    /// Never written because the bus was closed before the frame could be sent
+    Dropped,
+}
+
+/// Number of successful and failed transmissions of a scheduled entry
+#[derive(Default, Clone, Copy)]
+pub struct TxCounts {
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+pub struct TxTracker {
+    next_tx_id: TxId,
+    outcomes: HashMap<TxId, TxOutcome>,
+    outcome_order: VecDeque<TxId>,
+    entry_counts: HashMap<TransmitId, TxCounts>,
+}
+
+impl TxTracker {
+    pub fn new() -> Self {
+        Self {
+            next_tx_id: 0,
+            outcomes: HashMap::new(),
+            outcome_order: VecDeque::new(),
+            entry_counts: HashMap::new(),
+        }
+    }
+
+    /// Assigns an id to a frame that is about to be queued and marks it as pending
+    pub fn register(&mut self, source: TxSource, frame: CanFrame) -> TxRequest {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id += 1;
+
+        self.outcomes.insert(tx_id, TxOutcome::Pending);
+        self.outcome_order.push_back(tx_id);
+        if self.outcome_order.len() > MAX_TRACKED_OUTCOMES {
+            if let Some(oldest) = self.outcome_order.pop_front() {
+                self.outcomes.remove(&oldest);
+            }
+        }
+
+        TxRequest {
+            tx_id,
+            source,
+            frame,
+        }
+    }
+
+    pub fn record(&mut self, tx_id: TxId, source: TxSource, outcome: TxOutcome) {
+        if let TxSource::Scheduled(transmit_id) = source {
+            let counts = self.entry_counts.entry(transmit_id).or_default();
+            match outcome {
+                TxOutcome::Sent { .. } => counts.succeeded += 1,
+                TxOutcome::Failed(_) | TxOutcome::Dropped => counts.failed += 1,
+                TxOutcome::Pending => {}
+            }
+        }
+
+        if let Some(existing) = self.outcomes.get_mut(&tx_id) {
+            *existing = outcome;
+        }
+    }
+
+    /// Returns the outcome of a frame, or None if the id is unknown or too old to still be tracked
+    pub fn outcome(&self, tx_id: TxId) -> Option<&TxOutcome> {
+        self.outcomes.get(&tx_id)
+    }
+
+    pub fn entry_counts(&self, transmit_id: TransmitId) -> TxCounts {
+        self.entry_counts
+            .get(&transmit_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn clear_entry_counts(&mut self, transmit_id: TransmitId) {
+        self.entry_counts.remove(&transmit_id);
+    }
+}
+
+/// A frame written to an interface that loops sent frames back, waiting for its echo
+pub struct AwaitingEcho {
+    pub request: TxRequest,
+    /// When the frame was written, in microseconds since the bridge was started
+    pub written_us: u128,
+    deadline: Instant,
+}
+
+/// Frames waiting for their echo, in the order they were written
+#[derive(Default)]
+pub struct EchoQueue {
+    frames: VecDeque<AwaitingEcho>,
+}
+
+impl EchoQueue {
+    pub fn push(&mut self, request: TxRequest, written_us: u128) {
+        self.frames.push_back(AwaitingEcho {
+            request,
+            written_us,
+            deadline: Instant::now() + ECHO_TIMEOUT,
+        });
+    }
+
+    /// Removes the oldest frame that a received frame echoes, returning it after the frames written before it,
+    /// or None if the received frame is not one of ours. Interfaces echo frames in the order they were written,
+    /// so the echoes of the earlier frames will not arrive. Another node sending an identical frame before our
+    /// echo arrives is indistinguishable from the echo.
+    pub fn take_echoed(&mut self, frame: &CanFrame) -> Option<(Vec<AwaitingEcho>, AwaitingEcho)> {
+        let position = self.frames.iter().position(|awaiting| {
+            let written = &awaiting.request.frame;
+            written.id() == frame.id()
+                && written.is_extended() == frame.is_extended()
+                && written.data() == frame.data()
+        })?;
+        let mut earlier: Vec<AwaitingEcho> = self.frames.drain(..=position).collect();
+        let echoed = earlier.pop()?;
+        Some((earlier, echoed))
+    }
+
+    /// Returns when the oldest frame stops waiting for its echo
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.frames.front().map(|awaiting| awaiting.deadline)
+    }
+
+    /// Removes the frames that have waited too long for their echo
+    pub fn take_expired(&mut self, now: Instant) -> Vec<AwaitingEcho> {
+        let expired = self
+            .frames
+            .iter()
+            .take_while(|awaiting| awaiting.deadline <= now)
+            .count();
+        self.frames.drain(..expired).collect()
+    }
+
+    pub fn take_all(&mut self) -> Vec<AwaitingEcho> {
+        self.frames.drain(..).collect()
+    }
+}
+
+/// Registers and queues frames for transmission. Returns false, marking the frames as dropped, if the bus has been closed.
+pub async fn queue_frames(
+    tx_tracker: &Mutex<TxTracker>,
+    transmit_sender: &mpsc::UnboundedSender<TxRequest>,
+    frames: Vec<(TxSource, CanFrame)>,
+) -> bool {
+    if frames.is_empty() {
+        return true;
+    }
+
+    let mut tx_tracker = tx_tracker.lock().await;
+    let mut bus_open = true;
+    for (source, frame) in frames {
+        let request = tx_tracker.register(source, frame);
+        if bus_open {
+            if let Err(mpsc::error::SendError(request)) = transmit_sender.send(request) {
+                bus_open = false;
+                tx_tracker.record(request.tx_id, request.source, TxOutcome::Dropped);
+            }
+        } else {
+            tx_tracker.record(request.tx_id, request.source, TxOutcome::Dropped);
+        }
+    }
+    bus_open
+}
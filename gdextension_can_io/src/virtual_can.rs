@@ -0,0 +1,330 @@
+///
+/// virtual_can.rs
+///
+/// An in-process virtual CAN bus that requires no hardware, kernel vcan interface or root privileges.
+/// Every node opened on the same bus name receives the frames transmitted by all other nodes on that bus.
+///
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Interface names starting with this prefix are opened as virtual buses, e.g. `virtual:demo`
+pub const VIRTUAL_INTERFACE_PREFIX: &str = "virtual:";
+
+// Number of frames buffered per node before the slowest node starts dropping frames
+const BUS_CAPACITY: usize = 4096;
+
+// All virtual buses that currently have at least one node attached, keyed by bus name
+static VIRTUAL_BUSES: LazyLock<Mutex<HashMap<String, VirtualBus>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+struct VirtualBus {
+    sender: broadcast::Sender<VirtualFrame>,
+    next_node_id: usize,
+}
+
+#[derive(Clone)]
+struct VirtualFrame {
+    sender_node_id: usize,
+    frame: CanFrame,
+}
+
+/// A single node attached to a virtual CAN bus
+pub struct VirtualCan {
+    bus_name: String,
+    node_id: usize,
+    sender: broadcast::Sender<VirtualFrame>,
+    receiver: broadcast::Receiver<VirtualFrame>,
+}
+
+impl VirtualCan {
+    /// Attaches a new node to the virtual bus with the given name, creating the bus if it does not exist yet
+    pub fn open(bus_name: &str) -> Self {
+        let mut buses = VIRTUAL_BUSES.lock().unwrap();
+        let bus = buses
+            .entry(bus_name.to_string())
+            .or_insert_with(|| VirtualBus {
+                sender: broadcast::channel(BUS_CAPACITY).0,
+                next_node_id: 0,
+            });
+
+        let node_id = bus.next_node_id;
+        bus.next_node_id += 1;
+
+        Self {
+            bus_name: bus_name.to_string(),
+            node_id,
+            sender: bus.sender.clone(),
+            receiver: bus.sender.subscribe(),
+        }
+    }
+
+    /// Returns the virtual bus name portion of an interface name, or None if it does not name a virtual bus
+    pub fn bus_name_from_interface(interface_name: &str) -> Option<&str> {
+        interface_name.strip_prefix(VIRTUAL_INTERFACE_PREFIX)
+    }
+
+    /// Waits for the next frame transmitted by any other node on this bus
+    pub async fn read_frame(&mut self) -> CanFrame {
+        loop {
+            match self.receiver.recv().await {
+                Ok(virtual_frame) => {
+                    // Nodes do not receive their own transmissions
+                    if virtual_frame.sender_node_id != self.node_id {
+                        return virtual_frame.frame;
+                    }
+                }
+                // Frames are dropped when this node falls too far behind, as on a real overloaded receiver
+                Err(RecvError::Lagged(_)) => continue,
+                // Unreachable as this node holds a sender to the bus
+                Err(RecvError::Closed) => std::future::pending::<()>().await,
+            }
+        }
+    }
+
+    /// Transmits a frame to every other node on this bus
+    pub fn write_frame(&mut self, frame: CanFrame) {
+        // Sending only fails when there are no receivers, which cannot occur as this node is subscribed
+        let _ = self.sender.send(VirtualFrame {
+            sender_node_id: self.node_id,
+            frame,
+        });
+    }
+}
+
+impl Drop for VirtualCan {
+    fn drop(&mut self) {
+        // Remove the bus from the registry once its last node has detached
+        let mut buses = VIRTUAL_BUSES.lock().unwrap();
+        if self.sender.receiver_count() <= 1 {
+            buses.remove(&self.bus_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptance_filter::FilterSet;
+    use crate::can_parser::CanParser;
+    use crate::can_socket::{CanSocket, SocketFrame};
+    use crate::frame_history::FrameHistory;
+    use crate::trace_buffer::TraceBuffer;
+    use crate::tx_tracker::{TxOutcome, TxSource, TxTracker};
+    use crate::{CanIoState, Direction, new_can_frame, read_can};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::sync::{Mutex, Notify, mpsc, oneshot};
+    use tokio::time::timeout;
+
+    // Every test uses its own bus names, as tests run in parallel against the same bus registry
+    async fn open_interface(interface_name: &str) -> CanSocket {
+        CanSocket::open(interface_name).await.unwrap()
+    }
+
+    // Returns the next frame received by a socket, or None if no frame arrives shortly
+    async fn try_read_frame(socket: &mut CanSocket) -> Option<CanFrame> {
+        match timeout(Duration::from_millis(50), socket.read_frame()).await {
+            Ok(Ok(SocketFrame::Data(frame))) => Some(frame),
+            Ok(result) => panic!(
+                "Virtual buses only carry data frames, read {:?}",
+                result.err()
+            ),
+            Err(_) => None,
+        }
+    }
+
+    fn bus_exists(bus_name: &str) -> bool {
+        VIRTUAL_BUSES.lock().unwrap().contains_key(bus_name)
+    }
+
+    #[tokio::test]
+    async fn interfaces_with_the_same_name_share_a_bus() {
+        let mut first = open_interface("virtual:shared").await;
+        let mut second = open_interface("virtual:shared").await;
+        let mut unrelated = open_interface("virtual:unrelated").await;
+
+        let frame = new_can_frame(0x123, false, &[1, 2, 3]).unwrap();
+        first.write_frame(frame).await.unwrap();
+
+        let received = try_read_frame(&mut second).await.unwrap();
+        assert_eq!(received.id(), 0x123);
+        assert_eq!(received.data(), [1, 2, 3]);
+        assert!(try_read_frame(&mut unrelated).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn frames_loop_back_to_every_other_node() {
+        let mut nodes = Vec::new();
+        for _ in 0..3 {
+            nodes.push(open_interface("virtual:loopback").await);
+        }
+
+        for (sender, can_id) in [(0, 0x100), (2, 0x102)] {
+            let frame = new_can_frame(can_id, false, &[0xAA]).unwrap();
+            nodes[sender].write_frame(frame).await.unwrap();
+
+            for (receiver, node) in nodes.iter_mut().enumerate() {
+                let received = try_read_frame(node).await;
+                if receiver == sender {
+                    assert!(received.is_none(), "node {receiver} received its own frame");
+                } else {
+                    assert_eq!(received.map(|frame| frame.id()), Some(can_id));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bus_is_torn_down_with_its_last_node() {
+        let mut first = open_interface("virtual:teardown").await;
+        let second = open_interface("virtual:teardown").await;
+        assert!(bus_exists("teardown"));
+
+        // Left unread by the second node, so it is still queued when the bus is torn down
+        first
+            .write_frame(new_can_frame(0x100, false, &[]).unwrap())
+            .await
+            .unwrap();
+
+        drop(first);
+        assert!(bus_exists("teardown"));
+        drop(second);
+        assert!(!bus_exists("teardown"));
+
+        // The frame sent before the teardown is not delivered to a bus opened again under the same name
+        let mut sender = open_interface("virtual:teardown").await;
+        let mut receiver = open_interface("virtual:teardown").await;
+        assert!(try_read_frame(&mut receiver).await.is_none());
+        sender
+            .write_frame(new_can_frame(0x7FF, false, &[]).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            try_read_frame(&mut receiver).await.map(|frame| frame.id()),
+            Some(0x7FF)
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_decode_over_the_virtual_bus() {
+        let mut can_parser = CanParser::new();
+        can_parser
+            .open_dbc(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/imu.dbc").to_string())
+            .unwrap();
+
+        let mut sender = open_interface("virtual:decode").await;
+        let mut receiver = open_interface("virtual:decode").await;
+        let product_info = [0x78, 0x56, 0x34, 0x12, 0x03, 0x00, 0x02, 0x01];
+        sender
+            .write_frame(new_can_frame(0x2, true, &product_info).unwrap())
+            .await
+            .unwrap();
+
+        let received = try_read_frame(&mut receiver).await.unwrap();
+        let message = can_parser.message_for_frame(&received).unwrap();
+        assert_eq!(message.message_name(), "ProductInfo");
+        let signal_values = ["part_no", "patch", "minor", "major"]
+            .map(|signal_name| can_parser.physical_signal_value(message, signal_name, &received));
+        assert_eq!(
+            signal_values,
+            [Some(305_419_896.0), Some(3.0), Some(2.0), Some(1.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_can_tracks_frames_on_the_virtual_bus() {
+        let io_state = CanIoState {
+            bitrate: Arc::new(Mutex::new(0)),
+            bit_counter: Arc::new(Mutex::new(0)),
+            can_entries: Arc::new(Mutex::new(HashMap::new())),
+            frame_history: Arc::new(Mutex::new(FrameHistory::new())),
+            trace_buffer: Arc::new(Mutex::new(TraceBuffer::new())),
+            start_time: Arc::new(Mutex::new(Instant::now())),
+            tx_tracker: Arc::new(Mutex::new(TxTracker::new())),
+            show_transmitted_frames: Arc::new(Mutex::new(true)),
+            acceptance_filters: Arc::new(Mutex::new(FilterSet::default())),
+            filters_notify: Arc::new(Notify::new()),
+            kernel_filtering: Arc::new(Mutex::new(false)),
+            recorder: Arc::new(Mutex::new(None)),
+        };
+        let can_entries = Arc::clone(&io_state.can_entries);
+        let frame_history = Arc::clone(&io_state.frame_history);
+        let tx_tracker = Arc::clone(&io_state.tx_tracker);
+
+        let (transmit_sender, transmit_receiver) = mpsc::unbounded_channel();
+        let (close_sender, close_receiver) = oneshot::channel();
+        let read_handle = tokio::spawn(read_can(
+            "virtual:read_can".to_string(),
+            io_state,
+            transmit_receiver,
+            close_receiver,
+        ));
+        let mut peer = open_interface("virtual:read_can").await;
+        // The reader must have joined the bus before the peer sends, or it misses the frames
+        while VIRTUAL_BUSES.lock().unwrap()["read_can"]
+            .sender
+            .receiver_count()
+            < 2
+        {
+            tokio::task::yield_now().await;
+        }
+
+        for (can_id, data) in [(0x123, [1]), (0x456, [2]), (0x123, [3])] {
+            peer.write_frame(new_can_frame(can_id, false, &data).unwrap())
+                .await
+                .unwrap();
+        }
+        let transmitted = new_can_frame(0x7FF, false, &[4]).unwrap();
+        let request = tx_tracker
+            .lock()
+            .await
+            .register(TxSource::Manual, transmitted);
+        let tx_id = request.tx_id;
+        transmit_sender.send(request).unwrap();
+        assert_eq!(
+            try_read_frame(&mut peer).await.map(|frame| frame.id()),
+            Some(0x7FF)
+        );
+
+        timeout(Duration::from_secs(1), async {
+            while frame_history.lock().await.total_frames() < 4 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("read_can did not track every frame");
+        close_sender.send(()).unwrap();
+        read_handle.await.unwrap();
+
+        let can_entries = can_entries.lock().await;
+        let mut ids: Vec<_> = can_entries.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, [0x123, 0x456, 0x7FF]);
+        assert_eq!(can_entries[&0x123].frame.data(), [3]);
+        assert_eq!(can_entries[&0x123].timestamps.len(), 2);
+        assert_eq!(can_entries[&0x7FF].direction, Direction::Tx);
+
+        let frame_history = frame_history.lock().await;
+        let history: Vec<_> = frame_history
+            .last_frames(0x123, 10)
+            .iter()
+            .map(|history_frame| (history_frame.frame.data().to_vec(), history_frame.direction))
+            .collect();
+        assert_eq!(
+            history,
+            [(vec![1], Direction::Rx), (vec![3], Direction::Rx)]
+        );
+        assert_eq!(
+            frame_history.last_frames(0x7FF, 10)[0].direction,
+            Direction::Tx
+        );
+        assert!(matches!(
+            tx_tracker.lock().await.outcome(tx_id),
+            Some(TxOutcome::Sent { .. })
+        ));
+    }
+}
@@ -0,0 +1,25 @@
+extends LineEdit
+class_name FrameFilterBox
+
+@export_category("Node References")
+@export var _can_bridge: GodotCanBridge
+
+@export_category("Filter")
+## The output the filter expression is attached to
+@export_enum("table", "plot", "recording") var target: String = "table"
+
+
+func _ready() -> void:
+	self.text_submitted.connect(_apply_filter)
+	self.focus_exited.connect(func(): _apply_filter(self.text))
+
+
+# Attaches the expression to the filter target, highlighting the box if the expression is invalid
+func _apply_filter(expression: String) -> void:
+	var result: Dictionary = _can_bridge.set_frame_filter(target, expression)
+	if result["ok"]:
+		remove_theme_color_override("font_color")
+		self.tooltip_text = ""
+	else:
+		add_theme_color_override("font_color", Color.RED)
+		self.tooltip_text = "%s (at character %d)" % [result["error"], result["position"]]
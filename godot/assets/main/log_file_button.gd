@@ -0,0 +1,105 @@
+extends Button
+class_name LogFileButton
+
+@export_category("Node References")
+@export var _can_bridge: GodotCanBridge
+@export var _receive_table: ReceiveTable
+@export var _pause_button: PauseButton
+
+@export_category("Log File")
+## Whether the button imports a log file for offline analysis in place of the current traffic, or exports the trace
+## to a log file
+@export_enum("import", "export") var mode: String = "import"
+
+# Log file formats that can be imported or exported, selected by the extension of the chosen file
+const IMPORT_FILE_FILTERS: PackedStringArray = [
+	"*.asc, *.blf; Vector Log Files",
+	"*.trc; PEAK Trace Files",
+	"*.pcap, *.pcapng; Wireshark Capture Files",
+	"*.mf4; ASAM MDF4 Measurement Files",
+	"*.parquet; Apache Parquet Files",
+	"*.mcap; MCAP Files",
+	"*.db, *.sqlite; SQLite Capture Databases",
+	"*.log; candump Log Files",
+]
+const EXPORT_FILE_FILTERS: PackedStringArray = [
+	"*.asc; Vector ASCII Log Files",
+	"*.blf; Vector Binary Log Files",
+	"*.trc; PEAK Trace Files",
+	"*.pcapng; Wireshark pcapng Capture Files",
+	"*.pcap; Wireshark pcap Capture Files",
+	"*.mf4; ASAM MDF4 Measurement Files",
+	"*.parquet; Apache Parquet Files",
+	"*.mcap; MCAP Files",
+	"*.db, *.sqlite; SQLite Capture Databases",
+	"*.log; candump Log Files",
+]
+# Export file dialog check box choosing whether formats that can hold decoded signals also store them
+const DECODED_SIGNALS_OPTION: String = "Include Decoded Signals"
+
+# Log files are imported in the background, during which the button cancels the import
+var _importing: bool = false
+var _idle_text: String
+
+
+func _ready() -> void:
+	_idle_text = text
+	self.pressed.connect(_button_pressed)
+
+
+func _process(_delta: float) -> void:
+	if mode != "import":
+		return
+
+	# Importing replaces the received traffic, so it is only possible while the bus is closed
+	disabled = not _pause_button.is_paused()
+	if _importing:
+		_update_import()
+
+
+func _button_pressed() -> void:
+	if _importing:
+		_can_bridge.cancel_log_import()
+		return
+
+	var file_dialog = FileDialog.new()
+	add_child(file_dialog)
+	file_dialog.access = FileDialog.ACCESS_FILESYSTEM
+	if mode == "import":
+		file_dialog.set_file_mode(file_dialog.FILE_MODE_OPEN_FILE)
+		file_dialog.filters = IMPORT_FILE_FILTERS
+	else:
+		file_dialog.set_file_mode(file_dialog.FILE_MODE_SAVE_FILE)
+		file_dialog.filters = EXPORT_FILE_FILTERS
+		file_dialog.add_option(DECODED_SIGNALS_OPTION, [], 0)
+	file_dialog.popup()
+	file_dialog.file_selected.connect(func(file_path: String): _process_file(file_path, file_dialog))
+
+
+func _process_file(file_path: String, file_dialog: FileDialog) -> void:
+	if mode == "export":
+		var decode_signals := bool(file_dialog.get_selected_options()[DECODED_SIGNALS_OPTION])
+		_can_bridge.export_trace(file_path, decode_signals) # This emits an alert if the file cannot be written
+		return
+
+	# This emits an alert if the file cannot be opened
+	if not _can_bridge.import_log_file(file_path):
+		return
+	_importing = true
+	_receive_table.reload()
+	_pause_button.update_tab_selectability()
+
+
+# Shows the progress of the import, reloading the table with every imported frame once it has stopped
+func _update_import() -> void:
+	var info: Dictionary = _can_bridge.get_offline_log_info()
+	if info.get("state", "") == "importing":
+		text = "Cancel Import (%d frames)" % info["frame_count"]
+		return
+
+	_importing = false
+	text = _idle_text
+	_receive_table.reload()
+	# Frames read before an error remain imported
+	if info.get("state", "") == "failed":
+		AlertHandler.display_error("Failed to import all of %s: %s" % [info["path"], info["error"]])
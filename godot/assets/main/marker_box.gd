@@ -0,0 +1,17 @@
+extends LineEdit
+class_name MarkerBox
+
+@export_category("Node References")
+@export var _can_bridge: GodotCanBridge
+
+
+func _ready() -> void:
+	self.text_submitted.connect(_add_marker)
+
+
+# Marks the current time in the recording with the entered note, keeping the note if nothing is being recorded
+func _add_marker(note: String) -> void:
+	if note.strip_edges().is_empty():
+		return
+	if _can_bridge.add_recording_marker(note):
+		self.clear()
@@ -20,7 +20,7 @@ func _ready() -> void:
 	self.pressed.connect(_button_pressed)
 	_is_paused = true
 	_update_text()
-	_update_tab_selectability()
+	update_tab_selectability()
 	_update_dbc_editability()
 
 
@@ -47,9 +47,13 @@ func _button_pressed() -> void:
 		if not dbc_load_success:
 			return
 
+		# Opening the bus closes any loaded log file, whose frames are then cleared from the table
+		var was_offline: bool = _can_bridge.is_offline()
 		var can_up_success: bool = _can_bridge.configure_bus(_interface_box.text)
 		if not can_up_success:
 			return
+		if was_offline:
+			_receive_table.reload()
 	else:
 		_can_bridge.close_bus()
 
@@ -61,7 +65,7 @@ func _toggle_pause() -> void:
 	_is_paused = not _is_paused
 	_update_text()
 	_status_bar.update_text()
-	_update_tab_selectability()
+	update_tab_selectability()
 	_update_dbc_editability()
 
 
@@ -73,10 +77,10 @@ func _update_text() -> void:
 
 
 # Updates the selectability of the tabs (Can only use the tabs when an interface is actively connected)
-func _update_tab_selectability() -> void:
+func update_tab_selectability() -> void:
 	var should_disable_tabs: bool = _is_paused and _receive_table.is_empty()
 
-	const NUM_TABS: int = 3
+	const NUM_TABS: int = 4
 	for i in range(1, NUM_TABS):
 		_tab_container.set_tab_disabled(i, should_disable_tabs)
 
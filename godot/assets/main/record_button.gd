@@ -0,0 +1,72 @@
+extends Button
+class_name RecordButton
+
+@export_category("Node References")
+@export var _can_bridge: GodotCanBridge
+
+# Log file formats that can be recorded to, selected by the extension of the chosen file
+const FILE_FILTERS: PackedStringArray = [
+	"*.log; candump Log Files",
+	"*.asc; Vector ASCII Log Files",
+	"*.blf; Vector Binary Log Files",
+	"*.trc; PEAK Trace Files",
+	"*.pcapng; Wireshark pcapng Capture Files",
+	"*.pcap; Wireshark pcap Capture Files",
+	"*.mf4; ASAM MDF4 Measurement Files",
+	"*.parquet; Apache Parquet Files",
+	"*.mcap; MCAP Files",
+	"*.db, *.sqlite; SQLite Capture Databases",
+]
+# File dialog check box choosing whether formats that can hold decoded signals also store them
+const DECODED_SIGNALS_OPTION: String = "Include Decoded Signals"
+
+var _is_recording: bool
+
+
+func _ready() -> void:
+	self.pressed.connect(_button_pressed)
+	_update_text(0)
+
+
+func _process(_delta: float) -> void:
+	if not _is_recording:
+		return
+
+	var status: Dictionary = _can_bridge.get_recording_status()
+	if not status["recording"]:
+		# The recording stopped on its own because writing failed, stopping it reports the error
+		_can_bridge.stop_recording()
+		_is_recording = false
+	_update_text(status["frames_written"])
+
+
+func _button_pressed() -> void:
+	if _is_recording:
+		_can_bridge.stop_recording()
+		_is_recording = false
+		_update_text(0)
+		return
+
+	var file_dialog = FileDialog.new()
+	add_child(file_dialog)
+	file_dialog.set_file_mode(file_dialog.FILE_MODE_SAVE_FILE)
+	file_dialog.access = FileDialog.ACCESS_FILESYSTEM
+	file_dialog.filters = FILE_FILTERS
+	file_dialog.add_option(DECODED_SIGNALS_OPTION, [], 0)
+	file_dialog.popup()
+	file_dialog.file_selected.connect(func(file_path: String): _start_recording(file_path, file_dialog))
+
+
+func _start_recording(file_path: String, file_dialog: FileDialog) -> void:
+	var decode_signals := bool(file_dialog.get_selected_options()[DECODED_SIGNALS_OPTION])
+	_is_recording = _can_bridge.start_recording(file_path, decode_signals) # This emits an alert if the file cannot be created
+	_update_text(0)
+
+
+func _update_text(frames_written: int) -> void:
+	if _is_recording:
+		text = "Stop Recording (%d)" % frames_written
+		tooltip_text = _can_bridge.get_recording_status()["path"]
+	else:
+		text = "Record"
+		tooltip_text = ""
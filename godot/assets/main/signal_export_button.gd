@@ -0,0 +1,123 @@
+extends Button
+class_name SignalExportButton
+
+@export_category("Node References")
+@export var _can_bridge: GodotCanBridge
+
+# Log file formats that signals can be exported from, instead of the frame history
+const LOG_FILE_FILTERS: PackedStringArray = [
+	"*.asc, *.blf; Vector Log Files",
+	"*.trc; PEAK Trace Files",
+	"*.pcap, *.pcapng; Wireshark Capture Files",
+	"*.mf4; ASAM MDF4 Measurement Files",
+	"*.parquet; Apache Parquet Files",
+	"*.mcap; MCAP Files",
+	"*.db, *.sqlite; SQLite Capture Databases",
+	"*.log; candump Log Files",
+]
+# Interpolations in the order they are listed in the dialog
+const INTERPOLATIONS: PackedStringArray = ["previous", "linear", "nearest"]
+const SOURCE_FRAME_HISTORY: int = 0
+
+var _dialog: ConfirmationDialog
+var _signal_list: ItemList
+var _source_button: OptionButton
+var _resample_check_box: CheckBox
+var _period_box: SpinBox
+var _interpolation_button: OptionButton
+
+
+func _ready() -> void:
+	self.pressed.connect(_button_pressed)
+	_build_dialog()
+
+
+func _build_dialog() -> void:
+	_dialog = ConfirmationDialog.new()
+	_dialog.title = "Export Signals to CSV"
+	_dialog.ok_button_text = "Export"
+	_dialog.confirmed.connect(_choose_source)
+	add_child(_dialog)
+
+	var layout := VBoxContainer.new()
+	_dialog.add_child(layout)
+
+	_signal_list = ItemList.new()
+	_signal_list.select_mode = ItemList.SELECT_MULTI
+	_signal_list.custom_minimum_size = Vector2(420, 240)
+	_signal_list.tooltip_text = "Hold Ctrl or Shift to choose several signals"
+	layout.add_child(_labelled("Signals", _signal_list))
+
+	_source_button = OptionButton.new()
+	_source_button.add_item("Frame History")
+	_source_button.add_item("Log File...")
+	layout.add_child(_labelled("Source", _source_button))
+
+	_resample_check_box = CheckBox.new()
+	_resample_check_box.text = "Resample every"
+	_resample_check_box.tooltip_text = "Unchecked writes a row for each frame carrying a chosen signal"
+	_period_box = SpinBox.new()
+	_period_box.min_value = 0.1
+	_period_box.max_value = 60000.0
+	_period_box.step = 0.1
+	_period_box.value = 10.0
+	_period_box.suffix = "ms"
+	_interpolation_button = OptionButton.new()
+	for interpolation in INTERPOLATIONS:
+		_interpolation_button.add_item(interpolation.capitalize())
+	var resample_row := HBoxContainer.new()
+	resample_row.add_child(_resample_check_box)
+	resample_row.add_child(_period_box)
+	resample_row.add_child(_interpolation_button)
+	layout.add_child(resample_row)
+
+
+func _labelled(label_text: String, control: Control) -> VBoxContainer:
+	var container := VBoxContainer.new()
+	var label := Label.new()
+	label.text = label_text
+	container.add_child(label)
+	container.add_child(control)
+	return container
+
+
+func _button_pressed() -> void:
+	_signal_list.clear()
+	for signal_name in _can_bridge.get_dbc_signal_names():
+		_signal_list.add_item(signal_name)
+	_dialog.popup_centered()
+
+
+func _choose_source() -> void:
+	if _source_button.selected == SOURCE_FRAME_HISTORY:
+		_choose_csv_file("")
+		return
+
+	var file_dialog = FileDialog.new()
+	add_child(file_dialog)
+	file_dialog.set_file_mode(file_dialog.FILE_MODE_OPEN_FILE)
+	file_dialog.access = FileDialog.ACCESS_FILESYSTEM
+	file_dialog.filters = LOG_FILE_FILTERS
+	file_dialog.popup()
+	file_dialog.file_selected.connect(_choose_csv_file)
+
+
+func _choose_csv_file(log_file_path: String) -> void:
+	var file_dialog = FileDialog.new()
+	add_child(file_dialog)
+	file_dialog.set_file_mode(file_dialog.FILE_MODE_SAVE_FILE)
+	file_dialog.access = FileDialog.ACCESS_FILESYSTEM
+	file_dialog.filters = ["*.csv; CSV Files"]
+	file_dialog.popup()
+	file_dialog.file_selected.connect(func(file_path: String): _export(file_path, log_file_path))
+
+
+func _export(file_path: String, log_file_path: String) -> void:
+	var signal_names := PackedStringArray()
+	for index in _signal_list.get_selected_items():
+		signal_names.append(_signal_list.get_item_text(index))
+
+	var period_s: float = _period_box.value / 1000.0 if _resample_check_box.button_pressed else 0.0
+	var interpolation: String = INTERPOLATIONS[_interpolation_button.selected]
+	# This emits an alert if no signals were chosen, or if the files cannot be read or written
+	_can_bridge.export_signals_csv(file_path, signal_names, log_file_path, period_s, interpolation)
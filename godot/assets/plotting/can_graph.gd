@@ -43,6 +43,14 @@ func add_data_point(entry: ReceiveTable.ReceiveTableEntry, label: String, timest
 	_plot_elements[get_element_id(entry, label)].add_point(Vector2(timestamp, value))
 
 
+# Replaces every data point of a plot element currently being plotted, such as with the history of a loaded log file
+func set_data_points(entry: ReceiveTable.ReceiveTableEntry, label: String, points: PackedVector2Array) -> void:
+	var element: PlotItem = _plot_elements[get_element_id(entry, label)]
+	element.remove_all()
+	for point in points:
+		element.add_point(point)
+
+
 # A 'plot element' consists of the CAN_ID the series comes from, concatenated with the data label
 func get_element_id(entry: ReceiveTable.ReceiveTableEntry, label: String) -> String:
 	return entry.formatted_can_id() + label
@@ -19,6 +19,7 @@ func _process(delta: float) -> void:
 	# Update bitrate and loading every second
 	update_timer_s += delta
 	if update_timer_s >= LOADING_UPDATE_PERIOD_S:
+		connection_label.text = _get_status_text()
 		bitrate_label.text = _get_bitrate_text()
 		bus_loading_label.text = _get_busloading_text(update_timer_s)
 		
@@ -35,6 +36,9 @@ func _get_status_text() -> String:
 	if godot_can_bridge.is_alive():
 		return "Connected to " + godot_can_bridge.get_interface()
 	
+	if godot_can_bridge.is_offline():
+		return "Offline: " + str(godot_can_bridge.get_offline_log_info()["path"]).get_file()
+	
 	return "Disconnected"
 
 
@@ -23,7 +23,13 @@ const FREQUENCY_IDX = 1
 const CAN_ID_IDX = 2
 const MSG_NAME_IDX = 3
 const DATA_START_IDX = 4
-const IS_EXTENDED_IDX = -1
+const IS_EXTENDED_IDX = -4
+const DIRECTION_IDX = -3
+const TABLE_FILTER_IDX = -2
+const PLOT_FILTER_IDX = -1
+
+# Number of trailing flag elements after the data in each frame received from the CAN bridge
+const TRAILING_FLAG_COUNT = 4
 
 const CELL_HEIGHT = 25
 const CELL_WIDTHS = [100, 80, 100, 100, 80]
@@ -49,7 +55,7 @@ func _unhandled_input(event: InputEvent) -> void:
 		var last_mouse_pos = get_global_mouse_position()
 		for entry: ReceiveTableEntry in existing_can_entries.values():
 			var control_rect: Rect2 = entry.get_row().get_global_rect()
-			if control_rect.has_point(last_mouse_pos):
+			if entry.get_row().visible and control_rect.has_point(last_mouse_pos):
 				context_menu.add_item("Clear: " + entry.formatted_can_id(), clear_row.bind(entry.id()))
 				continue
 		
@@ -88,15 +94,27 @@ func render(data: Array) -> void:
 
 # Clears all rows from the table
 func clear_all() -> void:
-	# Clear rows and entries from Godot side
-	for entry: ReceiveTableEntry in existing_can_entries.values():
-		entry.get_row().queue_free()
-	existing_can_entries.clear()
+	_clear_rows()
 
 	# Clear entries from rust side
 	godot_can_bridge.clear_can_table()
 
 
+# Rebuilds the table from the entries on the rust side, used when they are replaced by an imported log file
+func reload() -> void:
+	_clear_rows()
+	# Frames of a log file are timed from its first frame, so its timestamps are shown as they are
+	starting_timestamp = 0 if godot_can_bridge.is_offline() else -1
+	render(godot_can_bridge.get_can_table())
+
+
+# Clears rows and entries from Godot side
+func _clear_rows() -> void:
+	for entry: ReceiveTableEntry in existing_can_entries.values():
+		entry.get_row().queue_free()
+	existing_can_entries.clear()
+
+
 # Clears a specific row from the table
 func clear_row(can_id: int) -> void:
 	if not existing_can_entries.has(can_id):
@@ -121,6 +139,14 @@ func update_formatting() -> void:
 	rows.get_child(0).get_child(FREQUENCY_IDX).get_node("Label").text = "FREQ [Hz]" if time_format_button.format_on() else "T [ms]"
 
 
+# Toggles plotting of a signal. While a log file is loaded the signal's whole history is plotted at once, as no more
+# frames will arrive.
+func toggle_plot_element(entry: ReceiveTableEntry, label: String) -> void:
+	can_graph.toggle_plot_element(entry, label)
+	if godot_can_bridge.is_offline() and can_graph.has_plot_element(entry, label):
+		can_graph.set_data_points(entry, label, godot_can_bridge.get_signal_history(entry.id(), label))
+
+
 # Converts a microsecond system timestamp to seconds from start of program
 func timestamp_to_s(timestamp: String) -> float:
 	return (int(timestamp) - starting_timestamp) * 1e-6
@@ -193,6 +219,9 @@ class ReceiveTableEntry:
 	var _can_id: int
 	var _msg_name: String
 	var _is_extended: bool
+	var _is_transmitted: bool
+	var _shown_in_table: bool
+	var _shown_in_plot: bool
 	var _data: Array[String]
 	var _row: Node
 	var _receive_table: ReceiveTable
@@ -209,7 +238,7 @@ class ReceiveTableEntry:
 
 		if self.is_deserialised():
 			# For deserialised data we need to add buttons to enable logging
-			for i in len(frame) - 1:
+			for i in len(frame) - TRAILING_FLAG_COUNT:
 				var cell_width = CELL_WIDTHS[i] if i < DATA_START_IDX else CELL_WIDTHS[DATA_START_IDX]
 				var cell_size = Vector2(cell_width, CELL_HEIGHT)
 
@@ -217,7 +246,7 @@ class ReceiveTableEntry:
 				var is_button = (i >= DATA_START_IDX) and (i % 2 == 0)
 				if is_button:
 					cell = _receive_table.table_button.instantiate()
-					cell.pressed.connect(_receive_table.can_graph.toggle_plot_element.bind(self, str(frame[i])))
+					cell.pressed.connect(_receive_table.toggle_plot_element.bind(self, str(frame[i])))
 				else:
 					cell = _receive_table.table_cell.instantiate()
 
@@ -227,7 +256,7 @@ class ReceiveTableEntry:
 
 		else:
 			# For unknown data we just print the raw bytes as labels
-			for i in len(frame) - 1:
+			for i in len(frame) - TRAILING_FLAG_COUNT:
 				var cell_width = CELL_WIDTHS[i] if i < DATA_START_IDX else CELL_WIDTHS[DATA_START_IDX]
 				var cell_size = Vector2(cell_width, CELL_HEIGHT)
 
@@ -254,8 +283,11 @@ class ReceiveTableEntry:
 
 		_msg_name = new_frame[MSG_NAME_IDX]
 		_is_extended = new_frame[IS_EXTENDED_IDX].to_lower() == "true"
+		_is_transmitted = new_frame[DIRECTION_IDX] == "TX"
+		_shown_in_table = new_frame[TABLE_FILTER_IDX] == "true"
+		_shown_in_plot = new_frame[PLOT_FILTER_IDX] == "true"
 		_data = []
-		for i in range(DATA_START_IDX, len(new_frame) - 1):
+		for i in range(DATA_START_IDX, len(new_frame) - TRAILING_FLAG_COUNT):
 			_data.append(new_frame[i])
 
 		# Always make sure a row exists before updating labels
@@ -266,6 +298,9 @@ class ReceiveTableEntry:
 		else:
 			update_labels()
 
+		# Rows filtered out of the table are hidden rather than removed, so they can still feed the plot
+		_row.visible = _shown_in_table
+
 
 	func id() -> int:
 		return _can_id
@@ -289,7 +324,7 @@ class ReceiveTableEntry:
 		ReceiveTable._update_label_and_font_size(entry_row_cells[TIMESTAMP_IDX].get_node("Label"), "%.3f" % _last_receive_time_ms, CELL_WIDTHS[TIMESTAMP_IDX])
 		ReceiveTable._update_label_and_font_size(entry_row_cells[FREQUENCY_IDX].get_node("Label"), _formatted_frequency(), CELL_WIDTHS[FREQUENCY_IDX])
 		ReceiveTable._update_label_and_font_size(entry_row_cells[CAN_ID_IDX].get_node("Label"), formatted_can_id(), CELL_WIDTHS[CAN_ID_IDX])
-		ReceiveTable._update_label_and_font_size(entry_row_cells[MSG_NAME_IDX].get_node("Label"), _msg_name, CELL_WIDTHS[MSG_NAME_IDX])
+		ReceiveTable._update_label_and_font_size(entry_row_cells[MSG_NAME_IDX].get_node("Label"), _formatted_msg_name(), CELL_WIDTHS[MSG_NAME_IDX])
 	
 		# If the payload is empty, then display an empty string
 		if len(_data) == 1 and _data[0] == "":
@@ -307,7 +342,7 @@ class ReceiveTableEntry:
 
 					# If the can graph is plotting this data point, forward it to the graph
 					var label: String = _data[i - 1]
-					if _receive_table.can_graph.has_plot_element(self, label):
+					if _shown_in_plot and _receive_table.can_graph.has_plot_element(self, label):
 						_receive_table.can_graph.add_data_point(self, label, _last_receive_time_ms, float(_data[i]))
 			else:
 				# For regular labels, update with CAN byte formatting
@@ -329,6 +364,12 @@ class ReceiveTableEntry:
 		else:
 			return "0d" + ("%03d" % byte)
 
+	# Marks frames that we transmitted ourselves so they can be told apart from received frames
+	func _formatted_msg_name() -> String:
+		if _is_transmitted:
+			return "[TX] " + _msg_name
+		return _msg_name
+
 	func _formatted_frequency() -> String:
 		if _receive_table.time_format_button.format_on():
 			return "%.2f" % _frequency_hz
@@ -0,0 +1,156 @@
+extends Control
+class_name TraceTable
+
+
+@export_category("Node References")
+@export var godot_can_bridge: GodotCanBridge
+@export var can_id_format_button: CanFormatButton
+@export var can_data_format_button: CanFormatButton
+
+@onready var table_row = preload("res://assets/tables/table_row.tscn")
+@onready var table_cell = preload("res://assets/tables/table_cell.tscn")
+@onready var rows: VBoxContainer = get_node("Rows")
+@onready var scroll_bar: VScrollBar = get_node("ScrollBar")
+@onready var follow_check_box: CheckBox = get_node("Toolbar/FollowCheckBox")
+@onready var clear_button: Button = get_node("Toolbar/ClearButton")
+@onready var frame_count_label: Label = get_node("Toolbar/FrameCountLabel")
+
+const INDEX_IDX = 0
+const TIME_IDX = 1
+const DELTA_IDX = 2
+const ID_DELTA_IDX = 3
+const DIRECTION_IDX = 4
+const KIND_IDX = 5
+const CAN_ID_IDX = 6
+const MSG_NAME_IDX = 7
+const DATA_IDX = 8
+
+const CELL_HEIGHT = 25
+const CELL_WIDTHS = [90, 100, 90, 100, 50, 90, 100, 120, 250]
+const HEADER_LABELS = ["INDEX", "TIME [s]", "DELTA [ms]", "ID DELTA [ms]", "DIR", "TYPE", "CAN ID", "MSG NAME", "DATA"]
+
+# Number of frames scrolled per mouse wheel step
+const WHEEL_SCROLL_STEP = 3
+
+# The labels of each displayed row. Rows are reused as the trace scrolls, so only the visible page is ever rendered.
+var _row_labels: Array[Array] = []
+
+
+func _ready() -> void:
+	_generate_header_row()
+	clear_button.pressed.connect(godot_can_bridge.clear_trace)
+
+	# Dragging the scroll bar stops following the newest frames
+	scroll_bar.scrolling.connect(func(): follow_check_box.button_pressed = false)
+
+
+func _process(_delta: float) -> void:
+	if not is_visible_in_tree():
+		return
+
+	_update_row_count()
+
+	var info: Dictionary = godot_can_bridge.get_trace_info()
+	var first_index: int = info["first_index"]
+	var end_index: int = info["end_index"]
+	frame_count_label.text = "%d frames" % (end_index - first_index)
+
+	var page_size := _row_labels.size()
+	scroll_bar.min_value = first_index
+	scroll_bar.max_value = end_index
+	scroll_bar.page = page_size
+	if follow_check_box.button_pressed:
+		scroll_bar.set_value_no_signal(max(end_index - page_size, first_index))
+
+	# Only the visible page of frames is requested from the CAN bridge
+	var frames: Array[Dictionary] = godot_can_bridge.get_trace_page(int(scroll_bar.value), page_size)
+	for i in range(page_size):
+		if i < frames.size():
+			_show_frame(_row_labels[i], frames[i])
+		else:
+			for label: Label in _row_labels[i]:
+				label.text = ""
+
+
+func _gui_input(event: InputEvent) -> void:
+	# Scroll through the trace with the mouse wheel, which stops following the newest frames
+	if event is InputEventMouseButton and event.is_pressed():
+		var step := 0
+		if event.button_index == MOUSE_BUTTON_WHEEL_UP:
+			step = -WHEEL_SCROLL_STEP
+		elif event.button_index == MOUSE_BUTTON_WHEEL_DOWN:
+			step = WHEEL_SCROLL_STEP
+
+		if step != 0:
+			follow_check_box.button_pressed = false
+			scroll_bar.value += step
+			accept_event()
+
+
+# Adds or removes rows so that the table fills the available height
+func _update_row_count() -> void:
+	var row_height := CELL_HEIGHT + rows.get_theme_constant("separation")
+	var row_count: int = max(int((size.y - rows.position.y) / row_height) - 1, 0)
+
+	while _row_labels.size() < row_count:
+		var row: BoxContainer = table_row.instantiate()
+		var labels: Array[Label] = []
+		for i in range(len(HEADER_LABELS)):
+			var cell: PanelContainer = _create_cell(i)
+			labels.append(cell.get_node("Label"))
+			row.add_child(cell)
+		rows.add_child(row)
+		_row_labels.append(labels)
+
+	while _row_labels.size() > row_count:
+		_row_labels.pop_back()
+		rows.get_child(rows.get_child_count() - 1).queue_free()
+
+
+func _show_frame(labels: Array, frame: Dictionary) -> void:
+	var id_delta_us: int = frame["id_delta_us"]
+
+	labels[INDEX_IDX].text = str(frame["index"])
+	labels[TIME_IDX].text = "%.6f" % (frame["timestamp_us"] * 1e-6)
+	labels[DELTA_IDX].text = "%.3f" % (frame["delta_us"] / 1000.0)
+	labels[ID_DELTA_IDX].text = "%.3f" % (id_delta_us / 1000.0) if id_delta_us >= 0 else "-"
+	labels[DIRECTION_IDX].text = frame["direction"]
+	labels[KIND_IDX].text = frame["kind"]
+	labels[CAN_ID_IDX].text = _format_can_id(frame["id"], frame["extended"])
+	labels[MSG_NAME_IDX].text = frame["msg"]
+	labels[MSG_NAME_IDX].tooltip_text = frame["msg"]
+	labels[DATA_IDX].text = " ".join(Array(frame["data"]).map(_format_can_data_byte))
+
+
+# Adds the header row to the table, should only be called once
+func _generate_header_row() -> void:
+	var header_row: BoxContainer = table_row.instantiate()
+	rows.add_child(header_row)
+	for i in range(len(HEADER_LABELS)):
+		var cell: PanelContainer = _create_cell(i)
+		cell.get_node("Label").text = HEADER_LABELS[i]
+		header_row.add_child(cell)
+
+
+func _create_cell(column: int) -> PanelContainer:
+	var cell: PanelContainer = table_cell.instantiate()
+	cell.custom_minimum_size = Vector2(CELL_WIDTHS[column], CELL_HEIGHT)
+	if column == DATA_IDX:
+		cell.size_flags_horizontal = Control.SIZE_EXPAND_FILL
+		cell.get_node("Label").horizontal_alignment = HORIZONTAL_ALIGNMENT_LEFT
+	cell.get_node("Label").text = ""
+	return cell
+
+
+func _format_can_id(can_id: int, is_extended: bool) -> String:
+	if can_id_format_button.format_on():
+		return "0x" + ("%08x" % can_id).to_upper() if is_extended else "0x" + ("%03x" % can_id).to_upper()
+	else:
+		return "0d" + ("%09d" % can_id) if is_extended else "0d" + ("%04d" % can_id)
+
+
+func _format_can_data_byte(byte: int) -> String:
+	if can_data_format_button.format_on():
+		return ("%02x" % byte).to_upper()
+	else:
+		return "%03d" % byte
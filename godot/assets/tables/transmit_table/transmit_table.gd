@@ -18,25 +18,29 @@ class_name TransmitTable
 const DELETE_IDX = 0
 const TOGGLE_IDX = 1
 const CYCLE_TIME_IDX = 2
-const EXTENDED_ID_IDX = 3
-const CAN_ID_IDX = 4
-const DATA_IDX = 5
+const PHASE_IDX = 3
+const BURST_IDX = 4
+const EXTENDED_ID_IDX = 5
+const CAN_ID_IDX = 6
+const DATA_IDX = 7
+const SIGNALS_IDX = 8
+const STATUS_IDX = 9
 
 const CELL_HEIGHT = 25
-const CELL_WIDTHS = [60, 60, 120, 60, 120, 250]
+const CELL_WIDTHS = [60, 60, 120, 90, 60, 60, 120, 250, 200, 100]
+
+const SIGNALS_TOOLTIP = "Optional signal generators, sending the DBC message with this CAN ID re-encoded every cycle.\nFormat: Signal=waveform(param, ...); Signal2=profile(path/to/profile.csv)\nSignals without a generator keep the value decoded from the data."
 
 
 func _ready() -> void:
 	_generate_header_row()
 
 
-func _physics_process(_delta: float) -> void:
-	if pause_button.is_paused():
-		return
-
+func _process(_delta: float) -> void:
+	# Frames are transmitted by the Rust scheduler, so only the displayed state needs refreshing here
 	for send_entry: TransmitTableEntry in send_entries:
 		if send_entry.sending():
-			send_entry.send_if_ready()
+			send_entry.update_status()
 
 
 func add_new_send_entry() -> void:
@@ -45,7 +49,7 @@ func add_new_send_entry() -> void:
 
 # Adds the header row to the table, should only be called once
 func _generate_header_row() -> void:
-	const HEADER = ["Delete", "Send", "Cycle Time [ms]", "EXT ID", "CAN ID [hex]", "Data [hex]"]
+	const HEADER = ["Delete", "Send", "Cycle Time [ms]", "Phase [ms]", "Burst", "EXT ID", "CAN ID [hex]", "Data [hex]", "Signals", "Status"]
 	var header_row: BoxContainer = table_row.instantiate()
 
 	for i in range(len(HEADER)):
@@ -94,21 +98,25 @@ class TransmitTableEntry:
 	var _godot_can_bridge: GodotCanBridge
 	var _transmit_table: TransmitTable
 
-	var _last_send_time_ms: float
+	# Id of this entry in the Rust transmit scheduler, -1 when not scheduled
+	var _transmit_id: int = -1
 
 	var _row: BoxContainer
 	var _check_box: CheckBox
 	var _extended_id_check_box: CheckBox
 	var _can_id_box: LineEdit
 	var _data_box: LineEdit
+	var _signals_box: LineEdit
 	var _cycle_time_box: LineEdit
+	var _phase_box: LineEdit
+	var _burst_box: LineEdit
+	var _status_label: Label
 
 
 	func _init(godot_can_bridge: GodotCanBridge, transmit_table: TransmitTable) -> void:
 		_godot_can_bridge = godot_can_bridge
 		_transmit_table = transmit_table
 
-		_last_send_time_ms = Time.get_ticks_msec()
 		_instantiate_labels()
 
 
@@ -130,6 +138,7 @@ class TransmitTableEntry:
 				_transmit_table.find_prev_valid_focus().grab_focus()
 
 				# Delete this entire row
+				_stop_sending()
 				_row.queue_free()
 				_transmit_table.send_entries.erase(self)
 		)
@@ -143,16 +152,27 @@ class TransmitTableEntry:
 			# Ensure that message fields cannot be edited while sending.
 			func(toggled_on: bool):
 				_cycle_time_box.editable = not toggled_on
+				_phase_box.editable = not toggled_on
+				_burst_box.editable = not toggled_on
 				_can_id_box.editable = not toggled_on
 				_data_box.editable = not toggled_on
+				_signals_box.editable = not toggled_on
 				_extended_id_check_box.disabled = toggled_on
 
 				# Update tooltips for each element
 				var tooltip_string: String = "Cannot modify message while sending" if toggled_on else ""
 				_cycle_time_box.tooltip_text = tooltip_string
+				_phase_box.tooltip_text = tooltip_string
+				_burst_box.tooltip_text = tooltip_string
 				_can_id_box.tooltip_text = tooltip_string
 				_data_box.tooltip_text = tooltip_string
+				_signals_box.tooltip_text = tooltip_string if toggled_on else SIGNALS_TOOLTIP
 				_extended_id_check_box.tooltip_text = tooltip_string
+
+				if toggled_on:
+					_start_sending()
+				else:
+					_stop_sending()
 		)
 
 		# Add cycle time box
@@ -164,18 +184,25 @@ class TransmitTableEntry:
 
 		# Only allow numeric characters for cycle time box
 		_cycle_time_box.max_length = 4
-		_cycle_time_box.text_changed.connect(
-			func(new_text: String):
-				var filtered := ""
-				for c in new_text:
-					if c.is_valid_int():
-						filtered += c
-				
-				# Update the text with the invalid characters removed, maintaining the old cursor position
-				var old_cursor_pos := _cycle_time_box.caret_column
-				_cycle_time_box.text = filtered
-				_cycle_time_box.caret_column = min(old_cursor_pos - (new_text.length() - filtered.length()), _cycle_time_box.text.length())
-		)
+		_connect_numeric_filter(_cycle_time_box)
+
+		# Add phase offset box
+		var phase_cell: PanelContainer = _transmit_table.table_send_text_cell.instantiate()
+		phase_cell.custom_minimum_size = Vector2(CELL_WIDTHS[PHASE_IDX], CELL_HEIGHT)
+		_phase_box = phase_cell.get_node("LineEdit")
+		_phase_box.text = "0"
+		_row.add_child(phase_cell)
+		_phase_box.max_length = 4
+		_connect_numeric_filter(_phase_box)
+
+		# Add burst count box
+		var burst_cell: PanelContainer = _transmit_table.table_send_text_cell.instantiate()
+		burst_cell.custom_minimum_size = Vector2(CELL_WIDTHS[BURST_IDX], CELL_HEIGHT)
+		_burst_box = burst_cell.get_node("LineEdit")
+		_burst_box.text = "1"
+		_row.add_child(burst_cell)
+		_burst_box.max_length = 3
+		_connect_numeric_filter(_burst_box)
 
 		# Add extended id check box
 		var extended_id_cell: PanelContainer = _transmit_table.table_send_check_box.instantiate()
@@ -238,6 +265,37 @@ class TransmitTableEntry:
 				_data_box.caret_column = min(old_cursor_pos - (new_text.length() - filtered.length()), _data_box.text.length())
 		)
 
+		# Add signals box, holding the generators of the signals of a DBC message
+		var signals_cell: PanelContainer = _transmit_table.table_send_text_cell.instantiate()
+		signals_cell.custom_minimum_size = Vector2(CELL_WIDTHS[SIGNALS_IDX], CELL_HEIGHT)
+		_signals_box = signals_cell.get_node("LineEdit")
+		_signals_box.text = ""
+		_signals_box.tooltip_text = SIGNALS_TOOLTIP
+		_row.add_child(signals_cell)
+
+		# Add status label, showing the measured TX jitter or why the entry could not be sent
+		var status_cell: PanelContainer = _transmit_table.table_cell.instantiate()
+		status_cell.custom_minimum_size = Vector2(CELL_WIDTHS[STATUS_IDX], CELL_HEIGHT)
+		_status_label = status_cell.get_node("Label")
+		_status_label.text = ""
+		_row.add_child(status_cell)
+
+
+	# Restricts a line edit to numeric characters only
+	func _connect_numeric_filter(line_edit: LineEdit) -> void:
+		line_edit.text_changed.connect(
+			func(new_text: String):
+				var filtered := ""
+				for c in new_text:
+					if c.is_valid_int():
+						filtered += c
+				
+				# Update the text with the invalid characters removed, maintaining the old cursor position
+				var old_cursor_pos := line_edit.caret_column
+				line_edit.text = filtered
+				line_edit.caret_column = min(old_cursor_pos - (new_text.length() - filtered.length()), line_edit.text.length())
+		)
+
 
 	func can_id() -> int:
 		return _can_id_box.text.replace(" ", "").hex_to_int()
@@ -255,6 +313,14 @@ class TransmitTableEntry:
 		return int(_cycle_time_box.text)
 
 
+	func phase_offset_ms() -> int:
+		return int(_phase_box.text)
+
+
+	func burst_count() -> int:
+		return max(int(_burst_box.text), 1)
+
+
 	# Returns true if this entry should be currently transmitting
 	func sending() -> bool:
 		return _check_box.button_pressed
@@ -274,38 +340,93 @@ class TransmitTableEntry:
 		return hex_to_byte_array(hex_data())
 
 
-	# Sends this entry over the CAN interface if cycle time has been exceeded
-	func send_if_ready() -> void:
-		var cycle_time = cycle_time_ms()
-		var current_time_ms := Time.get_ticks_msec()
-		if (current_time_ms - _last_send_time_ms) > cycle_time:
-			if not hex_data_valid():
-				_check_box.button_pressed = false
-				AlertHandler.display_error("Invalid hex data provided")
-				return
-			
-			if not can_id_valid():
-				_check_box.button_pressed = false
-				AlertHandler.display_error("Invalid CAN ID provided")
-				return
-			
-			const STANDARD_CAN_ID_MAX: int = 2047
-			if (not is_ext_can()) and (can_id() > STANDARD_CAN_ID_MAX):
-				_check_box.button_pressed = false
-				AlertHandler.display_error("CAN ID is greater than the max Standard CAN ID")
-				return
-			
-			const EXTENDED_CAN_ID_MAX: int = 536870911
-			if is_ext_can() and (can_id() > EXTENDED_CAN_ID_MAX):
-				_check_box.button_pressed = false
-				AlertHandler.display_error("CAN ID is greater than the max Extended CAN ID")
-				return
-
-			_godot_can_bridge.send_can_frame(can_id(), is_ext_can(), data())
-			_last_send_time_ms = current_time_ms
+	# Parses the signals box into a Dictionary of signal names to generators, or returns an error message if it is invalid
+	func signal_generators() -> Variant:
+		var generators: Dictionary = {}
+		for generator_spec in _signals_box.text.split(";", false):
+			var spec := generator_spec.strip_edges()
+			if spec.is_empty():
+				continue
+
+			var equals_pos := spec.find("=")
+			var open_pos := spec.find("(")
+			if equals_pos <= 0 or open_pos <= equals_pos or not spec.ends_with(")"):
+				return "Invalid signal generator \"%s\", expected Signal=waveform(params)" % spec
+
+			var signal_name := spec.left(equals_pos).strip_edges()
+			var waveform := spec.substr(equals_pos + 1, open_pos - equals_pos - 1).strip_edges()
+			var args := spec.substr(open_pos + 1, spec.length() - open_pos - 2).strip_edges()
+			if waveform == "profile":
+				generators[signal_name] = {"profile": args}
+				continue
+
+			var params := PackedFloat64Array()
+			for arg in args.split(",", false):
+				var param := arg.strip_edges()
+				if not param.is_valid_float():
+					return "Invalid parameter \"%s\" for signal %s" % [param, signal_name]
+				params.append(param.to_float())
+			generators[signal_name] = {"waveform": waveform, "params": params}
+		return generators
+
+
+	# Hands this entry to the Rust transmit scheduler, unchecking the send box and showing the error if the entry is invalid
+	func _start_sending() -> void:
+		if not hex_data_valid():
+			_show_error("Invalid hex data provided")
+			return
 		
-		# A cycle time of 0ms should be treated as 'one shot', so disable itself after sending the message
-		if cycle_time == 0:
+		if not can_id_valid():
+			_show_error("Invalid CAN ID provided")
+			return
+
+		var generators: Variant = signal_generators()
+		if generators is String:
+			_show_error(generators)
+			return
+
+		# A cycle time of 0ms is treated as 'one shot' by the scheduler.
+		# Entries with signal generators send the DBC message with their CAN ID, re-encoded every cycle.
+		var result: Dictionary
+		if generators.is_empty():
+			result = _godot_can_bridge.start_cyclic_transmit(can_id(), is_ext_can(), data(), cycle_time_ms(), phase_offset_ms(), burst_count())
+		else:
+			result = _godot_can_bridge.start_cyclic_message(can_id(), is_ext_can(), data(), generators, cycle_time_ms(), phase_offset_ms(), burst_count())
+		if not result["ok"]:
+			_show_error(result["error"])
+			return
+
+		_transmit_id = result["transmit_id"]
+		_status_label.remove_theme_color_override("font_color")
+		_status_label.text = ""
+		_status_label.tooltip_text = ""
+
+
+	func _show_error(message: String) -> void:
+		_check_box.button_pressed = false
+		_status_label.add_theme_color_override("font_color", Color.RED)
+		_status_label.text = message
+		_status_label.tooltip_text = message
+
+
+	func _stop_sending() -> void:
+		if _transmit_id != -1:
+			_godot_can_bridge.stop_cyclic_transmit(_transmit_id)
+			_transmit_id = -1
+
+
+	# Refreshes the measured jitter, and disables the entry once a 'one shot' message has been sent
+	func update_status() -> void:
+		if _transmit_id == -1:
+			return
+
+		var stats: Dictionary = _godot_can_bridge.get_cyclic_transmit_stats(_transmit_id)
+		if stats.has("jitter_mean_us"):
+			_status_label.text = "%.2f / %.2f" % [stats["jitter_mean_us"] / 1000.0, stats["jitter_max_us"] / 1000.0]
+			_status_label.tooltip_text = "Mean / max lateness of %d frames sent" % stats["frames_sent"]
+
+		if not _godot_can_bridge.is_cyclic_transmit_active(_transmit_id):
+			_transmit_id = -1
 			_check_box.button_pressed = false
 
 